@@ -0,0 +1,174 @@
+//! Procedural macros for [`futures-retry`](https://docs.rs/futures-retry).
+//!
+//! Use them through the `macros` feature of `futures-retry` rather than directly.
+
+#![deny(missing_docs)]
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{quote, quote_spanned};
+use syn::{
+    parse::Parser, punctuated::Punctuated, spanned::Spanned, Error, Expr, ExprLit, ItemFn, Lit,
+    LitStr, Meta, ReturnType, Token,
+};
+
+#[derive(Debug, PartialEq)]
+enum Jitter {
+    None,
+    Full,
+    Equal,
+}
+
+struct Config {
+    max_attempts: usize,
+    backoff: LitStr,
+    jitter: Jitter,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            backoff: LitStr::new("exponential(100ms..10s)", Span::call_site()),
+            jitter: Jitter::None,
+        }
+    }
+}
+
+fn string_literal(expr: &Expr) -> syn::Result<&LitStr> {
+    match expr {
+        Expr::Lit(ExprLit {
+            lit: Lit::Str(s), ..
+        }) => Ok(s),
+        _ => Err(Error::new(expr.span(), "expected a string literal")),
+    }
+}
+
+fn parse_config(args: TokenStream2) -> syn::Result<Config> {
+    let mut config = Config::default();
+    let metas = Punctuated::<Meta, Token![,]>::parse_terminated.parse2(args)?;
+    for meta in metas {
+        match &meta {
+            Meta::Path(path) if path.is_ident("jitter") => config.jitter = Jitter::Full,
+            Meta::NameValue(nv) if nv.path.is_ident("jitter") => {
+                config.jitter = match string_literal(&nv.value)?.value().as_str() {
+                    "none" => Jitter::None,
+                    "full" => Jitter::Full,
+                    "equal" => Jitter::Equal,
+                    _ => {
+                        return Err(Error::new(
+                            nv.value.span(),
+                            "expected `none`, `full` or `equal`",
+                        ))
+                    }
+                }
+            }
+            Meta::NameValue(nv) if nv.path.is_ident("max_attempts") => {
+                config.max_attempts = match &nv.value {
+                    Expr::Lit(ExprLit {
+                        lit: Lit::Int(i), ..
+                    }) => i.base10_parse()?,
+                    value => return Err(Error::new(value.span(), "expected an integer")),
+                }
+            }
+            Meta::NameValue(nv) if nv.path.is_ident("backoff") => {
+                config.backoff = string_literal(&nv.value)?.clone();
+            }
+            _ => {
+                return Err(Error::new(
+                    meta.span(),
+                    "expected `max_attempts = ..`, `backoff = \"..\"` or `jitter`",
+                ))
+            }
+        }
+    }
+    Ok(config)
+}
+
+fn expand(config: Config, function: ItemFn) -> syn::Result<TokenStream2> {
+    if function.sig.asyncness.is_none() {
+        return Err(Error::new(
+            function.sig.fn_token.span(),
+            "`#[retry]` can only be applied to async functions",
+        ));
+    }
+    let output = match &function.sig.output {
+        ReturnType::Type(_, ty) => ty.clone(),
+        ReturnType::Default => {
+            return Err(Error::new(
+                Span::call_site(),
+                "`#[retry]` requires a function returning a `Result`",
+            ))
+        }
+    };
+    // The backoff is parsed by `futures-retry` itself, in a constant, so a malformed one fails
+    // the build with an error pointing at the string.
+    let backoff = config.backoff;
+    let backoff = quote_spanned! {backoff.span()=>
+        {
+            const BACKOFF: ::futures_retry::__private::BackoffSpec =
+                ::futures_retry::__private::parse_backoff(#backoff);
+            BACKOFF
+        }
+    };
+    let jitter = match config.jitter {
+        Jitter::None => quote!(None),
+        Jitter::Full => quote!(Full),
+        Jitter::Equal => quote!(Equal),
+    };
+    let max_attempts = config.max_attempts;
+    let ItemFn {
+        attrs,
+        vis,
+        sig,
+        block,
+    } = function;
+    Ok(quote! {
+        #(#attrs)*
+        #vis #sig {
+            let __backoff = #backoff
+                .build()
+                .max_attempts(#max_attempts)
+                .jitter(::futures_retry::Jitter::#jitter);
+            ::futures_retry::FutureRetry::new(
+                || async {
+                    let __result: #output = #block;
+                    __result
+                },
+                __backoff,
+            )
+            .await
+            .map(|(__value, _)| __value)
+            .map_err(|(__error, _)| __error)
+        }
+    })
+}
+
+/// Retries an async function returning a `Result` with an exponential (or a constant) backoff.
+///
+/// ```ignore
+/// #[retry(max_attempts = 5, backoff = "exponential(100ms..10s)", jitter)]
+/// async fn fetch(url: &str) -> Result<String, Error> {
+///     // ...
+/// }
+/// ```
+///
+/// Supported arguments:
+///
+/// * `max_attempts = N`: how many attempts to make in total (3 by default),
+/// * `backoff = "exponential(min)"`, `backoff = "exponential(min..max)"` or
+///   `backoff = "constant(delay)"`: the delays between the attempts, written like in the
+///   `policy!` macro, e.g. `100ms` or `1.5s` with units `ns`, `us`, `ms`, `s`, `m` or `h`
+///   (`exponential(100ms..10s)` by default),
+/// * `jitter` or `jitter = "none" | "full" | "equal"`: randomization of the delays.
+///
+/// The body runs anew on every attempt and only borrows the arguments, so it can't move them out.
+#[proc_macro_attribute]
+pub fn retry(args: TokenStream, item: TokenStream) -> TokenStream {
+    let result = syn::parse::<ItemFn>(item)
+        .and_then(|function| expand(parse_config(args.into())?, function));
+    match result {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
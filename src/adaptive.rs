@@ -0,0 +1,219 @@
+use crate::{sleeper::wait_until, ConfigError, ErrorHandler, RetryPolicy, Sleeper, TokioSleeper};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// An error handler that adapts its delay to how the backend behaves, along the lines of the
+/// AIMD (additive increase, multiplicative decrease) congestion control, turned around.
+///
+/// Every failure multiplies the delay by a [`factor`](#method.factor), so a long outage is quickly
+/// backed off from, while every success takes a [`step`](#method.step) off it, so the delay winds
+/// down gradually once the backend recovers instead of being reset right away. The delay stays
+/// between the `min_delay` and the `max_delay`.
+///
+/// The handler also tracks how long the retried attempts take (a moving average, see
+/// [`latency`](#method.latency)) and never waits less than that: a backend that answers slowly is
+/// not hammered faster than it responds. The latency is measured by a
+/// [`Sleeper`](trait.Sleeper.html), which should be the one that times the retry loop.
+///
+/// Unlike [`ExponentialBackoff`](struct.ExponentialBackoff.html) the delay doesn't depend on the
+/// attempt number, so the handler is best kept for the lifetime of a stream or reused across many
+/// futures. It never gives up on its own.
+///
+/// ```
+/// use futures_retry::{AdaptiveBackoff, ErrorHandler, RetryPolicy};
+/// use std::time::Duration;
+///
+/// let mut backoff = AdaptiveBackoff::new(Duration::from_millis(100), Duration::from_secs(10))
+///     .factor(2.)
+///     .step(Duration::from_millis(50));
+/// assert_eq!(RetryPolicy::WaitRetry(Duration::from_millis(100)), backoff.handle(1, ()));
+/// assert_eq!(RetryPolicy::WaitRetry(Duration::from_millis(200)), backoff.handle(2, ()));
+/// ErrorHandler::<()>::ok(&mut backoff, 3);
+/// assert_eq!(Duration::from_millis(350), backoff.delay());
+/// ```
+#[derive(Debug, Clone)]
+pub struct AdaptiveBackoff<S = TokioSleeper> {
+    min_delay: Duration,
+    max_delay: Duration,
+    factor: f64,
+    step: Duration,
+    delay: Duration,
+    latency: Option<Duration>,
+    next_attempt_at: Option<Instant>,
+    sleeper: S,
+}
+
+impl AdaptiveBackoff {
+    /// Creates a handler with delays between the `min_delay` and the `max_delay`, that doubles the
+    /// delay on a failure and takes the `min_delay` off it on a success.
+    pub fn new(min_delay: Duration, max_delay: Duration) -> Self {
+        Self::with_sleeper(min_delay, max_delay, TokioSleeper)
+    }
+
+    /// Like `new`, but fails if the `min_delay` exceeds the `max_delay` instead of raising the
+    /// latter.
+    pub fn try_new(min_delay: Duration, max_delay: Duration) -> Result<Self, ConfigError> {
+        if min_delay > max_delay {
+            return Err(ConfigError::DelayRange {
+                min: min_delay,
+                max: max_delay,
+            });
+        }
+        Ok(Self::new(min_delay, max_delay))
+    }
+}
+
+impl<S> AdaptiveBackoff<S> {
+    /// Like a `new` method, but the latency is measured by a custom
+    /// [`Sleeper`](trait.Sleeper.html).
+    pub fn with_sleeper(min_delay: Duration, max_delay: Duration, sleeper: S) -> Self {
+        Self {
+            min_delay,
+            max_delay: max_delay.max(min_delay),
+            factor: 2.,
+            step: min_delay,
+            delay: min_delay,
+            latency: None,
+            next_attempt_at: None,
+            sleeper,
+        }
+    }
+
+    /// Sets a multiplier applied to the delay after each failure.
+    pub fn factor(mut self, factor: f64) -> Self {
+        self.factor = factor.max(1.);
+        self
+    }
+
+    /// Sets how much the delay decreases after each success.
+    pub fn step(mut self, step: Duration) -> Self {
+        self.step = step;
+        self
+    }
+
+    /// Returns the delay before the next retry, not accounting for the latency.
+    pub fn delay(&self) -> Duration {
+        self.delay
+    }
+
+    /// Returns the moving average of how long the retried attempts take, if any has been observed.
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency
+    }
+
+    /// Returns the planned delays before each retry if all the following attempts fail, accounting
+    /// for the latency observed so far. The iterator is endless, since the handler never gives up.
+    pub fn schedule(&self) -> impl Iterator<Item = Duration> {
+        let (factor, max_delay) = (self.factor, self.max_delay);
+        let floor = self.latency.unwrap_or_default();
+        std::iter::successors(Some(self.delay), move |delay| {
+            Some(
+                Duration::try_from_secs_f64(delay.as_secs_f64() * factor)
+                    .map_or(max_delay, |delay| delay.min(max_delay)),
+            )
+        })
+        .map(move |delay| delay.max(floor).min(max_delay))
+    }
+
+    fn observe_latency(&mut self)
+    where
+        S: Sleeper,
+    {
+        let started = match self.next_attempt_at.take() {
+            Some(started) => started,
+            None => return,
+        };
+        let latency = self.sleeper.now().saturating_duration_since(started);
+        self.latency = Some(match self.latency {
+            Some(average) => average.mul_f64(0.8) + latency.mul_f64(0.2),
+            None => latency,
+        });
+    }
+}
+
+impl<E, S: Sleeper> ErrorHandler<E> for AdaptiveBackoff<S> {
+    type OutError = E;
+
+    fn handle(&mut self, _attempt: usize, _e: E) -> RetryPolicy<E> {
+        self.observe_latency();
+        let delay = self
+            .latency
+            .map_or(self.delay, |latency| self.delay.max(latency))
+            .min(self.max_delay);
+        self.delay = Duration::try_from_secs_f64(self.delay.as_secs_f64() * self.factor)
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay));
+        self.next_attempt_at = Some(wait_until(self.sleeper.now(), delay));
+        RetryPolicy::WaitRetry(delay)
+    }
+
+    fn ok(&mut self, _attempt: usize) {
+        self.observe_latency();
+        self.delay = self.delay.saturating_sub(self.step).max(self.min_delay);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn increases_and_decays() {
+        let mut backoff =
+            AdaptiveBackoff::new(Duration::from_millis(10), Duration::from_millis(50));
+        let delays: Vec<_> = (1..=5)
+            .map(|attempt| match backoff.handle(attempt, ()) {
+                RetryPolicy::WaitRetry(delay) => delay.as_millis(),
+                policy => panic!("Unexpected policy {:?}", policy),
+            })
+            .collect();
+        assert_eq!(vec![10, 20, 40, 50, 50], delays);
+        let schedule: Vec<_> = backoff.schedule().take(2).collect();
+        assert_eq!(vec![Duration::from_millis(50); 2], schedule);
+        let decayed: Vec<_> = (0..6)
+            .map(|_| {
+                ErrorHandler::<()>::ok(&mut backoff, 1);
+                backoff.delay().as_millis()
+            })
+            .collect();
+        assert_eq!(vec![40, 30, 20, 10, 10, 10], decayed);
+        let schedule: Vec<_> = backoff.schedule().take(4).map(|d| d.as_millis()).collect();
+        assert_eq!(vec![10, 20, 40, 50], schedule);
+    }
+
+    #[test]
+    fn waits_at_least_the_latency() {
+        let mut backoff = AdaptiveBackoff::new(Duration::from_millis(1), Duration::from_secs(1));
+        assert_eq!(
+            RetryPolicy::WaitRetry(Duration::from_millis(1)),
+            backoff.handle(1, ())
+        );
+        // The retried attempt takes a while.
+        std::thread::sleep(Duration::from_millis(40));
+        match backoff.handle(2, ()) {
+            RetryPolicy::WaitRetry(delay) => assert!(delay >= Duration::from_millis(30)),
+            policy => panic!("Unexpected policy {:?}", policy),
+        }
+        assert!(backoff.latency().unwrap() >= Duration::from_millis(30));
+    }
+
+    #[cfg(feature = "test_util")]
+    #[test]
+    fn measures_latency_by_the_sleeper() {
+        let sleeper = crate::test_util::MockSleeper::new();
+        let mut backoff = AdaptiveBackoff::with_sleeper(
+            Duration::from_millis(1),
+            Duration::from_secs(1),
+            sleeper.clone(),
+        );
+        assert_eq!(
+            RetryPolicy::WaitRetry(Duration::from_millis(1)),
+            backoff.handle(1, ())
+        );
+        sleeper.advance(Duration::from_millis(41));
+        assert_eq!(
+            RetryPolicy::WaitRetry(Duration::from_millis(40)),
+            backoff.handle(2, ())
+        );
+        assert_eq!(Some(Duration::from_millis(40)), backoff.latency());
+    }
+}
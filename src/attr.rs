@@ -0,0 +1,28 @@
+//! The `#[retry]` attribute for async functions.
+//!
+//! Available with the `macros` feature.
+//!
+//! The attribute wraps the body of an async function returning a `Result` into a
+//! [`FutureRetry`](../struct.FutureRetry.html) with an
+//! [`ExponentialBackoff`](../struct.ExponentialBackoff.html) built from its arguments. It is
+//! exported from this module rather than from the crate root, so it doesn't clash with the
+//! [`retry!`](../macro.retry.html) macro.
+//!
+//! ```
+//! use futures_retry::attr::retry;
+//! use std::sync::atomic::{AtomicUsize, Ordering};
+//!
+//! #[retry(max_attempts = 5, backoff = "exponential(1ms..10ms)", jitter)]
+//! async fn flaky(calls: &AtomicUsize) -> Result<usize, std::io::Error> {
+//!     match calls.fetch_add(1, Ordering::SeqCst) {
+//!         0 | 1 => Err(std::io::ErrorKind::ConnectionReset.into()),
+//!         call => Ok(call),
+//!     }
+//! }
+//!
+//! # #[tokio::main] async fn main() {
+//! assert_eq!(2, flaky(&AtomicUsize::new(0)).await.unwrap());
+//! # }
+//! ```
+
+pub use futures_retry_macros::retry;
@@ -0,0 +1,430 @@
+use crate::{ErrorHandler, RetryPolicy};
+use std::{convert::TryFrom, fmt, time::Duration};
+
+/// Describes how to randomize a delay before it is used.
+///
+/// Randomized delays help to avoid "thundering herds" when many clients lose their connections at
+/// once and then try to reconnect at exactly the same moments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Jitter {
+    /// Use a delay as it is.
+    None,
+    /// Pick a random delay between zero and the original one.
+    Full,
+    /// Keep a half of the original delay and randomize the other half.
+    Equal,
+}
+
+impl Jitter {
+    /// Randomizes a delay.
+    pub fn apply(self, delay: Duration) -> Duration {
+        self.randomize(delay, |max| fastrand::u64(..=max))
+    }
+
+    /// Randomizes a delay with the given random number generator, so a seeded generator yields
+    /// reproducible delays.
+    pub fn apply_with(self, delay: Duration, rng: &mut fastrand::Rng) -> Duration {
+        self.randomize(delay, |max| rng.u64(..=max))
+    }
+
+    fn randomize(self, delay: Duration, random: impl FnOnce(u64) -> u64) -> Duration {
+        let nanos = u64::try_from(delay.as_nanos()).unwrap_or(u64::MAX);
+        match self {
+            Jitter::None => delay,
+            Jitter::Full => Duration::from_nanos(random(nanos)),
+            Jitter::Equal => {
+                let half = nanos / 2;
+                Duration::from_nanos(nanos - half + random(half))
+            }
+        }
+    }
+}
+
+/// An error handler that retries on any error, waiting exponentially longer before each new
+/// attempt.
+///
+/// The delay before the `n`-th attempt is `initial_delay * factor^(n - 1)`, optionally capped by
+/// [`max_delay`](#method.max_delay) and randomized by [`jitter`](#method.jitter). Once
+/// [`max_attempts`](#method.max_attempts) attempts have failed, the error is forwarded.
+///
+/// The jitter is drawn from a thread-local generator unless a [`seed`](#method.seed) is given,
+/// which makes the schedule reproducible.
+///
+/// ```
+/// use futures_retry::{ExponentialBackoff, Jitter};
+/// use std::time::Duration;
+///
+/// let backoff = ExponentialBackoff::new(Duration::from_millis(100))
+///     .max_delay(Duration::from_secs(30))
+///     .max_attempts(10)
+///     .jitter(Jitter::Full);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ExponentialBackoff {
+    initial_delay: Duration,
+    factor: u32,
+    max_delay: Option<Duration>,
+    max_attempts: Option<usize>,
+    jitter: Jitter,
+    rng: Option<fastrand::Rng>,
+}
+
+impl ExponentialBackoff {
+    /// Creates a backoff that starts with the `initial_delay` and doubles it after each failed
+    /// attempt, with no limits on both the delay and the number of attempts.
+    pub fn new(initial_delay: Duration) -> Self {
+        Self {
+            initial_delay,
+            factor: 2,
+            max_delay: None,
+            max_attempts: None,
+            jitter: Jitter::None,
+            rng: None,
+        }
+    }
+
+    /// Sets a multiplier applied to the delay after each failed attempt.
+    pub fn factor(mut self, factor: u32) -> Self {
+        self.factor = factor;
+        self
+    }
+
+    /// Sets an upper limit for the delay.
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = Some(max_delay);
+        self
+    }
+
+    /// Sets how many attempts might be made before giving up.
+    pub fn max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+
+    /// Sets how to randomize the delays.
+    pub fn jitter(mut self, jitter: Jitter) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Seeds the random number generator used for the jitter, so the backoff picks the same delays
+    /// on every run. Clones of a seeded backoff continue the same sequence.
+    pub fn seed(self, seed: u64) -> Self {
+        self.rng(fastrand::Rng::with_seed(seed))
+    }
+
+    /// Sets the random number generator used for the jitter.
+    pub fn rng(mut self, rng: fastrand::Rng) -> Self {
+        self.rng = Some(rng);
+        self
+    }
+
+    /// Calculates a delay before the next attempt when the `attempt` has failed, **without** the
+    /// jitter applied.
+    pub fn delay(&self, attempt: usize) -> Duration {
+        let exponent = u32::try_from(attempt.saturating_sub(1)).unwrap_or(u32::MAX);
+        let delay = self
+            .factor
+            .checked_pow(exponent)
+            .and_then(|multiplier| self.initial_delay.checked_mul(multiplier))
+            .unwrap_or(Duration::MAX);
+        match self.max_delay {
+            Some(max_delay) => delay.min(max_delay),
+            None => delay,
+        }
+    }
+
+    /// Returns the planned delays before each retry, **without** the jitter applied, if all the
+    /// attempts fail.
+    ///
+    /// The iterator ends once the [`max_attempts`](#method.max_attempts) are exhausted and is
+    /// endless otherwise.
+    ///
+    /// ```
+    /// use futures_retry::ExponentialBackoff;
+    /// use std::time::Duration;
+    ///
+    /// let backoff = ExponentialBackoff::new(Duration::from_secs(1)).max_attempts(4);
+    /// let schedule: Vec<_> = backoff.schedule().map(|delay| delay.as_secs()).collect();
+    /// assert_eq!(vec![1, 2, 4], schedule);
+    /// ```
+    pub fn schedule(&self) -> impl Iterator<Item = Duration> {
+        let backoff = Self {
+            rng: None,
+            ..self.clone()
+        };
+        let retries = self
+            .max_attempts
+            .map_or(usize::MAX, |max| max.saturating_sub(1));
+        (1..=retries).map(move |attempt| backoff.delay(attempt))
+    }
+}
+
+impl<E> ErrorHandler<E> for ExponentialBackoff {
+    type OutError = E;
+
+    fn handle(&mut self, attempt: usize, e: E) -> RetryPolicy<E> {
+        match self.max_attempts {
+            Some(max_attempts) if attempt >= max_attempts => RetryPolicy::ForwardError(e),
+            _ => {
+                let delay = self.delay(attempt);
+                RetryPolicy::WaitRetry(match &mut self.rng {
+                    Some(rng) => self.jitter.apply_with(delay, rng),
+                    None => self.jitter.apply(delay),
+                })
+            }
+        }
+    }
+}
+
+/// An error handler that waits for the delays a closure computes from the number of the failed
+/// attempt, so a custom curve comes with the same limits and jitter as an
+/// [`ExponentialBackoff`](struct.ExponentialBackoff.html).
+///
+/// Wrap the handler into a [`Classifier`](struct.Classifier.html) to forward the permanent errors
+/// right away.
+///
+/// ```
+/// use futures_retry::{BackoffFn, ErrorHandler, RetryPolicy};
+/// use std::{f64::consts::FRAC_2_PI, time::Duration};
+///
+/// // Approaches a second fast, but never gets there.
+/// let curve = |attempt: usize| Duration::from_secs_f64((attempt as f64 - 1.).atan() * FRAC_2_PI);
+/// let mut backoff = BackoffFn::new(curve)
+///     .max_delay(Duration::from_millis(800))
+///     .max_attempts(10);
+/// assert_eq!(RetryPolicy::WaitRetry(Duration::ZERO), backoff.handle(1, ()));
+/// assert_eq!(Duration::from_millis(800), backoff.delay(9));
+/// assert_eq!(RetryPolicy::ForwardError(()), backoff.handle(10, ()));
+/// ```
+#[derive(Clone)]
+pub struct BackoffFn<F> {
+    curve: F,
+    max_delay: Option<Duration>,
+    max_attempts: Option<usize>,
+    jitter: Jitter,
+    rng: Option<fastrand::Rng>,
+}
+
+impl<F> BackoffFn<F> {
+    /// Creates a backoff that waits for the delays the `curve` computes from the number of the
+    /// failed attempt, with no limits on both the delay and the number of attempts.
+    pub fn new(curve: F) -> Self {
+        Self {
+            curve,
+            max_delay: None,
+            max_attempts: None,
+            jitter: Jitter::None,
+            rng: None,
+        }
+    }
+
+    /// Sets an upper limit for the delay.
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = Some(max_delay);
+        self
+    }
+
+    /// Sets how many attempts might be made before giving up.
+    pub fn max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+
+    /// Sets how to randomize the delays.
+    pub fn jitter(mut self, jitter: Jitter) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Seeds the random number generator used for the jitter, so the backoff picks the same delays
+    /// on every run.
+    pub fn seed(self, seed: u64) -> Self {
+        self.rng(fastrand::Rng::with_seed(seed))
+    }
+
+    /// Sets the random number generator used for the jitter.
+    pub fn rng(mut self, rng: fastrand::Rng) -> Self {
+        self.rng = Some(rng);
+        self
+    }
+}
+
+impl<F: Fn(usize) -> Duration> BackoffFn<F> {
+    /// Calculates a delay before the next attempt when the `attempt` has failed, **without** the
+    /// jitter applied.
+    pub fn delay(&self, attempt: usize) -> Duration {
+        let delay = (self.curve)(attempt);
+        match self.max_delay {
+            Some(max_delay) => delay.min(max_delay),
+            None => delay,
+        }
+    }
+}
+
+impl<F> fmt::Debug for BackoffFn<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BackoffFn")
+            .field("max_delay", &self.max_delay)
+            .field("max_attempts", &self.max_attempts)
+            .field("jitter", &self.jitter)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<E, F: Fn(usize) -> Duration> ErrorHandler<E> for BackoffFn<F> {
+    type OutError = E;
+
+    fn handle(&mut self, attempt: usize, e: E) -> RetryPolicy<E> {
+        match self.max_attempts {
+            Some(max_attempts) if attempt >= max_attempts => RetryPolicy::ForwardError(e),
+            _ => {
+                let delay = self.delay(attempt);
+                RetryPolicy::WaitRetry(match &mut self.rng {
+                    Some(rng) => self.jitter.apply_with(delay, rng),
+                    None => self.jitter.apply(delay),
+                })
+            }
+        }
+    }
+}
+
+/// An error handler on top of a [`backoff`](https://docs.rs/backoff) strategy, so the existing
+/// `backoff` configurations might be used as they are.
+///
+/// The handler waits for the delays the strategy yields and forwards the error once it yields
+/// none. A success resets the strategy.
+///
+/// Available with the `backoff` feature.
+///
+/// ```
+/// use futures_retry::{BackoffHandler, ErrorHandler, RetryPolicy};
+/// use std::time::Duration;
+///
+/// let strategy = backoff::backoff::Constant::new(Duration::from_millis(10));
+/// let mut handler = BackoffHandler::new(strategy);
+/// assert_eq!(RetryPolicy::WaitRetry(Duration::from_millis(10)), handler.handle(1, ()));
+/// ```
+#[cfg(feature = "backoff")]
+#[derive(Debug, Clone)]
+pub struct BackoffHandler<B> {
+    backoff: B,
+}
+
+#[cfg(feature = "backoff")]
+impl<B> BackoffHandler<B> {
+    /// Wraps a `backoff` strategy.
+    pub fn new(backoff: B) -> Self {
+        Self { backoff }
+    }
+
+    /// Returns a reference to the strategy.
+    pub fn get_ref(&self) -> &B {
+        &self.backoff
+    }
+
+    /// Returns the strategy.
+    pub fn into_inner(self) -> B {
+        self.backoff
+    }
+}
+
+#[cfg(feature = "backoff")]
+impl<E, B: ::backoff::backoff::Backoff> ErrorHandler<E> for BackoffHandler<B> {
+    type OutError = E;
+
+    fn handle(&mut self, _attempt: usize, e: E) -> RetryPolicy<E> {
+        match self.backoff.next_backoff() {
+            Some(delay) => RetryPolicy::WaitRetry(delay),
+            None => RetryPolicy::ForwardError(e),
+        }
+    }
+
+    fn ok(&mut self, _attempt: usize) {
+        self.backoff.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays() {
+        let backoff = ExponentialBackoff::new(Duration::from_millis(10))
+            .factor(3)
+            .max_delay(Duration::from_millis(200));
+        let delays: Vec<_> = (1..=5).map(|attempt| backoff.delay(attempt)).collect();
+        assert_eq!(
+            vec![10, 30, 90, 200, 200],
+            delays.iter().map(Duration::as_millis).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Duration::MAX,
+            ExponentialBackoff::new(Duration::MAX).delay(100)
+        );
+    }
+
+    #[test]
+    fn attempts_limit() {
+        let mut backoff = ExponentialBackoff::new(Duration::from_millis(10)).max_attempts(2);
+        assert_eq!(
+            RetryPolicy::WaitRetry(Duration::from_millis(10)),
+            backoff.handle(1, ())
+        );
+        assert_eq!(RetryPolicy::ForwardError(()), backoff.handle(2, ()));
+        assert_eq!(1, backoff.schedule().count());
+        assert_eq!(0, backoff.max_attempts(0).schedule().count());
+    }
+
+    #[test]
+    fn jitter() {
+        let delay = Duration::from_millis(100);
+        for _ in 0..100 {
+            assert!(Jitter::Full.apply(delay) <= delay);
+            let equal = Jitter::Equal.apply(delay);
+            assert!(equal >= delay / 2 && equal <= delay);
+        }
+        assert_eq!(delay, Jitter::None.apply(delay));
+    }
+
+    #[test]
+    fn seeded_jitter() {
+        let backoff = ExponentialBackoff::new(Duration::from_millis(100)).jitter(Jitter::Full);
+        let schedule = |backoff: ExponentialBackoff| crate::simulate(backoff.seed(7), vec![(); 10]);
+        assert_eq!(schedule(backoff.clone()), schedule(backoff));
+    }
+
+    #[test]
+    fn closure_curve() {
+        let mut backoff = BackoffFn::new(|attempt| Duration::from_millis(attempt as u64 * 10))
+            .max_delay(Duration::from_millis(25))
+            .jitter(Jitter::Equal)
+            .seed(1);
+        let delays: Vec<_> = (1..=3).map(|attempt| backoff.delay(attempt)).collect();
+        assert_eq!(
+            vec![10, 20, 25],
+            delays.iter().map(Duration::as_millis).collect::<Vec<_>>()
+        );
+        match backoff.handle(3, ()) {
+            RetryPolicy::WaitRetry(delay) => {
+                assert!(
+                    delay >= Duration::from_micros(12_500) && delay <= Duration::from_millis(25)
+                )
+            }
+            policy => panic!("Unexpected policy {:?}", policy),
+        }
+    }
+
+    #[cfg(feature = "backoff")]
+    #[test]
+    fn backoff_strategies() {
+        let strategy = ::backoff::backoff::Zero {};
+        let mut handler = BackoffHandler::new(::backoff::backoff::Stop {});
+        assert_eq!(RetryPolicy::ForwardError(1), handler.handle(1, 1));
+        let mut handler = BackoffHandler::new(strategy);
+        assert_eq!(RetryPolicy::WaitRetry(Duration::ZERO), handler.handle(1, 1));
+        ErrorHandler::<u8>::ok(&mut handler, 2);
+    }
+}
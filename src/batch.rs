@@ -0,0 +1,299 @@
+use crate::{ErrorHandler, RetryPolicy, Sleeper};
+use futures::{
+    ready,
+    stream::{Chunks, Fuse},
+    Stream, StreamExt, TryFuture,
+};
+use pin_project_lite::pin_project;
+use std::{
+    collections::VecDeque,
+    fmt,
+    future::Future,
+    mem,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+pin_project! {
+    #[project = BatchStateProj]
+    enum BatchState<Fut, D> {
+        Idle,
+        Running { #[pin] future: Fut },
+        TimerActive { #[pin] delay: D },
+    }
+}
+
+pin_project! {
+    /// A stream that groups the items of an underlying stream into batches and runs an operation
+    /// on every batch, retrying a failed batch before moving on to the next one.
+    ///
+    /// It yields the outcome of every batch along with the number of the attempt, like a
+    /// [`FutureRetry`](struct.FutureRetry.html) does. The operation is given a clone of the batch
+    /// on every attempt.
+    ///
+    /// Created by [`retry_batches`](fn.retry_batches.html).
+    pub struct BatchRetry<S, Op, Fut, R, T>
+    where
+        S: Stream,
+        T: Sleeper,
+    {
+        #[pin]
+        chunks: Fuse<Chunks<S>>,
+        operation: Op,
+        error_action: R,
+        split: bool,
+        split_off: VecDeque<Vec<S::Item>>,
+        batch: Vec<S::Item>,
+        attempt: usize,
+        sleeper: T,
+        #[pin]
+        state: BatchState<Fut, T::Sleep>,
+    }
+}
+
+/// Runs the `operation` on the batches of up to `size` items of the `stream`, retrying a failed
+/// batch as the `error_action` decides, e.g. for bulk inserts. The delays between the attempts
+/// are timed by the `sleeper`.
+///
+/// ```
+/// use futures::{future::ready, stream, StreamExt};
+/// use futures_retry::{retry_batches, RetryPolicy, TokioSleeper};
+///
+/// # #[tokio::main] async fn main() {
+/// let mut fail = true;
+/// let inserted: Vec<_> = retry_batches(
+///     stream::iter(1..=5),
+///     2,
+///     |rows: Vec<u32>| {
+///         fail = !fail;
+///         ready(if fail { Err("deadlock") } else { Ok(rows.len()) })
+///     },
+///     |_| RetryPolicy::Repeat::<&str>,
+///     TokioSleeper,
+/// )
+/// .collect()
+/// .await;
+/// assert_eq!(vec![Ok((2, 1)), Ok((2, 2)), Ok((1, 2))], inserted);
+/// # }
+/// ```
+pub fn retry_batches<S, Op, Fut, R, T>(
+    stream: S,
+    size: usize,
+    operation: Op,
+    error_action: R,
+    sleeper: T,
+) -> BatchRetry<S, Op, Fut, R, T>
+where
+    S: Stream,
+    S::Item: Clone,
+    Op: FnMut(Vec<S::Item>) -> Fut,
+    Fut: TryFuture,
+    R: ErrorHandler<Fut::Error>,
+    T: Sleeper,
+{
+    BatchRetry {
+        chunks: stream.chunks(size).fuse(),
+        operation,
+        error_action,
+        split: false,
+        split_off: VecDeque::new(),
+        batch: Vec::new(),
+        attempt: 1,
+        sleeper,
+        state: BatchState::Idle,
+    }
+}
+
+impl<S: Stream, Op, Fut, R, T: Sleeper> BatchRetry<S, Op, Fut, R, T> {
+    /// Splits a batch in halves once the handler gives up on it, instead of yielding the error,
+    /// so a single bad item only fails a batch of its own. The halves are retried from the first
+    /// attempt, and only the errors of single-item batches are yielded.
+    pub fn split_failed(mut self) -> Self {
+        self.split = true;
+        self
+    }
+}
+
+impl<S: Stream, Op, Fut, R, T: Sleeper> fmt::Debug for BatchRetry<S, Op, Fut, R, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BatchRetry")
+            .field("batch_len", &self.batch.len())
+            .field("attempt", &self.attempt)
+            .field("split_off", &self.split_off.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S, Op, Fut, R, T> Stream for BatchRetry<S, Op, Fut, R, T>
+where
+    S: Stream,
+    S::Item: Clone,
+    Op: FnMut(Vec<S::Item>) -> Fut,
+    Fut: TryFuture,
+    R: ErrorHandler<Fut::Error>,
+    T: Sleeper,
+{
+    type Item = Result<(Fut::Ok, usize), (R::OutError, usize)>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            match this.state.as_mut().project() {
+                BatchStateProj::Idle => {
+                    *this.batch = match this.split_off.pop_front() {
+                        Some(batch) => batch,
+                        None => match ready!(this.chunks.as_mut().poll_next(cx)) {
+                            Some(batch) => batch,
+                            None => return Poll::Ready(None),
+                        },
+                    };
+                    *this.attempt = 1;
+                }
+                BatchStateProj::TimerActive { delay } => ready!(delay.poll(cx)),
+                BatchStateProj::Running { future } => {
+                    let result = ready!(future.try_poll(cx));
+                    this.state.set(BatchState::Idle);
+                    let attempt = *this.attempt;
+                    match result {
+                        Ok(x) => {
+                            this.error_action.ok_with(attempt, &x);
+                            return Poll::Ready(Some(Ok((x, attempt))));
+                        }
+                        Err(e) => {
+                            let policy = this.error_action.handle(attempt, e);
+                            let next = policy.next_attempt(attempt);
+                            match policy {
+                                RetryPolicy::ForwardError(_)
+                                    if *this.split && this.batch.len() > 1 =>
+                                {
+                                    let mut first = mem::take(this.batch);
+                                    let second = first.split_off(first.len() / 2);
+                                    this.split_off.push_front(second);
+                                    this.split_off.push_front(first);
+                                    continue;
+                                }
+                                RetryPolicy::ForwardError(e) => {
+                                    this.error_action.exhausted(attempt, &e);
+                                    return Poll::Ready(Some(Err((e, attempt))));
+                                }
+                                RetryPolicy::Repeat | RetryPolicy::RepeatWithoutCounting => {
+                                    *this.attempt = next
+                                }
+                                RetryPolicy::WaitRetry(duration)
+                                | RetryPolicy::RetryAs {
+                                    delay: duration, ..
+                                } => {
+                                    *this.attempt = next;
+                                    this.state.set(BatchState::TimerActive {
+                                        delay: this.sleeper.sleep(duration),
+                                    });
+                                    continue;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            let future = (this.operation)(this.batch.clone());
+            this.state.set(BatchState::Running { future });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error_handler::tests::Successes, TokioSleeper};
+    use futures::{future::ready, stream};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn splits_failed_batches() {
+        let results: Vec<_> = retry_batches(
+            stream::iter(1..=5),
+            4,
+            |rows: Vec<u8>| ready(if rows.contains(&3) { Err(3) } else { Ok(rows) }),
+            RetryPolicy::ForwardError,
+            TokioSleeper,
+        )
+        .split_failed()
+        .collect()
+        .await;
+        assert_eq!(
+            vec![
+                Ok((vec![1, 2], 1)),
+                Err((3, 1)),
+                Ok((vec![4], 1)),
+                Ok((vec![5], 1))
+            ],
+            results
+        );
+    }
+
+    #[tokio::test]
+    async fn waits_between_attempts() {
+        let mut calls = 0;
+        let results: Vec<_> = retry_batches(
+            stream::iter(vec!["a", "b"]),
+            2,
+            |rows: Vec<&str>| {
+                calls += 1;
+                ready(if calls < 3 {
+                    Err(())
+                } else {
+                    Ok(rows.concat())
+                })
+            },
+            |_| RetryPolicy::WaitRetry::<()>(Duration::from_millis(5)),
+            TokioSleeper,
+        )
+        .collect()
+        .await;
+        assert_eq!(vec![Ok(("ab".to_owned(), 3))], results);
+    }
+
+    #[cfg(feature = "test_util")]
+    #[tokio::test]
+    async fn waits_on_the_sleeper() {
+        let mut calls = 0;
+        let sleeper = crate::test_util::MockSleeper::new();
+        let hour = Duration::from_secs(3600);
+        let batches = retry_batches(
+            stream::iter(vec!["a", "b"]),
+            2,
+            |rows: Vec<&str>| {
+                calls += 1;
+                ready(if calls < 2 { Err(()) } else { Ok(rows.len()) })
+            },
+            |_| RetryPolicy::WaitRetry::<()>(hour),
+            sleeper.clone(),
+        );
+        tokio::pin!(batches);
+        let next = batches.next();
+        tokio::pin!(next);
+        assert!(futures::poll!(next.as_mut()).is_pending());
+        sleeper.advance(hour);
+        assert_eq!(Some(Ok((2, 2))), next.await);
+        assert_eq!(vec![hour], sleeper.requested());
+    }
+
+    #[tokio::test]
+    async fn passes_success_values() {
+        let successes = Successes::new(|_| RetryPolicy::Repeat::<()>);
+        let mut calls = 0;
+        let results: Vec<_> = retry_batches(
+            stream::iter(vec!["a", "b"]),
+            2,
+            |rows: Vec<&str>| {
+                calls += 1;
+                ready(if calls < 2 { Err(()) } else { Ok(rows.len()) })
+            },
+            successes.clone(),
+            TokioSleeper,
+        )
+        .collect()
+        .await;
+        assert_eq!(vec![Ok((2, 2))], results);
+        assert_eq!(vec![(2, "usize")], successes.seen());
+    }
+}
@@ -0,0 +1,245 @@
+use crate::{ConfigError, ErrorHandler, RetryPolicy};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use tokio::time::Instant;
+
+#[derive(Debug)]
+struct Bucket {
+    balance: f64,
+    max_balance: f64,
+    deposit: f64,
+    reserve_per_sec: f64,
+    last_refill: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.last_refill = now;
+        self.add(elapsed.as_secs_f64() * self.reserve_per_sec);
+    }
+
+    fn add(&mut self, amount: f64) {
+        self.balance = (self.balance + amount).min(self.max_balance);
+    }
+}
+
+/// A retry budget shared among many retrying futures and streams, in the spirit of Finagle's
+/// `RetryBudget`.
+///
+/// The budget is a token bucket: each successful attempt deposits a fraction of a token, each
+/// retry withdraws a whole token, and once the bucket is empty no more retries are allowed. This
+/// way retries can't add more than a fixed share of extra load to a backend, so an outage is not
+/// amplified by the clients retrying over and over. A small reserve might also be refilled over
+/// time so that rarely used clients are still able to retry.
+///
+/// The budget is supposed to be shared among the handlers via an `Arc`:
+///
+/// ```
+/// use futures_retry::{ExponentialBackoff, RetryBudget};
+/// use std::{sync::Arc, time::Duration};
+///
+/// // Allow 20% of extra load from retries, and up to 10 retries per second anyway.
+/// let budget = Arc::new(RetryBudget::new(0.2, 100).reserve_per_second(10));
+/// let handler = RetryBudget::handler(&budget, ExponentialBackoff::new(Duration::from_millis(10)));
+/// ```
+#[derive(Debug)]
+pub struct RetryBudget {
+    bucket: Mutex<Bucket>,
+}
+
+impl Default for RetryBudget {
+    /// A budget that allows retries to add up to 20% of extra load, holds at most 100 retries and
+    /// refills 10 retries per second.
+    fn default() -> Self {
+        Self::new(0.2, 100).reserve_per_second(10)
+    }
+}
+
+impl RetryBudget {
+    /// Creates a full budget of `max_retries` tokens, depositing `retry_ratio` of a token on each
+    /// success.
+    pub fn new(retry_ratio: f64, max_retries: usize) -> Self {
+        let max_balance = max_retries as f64;
+        Self {
+            bucket: Mutex::new(Bucket {
+                balance: max_balance,
+                max_balance,
+                deposit: retry_ratio,
+                reserve_per_sec: 0.,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Like `new`, but checks that the budget holds at least one token and the `retry_ratio` is a
+    /// non-negative finite number.
+    pub fn try_new(retry_ratio: f64, max_retries: usize) -> Result<Self, ConfigError> {
+        if max_retries == 0 {
+            Err(ConfigError::ZeroBudget)
+        } else if !retry_ratio.is_finite() || retry_ratio < 0. {
+            Err(ConfigError::InvalidRatio)
+        } else {
+            Ok(Self::new(retry_ratio, max_retries))
+        }
+    }
+
+    /// Sets how many tokens are added to the budget every second regardless of successes.
+    pub fn reserve_per_second(self, retries: u32) -> Self {
+        self.lock().reserve_per_sec = f64::from(retries);
+        self
+    }
+
+    /// Deposits tokens for a successful attempt.
+    pub fn deposit(&self) {
+        let mut bucket = self.lock();
+        let deposit = bucket.deposit;
+        bucket.add(deposit);
+    }
+
+    /// Tries to withdraw a token for a retry. Returns `false` if the budget is exhausted.
+    pub fn try_withdraw(&self) -> bool {
+        let mut bucket = self.lock();
+        bucket.refill(Instant::now());
+        if bucket.balance >= 1. {
+            bucket.balance -= 1.;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns an unused token back to the budget.
+    pub fn refund(&self) {
+        self.lock().add(1.);
+    }
+
+    /// Returns how many retries are currently available.
+    pub fn available(&self) -> usize {
+        let mut bucket = self.lock();
+        bucket.refill(Instant::now());
+        bucket.balance as usize
+    }
+
+    /// Wraps an error handler so every retry it requests is paid from the `budget`.
+    pub fn handler<H>(budget: &Arc<Self>, inner: H) -> BudgetHandler<H> {
+        BudgetHandler {
+            budget: Arc::clone(budget),
+            inner,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Bucket> {
+        self.bucket.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// An error handler that forwards errors instead of retrying once a shared
+/// [`RetryBudget`](struct.RetryBudget.html) is exhausted.
+///
+/// Created by [`RetryBudget::handler`](struct.RetryBudget.html#method.handler).
+#[derive(Debug, Clone)]
+pub struct BudgetHandler<H> {
+    budget: Arc<RetryBudget>,
+    inner: H,
+}
+
+impl<H> BudgetHandler<H> {
+    /// Returns the budget this handler withdraws from.
+    pub fn budget(&self) -> &Arc<RetryBudget> {
+        &self.budget
+    }
+}
+
+impl<E, H> ErrorHandler<E> for BudgetHandler<H>
+where
+    H: ErrorHandler<E>,
+    H::OutError: From<E>,
+{
+    type OutError = H::OutError;
+
+    fn handle(&mut self, attempt: usize, e: E) -> RetryPolicy<H::OutError> {
+        if !self.budget.try_withdraw() {
+            return RetryPolicy::ForwardError(e.into());
+        }
+        let policy = self.inner.handle(attempt, e);
+        // Neither giving up nor a benign error should eat into the budget.
+        if let RetryPolicy::ForwardError(_) | RetryPolicy::RepeatWithoutCounting = policy {
+            self.budget.refund();
+        }
+        policy
+    }
+
+    fn ok(&mut self, attempt: usize) {
+        self.budget.deposit();
+        self.inner.ok(attempt);
+    }
+
+    fn ok_with<V: ?Sized>(&mut self, attempt: usize, value: &V) {
+        self.budget.deposit();
+        self.inner.ok_with(attempt, value);
+    }
+
+    fn exhausted(&mut self, attempt: usize, error: &Self::OutError) {
+        self.inner.exhausted(attempt, error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn exhaustion() {
+        let budget = Arc::new(RetryBudget::new(0.5, 2));
+        let mut first = RetryBudget::handler(&budget, |_| RetryPolicy::Repeat::<u8>);
+        let mut second = RetryBudget::handler(&budget, |_| RetryPolicy::Repeat::<u8>);
+        assert_eq!(RetryPolicy::Repeat, first.handle(1, 1));
+        assert_eq!(RetryPolicy::Repeat, second.handle(1, 2));
+        assert_eq!(RetryPolicy::ForwardError(3), first.handle(2, 3));
+        first.ok(3);
+        second.ok(2);
+        assert_eq!(1, budget.available());
+        assert_eq!(RetryPolicy::Repeat, second.handle(1, 4));
+        assert_eq!(0, budget.available());
+    }
+
+    #[test]
+    fn clones_share_the_budget() {
+        let budget = Arc::new(RetryBudget::new(0.1, 1));
+        let mut first = RetryBudget::handler(&budget, |_| RetryPolicy::Repeat::<u8>);
+        let mut second = first.clone();
+        assert_eq!(RetryPolicy::Repeat, first.handle(1, 1));
+        assert_eq!(RetryPolicy::ForwardError(2), second.handle(1, 2));
+    }
+
+    #[test]
+    fn refund_on_forward() {
+        let budget = Arc::new(RetryBudget::new(0.1, 1));
+        let mut handler = RetryBudget::handler(&budget, RetryPolicy::ForwardError);
+        assert_eq!(RetryPolicy::ForwardError(1), handler.handle(1, 1));
+        assert_eq!(1, budget.available());
+    }
+
+    #[test]
+    fn validates() {
+        assert_eq!(
+            Some(ConfigError::ZeroBudget),
+            RetryBudget::try_new(0.2, 0).err()
+        );
+        assert_eq!(
+            Some(ConfigError::InvalidRatio),
+            RetryBudget::try_new(f64::NAN, 10).err()
+        );
+        assert_eq!(10, RetryBudget::try_new(0.2, 10).unwrap().available());
+    }
+
+    #[tokio::test]
+    async fn reserve() {
+        let budget = Arc::new(RetryBudget::new(0., 1).reserve_per_second(100));
+        assert!(budget.try_withdraw());
+        assert!(!budget.try_withdraw());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(budget.try_withdraw());
+    }
+}
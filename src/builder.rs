@@ -0,0 +1,294 @@
+use crate::{
+    DeadlineHandler, ErrorHandler, FutureFactory, FutureRetry, Jitter, RetryPolicy, Timeout,
+};
+use std::{fmt, time::Duration};
+use tokio::time::Instant;
+
+/// A builder of a [`FutureRetry`](struct.FutureRetry.html) with the commonly requested options
+/// in one place.
+///
+/// Created by [`FutureRetry::builder`](struct.FutureRetry.html#method.builder).
+///
+/// * [`handler`](#method.handler) sets the error handler, which is required;
+/// * [`attempt_timeout`](#method.attempt_timeout) limits how long each attempt might take, like a
+///   [`Timeout`](struct.Timeout.html) factory does, so the handler gets
+///   [`AttemptError`](enum.AttemptError.html)s;
+/// * [`deadline`](#method.deadline) stops the retries once the given moment has passed, like a
+///   [`DeadlineHandler`](struct.DeadlineHandler.html) does (the ambient deadline of
+///   [`with_deadline`](fn.with_deadline.html) is respected in any case);
+/// * [`initial_delay`](#method.initial_delay) and [`start_splay`](#method.start_splay) delay the
+///   first attempt, by a fixed or a random time;
+/// * [`observer`](#method.observer) is told about every failure and what is done about it.
+///
+/// ```
+/// use futures_retry::{AttemptError, FutureRetry, RetryPolicy};
+/// use std::time::Duration;
+/// use tokio::time::Instant;
+///
+/// # #[tokio::main] async fn main() {
+/// let mut failures = Vec::new();
+/// let result = FutureRetry::builder(|| async { Err::<(), _>("unavailable") })
+///     .handler(|e: AttemptError<&str>| match e {
+///         AttemptError::TimedOut(_) => RetryPolicy::Repeat,
+///         AttemptError::Failed(_) => RetryPolicy::WaitRetry(Duration::from_millis(10)),
+///     })
+///     .attempt_timeout(Duration::from_secs(1))
+///     .deadline(Instant::now() + Duration::from_millis(50))
+///     .observer(|attempt, _policy: &RetryPolicy<AttemptError<&str>>| failures.push(attempt))
+///     .build()
+///     .await;
+/// assert!(matches!(result, Err((AttemptError::Failed("unavailable"), _))));
+/// assert!(failures.len() > 1);
+/// # }
+/// ```
+#[must_use = "a builder does nothing until a retry loop is built"]
+pub struct FutureRetryBuilder<F, R = (), O = ()> {
+    factory: F,
+    error_action: R,
+    deadline: Option<Instant>,
+    initial_delay: Option<Duration>,
+    start_splay: Option<Duration>,
+    splay_rng: Option<fastrand::Rng>,
+    observer: O,
+}
+
+impl<F> FutureRetryBuilder<F> {
+    pub(crate) fn new(factory: F) -> Self {
+        Self {
+            factory,
+            error_action: (),
+            deadline: None,
+            initial_delay: None,
+            start_splay: None,
+            splay_rng: None,
+            observer: (),
+        }
+    }
+}
+
+impl<F, R, O> FutureRetryBuilder<F, R, O> {
+    /// Sets the error handler.
+    pub fn handler<H>(self, error_action: H) -> FutureRetryBuilder<F, H, O> {
+        FutureRetryBuilder {
+            factory: self.factory,
+            error_action,
+            deadline: self.deadline,
+            initial_delay: self.initial_delay,
+            start_splay: self.start_splay,
+            splay_rng: self.splay_rng,
+            observer: self.observer,
+        }
+    }
+
+    /// Limits how long each attempt might take. The handler gets the errors wrapped into
+    /// [`AttemptError`](enum.AttemptError.html)s.
+    pub fn attempt_timeout(self, timeout: Duration) -> FutureRetryBuilder<Timeout<F>, R, O> {
+        FutureRetryBuilder {
+            factory: Timeout::new(self.factory, timeout),
+            error_action: self.error_action,
+            deadline: self.deadline,
+            initial_delay: self.initial_delay,
+            start_splay: self.start_splay,
+            splay_rng: self.splay_rng,
+            observer: self.observer,
+        }
+    }
+
+    /// Sets the moment after which no more retries are made. The waits are cut to end at the
+    /// deadline at the latest; an attempt that is already running isn't interrupted.
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Delays the first attempt, see
+    /// [`FutureRetry::initial_delay`](struct.FutureRetry.html#method.initial_delay).
+    pub fn initial_delay(mut self, delay: Duration) -> Self {
+        self.initial_delay = Some(delay);
+        self
+    }
+
+    /// Delays the first attempt by a random time within the `window` (on top of the
+    /// [`initial_delay`](#method.initial_delay), if any), see
+    /// [`FutureRetry::start_splay`](struct.FutureRetry.html#method.start_splay).
+    pub fn start_splay(mut self, window: Duration) -> Self {
+        self.start_splay = Some(window);
+        self
+    }
+
+    /// Like [`start_splay`](#method.start_splay), but the delay is drawn from a generator forked
+    /// from the given one, so a seeded generator yields a reproducible start.
+    pub fn start_splay_with(mut self, window: Duration, rng: &mut fastrand::Rng) -> Self {
+        self.start_splay = Some(window);
+        self.splay_rng = Some(rng.fork());
+        self
+    }
+
+    /// Sets a function that is given the number of every failed attempt and the policy the handler
+    /// has picked for it, e.g. to log or count the retries.
+    pub fn observer<P>(self, observer: P) -> FutureRetryBuilder<F, R, P> {
+        FutureRetryBuilder {
+            factory: self.factory,
+            error_action: self.error_action,
+            deadline: self.deadline,
+            initial_delay: self.initial_delay,
+            start_splay: self.start_splay,
+            splay_rng: self.splay_rng,
+            observer,
+        }
+    }
+
+    /// Builds the retry loop.
+    pub fn build(self) -> FutureRetry<F, DeadlineHandler<Observed<R, O>>>
+    where
+        F: FutureFactory,
+    {
+        let observed = Observed {
+            inner: self.error_action,
+            observer: self.observer,
+        };
+        let handler = DeadlineHandler::new(observed);
+        let handler = match self.deadline {
+            Some(deadline) => handler.deadline(deadline),
+            None => handler,
+        };
+        let retry = FutureRetry::new(self.factory, handler);
+        let mut splay_rng = self.splay_rng;
+        let splay = self.start_splay.map(|window| match &mut splay_rng {
+            Some(rng) => Jitter::Full.apply_with(window, rng),
+            None => Jitter::Full.apply(window),
+        });
+        match (self.initial_delay, splay) {
+            (None, None) => retry,
+            (delay, splay) => {
+                retry.initial_delay(delay.unwrap_or_default() + splay.unwrap_or_default())
+            }
+        }
+    }
+}
+
+impl<F, R, O> fmt::Debug for FutureRetryBuilder<F, R, O> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FutureRetryBuilder")
+            .field("deadline", &self.deadline)
+            .field("initial_delay", &self.initial_delay)
+            .field("start_splay", &self.start_splay)
+            .finish_non_exhaustive()
+    }
+}
+
+/// An error handler that tells an observer about the policies an inner handler picks.
+///
+/// Used by a [`FutureRetryBuilder`](struct.FutureRetryBuilder.html); the `()` observer observes
+/// nothing.
+#[derive(Clone)]
+pub struct Observed<H, O> {
+    inner: H,
+    observer: O,
+}
+
+impl<H: fmt::Debug, O> fmt::Debug for Observed<H, O> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Observed")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<E, H: ErrorHandler<E>> ErrorHandler<E> for Observed<H, ()> {
+    type OutError = H::OutError;
+
+    fn handle(&mut self, attempt: usize, e: E) -> RetryPolicy<H::OutError> {
+        self.inner.handle(attempt, e)
+    }
+
+    fn ok(&mut self, attempt: usize) {
+        self.inner.ok(attempt);
+    }
+
+    fn ok_with<V: ?Sized>(&mut self, attempt: usize, value: &V) {
+        self.inner.ok_with(attempt, value);
+    }
+
+    fn exhausted(&mut self, attempt: usize, error: &Self::OutError) {
+        self.inner.exhausted(attempt, error);
+    }
+}
+
+impl<E, H, O> ErrorHandler<E> for Observed<H, O>
+where
+    H: ErrorHandler<E>,
+    O: FnMut(usize, &RetryPolicy<H::OutError>),
+{
+    type OutError = H::OutError;
+
+    fn handle(&mut self, attempt: usize, e: E) -> RetryPolicy<H::OutError> {
+        let policy = self.inner.handle(attempt, e);
+        (self.observer)(attempt, &policy);
+        policy
+    }
+
+    fn ok(&mut self, attempt: usize) {
+        self.inner.ok(attempt);
+    }
+
+    fn ok_with<V: ?Sized>(&mut self, attempt: usize, value: &V) {
+        self.inner.ok_with(attempt, value);
+    }
+
+    fn exhausted(&mut self, attempt: usize, error: &Self::OutError) {
+        self.inner.exhausted(attempt, error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::ready;
+
+    #[tokio::test]
+    async fn plain_handler() {
+        let mut results = vec![Ok(3), Err("busy")].into_iter();
+        let result = FutureRetry::builder(move || ready(results.next_back().unwrap()))
+            .handler(|_| RetryPolicy::Repeat::<&str>)
+            .build()
+            .await;
+        assert_eq!(Ok((3, 2)), result);
+    }
+
+    #[tokio::test]
+    async fn deadline_stops_retries() {
+        let mut observed = Vec::new();
+        let result = FutureRetry::builder(|| ready(Err::<(), _>("busy")))
+            .handler(|_| RetryPolicy::WaitRetry::<&str>(Duration::from_secs(60)))
+            .deadline(Instant::now() + Duration::from_millis(10))
+            .observer(|attempt, policy: &RetryPolicy<&str>| {
+                observed.push((attempt, matches!(policy, RetryPolicy::WaitRetry(_))))
+            })
+            .build()
+            .await;
+        assert_eq!(Err(("busy", 2)), result);
+        // The wait has been cut, and then the deadline has forwarded the error on its own.
+        assert_eq!(vec![(1, true)], observed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn splays_the_start() {
+        let window = Duration::from_millis(20);
+        let build = |seed| {
+            FutureRetry::builder(|| ready(Ok::<_, ()>(())))
+                .handler(RetryPolicy::ForwardError)
+                .initial_delay(Duration::from_millis(10))
+                .start_splay_with(window, &mut fastrand::Rng::with_seed(seed))
+                .build()
+        };
+        let splay = Jitter::Full.apply_with(window, &mut fastrand::Rng::with_seed(7).fork());
+        let retry = build(7);
+        assert_eq!(
+            Some(Duration::from_millis(10) + splay),
+            retry.time_until_next_retry()
+        );
+        assert_eq!(retry.next_retry_at(), build(7).next_retry_at());
+        assert_eq!(Ok(((), 1)), retry.await);
+    }
+}
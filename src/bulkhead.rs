@@ -0,0 +1,165 @@
+use crate::FutureFactory;
+use futures::{ready, TryFuture};
+use pin_project_lite::pin_project;
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::sync::{AcquireError, OwnedSemaphorePermit, Semaphore};
+
+type Acquire = Pin<Box<dyn Future<Output = Result<OwnedSemaphorePermit, AcquireError>> + Send>>;
+
+/// A factory adapter that limits how many attempts might run concurrently.
+///
+/// Every future created by the wrapped factory waits for a permit from a shared `Semaphore`
+/// before it is polled for the first time, and releases the permit as soon as it completes. When
+/// many retrying operations share the same semaphore, at most as many attempts as there are
+/// permits hit a fragile backend simultaneously, no matter how many operations are retrying.
+///
+/// If the semaphore is closed, the attempts run without any limitation.
+///
+/// ```
+/// use futures_retry::{Bulkhead, FutureRetry, RetryPolicy};
+/// use std::{sync::Arc, time::Duration};
+/// use tokio::sync::Semaphore;
+///
+/// # #[tokio::main] async fn main() {
+/// let semaphore = Arc::new(Semaphore::new(10));
+/// let factory = Bulkhead::new(|| async { Ok::<_, ()>(42) }, Arc::clone(&semaphore));
+/// let retry = FutureRetry::new(factory, |_| {
+///     RetryPolicy::WaitRetry::<()>(Duration::from_millis(10))
+/// });
+/// assert_eq!(Ok((42, 1)), retry.await);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Bulkhead<F> {
+    factory: F,
+    semaphore: Arc<Semaphore>,
+}
+
+impl<F> Bulkhead<F> {
+    /// Wraps a `factory` so the futures it creates have to acquire a permit from the `semaphore`
+    /// first.
+    pub fn new(factory: F, semaphore: Arc<Semaphore>) -> Self {
+        Self { factory, semaphore }
+    }
+
+    /// Returns the semaphore that limits the attempts.
+    pub fn semaphore(&self) -> &Arc<Semaphore> {
+        &self.semaphore
+    }
+}
+
+impl<F> fmt::Debug for Bulkhead<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Bulkhead")
+            .field("semaphore", &self.semaphore)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F: FutureFactory> FutureFactory for Bulkhead<F> {
+    type FutureItem = BulkheadFuture<F::FutureItem>;
+
+    fn new(&mut self) -> Self::FutureItem {
+        BulkheadFuture {
+            acquire: Some(Box::pin(Arc::clone(&self.semaphore).acquire_owned())),
+            permit: None,
+            future: self.factory.new(),
+        }
+    }
+}
+
+pin_project! {
+    /// A future that runs only while holding a permit from a semaphore.
+    ///
+    /// Created by [`Bulkhead`](struct.Bulkhead.html).
+    pub struct BulkheadFuture<Fut> {
+        acquire: Option<Acquire>,
+        permit: Option<OwnedSemaphorePermit>,
+        #[pin]
+        future: Fut,
+    }
+}
+
+impl<Fut> fmt::Debug for BulkheadFuture<Fut> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BulkheadFuture")
+            .field("acquired", &self.permit.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<Fut: TryFuture> Future for BulkheadFuture<Fut> {
+    type Output = Result<Fut::Ok, Fut::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.project();
+        if let Some(acquire) = this.acquire {
+            *this.permit = ready!(acquire.as_mut().poll(cx)).ok();
+            *this.acquire = None;
+        }
+        let output = ready!(this.future.try_poll(cx));
+        *this.permit = None;
+        Poll::Ready(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FutureRetry, RetryPolicy};
+    use futures::future::join_all;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    #[tokio::test]
+    async fn limits_attempts() {
+        let semaphore = Arc::new(Semaphore::new(2));
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let retries = (0..5).map(|_| {
+            let running = Arc::clone(&running);
+            let max_running = Arc::clone(&max_running);
+            let mut failures = 1;
+            let factory = move || {
+                let running = Arc::clone(&running);
+                let max_running = Arc::clone(&max_running);
+                let fail = failures > 0;
+                failures -= 1;
+                async move {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_running.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    if fail {
+                        Err(())
+                    } else {
+                        Ok(())
+                    }
+                }
+            };
+            FutureRetry::new(Bulkhead::new(factory, Arc::clone(&semaphore)), |_| {
+                RetryPolicy::Repeat::<()>
+            })
+        });
+        let results = join_all(retries).await;
+        assert!(results.iter().all(|result| *result == Ok(((), 2))));
+        assert_eq!(2, max_running.load(Ordering::SeqCst));
+        assert_eq!(2, semaphore.available_permits());
+    }
+
+    #[tokio::test]
+    async fn closed_semaphore() {
+        let semaphore = Arc::new(Semaphore::new(0));
+        semaphore.close();
+        let mut factory = Bulkhead::new(|| async { Ok::<_, ()>(1) }, semaphore);
+        assert_eq!(Ok(1), factory.new().await);
+    }
+}
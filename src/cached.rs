@@ -0,0 +1,230 @@
+use crate::{ErrorHandler, FutureFactory, FutureRetry};
+use futures::{ready, TryFuture};
+use pin_project_lite::pin_project;
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+#[derive(Debug)]
+struct Cache<T> {
+    value: Option<(T, Instant)>,
+    refreshing: usize,
+}
+
+/// The last successfully fetched value, kept around while a refresh is failing and being retried,
+/// i.e. stale-while-revalidate.
+///
+/// A [`refresh`](#method.refresh) is a retry loop that replaces the value once it succeeds;
+/// meanwhile, [`get`](#method.get) keeps returning the stale value along with its age, so the
+/// callers might decide whether it is still good enough. The refresh is usually spawned in the
+/// background. Clones share the same value.
+///
+/// ```
+/// use futures_retry::{CachedRetry, ExponentialBackoff};
+/// use std::time::Duration;
+///
+/// async fn fetch_config() -> Result<String, std::io::Error> {
+///     // ...
+/// #   Ok("verbose = true".to_owned())
+/// }
+///
+/// # #[tokio::main] async fn main() {
+/// let config = CachedRetry::new();
+/// let backoff = ExponentialBackoff::new(Duration::from_millis(100)).max_attempts(10);
+/// tokio::spawn(config.refresh(fetch_config, backoff)).await.unwrap().unwrap();
+/// match config.get() {
+///     Some((config, age)) if age < Duration::from_secs(60) => println!("{}", config),
+///     _ => println!("No fresh config"),
+/// }
+/// # }
+/// ```
+pub struct CachedRetry<T> {
+    cache: Arc<Mutex<Cache<T>>>,
+}
+
+impl<T> Clone for CachedRetry<T> {
+    fn clone(&self) -> Self {
+        Self {
+            cache: Arc::clone(&self.cache),
+        }
+    }
+}
+
+impl<T> Default for CachedRetry<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for CachedRetry<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cache = self.lock();
+        f.debug_struct("CachedRetry")
+            .field("value", &cache.value.as_ref().map(|(value, _)| value))
+            .field("refreshing", &(cache.refreshing > 0))
+            .finish()
+    }
+}
+
+impl<T> CachedRetry<T> {
+    /// Creates an empty cache.
+    pub fn new() -> Self {
+        Self {
+            cache: Arc::new(Mutex::new(Cache {
+                value: None,
+                refreshing: 0,
+            })),
+        }
+    }
+
+    /// Creates a cache holding a value that has just been fetched.
+    pub fn with_value(value: T) -> Self {
+        let cached = Self::new();
+        cached.set(value);
+        cached
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Cache<T>> {
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns the last fetched value along with how long ago it was fetched.
+    pub fn get(&self) -> Option<(T, Duration)>
+    where
+        T: Clone,
+    {
+        self.lock()
+            .value
+            .as_ref()
+            .map(|(value, at)| (value.clone(), at.elapsed()))
+    }
+
+    /// Returns how long ago the value was fetched, if it ever was.
+    pub fn age(&self) -> Option<Duration> {
+        self.lock().value.as_ref().map(|(_, at)| at.elapsed())
+    }
+
+    /// Checks whether a refresh is running (or waiting for a retry).
+    pub fn is_refreshing(&self) -> bool {
+        self.lock().refreshing > 0
+    }
+
+    /// Replaces the value.
+    pub fn set(&self, value: T) {
+        self.lock().value = Some((value, Instant::now()));
+    }
+
+    /// Creates a future that fetches a new value in a retry loop and stores it once it succeeds.
+    /// The future resolves into the number of attempts it took, or into the error it has given
+    /// up on, in which case the stale value is kept.
+    pub fn refresh<F, R>(&self, factory: F, error_action: R) -> CacheRefresh<F, R>
+    where
+        F: FutureFactory,
+        F::FutureItem: TryFuture<Ok = T>,
+    {
+        CacheRefresh {
+            retry: FutureRetry::new(factory, error_action),
+            cached: self.clone(),
+            guard: None,
+        }
+    }
+}
+
+struct RefreshGuard<T>(CachedRetry<T>);
+
+impl<T> Drop for RefreshGuard<T> {
+    fn drop(&mut self) {
+        self.0.lock().refreshing -= 1;
+    }
+}
+
+pin_project! {
+    /// A future that refreshes a [`CachedRetry`](struct.CachedRetry.html) value.
+    ///
+    /// Created by [`CachedRetry::refresh`](struct.CachedRetry.html#method.refresh).
+    pub struct CacheRefresh<F: FutureFactory, R> {
+        #[pin]
+        retry: FutureRetry<F, R>,
+        cached: CachedRetry<<F::FutureItem as TryFuture>::Ok>,
+        guard: Option<RefreshGuard<<F::FutureItem as TryFuture>::Ok>>,
+    }
+}
+
+impl<F: FutureFactory, R> fmt::Debug for CacheRefresh<F, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CacheRefresh")
+            .field("retry", &self.retry)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F, R> Future for CacheRefresh<F, R>
+where
+    F: FutureFactory,
+    R: ErrorHandler<<F::FutureItem as TryFuture>::Error>,
+{
+    type Output = Result<usize, (R::OutError, usize)>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.project();
+        if this.guard.is_none() {
+            this.cached.lock().refreshing += 1;
+            *this.guard = Some(RefreshGuard(this.cached.clone()));
+        }
+        let result = ready!(this.retry.poll(cx));
+        *this.guard = None;
+        let cached = this.cached;
+        Poll::Ready(result.map(|(value, attempt)| {
+            cached.set(value);
+            attempt
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RetryPolicy;
+    use futures::future::{err, ok};
+
+    #[tokio::test]
+    async fn serves_stale_values() {
+        let cached = CachedRetry::with_value(1);
+        let mut results = vec![ok(2), err(()), err(())].into_iter();
+        let refresh = cached.refresh(
+            move || results.next_back().unwrap(),
+            |_| RetryPolicy::WaitRetry::<()>(Duration::from_millis(20)),
+        );
+        futures::pin_mut!(refresh);
+        assert!(futures::poll!(refresh.as_mut()).is_pending());
+        assert!(cached.is_refreshing());
+        let (value, age) = cached.get().unwrap();
+        assert_eq!(1, value);
+        assert!(age < Duration::from_secs(1));
+        assert_eq!(Ok(3), refresh.await);
+        assert!(!cached.is_refreshing());
+        assert_eq!(Some(2), cached.get().map(|(value, _)| value));
+    }
+
+    #[tokio::test]
+    async fn keeps_values_on_errors() {
+        let cached = CachedRetry::<u8>::new();
+        let refresh = cached.refresh(|| err::<u8, _>(5u8), RetryPolicy::ForwardError);
+        assert_eq!(Err((5, 1)), refresh.await);
+        assert_eq!(None, cached.get());
+        let mut dropped = Box::pin(cached.refresh(
+            || err::<u8, _>(1u8),
+            |_| RetryPolicy::WaitRetry::<u8>(Duration::from_secs(10)),
+        ));
+        assert!(futures::poll!(dropped.as_mut()).is_pending());
+        assert!(cached.is_refreshing());
+        drop(dropped);
+        assert!(!cached.is_refreshing());
+    }
+}
@@ -0,0 +1,307 @@
+use crate::{ErrorHandler, FutureFactory, RetryPolicy, Sleeper, TokioSleeper};
+use futures::{ready, TryFuture};
+use pin_project_lite::pin_project;
+use std::{
+    error::Error,
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio_util::sync::{CancellationToken, WaitForCancellationFutureOwned};
+
+type FactoryError<F> = <<F as FutureFactory>::FutureItem as TryFuture>::Error;
+
+/// An outcome of a retry loop that has been cancelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled<E> {
+    /// The attempt that was running (or being waited for) when the loop was cancelled.
+    pub attempt: usize,
+    /// The error of the last failed attempt, if the loop
+    /// [keeps it](struct.CancellableRetry.html#method.keep_last_error).
+    pub last_error: Option<E>,
+}
+
+impl<E: fmt::Display> fmt::Display for Cancelled<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "retrying cancelled at attempt {}", self.attempt)?;
+        match &self.last_error {
+            Some(e) => write!(f, ", last error: {}", e),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for Cancelled<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.last_error.as_ref().map(|e| e as _)
+    }
+}
+
+pin_project! {
+    /// A retrying future that might be cancelled from the outside, e.g. on a graceful shutdown.
+    ///
+    /// It behaves like [`FutureRetry`](struct.FutureRetry.html), but also polls a cancellation
+    /// signal: as soon as the signal fires, the running attempt or the pending wait is dropped and
+    /// the future resolves into `Err(Cancelled)`. Otherwise it resolves into `Ok` with the result
+    /// that `FutureRetry` would have returned.
+    ///
+    /// A loop cancelled during a backoff has usually been stuck on some error, which is worth
+    /// reporting on a shutdown; use [`keep_last_error`](#method.keep_last_error) to get it along
+    /// with the `Cancelled` outcome.
+    ///
+    /// ```
+    /// use futures_retry::{CancellableRetry, Cancelled, RetryPolicy};
+    /// use std::time::Duration;
+    /// use tokio_util::sync::CancellationToken;
+    ///
+    /// # #[tokio::main] async fn main() {
+    /// let token = CancellationToken::new();
+    /// let retry = CancellableRetry::with_token(
+    ///     || async { Err::<(), _>(std::io::Error::from(std::io::ErrorKind::ConnectionRefused)) },
+    ///     |_| RetryPolicy::WaitRetry::<std::io::Error>(Duration::from_secs(10)),
+    ///     token.clone(),
+    /// );
+    /// let retry = tokio::spawn(retry);
+    /// // Let the first attempt fail.
+    /// tokio::time::sleep(Duration::from_millis(10)).await;
+    /// token.cancel();
+    /// let cancelled = retry.await.unwrap().unwrap_err();
+    /// assert_eq!(2, cancelled.attempt);
+    /// assert!(cancelled.last_error.is_none());
+    /// # }
+    /// ```
+    pub struct CancellableRetry<F, R, C, S = TokioSleeper>
+    where
+        F: FutureFactory,
+        S: Sleeper,
+    {
+        factory: F,
+        error_action: R,
+        attempt: usize,
+        clone_error: Option<fn(&FactoryError<F>) -> FactoryError<F>>,
+        last_error: Option<FactoryError<F>>,
+        sleeper: S,
+        #[pin]
+        signal: C,
+        #[pin]
+        state: RetryState<F::FutureItem, S::Sleep>,
+    }
+}
+
+pin_project! {
+    #[project = RetryStateProj]
+    enum RetryState<F, D> {
+        NotStarted,
+        WaitingForFuture { #[pin] future: F },
+        TimerActive { #[pin] delay: D },
+    }
+}
+
+impl<F: FutureFactory, R, C> CancellableRetry<F, R, C> {
+    /// Creates a retrying future that is cancelled when the `signal` future resolves.
+    pub fn new(factory: F, error_action: R, signal: C) -> Self {
+        Self::with_sleeper(factory, error_action, signal, TokioSleeper)
+    }
+}
+
+impl<F: FutureFactory, R, C, S: Sleeper> CancellableRetry<F, R, C, S> {
+    /// Like a `new` method, but the delays between the attempts are timed by a custom
+    /// [`Sleeper`](trait.Sleeper.html).
+    pub fn with_sleeper(factory: F, error_action: R, signal: C, sleeper: S) -> Self {
+        Self {
+            factory,
+            error_action,
+            attempt: 1,
+            clone_error: None,
+            last_error: None,
+            sleeper,
+            signal,
+            state: RetryState::NotStarted,
+        }
+    }
+
+    /// Keeps a copy of the error of every failed attempt, so a cancelled loop resolves into the
+    /// last observed error wrapped into [`Cancelled`](struct.Cancelled.html) instead of a bare
+    /// marker.
+    pub fn keep_last_error(mut self) -> Self
+    where
+        FactoryError<F>: Clone,
+    {
+        self.clone_error = Some(Clone::clone);
+        self
+    }
+}
+
+impl<F: FutureFactory, R> CancellableRetry<F, R, WaitForCancellationFutureOwned> {
+    /// Creates a retrying future that is cancelled along with the `token`.
+    pub fn with_token(factory: F, error_action: R, token: CancellationToken) -> Self {
+        Self::new(factory, error_action, token.cancelled_owned())
+    }
+}
+
+impl<F: FutureFactory, R, C, S: Sleeper> fmt::Debug for CancellableRetry<F, R, C, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CancellableRetry")
+            .field("attempt", &self.attempt)
+            .field("keeps_last_error", &self.clone_error.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F, R, C, S> Future for CancellableRetry<F, R, C, S>
+where
+    F: FutureFactory,
+    R: ErrorHandler<<F::FutureItem as TryFuture>::Error>,
+    C: Future<Output = ()>,
+    S: Sleeper,
+{
+    type Output = Result<
+        Result<(<F::FutureItem as TryFuture>::Ok, usize), (R::OutError, usize)>,
+        Cancelled<FactoryError<F>>,
+    >;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        loop {
+            let mut this = self.as_mut().project();
+            let attempt = *this.attempt;
+            if this.signal.poll(cx).is_ready() {
+                this.state.set(RetryState::NotStarted);
+                return Poll::Ready(Err(Cancelled {
+                    attempt,
+                    last_error: this.last_error.take(),
+                }));
+            }
+            let new_state = match this.state.project() {
+                RetryStateProj::NotStarted => RetryState::WaitingForFuture {
+                    future: this.factory.new(),
+                },
+                RetryStateProj::TimerActive { delay } => {
+                    ready!(delay.poll(cx));
+                    RetryState::WaitingForFuture {
+                        future: this.factory.new(),
+                    }
+                }
+                RetryStateProj::WaitingForFuture { future } => match ready!(future.try_poll(cx)) {
+                    Ok(x) => {
+                        this.error_action.ok_with(attempt, &x);
+                        *this.attempt = 1;
+                        return Poll::Ready(Ok(Ok((x, attempt))));
+                    }
+                    Err(e) => {
+                        if let Some(clone_error) = this.clone_error {
+                            *this.last_error = Some(clone_error(&e));
+                        }
+                        let policy = this.error_action.handle(attempt, e);
+                        *this.attempt = policy.next_attempt(attempt);
+                        match policy {
+                            RetryPolicy::ForwardError(e) => {
+                                this.error_action.exhausted(attempt, &e);
+                                return Poll::Ready(Ok(Err((e, attempt))));
+                            }
+                            RetryPolicy::Repeat | RetryPolicy::RepeatWithoutCounting => {
+                                RetryState::WaitingForFuture {
+                                    future: this.factory.new(),
+                                }
+                            }
+                            RetryPolicy::WaitRetry(duration)
+                            | RetryPolicy::RetryAs {
+                                delay: duration, ..
+                            } => RetryState::TimerActive {
+                                delay: this.sleeper.sleep(duration),
+                            },
+                        }
+                    }
+                },
+            };
+            self.as_mut().project().state.set(new_state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handler::tests::Successes;
+    use futures::future::{pending, ready};
+    use std::time::Duration;
+    use tokio::time;
+
+    #[tokio::test]
+    async fn cancels_attempt() {
+        let retry = CancellableRetry::new(
+            pending::<Result<(), ()>>,
+            RetryPolicy::ForwardError,
+            time::sleep(Duration::from_millis(5)),
+        );
+        assert_eq!(
+            Err(Cancelled {
+                attempt: 1,
+                last_error: None
+            }),
+            retry.await
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keeps_last_error() {
+        let mut errors = 0;
+        let retry = CancellableRetry::new(
+            move || {
+                errors += 1;
+                ready(Err::<(), _>(errors))
+            },
+            |_| RetryPolicy::WaitRetry::<u8>(Duration::from_millis(40)),
+            time::sleep(Duration::from_millis(60)),
+        )
+        .keep_last_error();
+        assert_eq!(
+            Err(Cancelled {
+                attempt: 3,
+                last_error: Some(2)
+            }),
+            retry.await
+        );
+    }
+
+    #[cfg(feature = "test_util")]
+    #[tokio::test]
+    async fn waits_on_the_sleeper() {
+        let mut errors = 0;
+        let sleeper = crate::test_util::MockSleeper::new();
+        let hour = Duration::from_secs(3600);
+        let retry = CancellableRetry::with_sleeper(
+            move || {
+                errors += 1;
+                ready(if errors < 2 { Err(errors) } else { Ok(()) })
+            },
+            |_| RetryPolicy::WaitRetry::<u8>(hour),
+            pending(),
+            sleeper.clone(),
+        );
+        tokio::pin!(retry);
+        assert!(futures::poll!(retry.as_mut()).is_pending());
+        sleeper.advance(hour);
+        assert_eq!(Ok(Ok(((), 2))), retry.await);
+        assert_eq!(vec![hour], sleeper.requested());
+    }
+
+    #[tokio::test]
+    async fn not_cancelled() {
+        let retry = CancellableRetry::new(
+            || ready(Ok::<_, ()>(1)),
+            RetryPolicy::ForwardError,
+            pending(),
+        );
+        assert_eq!(Ok(Ok((1, 1))), retry.await);
+    }
+
+    #[tokio::test]
+    async fn passes_success_values() {
+        let successes = Successes::new(RetryPolicy::ForwardError);
+        let retry =
+            CancellableRetry::new(|| ready(Ok::<_, ()>(1u64)), successes.clone(), pending());
+        assert_eq!(Ok(Ok((1, 1))), retry.await);
+        assert_eq!(vec![(1, "u64")], successes.seen());
+    }
+}
@@ -0,0 +1,499 @@
+use crate::{ConfigError, ErrorHandler, RetryPolicy};
+use std::{
+    fmt,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+/// A state of a [`CircuitBreaker`](struct.CircuitBreaker.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Everything works fine, retries are decided by the wrapped handlers.
+    Closed,
+    /// Too many failures have been observed, retries are postponed until the breaker goes
+    /// half-open.
+    Open,
+    /// The breaker is probing whether the backend has recovered.
+    HalfOpen,
+}
+
+/// Describes how a [`CircuitBreaker`](struct.CircuitBreaker.html) probes a backend once it has
+/// been open for long enough. Any failed probe opens the breaker again.
+///
+/// A retry that is let through while the breaker is half-open, or that is scheduled for the moment
+/// it goes half-open, is a probe. Only [`max_probes`](#method.max_probes) of them are in flight at
+/// once, the other retries are postponed by another open duration, so the loops that share the
+/// breaker don't hit a recovering backend all together. A probe ends once its handler is told how
+/// it went, or is dropped.
+#[derive(Debug, Clone)]
+pub struct HalfOpenConfig {
+    required_successes: usize,
+    max_probes: usize,
+    reopen_factor: u32,
+    max_open_duration: Option<Duration>,
+}
+
+impl Default for HalfOpenConfig {
+    /// A single success to close the breaker, a single probe in flight and the same open duration
+    /// each time the breaker re-opens.
+    fn default() -> Self {
+        Self {
+            required_successes: 1,
+            max_probes: 1,
+            reopen_factor: 1,
+            max_open_duration: None,
+        }
+    }
+}
+
+impl HalfOpenConfig {
+    /// Sets how many consecutive successes are required to close the breaker.
+    pub fn required_successes(mut self, required_successes: usize) -> Self {
+        self.required_successes = required_successes;
+        self
+    }
+
+    /// Sets how many probes might be in flight at once.
+    pub fn max_probes(mut self, max_probes: usize) -> Self {
+        self.max_probes = max_probes;
+        self
+    }
+
+    /// Sets a multiplier applied to the open duration each time the breaker re-opens after a
+    /// failed probing, and an upper limit for the resulting duration.
+    pub fn reopen_backoff(mut self, factor: u32, max_open_duration: Duration) -> Self {
+        self.reopen_factor = factor;
+        self.max_open_duration = Some(max_open_duration);
+        self
+    }
+}
+
+struct Breaker {
+    failure_threshold: usize,
+    open_duration: Duration,
+    half_open: HalfOpenConfig,
+    on_transition: Option<Box<dyn FnMut(CircuitState, CircuitState) + Send>>,
+    state: CircuitState,
+    failures: usize,
+    successes: usize,
+    opened_at: Instant,
+    current_open_duration: Duration,
+    // The probes in flight; they are counted anew each time the breaker opens or closes, so the
+    // handlers tag theirs with the epoch they belong to.
+    probes: usize,
+    epoch: u64,
+}
+
+impl Breaker {
+    fn transition(&mut self, to: CircuitState) {
+        let from = std::mem::replace(&mut self.state, to);
+        if from != to {
+            // The probes are admitted while the breaker is open, for the half-open phase that
+            // follows.
+            if to != CircuitState::HalfOpen {
+                self.epoch += 1;
+                self.probes = 0;
+            }
+            if let Some(on_transition) = &mut self.on_transition {
+                on_transition(from, to);
+            }
+        }
+    }
+
+    fn open(&mut self, now: Instant, duration: Duration) {
+        self.opened_at = now;
+        self.current_open_duration = duration;
+        self.transition(CircuitState::Open);
+    }
+
+    /// Returns when the breaker goes half-open, or `None` if the open duration is too long to be
+    /// represented, i.e. the breaker stays open forever.
+    fn half_opens_at(&self) -> Option<Instant> {
+        self.opened_at.checked_add(self.current_open_duration)
+    }
+
+    fn refresh(&mut self, now: Instant) {
+        if self.state == CircuitState::Open && self.half_opens_at().is_some_and(|at| now >= at) {
+            self.successes = 0;
+            self.transition(CircuitState::HalfOpen);
+        }
+    }
+
+    fn on_failure(&mut self, now: Instant) {
+        self.refresh(now);
+        self.successes = 0;
+        match self.state {
+            CircuitState::Closed => {
+                self.failures += 1;
+                if self.failures >= self.failure_threshold {
+                    self.open(now, self.open_duration);
+                }
+            }
+            CircuitState::HalfOpen => {
+                let duration = self
+                    .current_open_duration
+                    .checked_mul(self.half_open.reopen_factor)
+                    .unwrap_or(Duration::MAX);
+                let duration = match self.half_open.max_open_duration {
+                    Some(max) => duration.min(max),
+                    None => duration,
+                };
+                self.open(now, duration);
+            }
+            CircuitState::Open => {}
+        }
+    }
+
+    fn on_success(&mut self, now: Instant) {
+        self.refresh(now);
+        match self.state {
+            CircuitState::Closed => self.failures = 0,
+            CircuitState::HalfOpen => {
+                self.successes += 1;
+                if self.successes >= self.half_open.required_successes {
+                    self.failures = 0;
+                    self.transition(CircuitState::Closed);
+                }
+            }
+            CircuitState::Open => {}
+        }
+    }
+
+    fn remaining(&self, now: Instant) -> Option<Duration> {
+        match self.state {
+            CircuitState::Open => Some(
+                self.half_opens_at()
+                    .map_or(Duration::MAX, |at| at.saturating_duration_since(now)),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Decides how long a retry has to wait for the breaker, if at all, admitting it as a probe
+    /// while there is room for one.
+    fn admit(&mut self, now: Instant, probe: &mut Option<u64>) -> Option<Duration> {
+        let remaining = match self.state {
+            CircuitState::Closed => return None,
+            CircuitState::Open => self.remaining(now),
+            CircuitState::HalfOpen => None,
+        };
+        if self.probes < self.half_open.max_probes {
+            self.probes += 1;
+            *probe = Some(self.epoch);
+            remaining
+        } else {
+            Some(
+                remaining
+                    .unwrap_or_default()
+                    .saturating_add(self.current_open_duration),
+            )
+        }
+    }
+
+    fn end_probe(&mut self, probe: Option<u64>) {
+        if probe == Some(self.epoch) {
+            self.probes -= 1;
+        }
+    }
+}
+
+/// A circuit breaker shared among many retrying futures and streams that talk to the same
+/// backend.
+///
+/// The breaker counts consecutive failures reported by all the handlers created with
+/// [`handler`](#method.handler). Once `failure_threshold` failures in a row are observed, the
+/// breaker opens: retries are postponed until `open_duration` elapses, so a struggling backend is
+/// not hammered by every retry loop at once. Then the breaker goes half-open and probes the
+/// backend as described by [`HalfOpenConfig`](struct.HalfOpenConfig.html): successful probes
+/// close it, failed ones open it again.
+///
+/// The breaker never gives up on its own: the wrapped handlers still decide when to forward an
+/// error.
+///
+/// ```
+/// use futures_retry::{CircuitBreaker, ExponentialBackoff, HalfOpenConfig};
+/// use std::time::Duration;
+///
+/// let breaker = CircuitBreaker::new(5, Duration::from_secs(10))
+///     .half_open(
+///         HalfOpenConfig::default()
+///             .max_probes(3)
+///             .required_successes(2)
+///             .reopen_backoff(2, Duration::from_secs(300)),
+///     )
+///     .on_transition(|from, to| eprintln!("Circuit breaker: {:?} -> {:?}", from, to));
+/// // Each retrying future gets its own handler sharing the breaker.
+/// let handler = breaker.handler(ExponentialBackoff::new(Duration::from_millis(100)));
+/// ```
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    breaker: Arc<Mutex<Breaker>>,
+}
+
+impl fmt::Debug for CircuitBreaker {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CircuitBreaker")
+            .field("state", &self.state())
+            .finish()
+    }
+}
+
+impl CircuitBreaker {
+    /// Creates a closed circuit breaker that opens after `failure_threshold` consecutive failures
+    /// and stays open for `open_duration`.
+    pub fn new(failure_threshold: usize, open_duration: Duration) -> Self {
+        Self {
+            breaker: Arc::new(Mutex::new(Breaker {
+                failure_threshold,
+                open_duration,
+                half_open: HalfOpenConfig::default(),
+                on_transition: None,
+                state: CircuitState::Closed,
+                failures: 0,
+                successes: 0,
+                opened_at: Instant::now(),
+                current_open_duration: open_duration,
+                probes: 0,
+                epoch: 0,
+            })),
+        }
+    }
+
+    /// Like `new`, but checks that the `failure_threshold` is at least 1, since otherwise the
+    /// breaker would open on the first call.
+    pub fn try_new(failure_threshold: usize, open_duration: Duration) -> Result<Self, ConfigError> {
+        if failure_threshold == 0 {
+            return Err(ConfigError::ZeroThreshold);
+        }
+        Ok(Self::new(failure_threshold, open_duration))
+    }
+
+    /// Configures the half-open phase.
+    pub fn half_open(self, half_open: HalfOpenConfig) -> Self {
+        self.lock().half_open = half_open;
+        self
+    }
+
+    /// Sets a hook that is called on every state transition with the old and the new states.
+    ///
+    /// The hook is called while the breaker is locked, so it must not call the breaker's methods.
+    pub fn on_transition<F>(self, on_transition: F) -> Self
+    where
+        F: FnMut(CircuitState, CircuitState) + Send + 'static,
+    {
+        self.lock().on_transition = Some(Box::new(on_transition));
+        self
+    }
+
+    /// Returns the current state of the breaker.
+    pub fn state(&self) -> CircuitState {
+        let mut breaker = self.lock();
+        breaker.refresh(Instant::now());
+        breaker.state
+    }
+
+    /// Wraps an error handler so it reports to this breaker.
+    pub fn handler<H>(&self, inner: H) -> CircuitBreakerHandler<H> {
+        CircuitBreakerHandler {
+            breaker: self.clone(),
+            inner,
+            probe: None,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Breaker> {
+        self.breaker.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// An error handler that reports to a shared [`CircuitBreaker`](struct.CircuitBreaker.html).
+///
+/// Created by [`CircuitBreaker::handler`](struct.CircuitBreaker.html#method.handler).
+#[derive(Debug)]
+pub struct CircuitBreakerHandler<H> {
+    breaker: CircuitBreaker,
+    inner: H,
+    probe: Option<u64>,
+}
+
+impl<H: Clone> Clone for CircuitBreakerHandler<H> {
+    /// The clone doesn't share the probe of this handler, if there is one in flight.
+    fn clone(&self) -> Self {
+        self.breaker.handler(self.inner.clone())
+    }
+}
+
+impl<H> Drop for CircuitBreakerHandler<H> {
+    fn drop(&mut self) {
+        self.breaker.lock().end_probe(self.probe.take());
+    }
+}
+
+impl<H> CircuitBreakerHandler<H> {
+    /// Returns the breaker this handler reports to.
+    pub fn breaker(&self) -> &CircuitBreaker {
+        &self.breaker
+    }
+
+    fn succeeded(&mut self) {
+        let mut breaker = self.breaker.lock();
+        breaker.end_probe(self.probe.take());
+        breaker.on_success(Instant::now());
+    }
+}
+
+impl<E, H> ErrorHandler<E> for CircuitBreakerHandler<H>
+where
+    H: ErrorHandler<E>,
+{
+    type OutError = H::OutError;
+
+    fn handle(&mut self, attempt: usize, e: E) -> RetryPolicy<H::OutError> {
+        let now = Instant::now();
+        let mut breaker = self.breaker.lock();
+        breaker.end_probe(self.probe.take());
+        breaker.on_failure(now);
+        drop(breaker);
+        let policy = self.inner.handle(attempt, e);
+        let remaining = match policy {
+            RetryPolicy::ForwardError(_) => None,
+            _ => self.breaker.lock().admit(now, &mut self.probe),
+        };
+        match (policy, remaining) {
+            (RetryPolicy::Repeat | RetryPolicy::RepeatWithoutCounting, Some(remaining)) => {
+                RetryPolicy::WaitRetry(remaining)
+            }
+            (RetryPolicy::WaitRetry(duration), Some(remaining)) => {
+                RetryPolicy::WaitRetry(duration.max(remaining))
+            }
+            (RetryPolicy::RetryAs { attempt, delay }, Some(remaining)) => RetryPolicy::RetryAs {
+                attempt,
+                delay: delay.max(remaining),
+            },
+            (policy, _) => policy,
+        }
+    }
+
+    fn ok(&mut self, attempt: usize) {
+        self.succeeded();
+        self.inner.ok(attempt);
+    }
+
+    fn ok_with<V: ?Sized>(&mut self, attempt: usize, value: &V) {
+        self.succeeded();
+        self.inner.ok_with(attempt, value);
+    }
+
+    fn exhausted(&mut self, attempt: usize, error: &Self::OutError) {
+        self.inner.exhausted(attempt, error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn opens_and_closes() {
+        let breaker = CircuitBreaker::new(2, Duration::from_millis(20));
+        let mut handler = breaker.handler(|_| RetryPolicy::Repeat::<()>);
+        assert_eq!(RetryPolicy::Repeat, handler.handle(1, ()));
+        assert_eq!(CircuitState::Closed, breaker.state());
+        match handler.handle(2, ()) {
+            RetryPolicy::WaitRetry(duration) => assert!(duration <= Duration::from_millis(20)),
+            policy => panic!("Unexpected policy {:?}", policy),
+        }
+        assert_eq!(CircuitState::Open, breaker.state());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(CircuitState::HalfOpen, breaker.state());
+        handler.ok(3);
+        assert_eq!(CircuitState::Closed, breaker.state());
+    }
+
+    #[tokio::test]
+    async fn half_open_probing() {
+        let transitions = Arc::new(Mutex::new(Vec::new()));
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(10))
+            .half_open(
+                HalfOpenConfig::default()
+                    .required_successes(2)
+                    .reopen_backoff(3, Duration::from_millis(25)),
+            )
+            .on_transition({
+                let transitions = Arc::clone(&transitions);
+                move |from, to| transitions.lock().unwrap().push((from, to))
+            });
+        let mut handler = breaker.handler(|_| RetryPolicy::Repeat::<()>);
+        handler.handle(1, ());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(CircuitState::HalfOpen, breaker.state());
+        // A single failed probe re-opens the breaker.
+        match handler.handle(2, ()) {
+            RetryPolicy::WaitRetry(duration) => {
+                assert!(duration > Duration::from_millis(10));
+                assert!(duration <= Duration::from_millis(25));
+            }
+            policy => panic!("Unexpected policy {:?}", policy),
+        }
+        assert_eq!(CircuitState::Open, breaker.state());
+        tokio::time::sleep(Duration::from_millis(25)).await;
+        handler.ok(3);
+        assert_eq!(CircuitState::HalfOpen, breaker.state());
+        handler.ok(1);
+        assert_eq!(CircuitState::Closed, breaker.state());
+
+        let (closed, open, half_open) = (
+            CircuitState::Closed,
+            CircuitState::Open,
+            CircuitState::HalfOpen,
+        );
+        assert_eq!(
+            vec![
+                (closed, open),
+                (open, half_open),
+                (half_open, open),
+                (open, half_open),
+                (half_open, closed)
+            ],
+            *transitions.lock().unwrap()
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn limits_probes() {
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(10));
+        let mut first = breaker.handler(|_| RetryPolicy::Repeat::<()>);
+        let mut second = breaker.handler(|_| RetryPolicy::Repeat::<()>);
+        // The first retry is going to probe the backend, the second one waits for another open
+        // duration.
+        let open = Duration::from_millis(10);
+        assert_eq!(RetryPolicy::WaitRetry(open), first.handle(1, ()));
+        assert_eq!(RetryPolicy::WaitRetry(open * 2), second.handle(1, ()));
+        // A dropped handler gives its probe up.
+        drop(first);
+        let mut third = breaker.handler(|_| RetryPolicy::Repeat::<()>);
+        assert_eq!(RetryPolicy::WaitRetry(open), third.handle(1, ()));
+        tokio::time::sleep(open).await;
+        assert_eq!(CircuitState::HalfOpen, breaker.state());
+        third.ok(2);
+        assert_eq!(CircuitState::Closed, breaker.state());
+        second.ok(2);
+    }
+
+    #[test]
+    fn open_forever() {
+        let breaker = CircuitBreaker::new(1, Duration::MAX);
+        let mut handler = breaker.handler(|_| RetryPolicy::Repeat::<()>);
+        assert_eq!(RetryPolicy::WaitRetry(Duration::MAX), handler.handle(1, ()));
+        assert_eq!(CircuitState::Open, breaker.state());
+    }
+
+    #[test]
+    fn forward_is_kept() {
+        let breaker = CircuitBreaker::new(1, Duration::from_secs(10));
+        let mut handler = breaker.handler(RetryPolicy::ForwardError);
+        assert_eq!(RetryPolicy::ForwardError(1), handler.handle(1, 1));
+        assert_eq!(RetryPolicy::ForwardError(2), handler.handle(2, 2));
+    }
+}
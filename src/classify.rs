@@ -0,0 +1,235 @@
+use crate::{AttemptTimedOut, ErrorHandler, RetryPolicy};
+use std::{error::Error, fmt, io, sync::Arc};
+
+type Predicate = Arc<dyn Fn(&(dyn Error + 'static)) -> bool + Send + Sync>;
+
+/// An error handler for type-erased errors that decides whether an error is transient by walking
+/// its `source()` chain.
+///
+/// Every link of the chain is checked against a set of predicates. Out of the box the set
+/// recognizes `io::Error`s of transient kinds (refused, reset or aborted connections, timeouts,
+/// interruptions, broken pipes), `tokio::time::error::Elapsed` and
+/// [`AttemptTimedOut`](struct.AttemptTimedOut.html); more predicates might be registered with
+/// [`transient_if`](#method.transient_if) and [`transient_type`](#method.transient_type).
+///
+/// Transient errors are passed to the `inner` handler (a backoff for example), while the others
+/// are forwarded right away.
+///
+/// The classifier handles `Box<dyn Error + Send + Sync>`, `Box<dyn Error>` and `io::Error`, and
+/// with the `anyhow` feature `anyhow::Error` as well.
+///
+/// ```
+/// # #[cfg(feature = "anyhow")] {
+/// use anyhow::Context;
+/// use futures_retry::{Classifier, ErrorHandler, ExponentialBackoff, RetryPolicy};
+/// use std::{io, time::Duration};
+///
+/// let mut classifier = Classifier::new(ExponentialBackoff::new(Duration::from_millis(10)));
+/// let transient = Err::<(), _>(io::Error::from(io::ErrorKind::ConnectionReset))
+///     .context("fetching the config")
+///     .unwrap_err();
+/// assert!(matches!(classifier.handle(1, transient), RetryPolicy::WaitRetry(_)));
+/// let fatal = anyhow::anyhow!("invalid config");
+/// assert!(matches!(classifier.handle(1, fatal), RetryPolicy::ForwardError(_)));
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Classifier<H> {
+    inner: H,
+    predicates: Vec<Predicate>,
+}
+
+impl<H: fmt::Debug> fmt::Debug for Classifier<H> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Classifier")
+            .field("inner", &self.inner)
+            .field("predicates", &self.predicates.len())
+            .finish()
+    }
+}
+
+fn is_transient_io(e: &(dyn Error + 'static)) -> bool {
+    use io::ErrorKind::*;
+    matches!(
+        e.downcast_ref::<io::Error>().map(io::Error::kind),
+        Some(
+            ConnectionRefused
+                | ConnectionReset
+                | ConnectionAborted
+                | TimedOut
+                | Interrupted
+                | WouldBlock
+                | BrokenPipe
+        )
+    )
+}
+
+impl<H> Classifier<H> {
+    /// Creates a classifier with the built-in predicates that passes the transient errors to the
+    /// `inner` handler.
+    pub fn new(inner: H) -> Self {
+        Self::empty(inner)
+            .transient_if(is_transient_io)
+            .transient_type::<tokio::time::error::Elapsed>()
+            .transient_type::<AttemptTimedOut>()
+    }
+
+    /// Creates a classifier without any predicates, so nothing is transient until some predicates
+    /// are registered.
+    pub fn empty(inner: H) -> Self {
+        Self {
+            inner,
+            predicates: Vec::new(),
+        }
+    }
+
+    /// Registers a predicate: an error is transient if the predicate holds for any link of its
+    /// source chain.
+    pub fn transient_if<P>(mut self, predicate: P) -> Self
+    where
+        P: Fn(&(dyn Error + 'static)) -> bool + Send + Sync + 'static,
+    {
+        self.predicates.push(Arc::new(predicate));
+        self
+    }
+
+    /// Registers an error type that is always transient.
+    pub fn transient_type<T: Error + 'static>(self) -> Self {
+        self.transient_if(|e| e.is::<T>())
+    }
+
+    /// Walks the source chain of an error and checks whether any link of it is transient.
+    pub fn is_transient(&self, e: &(dyn Error + 'static)) -> bool {
+        let mut link = Some(e);
+        while let Some(e) = link {
+            if self.predicates.iter().any(|predicate| predicate(e)) {
+                return true;
+            }
+            link = e.source();
+        }
+        false
+    }
+}
+
+macro_rules! impl_error_handler {
+    ($error:ty) => {
+        impl<H> ErrorHandler<$error> for Classifier<H>
+        where
+            H: ErrorHandler<$error>,
+            H::OutError: From<$error>,
+        {
+            type OutError = H::OutError;
+
+            fn handle(&mut self, attempt: usize, e: $error) -> RetryPolicy<H::OutError> {
+                if self.is_transient(&*e) {
+                    self.inner.handle(attempt, e)
+                } else {
+                    RetryPolicy::ForwardError(e.into())
+                }
+            }
+
+            fn ok(&mut self, attempt: usize) {
+                self.inner.ok(attempt);
+            }
+
+            fn ok_with<V: ?Sized>(&mut self, attempt: usize, value: &V) {
+                self.inner.ok_with(attempt, value);
+            }
+
+            fn exhausted(&mut self, attempt: usize, error: &Self::OutError) {
+                self.inner.exhausted(attempt, error);
+            }
+        }
+    };
+}
+
+impl_error_handler!(Box<dyn Error + Send + Sync>);
+impl_error_handler!(Box<dyn Error>);
+
+#[cfg(feature = "anyhow")]
+impl_error_handler!(anyhow::Error);
+
+impl<H> ErrorHandler<io::Error> for Classifier<H>
+where
+    H: ErrorHandler<io::Error>,
+    H::OutError: From<io::Error>,
+{
+    type OutError = H::OutError;
+
+    fn handle(&mut self, attempt: usize, e: io::Error) -> RetryPolicy<H::OutError> {
+        if self.is_transient(&e) {
+            self.inner.handle(attempt, e)
+        } else {
+            RetryPolicy::ForwardError(e.into())
+        }
+    }
+
+    fn ok(&mut self, attempt: usize) {
+        self.inner.ok(attempt);
+    }
+
+    fn ok_with<V: ?Sized>(&mut self, attempt: usize, value: &V) {
+        self.inner.ok_with(attempt, value);
+    }
+
+    fn exhausted(&mut self, attempt: usize, error: &Self::OutError) {
+        self.inner.exhausted(attempt, error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Wrapper(io::Error);
+
+    impl fmt::Display for Wrapper {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("wrapper")
+        }
+    }
+
+    impl Error for Wrapper {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    #[test]
+    fn walks_chain() {
+        let classifier = Classifier::new(());
+        let reset = Wrapper(io::ErrorKind::ConnectionReset.into());
+        assert!(classifier.is_transient(&reset));
+        let denied = Wrapper(io::ErrorKind::PermissionDenied.into());
+        assert!(!classifier.is_transient(&denied));
+        let classifier = classifier.transient_if(|e| e.to_string() == "wrapper");
+        assert!(classifier.is_transient(&denied));
+        assert!(!Classifier::empty(()).is_transient(&reset));
+    }
+
+    #[test]
+    fn boxed() {
+        type BoxError = Box<dyn Error + Send + Sync>;
+        let mut classifier = Classifier::empty(|_| RetryPolicy::Repeat::<BoxError>)
+            .transient_if(|e| e.to_string().contains("busy"));
+        let busy = BoxError::from(Wrapper(io::Error::other("busy")));
+        assert!(matches!(classifier.handle(1, busy), RetryPolicy::Repeat));
+        let fatal = BoxError::from("invalid request");
+        assert!(matches!(
+            classifier.handle(1, fatal),
+            RetryPolicy::ForwardError(_)
+        ));
+    }
+
+    #[cfg(feature = "anyhow")]
+    #[test]
+    fn anyhow() {
+        let mut classifier = Classifier::new(|_| RetryPolicy::Repeat::<anyhow::Error>);
+        let e = anyhow::Error::new(AttemptTimedOut {
+            timeout: std::time::Duration::from_secs(1),
+        })
+        .context("calling the backend");
+        assert!(matches!(classifier.handle(1, e), RetryPolicy::Repeat));
+    }
+}
@@ -0,0 +1,142 @@
+//! Reconnecting streams of frames decoded with [`tokio-util`](https://docs.rs/tokio-util) codecs.
+//!
+//! Available with the `codec` feature.
+
+use crate::{FutureFactory, ReconnectingStream};
+use futures::{ready, TryFuture};
+use pin_project_lite::pin_project;
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::AsyncRead;
+use tokio_util::codec::{Decoder, FramedRead};
+
+/// A factory that connects a transport with a connector and wraps it into a `FramedRead` with a
+/// fresh clone of the codec.
+///
+/// Created by [`reconnect_framed`](fn.reconnect_framed.html).
+#[derive(Clone)]
+pub struct FramedFactory<C, D> {
+    connector: C,
+    codec: D,
+}
+
+impl<C, D> fmt::Debug for FramedFactory<C, D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FramedFactory").finish_non_exhaustive()
+    }
+}
+
+impl<C, Fut, D> FutureFactory for FramedFactory<C, D>
+where
+    C: FnMut() -> Fut,
+    Fut: TryFuture,
+    Fut::Ok: AsyncRead,
+    D: Decoder + Clone,
+    D::Error: From<Fut::Error>,
+{
+    type FutureItem = Connect<Fut, D>;
+
+    fn new(&mut self) -> Self::FutureItem {
+        Connect {
+            future: (self.connector)(),
+            codec: Some(self.codec.clone()),
+        }
+    }
+}
+
+pin_project! {
+    /// A future that connects a transport and wraps it into a `FramedRead`.
+    pub struct Connect<Fut, D> {
+        #[pin]
+        future: Fut,
+        codec: Option<D>,
+    }
+}
+
+impl<Fut, D> Future for Connect<Fut, D>
+where
+    Fut: TryFuture,
+    D: Decoder,
+    D::Error: From<Fut::Error>,
+{
+    type Output = Result<FramedRead<Fut::Ok, D>, D::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.project();
+        let transport = ready!(this.future.try_poll(cx))?;
+        let codec = this.codec.take().expect("Connect polled after completion");
+        Poll::Ready(Ok(FramedRead::new(transport, codec)))
+    }
+}
+
+/// Creates a [`ReconnectingStream`](../struct.ReconnectingStream.html) of frames: whenever the
+/// transport fails (or ends), a new one is connected with the `connector` and decoded with a clone
+/// of the `codec`, as the `error_action` decides.
+///
+/// Both the connection errors and the decoding errors are passed to the error handler.
+///
+/// ```
+/// use futures::{future::ready, StreamExt};
+/// use futures_retry::{codec::reconnect_framed, RetryPolicy};
+/// use std::io;
+/// use tokio_util::codec::{LinesCodec, LinesCodecError};
+///
+/// # #[tokio::main] async fn main() {
+/// // Pretend that every connection sends two lines and then hangs up.
+/// let lines = reconnect_framed(
+///     || ready(Ok::<_, io::Error>(&b"hello\nworld\n"[..])),
+///     LinesCodec::new(),
+///     |_| RetryPolicy::Repeat::<LinesCodecError>,
+/// );
+/// let lines: Vec<_> = lines.take(3).map(|line| line.unwrap().0).collect().await;
+/// assert_eq!(vec!["hello", "world", "hello"], lines);
+/// # }
+/// ```
+pub fn reconnect_framed<C, Fut, D, R>(
+    connector: C,
+    codec: D,
+    error_action: R,
+) -> ReconnectingStream<FramedFactory<C, D>, R>
+where
+    C: FnMut() -> Fut,
+    Fut: TryFuture,
+    Fut::Ok: AsyncRead,
+    D: Decoder + Clone,
+    D::Error: From<Fut::Error>,
+{
+    ReconnectingStream::new(FramedFactory { connector, codec }, error_action)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RetryPolicy;
+    use futures::{future::ready, StreamExt};
+    use std::io;
+    use tokio_util::codec::{LinesCodec, LinesCodecError};
+
+    #[tokio::test]
+    async fn reconnects_on_decoding_errors() {
+        let mut transports = vec![&b"first\n\xff\n"[..], &b"second\n"[..]].into_iter();
+        let lines = reconnect_framed(
+            move || ready(Ok::<_, io::Error>(transports.next().unwrap())),
+            LinesCodec::new(),
+            |e| match e {
+                // An invalid UTF-8 line.
+                LinesCodecError::Io(e) if e.kind() == io::ErrorKind::InvalidData => {
+                    RetryPolicy::Repeat
+                }
+                e => RetryPolicy::ForwardError(e),
+            },
+        );
+        let lines: Vec<_> = lines.take(2).map(Result::unwrap).collect().await;
+        assert_eq!(
+            vec![("first".to_owned(), 1), ("second".to_owned(), 2)],
+            lines
+        );
+    }
+}
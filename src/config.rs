@@ -0,0 +1,413 @@
+use crate::{
+    ExponentialBackoff, FutureFactory, FutureRetry, Jitter, Overflow, ReaderFactory, RetryRead,
+    RetryWrite, SinkRetry, StreamRetry, WriterFactory,
+};
+use futures::TryStream;
+use std::{convert::TryFrom, env, error::Error, fmt, str::FromStr, time::Duration};
+
+/// An error in a retry configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// An environment variable holds a value that can't be parsed.
+    InvalidVar {
+        /// The name of the variable.
+        name: String,
+        /// The value of the variable.
+        value: String,
+    },
+    /// No attempt would be allowed at all.
+    ZeroAttempts,
+    /// A multiplier of the delays is zero, so the delays would collapse to nothing.
+    ZeroFactor,
+    /// The minimal delay exceeds the maximal one.
+    DelayRange {
+        /// The minimal (or the initial) delay.
+        min: Duration,
+        /// The maximal delay.
+        max: Duration,
+    },
+    /// A retry budget can't hold a single retry.
+    ZeroBudget,
+    /// A retry ratio of a budget is negative or not a number.
+    InvalidRatio,
+    /// A circuit breaker would be open right away.
+    ZeroThreshold,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::InvalidVar { name, value } => {
+                write!(f, "invalid value {:?} of the {} variable", value, name)
+            }
+            ConfigError::ZeroAttempts => f.write_str("at least one attempt must be allowed"),
+            ConfigError::ZeroFactor => f.write_str("the delay factor must be at least 1"),
+            ConfigError::DelayRange { min, max } => write!(
+                f,
+                "the minimal delay {:?} exceeds the maximal delay {:?}",
+                min, max
+            ),
+            ConfigError::ZeroBudget => f.write_str("the retry budget must allow some retries"),
+            ConfigError::InvalidRatio => {
+                f.write_str("the retry ratio must be a non-negative finite number")
+            }
+            ConfigError::ZeroThreshold => f.write_str("the failure threshold must be at least 1"),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+/// The settings of an [`ExponentialBackoff`](struct.ExponentialBackoff.html) and of the retrying
+/// adapters, so they might be loaded from a deployment's configuration rather than hardcoded.
+///
+/// A config describes the retry posture of an application in a single place: it is applied to
+/// the futures with [`future`](#method.future), to the streams with [`stream`](#method.stream),
+/// and to the sinks, readers and writers with [`sink`](#method.sink), [`reader`](#method.reader)
+/// and [`writer`](#method.writer).
+///
+/// The defaults are the ones of [`safe_defaults`](fn.safe_defaults.html), without the budget.
+/// With the `serde` feature the config implements `Serialize` and `Deserialize`.
+///
+/// ```
+/// use futures::{stream, TryStreamExt};
+/// use futures_retry::RetryConfig;
+///
+/// # #[tokio::main] async fn main() {
+/// let config = RetryConfig::default();
+/// let (value, _) = config
+///     .future(|| async { Ok::<_, std::io::Error>(1) })
+///     .unwrap()
+///     .await
+///     .unwrap();
+/// let items: Vec<_> = config
+///     .stream(stream::iter(vec![Ok::<_, std::io::Error>(value)]))
+///     .unwrap()
+///     .try_collect()
+///     .await
+///     .unwrap();
+/// assert_eq!(vec![(1, 1)], items);
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RetryConfig {
+    /// How many attempts might be made before giving up, or `None` to retry forever.
+    pub max_attempts: Option<usize>,
+    /// The delay after the first failed attempt.
+    pub base_delay: Duration,
+    /// An upper limit for the delay, if any.
+    pub max_delay: Option<Duration>,
+    /// A multiplier applied to the delay after each failed attempt.
+    pub factor: u32,
+    /// How to randomize the delays.
+    pub jitter: Jitter,
+    /// How many items a [`SinkRetry`](struct.SinkRetry.html) might buffer; at least one.
+    #[cfg_attr(feature = "serde", serde(default = "default_sink_capacity"))]
+    pub sink_capacity: usize,
+    /// What a [`SinkRetry`](struct.SinkRetry.html) does with the new items once its buffer is
+    /// full.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub overflow: Overflow,
+}
+
+fn default_sink_capacity() -> usize {
+    1
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: Some(5),
+            base_delay: Duration::from_millis(100),
+            max_delay: Some(Duration::from_secs(10)),
+            factor: 2,
+            jitter: Jitter::Full,
+            sink_capacity: default_sink_capacity(),
+            overflow: Overflow::Backpressure,
+        }
+    }
+}
+
+fn parse<T: FromStr>(name: &str, value: &str) -> Result<T, ConfigError> {
+    value.trim().parse().map_err(|_| ConfigError::InvalidVar {
+        name: name.to_owned(),
+        value: value.to_owned(),
+    })
+}
+
+impl RetryConfig {
+    /// Reads the config from the environment variables named after the `prefix`, keeping the
+    /// defaults for the variables that aren't set:
+    ///
+    /// * `<PREFIX>_RETRY_MAX_ATTEMPTS`: a number, or `0` to retry forever,
+    /// * `<PREFIX>_RETRY_BASE_DELAY_MS`: milliseconds,
+    /// * `<PREFIX>_RETRY_MAX_DELAY_MS`: milliseconds, or `0` for no limit,
+    /// * `<PREFIX>_RETRY_FACTOR`: a number,
+    /// * `<PREFIX>_RETRY_JITTER`: `none`, `full` or `equal`,
+    /// * `<PREFIX>_RETRY_SINK_CAPACITY`: a number,
+    /// * `<PREFIX>_RETRY_OVERFLOW`: `backpressure`, `drop-oldest` or `drop-newest`.
+    ///
+    /// With an empty prefix the variables are named `RETRY_MAX_ATTEMPTS` and so on. The resulting
+    /// config is [validated](#method.validate).
+    ///
+    /// ```
+    /// use futures_retry::RetryConfig;
+    /// use std::time::Duration;
+    ///
+    /// std::env::set_var("MYAPP_RETRY_BASE_DELAY_MS", "250");
+    /// let config = RetryConfig::from_env("MYAPP").unwrap();
+    /// assert_eq!(Duration::from_millis(250), config.base_delay);
+    /// ```
+    pub fn from_env(prefix: &str) -> Result<Self, ConfigError> {
+        Self::from_vars(prefix, |name| env::var(name).ok())
+    }
+
+    fn from_vars(prefix: &str, var: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let prefix = if prefix.is_empty() {
+            "RETRY_".to_owned()
+        } else {
+            format!("{}_RETRY_", prefix)
+        };
+        let var = |suffix: &str| {
+            let name = format!("{}{}", prefix, suffix);
+            var(&name).map(|value| (name, value))
+        };
+        let mut config = Self::default();
+        if let Some((name, value)) = var("MAX_ATTEMPTS") {
+            config.max_attempts = Some(parse(&name, &value)?).filter(|&max| max != 0);
+        }
+        if let Some((name, value)) = var("BASE_DELAY_MS") {
+            config.base_delay = Duration::from_millis(parse(&name, &value)?);
+        }
+        if let Some((name, value)) = var("MAX_DELAY_MS") {
+            config.max_delay = Some(parse(&name, &value)?)
+                .filter(|&max| max != 0)
+                .map(Duration::from_millis);
+        }
+        if let Some((name, value)) = var("FACTOR") {
+            config.factor = parse(&name, &value)?;
+        }
+        if let Some((name, value)) = var("JITTER") {
+            config.jitter = match value.trim().to_ascii_lowercase().as_str() {
+                "none" => Jitter::None,
+                "full" => Jitter::Full,
+                "equal" => Jitter::Equal,
+                _ => return Err(ConfigError::InvalidVar { name, value }),
+            };
+        }
+        if let Some((name, value)) = var("SINK_CAPACITY") {
+            config.sink_capacity = parse(&name, &value)?;
+        }
+        if let Some((name, value)) = var("OVERFLOW") {
+            config.overflow = match value.trim().to_ascii_lowercase().as_str() {
+                "backpressure" => Overflow::Backpressure,
+                "drop-oldest" => Overflow::DropOldest,
+                "drop-newest" => Overflow::DropNewest,
+                _ => return Err(ConfigError::InvalidVar { name, value }),
+            };
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks that at least one attempt is allowed, the factor is not zero and the base delay
+    /// doesn't exceed the maximal one.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.max_attempts == Some(0) {
+            return Err(ConfigError::ZeroAttempts);
+        }
+        if self.factor == 0 {
+            return Err(ConfigError::ZeroFactor);
+        }
+        match self.max_delay {
+            Some(max) if max < self.base_delay => Err(ConfigError::DelayRange {
+                min: self.base_delay,
+                max,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Creates a backoff with these settings, once they are [validated](#method.validate).
+    pub fn backoff(&self) -> Result<ExponentialBackoff, ConfigError> {
+        self.validate()?;
+        let mut backoff = ExponentialBackoff::new(self.base_delay)
+            .factor(self.factor)
+            .jitter(self.jitter);
+        if let Some(max_delay) = self.max_delay {
+            backoff = backoff.max_delay(max_delay);
+        }
+        if let Some(max_attempts) = self.max_attempts {
+            backoff = backoff.max_attempts(max_attempts);
+        }
+        Ok(backoff)
+    }
+
+    /// Creates a [`FutureRetry`](struct.FutureRetry.html) with the backoff of these settings.
+    pub fn future<F: FutureFactory>(
+        &self,
+        factory: F,
+    ) -> Result<FutureRetry<F, ExponentialBackoff>, ConfigError> {
+        Ok(FutureRetry::new(factory, self.backoff()?))
+    }
+
+    /// Creates a [`StreamRetry`](struct.StreamRetry.html) with the backoff of these settings.
+    pub fn stream<S: TryStream>(
+        &self,
+        stream: S,
+    ) -> Result<StreamRetry<ExponentialBackoff, S>, ConfigError> {
+        Ok(StreamRetry::new(stream, self.backoff()?))
+    }
+
+    /// Creates a [`SinkRetry`](struct.SinkRetry.html) with the backoff, the capacity and the
+    /// overflow policy of these settings.
+    pub fn sink<Si, Item>(
+        &self,
+        sink: Si,
+    ) -> Result<SinkRetry<Si, Item, ExponentialBackoff>, ConfigError> {
+        Ok(SinkRetry::new(sink, self.backoff()?)
+            .capacity(self.sink_capacity)
+            .overflow(self.overflow))
+    }
+
+    /// Creates a [`RetryRead`](struct.RetryRead.html) with the backoff of these settings.
+    pub fn reader<R, F: ReaderFactory>(
+        &self,
+        reader: R,
+        factory: F,
+    ) -> Result<RetryRead<R, F, ExponentialBackoff>, ConfigError> {
+        Ok(RetryRead::new(reader, factory, self.backoff()?))
+    }
+
+    /// Creates a [`RetryWrite`](struct.RetryWrite.html) with the backoff of these settings.
+    pub fn writer<W, F: WriterFactory>(
+        &self,
+        writer: W,
+        factory: F,
+    ) -> Result<RetryWrite<W, F, ExponentialBackoff>, ConfigError> {
+        Ok(RetryWrite::new(writer, factory, self.backoff()?))
+    }
+}
+
+impl TryFrom<RetryConfig> for ExponentialBackoff {
+    type Error = ConfigError;
+
+    fn try_from(config: RetryConfig) -> Result<Self, ConfigError> {
+        config.backoff()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn vars(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<_, _> = pairs
+            .iter()
+            .map(|&(name, value)| (name.to_owned(), value.to_owned()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn reads_vars() {
+        let config = RetryConfig::from_vars(
+            "APP",
+            vars(&[
+                ("APP_RETRY_MAX_ATTEMPTS", "0"),
+                ("APP_RETRY_BASE_DELAY_MS", " 20"),
+                ("APP_RETRY_MAX_DELAY_MS", "1000"),
+                ("APP_RETRY_FACTOR", "3"),
+                ("APP_RETRY_JITTER", "None"),
+                ("RETRY_FACTOR", "7"),
+            ]),
+        )
+        .unwrap();
+        let expected = RetryConfig {
+            max_attempts: None,
+            base_delay: Duration::from_millis(20),
+            max_delay: Some(Duration::from_secs(1)),
+            factor: 3,
+            jitter: Jitter::None,
+            sink_capacity: 1,
+            overflow: Overflow::Backpressure,
+        };
+        assert_eq!(expected, config);
+        let schedule: Vec<_> = config.backoff().unwrap().schedule().take(4).collect();
+        assert_eq!(
+            vec![20, 60, 180, 540],
+            schedule.iter().map(|d| d.as_millis()).collect::<Vec<_>>()
+        );
+        let unprefixed = RetryConfig::from_vars("", vars(&[("RETRY_FACTOR", "7")])).unwrap();
+        assert_eq!(7, unprefixed.factor);
+        assert_eq!(
+            RetryConfig::default(),
+            RetryConfig::from_vars("NONE", vars(&[])).unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        let e = RetryConfig::from_vars("APP", vars(&[("APP_RETRY_JITTER", "some")])).unwrap_err();
+        assert_eq!(
+            ConfigError::InvalidVar {
+                name: "APP_RETRY_JITTER".to_owned(),
+                value: "some".to_owned()
+            },
+            e
+        );
+        let e = RetryConfig::from_vars("APP", vars(&[("APP_RETRY_FACTOR", "-1")])).unwrap_err();
+        assert_eq!(
+            "invalid value \"-1\" of the APP_RETRY_FACTOR variable",
+            e.to_string()
+        );
+    }
+
+    #[test]
+    fn validates() {
+        let e = RetryConfig::from_vars("APP", vars(&[("APP_RETRY_BASE_DELAY_MS", "20000")]))
+            .unwrap_err();
+        assert_eq!(
+            ConfigError::DelayRange {
+                min: Duration::from_secs(20),
+                max: Duration::from_secs(10)
+            },
+            e
+        );
+        let config = RetryConfig {
+            max_attempts: Some(0),
+            ..RetryConfig::default()
+        };
+        assert_eq!(
+            Err(ConfigError::ZeroAttempts),
+            ExponentialBackoff::try_from(config).map(drop)
+        );
+        let config = RetryConfig {
+            factor: 0,
+            ..RetryConfig::default()
+        };
+        assert_eq!(Err(ConfigError::ZeroFactor), config.validate());
+        assert_eq!(Ok(()), RetryConfig::default().validate());
+    }
+
+    #[test]
+    fn configures_sinks() {
+        let config = RetryConfig::from_vars(
+            "APP",
+            vars(&[
+                ("APP_RETRY_SINK_CAPACITY", "16"),
+                ("APP_RETRY_OVERFLOW", "Drop-Oldest"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            (16, Overflow::DropOldest),
+            (config.sink_capacity, config.overflow)
+        );
+        let mut sink = config.sink(futures::sink::drain::<u8>()).unwrap();
+        futures::executor::block_on(futures::SinkExt::send(&mut sink, 1)).unwrap();
+        assert_eq!(0, sink.buffered());
+    }
+}
@@ -0,0 +1,247 @@
+use crate::{ErrorHandler, RetryPolicy};
+use std::{
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+#[derive(Debug)]
+struct Epoch {
+    id: u64,
+    started: Instant,
+    next_slot: usize,
+}
+
+#[derive(Debug)]
+struct Coordinator {
+    delay: Duration,
+    window: Duration,
+    members: usize,
+    epochs: u64,
+    epoch: Option<Epoch>,
+}
+
+impl Coordinator {
+    fn current_epoch(&mut self, now: Instant) -> &mut Epoch {
+        let expired = match &self.epoch {
+            Some(epoch) => now >= epoch.started + self.delay + self.window,
+            None => true,
+        };
+        if expired {
+            self.epochs += 1;
+            self.epoch = Some(Epoch {
+                id: self.epochs,
+                started: now,
+                next_slot: 0,
+            });
+        }
+        self.epoch.as_mut().expect("An epoch has just been started")
+    }
+}
+
+/// Coordinates backoffs of many retry loops that talk to the same backend.
+///
+/// Independent exponential backoffs of thousands of clients that have lost their connections at
+/// the same time tend to synchronize into bursts. A coordinator handles this differently: the
+/// first failure reported by any of its handlers (or an explicit [`signal`](#method.signal))
+/// starts a backoff period, and every handler that fails during the period waits for the common
+/// `delay` and then for its own slot within the `window`, so the reconnections are spread evenly
+/// over the window.
+///
+/// ```
+/// use futures_retry::{BackoffCoordinator, RetryPolicy};
+/// use std::time::Duration;
+///
+/// let coordinator = BackoffCoordinator::new(Duration::from_secs(1), Duration::from_secs(10));
+/// let handlers: Vec<_> = (0..1000)
+///     .map(|_| coordinator.handler(|_: std::io::Error| RetryPolicy::Repeat::<std::io::Error>))
+///     .collect();
+/// assert_eq!(1000, coordinator.members());
+/// ```
+#[derive(Debug, Clone)]
+pub struct BackoffCoordinator {
+    coordinator: Arc<Mutex<Coordinator>>,
+}
+
+impl BackoffCoordinator {
+    /// Creates a coordinator that makes failed loops wait for `delay` and then spreads their
+    /// retries over the `window`.
+    pub fn new(delay: Duration, window: Duration) -> Self {
+        Self {
+            coordinator: Arc::new(Mutex::new(Coordinator {
+                delay,
+                window,
+                members: 0,
+                epochs: 0,
+                epoch: None,
+            })),
+        }
+    }
+
+    /// Starts a backoff period (unless one is already in progress), for example when a broker
+    /// restart has been detected by some other means.
+    pub fn signal(&self) {
+        self.lock().current_epoch(Instant::now());
+    }
+
+    /// Returns how many handlers are registered with the coordinator.
+    pub fn members(&self) -> usize {
+        self.lock().members
+    }
+
+    /// Wraps an error handler and registers it with the coordinator.
+    pub fn handler<H>(&self, inner: H) -> CoordinatedHandler<H> {
+        self.lock().members += 1;
+        CoordinatedHandler {
+            coordinator: self.clone(),
+            inner,
+            slot: None,
+        }
+    }
+
+    /// Returns when a member holding the `slot` (an epoch and an offset) should resume.
+    fn resume_at(&self, slot: &mut Option<(u64, Duration)>, now: Instant) -> Instant {
+        let mut coordinator = self.lock();
+        let (delay, window, members) = (
+            coordinator.delay,
+            coordinator.window,
+            coordinator.members.max(1),
+        );
+        let epoch = coordinator.current_epoch(now);
+        let offset = match *slot {
+            Some((id, offset)) if id == epoch.id => offset,
+            _ => {
+                let index = (epoch.next_slot % members) as u32;
+                epoch.next_slot += 1;
+                let offset = window / members as u32 * index;
+                *slot = Some((epoch.id, offset));
+                offset
+            }
+        };
+        epoch.started + delay + offset
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Coordinator> {
+        self.coordinator
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// An error handler registered with a [`BackoffCoordinator`](struct.BackoffCoordinator.html).
+///
+/// Created by [`BackoffCoordinator::handler`](struct.BackoffCoordinator.html#method.handler).
+#[derive(Debug)]
+pub struct CoordinatedHandler<H> {
+    coordinator: BackoffCoordinator,
+    inner: H,
+    slot: Option<(u64, Duration)>,
+}
+
+impl<H: Clone> Clone for CoordinatedHandler<H> {
+    /// The clone is registered with the coordinator as another member, with a slot of its own.
+    fn clone(&self) -> Self {
+        self.coordinator.handler(self.inner.clone())
+    }
+}
+
+impl<H> Drop for CoordinatedHandler<H> {
+    fn drop(&mut self) {
+        self.coordinator.lock().members -= 1;
+    }
+}
+
+impl<E, H> ErrorHandler<E> for CoordinatedHandler<H>
+where
+    H: ErrorHandler<E>,
+{
+    type OutError = H::OutError;
+
+    fn handle(&mut self, attempt: usize, e: E) -> RetryPolicy<H::OutError> {
+        let delay = match self.inner.handle(attempt, e) {
+            RetryPolicy::ForwardError(e) => return RetryPolicy::ForwardError(e),
+            // A benign error tells nothing about the backend, so it's not coordinated.
+            RetryPolicy::RepeatWithoutCounting => return RetryPolicy::RepeatWithoutCounting,
+            RetryPolicy::Repeat => Duration::from_secs(0),
+            RetryPolicy::WaitRetry(delay) => delay,
+            RetryPolicy::RetryAs { attempt, delay } => {
+                let now = Instant::now();
+                let resume_at = self.coordinator.resume_at(&mut self.slot, now);
+                let delay = delay.max(resume_at.saturating_duration_since(now));
+                return RetryPolicy::RetryAs { attempt, delay };
+            }
+        };
+        let now = Instant::now();
+        let resume_at = self.coordinator.resume_at(&mut self.slot, now);
+        RetryPolicy::WaitRetry(delay.max(resume_at.saturating_duration_since(now)))
+    }
+
+    fn ok(&mut self, attempt: usize) {
+        self.inner.ok(attempt);
+    }
+
+    fn ok_with<V: ?Sized>(&mut self, attempt: usize, value: &V) {
+        self.inner.ok_with(attempt, value);
+    }
+
+    fn exhausted(&mut self, attempt: usize, error: &Self::OutError) {
+        self.inner.exhausted(attempt, error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delay<E>(policy: RetryPolicy<E>) -> Duration {
+        match policy {
+            RetryPolicy::WaitRetry(delay) => delay,
+            _ => panic!("Expected a delay"),
+        }
+    }
+
+    #[test]
+    fn staggered() {
+        let coordinator = BackoffCoordinator::new(Duration::from_secs(1), Duration::from_secs(4));
+        let mut handlers: Vec<_> = (0..4)
+            .map(|_| coordinator.handler(|_| RetryPolicy::Repeat::<()>))
+            .collect();
+        let delays: Vec<_> = handlers
+            .iter_mut()
+            .map(|handler| delay(handler.handle(1, ())).as_millis())
+            .collect();
+        // Some time passes between the calls, so the delays are slightly shorter.
+        for (delay, expected) in delays.iter().zip(&[1000, 2000, 3000, 4000]) {
+            assert!(*delay <= *expected && *delay > *expected - 100);
+        }
+        // The same slot is kept during the backoff period.
+        let again = delay(handlers[3].handle(2, ())).as_millis();
+        assert!(again <= delays[3] && again > delays[3] - 100);
+        assert_eq!(4, coordinator.members());
+        drop(handlers);
+        assert_eq!(0, coordinator.members());
+    }
+
+    #[test]
+    fn clones_are_members() {
+        let coordinator = BackoffCoordinator::new(Duration::from_secs(1), Duration::from_secs(4));
+        let handler = coordinator.handler(|_: ()| RetryPolicy::Repeat::<()>);
+        let clone = handler.clone();
+        assert_eq!(2, coordinator.members());
+        drop(clone);
+        assert_eq!(1, coordinator.members());
+        drop(handler);
+        assert_eq!(0, coordinator.members());
+    }
+
+    #[test]
+    fn own_delay_is_respected() {
+        let coordinator =
+            BackoffCoordinator::new(Duration::from_millis(10), Duration::from_millis(10));
+        let mut handler =
+            coordinator.handler(|_| RetryPolicy::WaitRetry::<()>(Duration::from_secs(5)));
+        assert_eq!(Duration::from_secs(5), delay(handler.handle(1, ())));
+        let mut handler = coordinator.handler(RetryPolicy::ForwardError);
+        assert_eq!(RetryPolicy::ForwardError(()), handler.handle(1, ()));
+    }
+}
@@ -0,0 +1,296 @@
+use crate::{ErrorHandler, ReaderFactory, RetryPolicy, Sleeper};
+use futures::{future::poll_fn, ready, TryFuture, TryFutureExt};
+use std::{io, ops::Range, pin::Pin, task::Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+struct Copy<'a, F, R, W> {
+    factory: F,
+    reader: Option<R>,
+    writer: &'a mut W,
+    buf: Box<[u8]>,
+    pending: Range<usize>,
+    copied: u64,
+}
+
+impl<F, R, W> Copy<'_, F, R, W>
+where
+    F: ReaderFactory,
+    F::Future: TryFuture<Ok = R>,
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    /// Copies a chunk, returns `true` once the reader is exhausted and the writer is flushed.
+    async fn step(&mut self) -> io::Result<bool> {
+        if self.pending.is_empty() {
+            let reader = match &mut self.reader {
+                Some(reader) => reader,
+                None => self
+                    .reader
+                    .insert(self.factory.reopen(self.copied).into_future().await?),
+            };
+            let buf = &mut self.buf;
+            let read = poll_fn(|cx| {
+                let mut buf = ReadBuf::new(buf);
+                ready!(Pin::new(&mut *reader).poll_read(cx, &mut buf))?;
+                Poll::Ready(Ok::<_, io::Error>(buf.filled().len()))
+            })
+            .await?;
+            if read == 0 {
+                let writer = &mut *self.writer;
+                poll_fn(|cx| Pin::new(&mut *writer).poll_flush(cx)).await?;
+                return Ok(true);
+            }
+            self.pending = 0..read;
+        }
+        while !self.pending.is_empty() {
+            let (writer, chunk) = (&mut *self.writer, &self.buf[self.pending.clone()]);
+            let written = poll_fn(|cx| Pin::new(&mut *writer).poll_write(cx, chunk)).await?;
+            if written == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            self.pending.start += written;
+            self.copied += written as u64;
+        }
+        Ok(false)
+    }
+}
+
+/// Copies everything from a reader to the `writer`, like `tokio::io::copy`, but survives
+/// transient errors, resolving into the number of bytes copied along with the number of the
+/// attempt.
+///
+/// The reader is opened by a [`ReaderFactory`](trait.ReaderFactory.html), which is given the
+/// number of bytes copied so far. After a read error the factory is asked for a new reader that
+/// starts at that offset, e.g. by seeking a file or with an HTTP range request. After a write
+/// error the bytes that haven't been written are written again. The error handler paces both,
+/// with the delays timed by the `sleeper`. The attempt counter is reset back to `1` whenever
+/// some bytes have been copied since the last error.
+///
+/// ```
+/// use futures_retry::{copy_with_retry, RetryPolicy, TokioSleeper};
+/// use std::io::{self, SeekFrom};
+/// use tokio::io::AsyncSeekExt;
+///
+/// # #[tokio::main] async fn main() {
+/// # let path = std::env::temp_dir().join("futures-retry-copy-doctest");
+/// # std::fs::write(&path, b"hello, world").unwrap();
+/// let mut copy = Vec::new();
+/// let result = copy_with_retry(
+///     |offset: u64| {
+///         let path = path.clone();
+///         async move {
+///             let mut file = tokio::fs::File::open(path).await?;
+///             file.seek(SeekFrom::Start(offset)).await?;
+///             Ok(file)
+///         }
+///     },
+///     &mut copy,
+///     |e: io::Error| match e.kind() {
+///         io::ErrorKind::Interrupted => RetryPolicy::Repeat,
+///         _ => RetryPolicy::ForwardError(e),
+///     },
+///     TokioSleeper,
+/// )
+/// .await;
+/// assert_eq!((12, 1), result.unwrap());
+/// assert_eq!(b"hello, world", &copy[..]);
+/// # }
+/// ```
+pub async fn copy_with_retry<F, R, W, H, S>(
+    factory: F,
+    writer: &mut W,
+    mut error_action: H,
+    sleeper: S,
+) -> Result<(u64, usize), (io::Error, usize)>
+where
+    F: ReaderFactory,
+    F::Future: TryFuture<Ok = R>,
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+    H: ErrorHandler<io::Error, OutError = io::Error>,
+    S: Sleeper,
+{
+    let mut copy = Copy {
+        factory,
+        reader: None,
+        writer,
+        buf: vec![0; 8 * 1024].into_boxed_slice(),
+        pending: 0..0,
+        copied: 0,
+    };
+    let mut attempt = 1;
+    loop {
+        let copied = copy.copied;
+        let step = copy.step().await;
+        if copy.copied > copied {
+            attempt = 1;
+        }
+        match step {
+            Ok(false) => continue,
+            Ok(true) => {
+                error_action.ok_with(attempt, &copy.copied);
+                return Ok((copy.copied, attempt));
+            }
+            Err(e) => {
+                if copy.pending.is_empty() {
+                    // The reader has failed, so a new one is needed.
+                    copy.reader = None;
+                }
+                let policy = error_action.handle(attempt, e);
+                let next = policy.next_attempt(attempt);
+                match policy {
+                    RetryPolicy::ForwardError(e) => {
+                        error_action.exhausted(attempt, &e);
+                        return Err((e, attempt));
+                    }
+                    RetryPolicy::Repeat | RetryPolicy::RepeatWithoutCounting => {}
+                    RetryPolicy::WaitRetry(delay) | RetryPolicy::RetryAs { delay, .. } => {
+                        sleeper.sleep(delay).await
+                    }
+                }
+                attempt = next;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error_handler::tests::Successes, MaxAttempts, TokioSleeper};
+    use futures::future::{ready, Ready};
+    use std::task::Context;
+
+    /// Fails every other read, or every other write.
+    #[derive(Default)]
+    struct Flaky {
+        data: Vec<u8>,
+        calls: usize,
+    }
+
+    impl Flaky {
+        fn fail(&mut self) -> bool {
+            self.calls += 1;
+            self.calls.is_multiple_of(2)
+        }
+    }
+
+    impl AsyncRead for Flaky {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context,
+            buf: &mut ReadBuf,
+        ) -> Poll<io::Result<()>> {
+            if self.fail() {
+                return Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()));
+            }
+            let len = self.data.len().min(2);
+            buf.put_slice(&self.data[..len]);
+            self.data.drain(..len);
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncWrite for Flaky {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            if self.fail() {
+                return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+            }
+            self.data.extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn resumes() {
+        let data = b"abcdef";
+        let mut offsets = Vec::new();
+        let factory = |offset: u64| -> Ready<io::Result<Flaky>> {
+            offsets.push(offset);
+            ready(Ok(Flaky {
+                data: data[offset as usize..].to_vec(),
+                calls: 0,
+            }))
+        };
+        let mut writer = Flaky::default();
+        let result =
+            copy_with_retry(factory, &mut writer, |_| RetryPolicy::Repeat, TokioSleeper).await;
+        assert_eq!(data, &writer.data[..]);
+        assert_eq!(6, result.unwrap().0);
+        assert_eq!(vec![0, 2, 4, 6], offsets);
+    }
+
+    #[tokio::test]
+    async fn resets_attempt_after_progress() {
+        let factory = |offset: u64| -> Ready<io::Result<Flaky>> {
+            ready(Ok(Flaky {
+                data: b"abcdefghij"[offset as usize..].to_vec(),
+                calls: 0,
+            }))
+        };
+        // Every other read and write fails, but some bytes get copied between the errors.
+        let handler = MaxAttempts::<3, _>::new(|_| RetryPolicy::Repeat);
+        let mut writer = Flaky::default();
+        let (copied, attempt) = copy_with_retry(factory, &mut writer, handler, TokioSleeper)
+            .await
+            .unwrap();
+        assert_eq!(10, copied);
+        assert!(attempt < 3);
+    }
+
+    #[cfg(feature = "test_util")]
+    #[tokio::test]
+    async fn waits_on_the_sleeper() {
+        let factory = |offset: u64| -> Ready<io::Result<Flaky>> {
+            ready(Ok(Flaky {
+                data: b"ab"[offset as usize..].to_vec(),
+                calls: 0,
+            }))
+        };
+        let sleeper = crate::test_util::MockSleeper::new();
+        let hour = std::time::Duration::from_secs(3600);
+        let mut writer = Vec::new();
+        let copy = copy_with_retry(
+            factory,
+            &mut writer,
+            |_| RetryPolicy::WaitRetry(hour),
+            sleeper.clone(),
+        );
+        tokio::pin!(copy);
+        assert!(futures::poll!(copy.as_mut()).is_pending());
+        sleeper.advance(hour);
+        assert_eq!((2, 2), copy.await.unwrap());
+        assert_eq!(vec![hour], sleeper.requested());
+    }
+
+    #[tokio::test]
+    async fn passes_success_values() {
+        let factory = |offset: u64| -> Ready<io::Result<Flaky>> {
+            ready(Ok(Flaky {
+                data: b"abcdef"[offset as usize..].to_vec(),
+                calls: 0,
+            }))
+        };
+        let successes = Successes::new(|_| RetryPolicy::Repeat);
+        let mut writer = Flaky::default();
+        let (copied, attempt) =
+            copy_with_retry(factory, &mut writer, successes.clone(), TokioSleeper)
+                .await
+                .unwrap();
+        assert_eq!(6, copied);
+        // The number of the bytes copied.
+        assert_eq!(vec![(attempt, "u64")], successes.seen());
+    }
+}
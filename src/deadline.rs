@@ -0,0 +1,183 @@
+use crate::{ErrorHandler, RetryPolicy, Sleeper, TokioSleeper};
+use std::future::Future;
+use tokio::time::Instant;
+
+tokio::task_local! {
+    static DEADLINE: Instant;
+}
+
+/// Runs a `future` with an ambient deadline that the nested retry loops respect.
+///
+/// The deadline is stored in a task-local, so it doesn't have to be passed down a deep call
+/// stack: every [`DeadlineHandler`](struct.DeadlineHandler.html) polled within the `future` reads
+/// it on its own. Nested scopes can only shorten the deadline, never extend it.
+///
+/// ```
+/// use futures_retry::{with_deadline, DeadlineHandler, FutureRetry, RetryPolicy};
+/// use std::time::Duration;
+/// use tokio::time::Instant;
+///
+/// # #[tokio::main] async fn main() {
+/// async fn fetch() -> Result<(), (std::io::Error, usize)> {
+///     // Somewhere deep down the call stack.
+///     let handler = DeadlineHandler::new(|_| RetryPolicy::WaitRetry::<std::io::Error>(
+///         Duration::from_secs(1),
+///     ));
+///     FutureRetry::new(
+///         || async { Err(std::io::Error::from(std::io::ErrorKind::TimedOut)) },
+///         handler,
+///     )
+///     .await
+///     .map(|_: ((), usize)| ())
+/// }
+///
+/// let deadline = Instant::now() + Duration::from_millis(100);
+/// let started = Instant::now();
+/// assert!(with_deadline(deadline, fetch()).await.is_err());
+/// assert!(started.elapsed() < Duration::from_secs(1));
+/// # }
+/// ```
+pub async fn with_deadline<F: Future>(deadline: Instant, future: F) -> F::Output {
+    let deadline = current_deadline().map_or(deadline, |outer| outer.min(deadline));
+    DEADLINE.scope(deadline, future).await
+}
+
+/// Returns the ambient deadline set by [`with_deadline`](fn.with_deadline.html), if any.
+pub fn current_deadline() -> Option<Instant> {
+    DEADLINE.try_with(|deadline| *deadline).ok()
+}
+
+/// An error handler that keeps a retry loop within the ambient deadline.
+///
+/// Waits are cut so they end at the deadline at the latest, and once the deadline has passed any
+/// error is forwarded right away, without consulting the inner handler. Outside of
+/// [`with_deadline`](fn.with_deadline.html) and with no fixed [`deadline`](#method.deadline) the
+/// inner handler is used as is.
+///
+/// The time left is measured by a [`Sleeper`](trait.Sleeper.html), which should be the one that
+/// times the retry loop.
+#[derive(Debug, Clone)]
+pub struct DeadlineHandler<H, S = TokioSleeper> {
+    inner: H,
+    deadline: Option<Instant>,
+    sleeper: S,
+}
+
+impl<H> DeadlineHandler<H> {
+    /// Wraps an error handler.
+    pub fn new(inner: H) -> Self {
+        Self::with_sleeper(inner, TokioSleeper)
+    }
+}
+
+impl<H, S> DeadlineHandler<H, S> {
+    /// Like a `new` method, but the time is told by a custom [`Sleeper`](trait.Sleeper.html).
+    pub fn with_sleeper(inner: H, sleeper: S) -> Self {
+        Self {
+            inner,
+            deadline: None,
+            sleeper,
+        }
+    }
+
+    /// Sets a fixed deadline, respected along with the ambient one: the earlier of the two wins.
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+}
+
+impl<E, H, S> ErrorHandler<E> for DeadlineHandler<H, S>
+where
+    H: ErrorHandler<E>,
+    H::OutError: From<E>,
+    S: Sleeper,
+{
+    type OutError = H::OutError;
+
+    fn handle(&mut self, attempt: usize, e: E) -> RetryPolicy<H::OutError> {
+        let deadline = match (self.deadline, current_deadline()) {
+            (Some(fixed), Some(ambient)) => Some(fixed.min(ambient)),
+            (fixed, ambient) => fixed.or(ambient),
+        };
+        let remaining = match deadline {
+            None => return self.inner.handle(attempt, e),
+            Some(deadline) => deadline.saturating_duration_since(self.sleeper.now()),
+        };
+        if remaining.as_nanos() == 0 {
+            return RetryPolicy::ForwardError(e.into());
+        }
+        match self.inner.handle(attempt, e) {
+            RetryPolicy::WaitRetry(delay) => RetryPolicy::WaitRetry(delay.min(remaining)),
+            RetryPolicy::RetryAs { attempt, delay } => RetryPolicy::RetryAs {
+                attempt,
+                delay: delay.min(remaining),
+            },
+            policy => policy,
+        }
+    }
+
+    fn ok(&mut self, attempt: usize) {
+        self.inner.ok(attempt);
+    }
+
+    fn ok_with<V: ?Sized>(&mut self, attempt: usize, value: &V) {
+        self.inner.ok_with(attempt, value);
+    }
+
+    fn exhausted(&mut self, attempt: usize, error: &Self::OutError) {
+        self.inner.exhausted(attempt, error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn nested_deadlines() {
+        let outer = Instant::now() + Duration::from_secs(1);
+        let inner = outer + Duration::from_secs(10);
+        let deadline = with_deadline(outer, with_deadline(inner, async { current_deadline() }));
+        assert_eq!(Some(outer), deadline.await);
+        assert_eq!(None, current_deadline());
+    }
+
+    #[tokio::test]
+    async fn cuts_waits() {
+        let mut handler =
+            DeadlineHandler::new(|_| RetryPolicy::WaitRetry::<u8>(Duration::from_secs(5)));
+        assert_eq!(
+            RetryPolicy::WaitRetry(Duration::from_secs(5)),
+            handler.handle(1, 1)
+        );
+        let deadline = Instant::now() + Duration::from_secs(1);
+        with_deadline(deadline, async {
+            match handler.handle(1, 1) {
+                RetryPolicy::WaitRetry(delay) => assert!(delay <= Duration::from_secs(1)),
+                policy => panic!("Unexpected policy {:?}", policy),
+            }
+        })
+        .await;
+        let deadline = Instant::now();
+        let policy = with_deadline(deadline, async { handler.handle(2, 2) }).await;
+        assert_eq!(RetryPolicy::ForwardError(2), policy);
+    }
+
+    #[cfg(feature = "test_util")]
+    #[test]
+    fn tells_time_by_the_sleeper() {
+        let sleeper = crate::test_util::MockSleeper::new();
+        let minute = Duration::from_secs(60);
+        let mut handler = DeadlineHandler::with_sleeper(
+            |_| RetryPolicy::WaitRetry::<u8>(Duration::from_secs(3600)),
+            sleeper.clone(),
+        )
+        .deadline(sleeper.now() + 2 * minute);
+        sleeper.advance(minute);
+        assert_eq!(RetryPolicy::WaitRetry(minute), handler.handle(1, 1));
+        sleeper.advance(minute);
+        assert_eq!(RetryPolicy::ForwardError(2), handler.handle(2, 2));
+    }
+}
@@ -1,4 +1,6 @@
 use crate::RetryPolicy;
+use futures::future::Either;
+use std::ops::DerefMut;
 
 /// An error handler trait.
 ///
@@ -59,6 +61,56 @@ pub trait ErrorHandler<InError> {
     ///
     /// By default the method is a no-op.
     fn ok(&mut self, _attempt: usize) {}
+
+    /// Like [`ok`](#method.ok), but also receives the successful item/output, so a handler might
+    /// learn from it. The type of the value is not known to the handler, so it might only look at
+    /// what all the values have, e.g. their size (the length of a slice or a string).
+    ///
+    /// The retry loops of this crate call this method instead of `ok`. By default it calls `ok`;
+    /// the handlers that wrap other ones pass the value through. The method is not available on
+    /// trait objects, so a boxed handler only learns about the successes through `ok`.
+    ///
+    /// ```
+    /// use futures_retry::{ErrorHandler, RetryPolicy};
+    /// use std::{mem, time::Duration};
+    ///
+    /// /// Waits for as long as it takes to transfer the last payload again, a microsecond a byte.
+    /// #[derive(Default)]
+    /// struct PayloadAware {
+    ///     last_len: usize,
+    /// }
+    ///
+    /// impl ErrorHandler<()> for PayloadAware {
+    ///     type OutError = ();
+    ///
+    ///     fn handle(&mut self, _attempt: usize, _e: ()) -> RetryPolicy<()> {
+    ///         RetryPolicy::WaitRetry(Duration::from_micros(self.last_len as u64))
+    ///     }
+    ///
+    ///     fn ok_with<V: ?Sized>(&mut self, _attempt: usize, value: &V) {
+    ///         self.last_len = mem::size_of_val(value);
+    ///     }
+    /// }
+    ///
+    /// let mut handler = PayloadAware::default();
+    /// handler.ok_with(1, &b"a 16 bytes frame"[..]);
+    /// assert_eq!(RetryPolicy::WaitRetry(Duration::from_micros(16)), handler.handle(2, ()));
+    /// ```
+    fn ok_with<V: ?Sized>(&mut self, attempt: usize, _value: &V)
+    where
+        Self: Sized,
+    {
+        self.ok(attempt);
+    }
+
+    /// This method is called once a retry loop gives up, right before it returns the error the
+    /// handler has forwarded, so a handler that maintains some state (e.g. a circuit or a budget)
+    /// learns about the failures of the whole operations, not only of the attempts.
+    ///
+    /// The retry loops call it exactly once per forwarded error, symmetrically with
+    /// [`ok_with`](#method.ok_with). By default the method is a no-op; the handlers that wrap
+    /// other ones pass the call through.
+    fn exhausted(&mut self, _attempt: usize, _error: &Self::OutError) {}
 }
 
 impl<InError, F, OutError> ErrorHandler<InError> for F
@@ -71,3 +123,381 @@ where
         (self)(e)
     }
 }
+
+/// A handler that is held indirectly: borrowed (`&mut H`), boxed (`Box<H>`, a boxed trait object
+/// included) or behind any other `DerefMut` pointer.
+///
+/// The pointers can't implement [`ErrorHandler`](trait.ErrorHandler.html) on their own, as their
+/// implementations would conflict with the one for the closures (a `&mut` or a boxed closure is a
+/// closure as well, and these are handlers already), so they are wrapped instead.
+///
+/// The successful values are passed through to a handler of a known type; a boxed trait object
+/// (`dyn ErrorHandler`, possibly `+ Send` and `+ Sync`) only learns about the successes through
+/// [`ok`](trait.ErrorHandler.html#method.ok).
+///
+/// ```
+/// use futures_retry::{ErrorHandler, ExponentialBackoff, FutureRetry, Indirect, RetryPolicy};
+/// use std::time::Duration;
+///
+/// # #[tokio::main] async fn main() {
+/// let mut backoff = ExponentialBackoff::new(Duration::from_millis(1)).max_attempts(2);
+/// let result = FutureRetry::new(|| async { Err::<(), _>("busy") }, Indirect(&mut backoff)).await;
+/// assert_eq!(Err(("busy", 2)), result);
+/// // The handler is still around once the loop is done.
+/// assert_eq!(RetryPolicy::ForwardError("gone"), backoff.handle(2, "gone"));
+///
+/// let boxed: Box<dyn ErrorHandler<&str, OutError = &str> + Send> = Box::new(backoff);
+/// let mut handler = Indirect(boxed);
+/// assert!(matches!(handler.handle(1, "busy"), RetryPolicy::WaitRetry(_)));
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Indirect<P>(pub P);
+
+impl<InError, P> ErrorHandler<InError> for Indirect<P>
+where
+    P: DerefMut,
+    P::Target: IndirectTarget<InError>,
+{
+    type OutError = <P::Target as ErrorHandler<InError>>::OutError;
+
+    fn handle(&mut self, attempt: usize, e: InError) -> RetryPolicy<Self::OutError> {
+        self.0.deref_mut().handle(attempt, e)
+    }
+
+    fn ok(&mut self, attempt: usize) {
+        self.0.deref_mut().ok(attempt);
+    }
+
+    fn ok_with<V: ?Sized>(&mut self, attempt: usize, value: &V) {
+        self.0.deref_mut().forward_ok_with(attempt, value);
+    }
+
+    fn exhausted(&mut self, attempt: usize, error: &Self::OutError) {
+        self.0.deref_mut().exhausted(attempt, error);
+    }
+}
+
+/// A handler an [`Indirect`](struct.Indirect.html) might point to: either a handler of a known
+/// type, which gets the successful values, or a trait object, which can't.
+pub trait IndirectTarget<InError>: ErrorHandler<InError> {
+    /// Passes a successful value to the handler, or just reports the success if the handler is a
+    /// trait object.
+    fn forward_ok_with<V: ?Sized>(&mut self, attempt: usize, value: &V);
+}
+
+impl<InError, H: ErrorHandler<InError>> IndirectTarget<InError> for H {
+    fn forward_ok_with<V: ?Sized>(&mut self, attempt: usize, value: &V) {
+        self.ok_with(attempt, value);
+    }
+}
+
+macro_rules! dyn_indirect_target {
+    ($($bounds:tt)*) => {
+        impl<InError, OutError> IndirectTarget<InError>
+            for dyn ErrorHandler<InError, OutError = OutError> $($bounds)* + '_
+        {
+            fn forward_ok_with<V: ?Sized>(&mut self, attempt: usize, _value: &V) {
+                self.ok(attempt);
+            }
+        }
+    };
+}
+
+dyn_indirect_target!();
+dyn_indirect_target!(+ Send);
+dyn_indirect_target!(+ Sync);
+dyn_indirect_target!(+ Send + Sync);
+
+/// Limits a handler to `N` attempts, the limit being a compile-time constant rather than a field,
+/// so the wrapper is the size of the handler and the check compares with a literal.
+///
+/// Once the `N`th attempt has failed, the error is forwarded without consulting the inner handler.
+///
+/// ```
+/// use futures_retry::{ErrorHandler, MaxAttempts, RetryPolicy};
+///
+/// let mut handler = MaxAttempts::<3, _>::new(|_| RetryPolicy::Repeat::<&str>);
+/// assert_eq!(RetryPolicy::Repeat, handler.handle(2, "busy"));
+/// assert_eq!(RetryPolicy::ForwardError("busy"), handler.handle(3, "busy"));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct MaxAttempts<const N: usize, H>(pub H);
+
+impl<const N: usize, H> MaxAttempts<N, H> {
+    /// The number of attempts that might be made.
+    pub const LIMIT: usize = N;
+
+    /// Wraps an error handler.
+    pub fn new(inner: H) -> Self {
+        Self(inner)
+    }
+
+    /// Returns the inner handler.
+    pub fn into_inner(self) -> H {
+        self.0
+    }
+}
+
+impl<InError, const N: usize, H> ErrorHandler<InError> for MaxAttempts<N, H>
+where
+    H: ErrorHandler<InError>,
+    H::OutError: From<InError>,
+{
+    type OutError = H::OutError;
+
+    fn handle(&mut self, attempt: usize, e: InError) -> RetryPolicy<H::OutError> {
+        if attempt >= N {
+            RetryPolicy::ForwardError(e.into())
+        } else {
+            self.0.handle(attempt, e)
+        }
+    }
+
+    fn ok(&mut self, attempt: usize) {
+        self.0.ok(attempt);
+    }
+
+    fn ok_with<V: ?Sized>(&mut self, attempt: usize, value: &V) {
+        self.0.ok_with(attempt, value);
+    }
+
+    fn exhausted(&mut self, attempt: usize, error: &H::OutError) {
+        self.0.exhausted(attempt, error);
+    }
+}
+
+/// Picks one of two handlers at runtime without boxing, e.g. an aggressive or a conservative
+/// policy depending on the environment. Both handlers have to produce the same error type.
+///
+/// ```
+/// use futures::future::Either;
+/// use futures_retry::{ErrorHandler, ExponentialBackoff, RetryPolicy};
+/// use std::time::Duration;
+///
+/// let aggressive = false;
+/// let mut handler = if aggressive {
+///     Either::Left(|_| RetryPolicy::Repeat)
+/// } else {
+///     Either::Right(ExponentialBackoff::new(Duration::from_millis(100)).max_attempts(3))
+/// };
+/// assert_eq!(RetryPolicy::WaitRetry(Duration::from_millis(100)), handler.handle(1, ()));
+/// ```
+impl<InError, A, B> ErrorHandler<InError> for Either<A, B>
+where
+    A: ErrorHandler<InError>,
+    B: ErrorHandler<InError, OutError = A::OutError>,
+{
+    type OutError = A::OutError;
+
+    fn handle(&mut self, attempt: usize, e: InError) -> RetryPolicy<A::OutError> {
+        match self {
+            Either::Left(handler) => handler.handle(attempt, e),
+            Either::Right(handler) => handler.handle(attempt, e),
+        }
+    }
+
+    fn ok(&mut self, attempt: usize) {
+        match self {
+            Either::Left(handler) => handler.ok(attempt),
+            Either::Right(handler) => handler.ok(attempt),
+        }
+    }
+
+    fn ok_with<V: ?Sized>(&mut self, attempt: usize, value: &V) {
+        match self {
+            Either::Left(handler) => handler.ok_with(attempt, value),
+            Either::Right(handler) => handler.ok_with(attempt, value),
+        }
+    }
+
+    fn exhausted(&mut self, attempt: usize, error: &A::OutError) {
+        match self {
+            Either::Left(handler) => handler.exhausted(attempt, error),
+            Either::Right(handler) => handler.exhausted(attempt, error),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{AdaptiveBackoff, ErrorHistory, FutureRetry, StreamRetryExt};
+    use futures::{future::ready, stream, StreamExt, TryStreamExt};
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    /// Records the types of the success values a handler is given, passing everything through.
+    #[derive(Clone)]
+    pub(crate) struct Successes<H> {
+        inner: H,
+        seen: Arc<Mutex<Vec<(usize, &'static str)>>>,
+    }
+
+    impl<H> Successes<H> {
+        pub(crate) fn new(inner: H) -> Self {
+            Self {
+                inner,
+                seen: Arc::default(),
+            }
+        }
+
+        /// The attempts and the type names of the values seen so far.
+        pub(crate) fn seen(&self) -> Vec<(usize, &'static str)> {
+            self.seen.lock().unwrap().clone()
+        }
+    }
+
+    impl<E, H: ErrorHandler<E>> ErrorHandler<E> for Successes<H> {
+        type OutError = H::OutError;
+
+        fn handle(&mut self, attempt: usize, e: E) -> RetryPolicy<H::OutError> {
+            self.inner.handle(attempt, e)
+        }
+
+        fn ok(&mut self, attempt: usize) {
+            self.seen.lock().unwrap().push((attempt, "(no value)"));
+            self.inner.ok(attempt);
+        }
+
+        fn ok_with<V: ?Sized>(&mut self, attempt: usize, value: &V) {
+            self.seen
+                .lock()
+                .unwrap()
+                .push((attempt, std::any::type_name::<V>()));
+            self.inner.ok_with(attempt, value);
+        }
+
+        fn exhausted(&mut self, attempt: usize, error: &H::OutError) {
+            self.inner.exhausted(attempt, error);
+        }
+    }
+
+    #[tokio::test]
+    async fn indirect_passes_success_values() {
+        let mut successes = Successes::new(|_| RetryPolicy::Repeat::<u8>);
+        let mut failed = false;
+        let retry = FutureRetry::new(
+            || {
+                ready(if std::mem::replace(&mut failed, true) {
+                    Ok(7u16)
+                } else {
+                    Err(1)
+                })
+            },
+            Indirect(&mut successes),
+        );
+        assert_eq!(Ok((7, 2)), retry.await);
+        let boxed: Box<dyn ErrorHandler<u8, OutError = u8> + Send> = Box::new(successes.clone());
+        Indirect(boxed).ok_with(3, &7u16);
+        assert_eq!(vec![(2, "u16"), (3, "(no value)")], successes.seen());
+    }
+
+    #[test]
+    fn either() {
+        let backoff = AdaptiveBackoff::new(Duration::from_millis(10), Duration::from_secs(1));
+        let mut handler: Either<fn(u8) -> RetryPolicy<u8>, _> = Either::Right(backoff);
+        handler.handle(1, 0);
+        handler.ok(2);
+        match &handler {
+            Either::Right(backoff) => assert_eq!(Duration::from_millis(10), backoff.delay()),
+            Either::Left(_) => unreachable!(),
+        }
+        let mut handler: Either<_, AdaptiveBackoff> = Either::Left(RetryPolicy::ForwardError);
+        assert_eq!(RetryPolicy::ForwardError(3), handler.handle(1, 3u8));
+    }
+
+    #[tokio::test]
+    async fn passes_success_values() {
+        let successes = Successes::new(|_: u8| RetryPolicy::Repeat::<u8>);
+        let mut failed = false;
+        let retry = FutureRetry::new(
+            move || {
+                ready(if std::mem::replace(&mut failed, true) {
+                    Ok(5u8)
+                } else {
+                    Err(1)
+                })
+            },
+            successes.clone(),
+        );
+        assert_eq!(Ok((5, 2)), retry.await);
+        // Through a wrapping handler.
+        let history = ErrorHistory::new(4);
+        stream::iter(vec![Ok(vec![7u8]), Err(2), Ok(vec![9])])
+            .retry(history.handler(successes.clone()))
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        let vec = std::any::type_name::<Vec<u8>>();
+        assert_eq!(vec![(2, "u8"), (1, vec), (2, vec)], successes.seen());
+        assert_eq!(vec![2], history.errors());
+    }
+
+    #[tokio::test]
+    async fn borrowed_success_values() {
+        let text = String::from("borrowed");
+        let successes = Successes::new(|e: u8| RetryPolicy::ForwardError(e));
+        let retry = FutureRetry::new(|| ready(Ok::<_, u8>(text.as_str())), successes.clone());
+        assert_eq!(Ok(("borrowed", 1)), retry.await);
+        let items: Vec<_> = stream::iter(text.split(' ').map(Ok::<_, u8>))
+            .retry(successes.clone())
+            .map_ok(|(item, _)| item)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(vec!["borrowed"], items);
+        assert_eq!(vec![(1, "&str"), (1, "&str")], successes.seen());
+    }
+
+    #[derive(Clone, Default)]
+    struct GaveUp(Arc<Mutex<Vec<(usize, u8)>>>);
+
+    impl ErrorHandler<u8> for GaveUp {
+        type OutError = u8;
+
+        fn handle(&mut self, _attempt: usize, e: u8) -> RetryPolicy<u8> {
+            match e {
+                0 => RetryPolicy::Repeat,
+                e => RetryPolicy::ForwardError(e),
+            }
+        }
+
+        fn exhausted(&mut self, attempt: usize, error: &u8) {
+            self.0.lock().unwrap().push((attempt, *error));
+        }
+    }
+
+    #[tokio::test]
+    async fn exhausted_once_per_forwarded_error() {
+        let gave_up = GaveUp::default();
+        let mut errors = vec![0, 3].into_iter();
+        let result = FutureRetry::new(
+            move || ready(Err::<(), _>(errors.next().unwrap())),
+            gave_up.clone(),
+        )
+        .await;
+        assert_eq!(Err((3, 2)), result);
+        // Through a wrapping handler that changes the error type.
+        let results: Vec<_> = stream::iter(vec![Err(0), Err(4), Ok(()), Err(5)])
+            .retry(crate::Enriched::new(gave_up.clone()))
+            .collect()
+            .await;
+        assert_eq!(3, results.len());
+        assert_eq!(vec![(2, 3), (2, 4), (1, 5)], *gave_up.0.lock().unwrap());
+    }
+
+    #[tokio::test]
+    async fn max_attempts_const() {
+        type Limited = MaxAttempts<4, fn(u8) -> RetryPolicy<u8>>;
+        assert_eq!(
+            std::mem::size_of::<fn(u8) -> RetryPolicy<u8>>(),
+            std::mem::size_of::<Limited>()
+        );
+        let handler: Limited = MaxAttempts(|_| RetryPolicy::Repeat);
+        let result = FutureRetry::new(|| ready(Err::<(), _>(1u8)), handler).await;
+        assert_eq!(Err((1, Limited::LIMIT)), result);
+    }
+}
@@ -0,0 +1,83 @@
+//! Helpers for [`etcd-client`](https://docs.rs/etcd-client) watch streams.
+//!
+//! Available with the `etcd` feature.
+
+use crate::{ErrorHandler, ReconnectingStream};
+use etcd_client::{Error, ResponseHeader, WatchClient, WatchOptions, WatchResponse};
+use futures::{Stream, TryStreamExt};
+
+/// Watches a `key` and transparently re-establishes the watch whenever the stream fails or is
+/// closed by the server.
+///
+/// A new watch starts right after the revision of the last received event (or the revision of the
+/// last received response header if it carried no events), so no events are missed or delivered
+/// twice. A response telling that the watch has been cancelled by the server is turned into an
+/// [`Error::WatchError`], so the `error_action` is able to decide what to do, for example when the
+/// required revision has already been compacted.
+///
+/// ```no_run
+/// use futures::TryStreamExt;
+/// use futures_retry::ExponentialBackoff;
+/// use std::time::Duration;
+///
+/// # async fn run() -> Result<(), etcd_client::Error> {
+/// let client = etcd_client::Client::connect(["localhost:2379"], None).await?;
+/// let backoff = ExponentialBackoff::new(Duration::from_millis(100))
+///     .max_delay(Duration::from_secs(10))
+///     .max_attempts(10);
+/// let options = etcd_client::WatchOptions::new().with_prefix();
+/// futures_retry::etcd::watch(client.watch_client(), "/config/", Some(options), backoff)
+///     .try_for_each(|(response, _attempt)| async move {
+///         for event in response.events() {
+///             println!("{:?}", event);
+///         }
+///         Ok(())
+///     })
+///     .await
+///     .map_err(|(e, _attempt)| e)?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn watch<K, R>(
+    client: WatchClient,
+    key: K,
+    options: Option<WatchOptions>,
+    error_action: R,
+) -> impl Stream<Item = Result<(WatchResponse, usize), (R::OutError, usize)>>
+where
+    K: Into<Vec<u8>>,
+    R: ErrorHandler<Error>,
+{
+    let key = key.into();
+    let options = options.unwrap_or_default();
+    ReconnectingStream::resuming(
+        move |revision: Option<&i64>| {
+            let mut client = client.clone();
+            let key = key.clone();
+            let options = match revision {
+                Some(revision) => options.clone().with_start_revision(revision + 1),
+                None => options.clone(),
+            };
+            async move {
+                let stream = client.watch(key, Some(options)).await?;
+                Ok::<_, Error>(stream.and_then(|response| async move {
+                    if response.canceled() {
+                        Err(Error::WatchError(response.cancel_reason().to_owned()))
+                    } else {
+                        Ok(response)
+                    }
+                }))
+            }
+        },
+        |response: &WatchResponse| {
+            response
+                .events()
+                .iter()
+                .filter_map(|event| event.kv())
+                .map(|kv| kv.mod_revision())
+                .max()
+                .or_else(|| response.header().map(ResponseHeader::revision))
+        },
+        error_action,
+    )
+}
@@ -0,0 +1,270 @@
+use crate::FutureFactory;
+use futures::{ready, TryFuture};
+use pin_project_lite::pin_project;
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    task::{Context, Poll},
+};
+
+/// A factory that spreads the attempts over several endpoints, e.g. the replicas of a service,
+/// so a retry fails over to another endpoint while a single error handler governs the delays and
+/// when to give up.
+///
+/// Every attempt goes to the next endpoint in turn, and clones of the factory share the rotation.
+/// By default the rotation simply continues from where it has stopped (round-robin); with
+/// [`ordered`](#method.ordered) the endpoints are tried in the order of preference, and once an
+/// attempt succeeds the next one starts over with the first endpoint. A factory created with
+/// [`weighted`](#method.weighted) picks the endpoints at random instead, in proportion to their
+/// weights, e.g. to match the capacities of the backends; the generator they are picked with might
+/// be [`seed`](#method.seed)ed, so the same endpoints are picked on every run.
+///
+/// ```
+/// use futures::future::ready;
+/// use futures_retry::{Failover, FutureRetry, RetryPolicy};
+///
+/// # #[tokio::main] async fn main() {
+/// let replicas = vec!["primary", "replica-1", "replica-2"];
+/// let factory = Failover::new(replicas, |endpoint: &&str| {
+///     ready(match *endpoint {
+///         "replica-2" => Ok(endpoint.to_string()),
+///         _ => Err("connection refused"),
+///     })
+/// })
+/// .ordered();
+/// let retry = FutureRetry::new(factory, |_| RetryPolicy::Repeat::<&str>);
+/// assert_eq!(Ok(("replica-2".to_owned(), 3)), retry.await);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Failover<E, F> {
+    endpoints: Vec<E>,
+    make: F,
+    ordered: bool,
+    // The cumulative weights of the endpoints, if they are picked at random.
+    weights: Option<Vec<u64>>,
+    rng: Arc<Mutex<fastrand::Rng>>,
+    next: Arc<AtomicUsize>,
+}
+
+impl<E, F> Failover<E, F> {
+    /// Creates a factory that makes the attempts with `make`, rotating over the `endpoints`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are no endpoints.
+    pub fn new(endpoints: Vec<E>, make: F) -> Self {
+        assert!(!endpoints.is_empty(), "No endpoints to fail over between");
+        Self {
+            endpoints,
+            make,
+            ordered: false,
+            weights: None,
+            rng: Arc::new(Mutex::new(fastrand::Rng::new())),
+            next: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Creates a factory that makes the attempts with `make`, sending each one to an endpoint
+    /// picked at random, in proportion to the weights: an endpoint of weight 2 gets twice as many
+    /// attempts as one of weight 1, and one of weight 0 gets none. The order of preference of
+    /// [`ordered`](#method.ordered) doesn't apply to the weighted endpoints.
+    ///
+    /// ```
+    /// use futures::future::ready;
+    /// use futures_retry::{Failover, FutureRetry, RetryPolicy};
+    ///
+    /// # #[tokio::main] async fn main() {
+    /// let backends = vec![("large", 3), ("small", 1), ("draining", 0)];
+    /// let connect = |backend: &&'static str| ready(Ok::<_, ()>(*backend));
+    /// let factory = Failover::weighted(backends, connect);
+    /// let (backend, _) = FutureRetry::new(factory, |_| RetryPolicy::Repeat::<()>).await.unwrap();
+    /// assert_ne!("draining", backend);
+    /// # }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are no endpoints, or if all the weights are zero.
+    pub fn weighted(endpoints: Vec<(E, u32)>, make: F) -> Self {
+        let mut total = 0;
+        let (endpoints, weights): (Vec<_>, Vec<_>) = endpoints
+            .into_iter()
+            .map(|(endpoint, weight)| {
+                total += u64::from(weight);
+                (endpoint, total)
+            })
+            .unzip();
+        assert!(
+            total > 0,
+            "No endpoints with non-zero weights to fail over between"
+        );
+        let mut failover = Self::new(endpoints, make);
+        failover.weights = Some(weights);
+        failover.pick_next();
+        failover
+    }
+
+    /// Seeds the generator that picks the weighted endpoints, so the same endpoints are picked on
+    /// every run, the first one included.
+    pub fn seed(self, seed: u64) -> Self {
+        self.rng(fastrand::Rng::with_seed(seed))
+    }
+
+    /// Sets the generator that picks the weighted endpoints. Clones of the factory share it.
+    pub fn rng(mut self, rng: fastrand::Rng) -> Self {
+        self.rng = Arc::new(Mutex::new(rng));
+        self.pick_next();
+        self
+    }
+
+    /// Tries the endpoints in the order of preference, starting over with the first one after a
+    /// success.
+    pub fn ordered(mut self) -> Self {
+        self.ordered = true;
+        self
+    }
+
+    /// Returns the endpoint the next attempt is going to be made to.
+    pub fn next_endpoint(&self) -> &E {
+        &self.endpoints[self.next.load(Ordering::Relaxed) % self.endpoints.len()]
+    }
+
+    /// Picks the endpoint of the next attempt by the weights, if there are any, returning the
+    /// previously picked one.
+    fn pick_next(&self) -> usize {
+        let weights = match &self.weights {
+            Some(weights) => weights,
+            None => return self.next.load(Ordering::Relaxed),
+        };
+        let total = weights.last().copied().unwrap_or(0);
+        let point = self
+            .rng
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .u64(..total);
+        let index = weights.partition_point(|&weight| weight <= point);
+        self.next.swap(index, Ordering::Relaxed)
+    }
+}
+
+impl<E: fmt::Debug, F> fmt::Debug for Failover<E, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Failover")
+            .field("endpoints", &self.endpoints)
+            .field("ordered", &self.ordered)
+            .field("weighted", &self.weights.is_some())
+            .field("next_endpoint", self.next_endpoint())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<E, F, Fut> FutureFactory for Failover<E, F>
+where
+    F: FnMut(&E) -> Fut,
+    Fut: TryFuture,
+{
+    type FutureItem = FailoverFuture<Fut>;
+
+    fn new(&mut self) -> Self::FutureItem {
+        let index = match &self.weights {
+            // The next endpoint is picked in advance, so `next_endpoint` can tell it.
+            Some(_) => self.pick_next(),
+            None => self.next.fetch_add(1, Ordering::Relaxed) % self.endpoints.len(),
+        };
+        FailoverFuture {
+            future: (self.make)(&self.endpoints[index]),
+            reset: if self.ordered && self.weights.is_none() {
+                Some(Arc::clone(&self.next))
+            } else {
+                None
+            },
+        }
+    }
+}
+
+pin_project! {
+    /// An attempt made by a [`Failover`](struct.Failover.html) factory.
+    pub struct FailoverFuture<Fut> {
+        #[pin]
+        future: Fut,
+        reset: Option<Arc<AtomicUsize>>,
+    }
+}
+
+impl<Fut> fmt::Debug for FailoverFuture<Fut> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FailoverFuture").finish_non_exhaustive()
+    }
+}
+
+impl<Fut: TryFuture> Future for FailoverFuture<Fut> {
+    type Output = Result<Fut::Ok, Fut::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.project();
+        let result = ready!(this.future.try_poll(cx));
+        if let (Ok(_), Some(next)) = (&result, this.reset) {
+            next.store(0, Ordering::Relaxed);
+        }
+        Poll::Ready(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FutureRetry, RetryPolicy};
+    use futures::future::{ready, Ready};
+
+    fn up_on(up: u8) -> impl FnMut(&u8) -> Ready<Result<u8, u8>> + Clone {
+        move |endpoint| {
+            ready(if *endpoint == up {
+                Ok(*endpoint)
+            } else {
+                Err(*endpoint)
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn rotates() {
+        // The clones share the rotation.
+        let factory = Failover::new(vec![1, 2, 3], up_on(2));
+        let retry = FutureRetry::new(factory.clone(), |_| RetryPolicy::Repeat::<u8>);
+        assert_eq!(Ok((2, 2)), retry.await);
+        // Round-robin continues with the endpoint after the one that has succeeded.
+        assert_eq!(3, *factory.next_endpoint());
+        let ordered = Failover::new(vec![1, 2, 3], up_on(2)).ordered();
+        let retry = FutureRetry::new(ordered.clone(), |_| RetryPolicy::Repeat::<u8>);
+        assert_eq!(Ok((2, 2)), retry.await);
+        assert_eq!(1, *ordered.next_endpoint());
+    }
+
+    #[test]
+    fn picks_by_seeded_weights() {
+        let picks = |seed| {
+            let mut factory = Failover::weighted(vec![(0, 3), (1, 0), (2, 1)], |e: &usize| {
+                ready(Ok::<_, ()>(*e))
+            })
+            .seed(seed);
+            (0..12)
+                .map(|_| {
+                    let expected = *factory.next_endpoint();
+                    let attempt = futures::executor::block_on(factory.new()).unwrap();
+                    assert_eq!(expected, attempt);
+                    attempt
+                })
+                .collect::<Vec<_>>()
+        };
+        // The zero-weighted endpoint is never picked, the first one about three times as often as
+        // the last one.
+        assert_eq!(picks(42), [2, 0, 2, 0, 0, 0, 2, 0, 0, 0, 0, 2]);
+        assert_eq!(picks(42), picks(42));
+    }
+}
@@ -0,0 +1,140 @@
+use crate::FutureFactory;
+use futures::{
+    future::{ready, Either, IntoFuture, Ready},
+    TryFuture, TryFutureExt,
+};
+
+type FactoryResult<F> = Result<
+    <<F as FutureFactory>::FutureItem as TryFuture>::Ok,
+    <<F as FutureFactory>::FutureItem as TryFuture>::Error,
+>;
+
+/// A factory adapter that injects synthetic failures, to check how the retry logic copes with an
+/// outage without touching the network.
+///
+/// An injected failure doesn't call the wrapped factory at all: the attempt fails right away with
+/// an error made by `make_error`, which the error handler sees as any other error. Failures might
+/// be injected at random with a [`probability`](#method.probability), in
+/// [`bursts`](#method.bursts), or both.
+///
+/// ```
+/// use futures_retry::{FaultInjector, FutureRetry, RetryPolicy};
+///
+/// # #[tokio::main] async fn main() {
+/// let factory = FaultInjector::new(
+///     || async { Ok::<_, std::io::Error>("response") },
+///     || std::io::Error::from(std::io::ErrorKind::ConnectionReset),
+/// )
+/// .bursts(5, 3);
+/// let retry = FutureRetry::new(factory, |_| RetryPolicy::Repeat::<std::io::Error>);
+/// assert_eq!(("response", 4), retry.await.unwrap());
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct FaultInjector<F, G> {
+    factory: F,
+    make_error: G,
+    probability: f64,
+    bursts: Option<(usize, usize)>,
+    rng: fastrand::Rng,
+    calls: usize,
+    injected: usize,
+}
+
+impl<F, G> FaultInjector<F, G> {
+    /// Wraps a `factory`. No failures are injected until they are configured.
+    pub fn new(factory: F, make_error: G) -> Self {
+        Self {
+            factory,
+            make_error,
+            probability: 0.,
+            bursts: None,
+            rng: fastrand::Rng::new(),
+            calls: 0,
+            injected: 0,
+        }
+    }
+
+    /// Fails every attempt with the given probability, between `0` and `1`.
+    pub fn probability(mut self, probability: f64) -> Self {
+        self.probability = probability;
+        self
+    }
+
+    /// Fails the first `length` attempts out of every `period` ones, e.g. `bursts(10, 3)` fails
+    /// attempts 1-3, 11-13 and so on.
+    pub fn bursts(mut self, period: usize, length: usize) -> Self {
+        self.bursts = Some((period.max(1), length));
+        self
+    }
+
+    /// Seeds the random number generator, so the same attempts fail on every run.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.rng = fastrand::Rng::with_seed(seed);
+        self
+    }
+
+    /// Returns how many failures have been injected so far.
+    pub fn injected(&self) -> usize {
+        self.injected
+    }
+
+    fn should_fail(&mut self) -> bool {
+        let call = self.calls;
+        self.calls += 1;
+        let burst = matches!(self.bursts, Some((period, length)) if call % period < length);
+        burst || (self.probability > 0. && self.rng.f64() < self.probability)
+    }
+}
+
+impl<F, G> FutureFactory for FaultInjector<F, G>
+where
+    F: FutureFactory,
+    G: FnMut() -> <F::FutureItem as TryFuture>::Error,
+{
+    type FutureItem = Either<Ready<FactoryResult<F>>, IntoFuture<F::FutureItem>>;
+
+    fn new(&mut self) -> Self::FutureItem {
+        if self.should_fail() {
+            self.injected += 1;
+            Either::Left(ready(Err((self.make_error)())))
+        } else {
+            Either::Right(self.factory.new().into_future())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+
+    fn outcomes<F, G>(injector: &mut FaultInjector<F, G>, n: usize) -> Vec<bool>
+    where
+        FaultInjector<F, G>: FutureFactory,
+    {
+        (0..n)
+            .map(|_| injector.new().into_future().now_or_never().unwrap().is_ok())
+            .collect()
+    }
+
+    #[test]
+    fn bursts() {
+        let mut injector = FaultInjector::new(|| ready(Ok::<_, ()>(())), || ()).bursts(3, 2);
+        assert_eq!(
+            vec![false, false, true, false, false, true, false],
+            outcomes(&mut injector, 7)
+        );
+        assert_eq!(5, injector.injected());
+    }
+
+    #[test]
+    fn seeded_probability() {
+        let injector = FaultInjector::new(|| ready(Ok::<_, ()>(())), || ()).probability(0.5);
+        let mut first = injector.clone().seed(3);
+        let mut second = injector.seed(3);
+        let first = outcomes(&mut first, 50);
+        assert_eq!(first, outcomes(&mut second, 50));
+        assert!(first.contains(&true) && first.contains(&false));
+    }
+}
@@ -1,13 +1,19 @@
-use crate::{ErrorHandler, RetryPolicy};
-use futures::{ready, TryFuture};
+use crate::{
+    sleeper::{poll_timer, wait_until},
+    Classifier, ErrorHandler, FutureRetryBuilder, Jitter, RetryPolicy, RetrySnapshot, RetryStatus,
+    Sleeper, TokioSleeper, WithStats, WrapError,
+};
+use futures::{ready, Stream, TryFuture};
 use pin_project_lite::pin_project;
 use std::{
+    fmt,
     future::Future,
     marker::Unpin,
     pin::Pin,
     task::{Context, Poll},
+    time::Duration,
 };
-use tokio::time;
+use tokio::time::Instant;
 
 /// A factory trait used to create futures.
 ///
@@ -22,6 +28,7 @@ pub trait FutureFactory {
 
     /// Creates a new future. We don't need the factory to be immutable so we pass `self` as a
     /// mutable reference.
+    #[allow(clippy::wrong_self_convention)]
     fn new(&mut self) -> Self::FutureItem;
 }
 
@@ -46,24 +53,26 @@ pin_project! {
     /// connections, RPC calls.
     ///
     /// There is also a type to handle `Stream` errors: [`StreamRetry`](struct.StreamRetry.html).#[pin_project]
-    pub struct FutureRetry<F, R>
+    pub struct FutureRetry<F, R, S = TokioSleeper>
     where
         F: FutureFactory,
+        S: Sleeper,
     {
         factory: F,
         error_action: R,
+        sleeper: S,
         attempt: usize,
         #[pin]
-        state: RetryState<F::FutureItem>,
+        state: RetryState<F::FutureItem, S::Sleep>,
     }
 }
 
 pin_project! {
     #[project = RetryStateProj]
-    enum RetryState<F> {
+    enum RetryState<F, D> {
         NotStarted,
         WaitingForFuture { #[pin] future: F },
-        TimerActive { #[pin] delay: time::Sleep },
+        TimerActive { #[pin] delay: Option<D>, since: Instant, wait: Duration },
     }
 }
 
@@ -78,19 +87,229 @@ impl<F: FutureFactory, R> FutureRetry<F, R> {
     ///
     /// * `factory`: a factory that creates futures,
     /// * `error_action`: a type that handles an error and decides which route to take: simply
-    ///                   try again, wait and then try, or give up (on a critical error for
-    ///                   exapmle).
+    ///   try again, wait and then try, or give up (on a critical error for
+    ///   exapmle).
     pub fn new(factory: F, error_action: R) -> Self {
+        Self::with_sleeper(factory, error_action, TokioSleeper)
+    }
+
+    /// Creates a `FutureRetry` that continues a retry loop from a
+    /// [`RetrySnapshot`](struct.RetrySnapshot.html), e.g. after a process restart: the attempts
+    /// are counted from the snapshot's one, and the first attempt is made only when the
+    /// snapshot's next retry is due.
+    pub fn resume(factory: F, error_action: R, snapshot: &RetrySnapshot) -> Self {
+        let sleeper = TokioSleeper;
+        let state = match snapshot.next_retry {
+            Some(_) => RetryState::TimerActive {
+                since: sleeper.now(),
+                wait: snapshot.remaining_delay(),
+                delay: None,
+            },
+            None => RetryState::NotStarted,
+        };
+        Self {
+            factory,
+            error_action,
+            sleeper,
+            state,
+            attempt: snapshot.attempt.max(1),
+        }
+    }
+}
+
+impl<F: FutureFactory> FutureRetry<F, ()> {
+    /// Starts building a `FutureRetry` with a
+    /// [`FutureRetryBuilder`](struct.FutureRetryBuilder.html), for the loops that need more than
+    /// an error handler, e.g. attempt timeouts, a deadline or an observer.
+    pub fn builder(factory: F) -> FutureRetryBuilder<F> {
+        FutureRetryBuilder::new(factory)
+    }
+}
+
+impl<F: FutureFactory, B> FutureRetry<F, Classifier<B>> {
+    /// Creates a `FutureRetry` that retries the transient errors (as told by a
+    /// [`Classifier`](struct.Classifier.html)) with a backoff strategy, e.g. an
+    /// [`ExponentialBackoff`](struct.ExponentialBackoff.html), and forwards the others right away.
+    ///
+    /// ```
+    /// use futures_retry::{ExponentialBackoff, FutureRetry};
+    /// use std::{io, time::Duration};
+    ///
+    /// # #[tokio::main] async fn main() {
+    /// let backoff = ExponentialBackoff::new(Duration::from_millis(10)).max_attempts(3);
+    /// let result = FutureRetry::with_backoff(
+    ///     || async { Err::<(), _>(io::Error::from(io::ErrorKind::ConnectionRefused)) },
+    ///     backoff,
+    /// )
+    /// .await;
+    /// assert!(matches!(result, Err((_, 3))));
+    /// # }
+    /// ```
+    pub fn with_backoff(factory: F, backoff: B) -> Self {
+        Self::new(factory, Classifier::new(backoff))
+    }
+}
+
+impl<F: FutureFactory, R, S: Sleeper> FutureRetry<F, R, S> {
+    /// Like a `new` method, but the delays between the attempts are timed by a custom
+    /// [`Sleeper`](trait.Sleeper.html).
+    pub fn with_sleeper(factory: F, error_action: R, sleeper: S) -> Self {
         Self {
             factory,
             error_action,
+            sleeper,
             state: RetryState::NotStarted,
             attempt: 1,
         }
     }
+
+    /// Delays the first attempt, e.g. for a reconnect loop that is created right after a known
+    /// failure. The wait is reported by [`state`](#method.state) and the other introspection
+    /// methods the same way as a wait for a retry; if the loop is already waiting, e.g. one
+    /// [`resume`](#method.resume)d from a snapshot, the longer wait is kept. The timer is only
+    /// created on the first poll, so the loop might be built outside of a runtime.
+    ///
+    /// ```
+    /// use futures_retry::{FutureRetry, RetryPolicy, RetryStatus};
+    /// use std::time::Duration;
+    ///
+    /// # #[tokio::main] async fn main() {
+    /// let connect = || async { Ok::<_, ()>("connected") };
+    /// let retry = FutureRetry::new(connect, RetryPolicy::ForwardError)
+    ///     .initial_delay(Duration::from_millis(10));
+    /// assert_eq!(RetryStatus::BackingOff, retry.state());
+    /// assert_eq!(Ok(("connected", 1)), retry.await);
+    /// # }
+    /// ```
+    pub fn initial_delay(mut self, delay: Duration) -> Self {
+        let now = self.sleeper.now();
+        if self
+            .next_retry_at()
+            .is_none_or(|at| at < wait_until(now, delay))
+        {
+            self.state = RetryState::TimerActive {
+                since: now,
+                wait: delay,
+                delay: None,
+            };
+        }
+        self
+    }
+
+    /// Delays the first attempt by a random time within the `window`, so the clients that all
+    /// start at the same moment (after a deploy, say) don't hit a server at once. Like
+    /// [`initial_delay`](#method.initial_delay), with the delay picked with a
+    /// [`Jitter::Full`](enum.Jitter.html#variant.Full).
+    pub fn start_splay(self, window: Duration) -> Self {
+        self.initial_delay(Jitter::Full.apply(window))
+    }
+
+    /// Like [`start_splay`](#method.start_splay), but the delay is drawn from the given random
+    /// number generator, so a seeded generator yields a reproducible start.
+    pub fn start_splay_with(self, window: Duration, rng: &mut fastrand::Rng) -> Self {
+        self.initial_delay(Jitter::Full.apply_with(window, rng))
+    }
+
+    /// Resolves into the bare value on success, or into a [`RetryError`](struct.RetryError.html)
+    /// carrying the final error, the number of attempts and the time spent on them.
+    pub fn wrap_error(self) -> WrapError<Self> {
+        WrapError::new(self)
+    }
+
+    /// Resolves into the outcome of the loop along with [`RetryStats`](struct.RetryStats.html),
+    /// which tell how long the loop has really waited before each retry.
+    pub fn with_stats(self) -> WithStats<F, R, S> {
+        WithStats::new(self)
+    }
+
+    /// Turns the retry loop into a stream that yields the outcome of every attempt as it happens,
+    /// ending after a success or once the handler gives up.
+    ///
+    /// ```
+    /// use futures::{future::ready, StreamExt};
+    /// use futures_retry::{AttemptFailed, FutureRetry, RetryPolicy};
+    /// use std::time::Duration;
+    ///
+    /// # #[tokio::main] async fn main() {
+    /// let mut results = vec![Err("busy"), Ok("done")].into_iter();
+    /// let attempts: Vec<_> = FutureRetry::new(
+    ///     || ready(results.next().unwrap()),
+    ///     |_| RetryPolicy::WaitRetry::<&str>(Duration::from_millis(10)),
+    /// )
+    /// .into_attempt_stream()
+    /// .collect()
+    /// .await;
+    /// let retrying = AttemptFailed::Retrying { attempt: 1, delay: Duration::from_millis(10) };
+    /// assert_eq!(vec![Err(retrying), Ok(("done", 2))], attempts);
+    /// # }
+    /// ```
+    pub fn into_attempt_stream(self) -> AttemptStream<F, R, S> {
+        AttemptStream {
+            factory: self.factory,
+            error_action: self.error_action,
+            sleeper: self.sleeper,
+            attempt: self.attempt,
+            done: false,
+            state: self.state,
+        }
+    }
+
+    /// Returns the number of the current attempt, i.e. of the one that is running, or of the one
+    /// that the loop is waiting for.
+    pub fn attempt(&self) -> usize {
+        self.attempt
+    }
+
+    /// Returns what the loop is busy with: nothing (before the first poll or once resolved), an
+    /// attempt, or waiting for a retry.
+    pub fn state(&self) -> RetryStatus {
+        match self.state {
+            RetryState::NotStarted => RetryStatus::Idle,
+            RetryState::WaitingForFuture { .. } => RetryStatus::InFlight,
+            RetryState::TimerActive { .. } => RetryStatus::BackingOff,
+        }
+    }
+
+    /// Returns when the next attempt is going to be made, if the loop is currently waiting for a
+    /// retry.
+    pub fn next_retry_at(&self) -> Option<Instant> {
+        match &self.state {
+            RetryState::TimerActive { since, wait, .. } => Some(wait_until(*since, *wait)),
+            _ => None,
+        }
+    }
+
+    /// Returns how much time is left until the next attempt, if the loop is currently waiting for
+    /// a retry.
+    pub fn time_until_next_retry(&self) -> Option<Duration> {
+        self.next_retry_at()
+            .map(|at| at.saturating_duration_since(self.sleeper.now()))
+    }
+
+    /// Returns when the current wait for a retry has started and how long it is.
+    pub(crate) fn backoff_window(&self) -> Option<(Instant, Duration)> {
+        match &self.state {
+            RetryState::TimerActive { since, wait, .. } => Some((*since, *wait)),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn now(&self) -> Instant {
+        self.sleeper.now()
+    }
 }
 
-impl<F: FutureFactory, R> Future for FutureRetry<F, R>
+impl<F: FutureFactory, R, S: Sleeper> fmt::Debug for FutureRetry<F, R, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FutureRetry")
+            .field("attempt", &self.attempt)
+            .field("state", &self.state())
+            .field("next_retry_at", &self.next_retry_at())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F: FutureFactory, R, S: Sleeper> Future for FutureRetry<F, R, S>
 where
     R: ErrorHandler<<F::FutureItem as TryFuture>::Error>,
 {
@@ -99,33 +318,51 @@ where
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
         loop {
-            let this = self.as_mut().project();
+            let mut this = self.as_mut().project();
             let attempt = *this.attempt;
-            let new_state = match this.state.project() {
+            let new_state = match this.state.as_mut().project() {
                 RetryStateProj::NotStarted => RetryState::WaitingForFuture {
                     future: this.factory.new(),
                 },
-                RetryStateProj::TimerActive { delay } => {
-                    ready!(delay.poll(cx));
+                RetryStateProj::TimerActive { delay, since, wait } => {
+                    ready!(poll_timer(
+                        delay,
+                        wait_until(*since, *wait),
+                        this.sleeper,
+                        cx
+                    ));
                     RetryState::WaitingForFuture {
                         future: this.factory.new(),
                     }
                 }
                 RetryStateProj::WaitingForFuture { future } => match ready!(future.try_poll(cx)) {
                     Ok(x) => {
-                        this.error_action.ok(attempt);
+                        this.state.set(RetryState::NotStarted);
+                        this.error_action.ok_with(attempt, &x);
                         *this.attempt = 1;
                         return Poll::Ready(Ok((x, attempt)));
                     }
                     Err(e) => {
-                        *this.attempt += 1;
-                        match this.error_action.handle(attempt, e) {
-                            RetryPolicy::ForwardError(e) => return Poll::Ready(Err((e, attempt))),
-                            RetryPolicy::Repeat => RetryState::WaitingForFuture {
-                                future: this.factory.new(),
-                            },
-                            RetryPolicy::WaitRetry(duration) => RetryState::TimerActive {
-                                delay: time::sleep(duration),
+                        let policy = this.error_action.handle(attempt, e);
+                        *this.attempt = policy.next_attempt(attempt);
+                        match policy {
+                            RetryPolicy::ForwardError(e) => {
+                                this.error_action.exhausted(attempt, &e);
+                                this.state.set(RetryState::NotStarted);
+                                return Poll::Ready(Err((e, attempt)));
+                            }
+                            RetryPolicy::Repeat | RetryPolicy::RepeatWithoutCounting => {
+                                RetryState::WaitingForFuture {
+                                    future: this.factory.new(),
+                                }
+                            }
+                            RetryPolicy::WaitRetry(duration)
+                            | RetryPolicy::RetryAs {
+                                delay: duration, ..
+                            } => RetryState::TimerActive {
+                                since: this.sleeper.now(),
+                                wait: duration,
+                                delay: Some(this.sleeper.sleep(duration)),
                             },
                         }
                     }
@@ -137,14 +374,131 @@ where
     }
 }
 
+/// A failed attempt reported by an [`AttemptStream`](struct.AttemptStream.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttemptFailed<E> {
+    /// The attempt has failed and another one is going to be made after the `delay`.
+    Retrying {
+        /// The number of the failed attempt.
+        attempt: usize,
+        /// The delay before the next attempt; zero when it is made right away.
+        delay: Duration,
+    },
+    /// The attempt has failed and the handler has given up.
+    GaveUp {
+        /// The forwarded error.
+        error: E,
+        /// The number of the failed attempt.
+        attempt: usize,
+    },
+}
+
+pin_project! {
+    /// A stream of the outcomes of the attempts made by a retry loop.
+    ///
+    /// Created by
+    /// [`FutureRetry::into_attempt_stream`](struct.FutureRetry.html#method.into_attempt_stream).
+    pub struct AttemptStream<F, R, S = TokioSleeper>
+    where
+        F: FutureFactory,
+        S: Sleeper,
+    {
+        factory: F,
+        error_action: R,
+        sleeper: S,
+        attempt: usize,
+        done: bool,
+        #[pin]
+        state: RetryState<F::FutureItem, S::Sleep>,
+    }
+}
+
+impl<F: FutureFactory, R, S: Sleeper> fmt::Debug for AttemptStream<F, R, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AttemptStream")
+            .field("attempt", &self.attempt)
+            .field("done", &self.done)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F: FutureFactory, R, S: Sleeper> Stream for AttemptStream<F, R, S>
+where
+    R: ErrorHandler<<F::FutureItem as TryFuture>::Error>,
+{
+    type Item = Result<(<F::FutureItem as TryFuture>::Ok, usize), AttemptFailed<R::OutError>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        if *this.done {
+            return Poll::Ready(None);
+        }
+        loop {
+            let attempt = *this.attempt;
+            match this.state.as_mut().project() {
+                RetryStateProj::NotStarted => {}
+                RetryStateProj::TimerActive { delay, since, wait } => {
+                    ready!(poll_timer(
+                        delay,
+                        wait_until(*since, *wait),
+                        this.sleeper,
+                        cx
+                    ))
+                }
+                RetryStateProj::WaitingForFuture { future } => {
+                    let result = ready!(future.try_poll(cx));
+                    this.state.set(RetryState::NotStarted);
+                    let failed = match result {
+                        Ok(x) => {
+                            *this.done = true;
+                            this.error_action.ok_with(attempt, &x);
+                            return Poll::Ready(Some(Ok((x, attempt))));
+                        }
+                        Err(e) => {
+                            let policy = this.error_action.handle(attempt, e);
+                            *this.attempt = policy.next_attempt(attempt);
+                            match policy {
+                                RetryPolicy::ForwardError(error) => {
+                                    this.error_action.exhausted(attempt, &error);
+                                    *this.done = true;
+                                    AttemptFailed::GaveUp { error, attempt }
+                                }
+                                RetryPolicy::Repeat | RetryPolicy::RepeatWithoutCounting => {
+                                    AttemptFailed::Retrying {
+                                        attempt,
+                                        delay: Duration::ZERO,
+                                    }
+                                }
+                                RetryPolicy::WaitRetry(delay)
+                                | RetryPolicy::RetryAs { delay, .. } => {
+                                    this.state.set(RetryState::TimerActive {
+                                        since: this.sleeper.now(),
+                                        wait: delay,
+                                        delay: Some(this.sleeper.sleep(delay)),
+                                    });
+                                    AttemptFailed::Retrying { attempt, delay }
+                                }
+                            }
+                        }
+                    };
+                    return Poll::Ready(Some(Err(failed)));
+                }
+            }
+            this.state.set(RetryState::WaitingForFuture {
+                future: this.factory.new(),
+            });
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::error_handler::tests::Successes;
     use futures::{
         future::{err, ok},
         TryFutureExt,
     };
-    use std::time::Duration;
 
     /// Just a help type for the tests.
     struct FutureIterator<F>(F);
@@ -159,6 +513,7 @@ mod tests {
         /// # Warning
         ///
         /// Will panic if there is no *next* future.
+        #[allow(clippy::new_ret_no_self)]
         fn new(&mut self) -> Self::FutureItem {
             self.0.next().expect("No more futures!")
         }
@@ -201,4 +556,112 @@ mod tests {
         );
         assert_eq!(Err((2u8, 1)), f.await);
     }
+
+    #[tokio::test]
+    async fn repeat_without_counting() {
+        let factory = FutureIterator(vec![err(0u8), err(1), err(0), ok(3u8)].into_iter());
+        let f = FutureRetry::new(factory, |e| match e {
+            0 => RetryPolicy::RepeatWithoutCounting,
+            _ => RetryPolicy::Repeat::<u8>,
+        });
+        assert_eq!(Ok((3, 2)), f.await);
+    }
+
+    #[tokio::test]
+    async fn introspection() {
+        let f = FutureRetry::new(FutureIterator(vec![err(2u8), ok(3u8)].into_iter()), |_| {
+            RetryPolicy::WaitRetry::<u8>(Duration::from_millis(100))
+        });
+        futures::pin_mut!(f);
+        assert_eq!((1, None), (f.attempt(), f.time_until_next_retry()));
+        assert_eq!(RetryStatus::Idle, f.state());
+        assert!(futures::poll!(f.as_mut()).is_pending());
+        assert_eq!((2, RetryStatus::BackingOff), (f.attempt(), f.state()));
+        assert!(f.time_until_next_retry().unwrap() <= Duration::from_millis(100));
+        assert_eq!(Ok((3, 2)), f.as_mut().await);
+        assert_eq!(RetryStatus::Idle, f.state());
+    }
+
+    #[tokio::test]
+    async fn attempt_stream() {
+        use futures::StreamExt;
+        let factory = FutureIterator(vec![err(1u8), err(2), err(3)].into_iter());
+        let attempts: Vec<_> = FutureRetry::new(factory, |e| match e {
+            1 => RetryPolicy::Repeat,
+            e => RetryPolicy::ForwardError(e),
+        })
+        .into_attempt_stream()
+        .collect()
+        .await;
+        assert_eq!(
+            vec![
+                Err::<((), usize), _>(AttemptFailed::Retrying {
+                    attempt: 1,
+                    delay: Duration::ZERO
+                }),
+                Err(AttemptFailed::GaveUp {
+                    error: 2,
+                    attempt: 2
+                }),
+            ],
+            attempts
+        );
+    }
+
+    #[tokio::test]
+    async fn attempt_stream_passes_success_values() {
+        use futures::StreamExt;
+        let successes = Successes::new(|_| RetryPolicy::Repeat::<u8>);
+        let factory = FutureIterator(vec![err(1u8), ok("done")].into_iter());
+        let attempts: Vec<_> = FutureRetry::new(factory, successes.clone())
+            .into_attempt_stream()
+            .collect()
+            .await;
+        assert_eq!(2, attempts.len());
+        assert_eq!(vec![(2, "&str")], successes.seen());
+    }
+
+    #[tokio::test]
+    async fn initial_delay_keeps_longer_wait() {
+        let started = Instant::now();
+        let f = FutureRetry::new(|| ok::<_, u8>(1u8), RetryPolicy::ForwardError)
+            .initial_delay(Duration::from_millis(50))
+            .initial_delay(Duration::from_millis(10));
+        assert!(f.time_until_next_retry().unwrap() > Duration::from_millis(10));
+        assert_eq!(Ok((1, 1)), f.await);
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn initial_delay_outside_of_runtime() {
+        let f = FutureRetry::new(|| ok::<_, u8>(1u8), RetryPolicy::ForwardError)
+            .initial_delay(Duration::from_millis(10));
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()
+            .unwrap();
+        assert_eq!(Ok((1, 1)), runtime.block_on(f));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn seeded_start_splay() {
+        let window = Duration::from_secs(60);
+        let splayed = |seed| {
+            FutureRetry::new(|| ok::<_, u8>(1u8), RetryPolicy::ForwardError)
+                .start_splay_with(window, &mut fastrand::Rng::with_seed(seed))
+        };
+        let splay = Jitter::Full.apply_with(window, &mut fastrand::Rng::with_seed(3));
+        assert_eq!(Some(splay), splayed(3).time_until_next_retry());
+        assert_eq!(Ok((1, 1)), splayed(3).await);
+    }
+
+    #[test]
+    fn debug() {
+        let f = FutureRetry::new(|| ok::<_, u8>(1u8), |_: u8| RetryPolicy::Repeat::<u8>);
+        assert_eq!(
+            "FutureRetry { attempt: 1, state: Idle, next_retry_at: None, .. }",
+            format!("{:?}", f)
+        );
+    }
 }
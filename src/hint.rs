@@ -0,0 +1,156 @@
+use crate::{AttemptError, ErrorHandler, RetryPolicy};
+use std::time::Duration;
+
+/// An error that suggests how long to wait before retrying, e.g. an HTTP 429 response with a
+/// `Retry-After` header or an SMTP 4xx reply.
+pub trait RetryHint {
+    /// Returns the suggested delay, if any.
+    fn retry_after(&self) -> Option<Duration>;
+}
+
+impl<T: RetryHint + ?Sized> RetryHint for &T {
+    fn retry_after(&self) -> Option<Duration> {
+        (**self).retry_after()
+    }
+}
+
+impl<T: RetryHint + ?Sized> RetryHint for Box<T> {
+    fn retry_after(&self) -> Option<Duration> {
+        (**self).retry_after()
+    }
+}
+
+impl<T: RetryHint + ?Sized> RetryHint for std::sync::Arc<T> {
+    fn retry_after(&self) -> Option<Duration> {
+        (**self).retry_after()
+    }
+}
+
+impl<E: RetryHint> RetryHint for AttemptError<E> {
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            AttemptError::TimedOut(_) => None,
+            AttemptError::Failed(e) => e.retry_after(),
+        }
+    }
+}
+
+/// An error handler that prefers the delays hinted by the errors over the schedule of an inner
+/// handler, e.g. an [`ExponentialBackoff`](struct.ExponentialBackoff.html).
+///
+/// The inner handler still decides whether to retry at all: when it forwards an error, the hint
+/// is ignored. Otherwise a hinted delay replaces the inner one, so a `Repeat` policy might turn
+/// into a `WaitRetry`. The hints might be capped with a [`max_hint`](#method.max_hint), so a
+/// misbehaving server can't stall the client for too long.
+///
+/// ```
+/// use futures_retry::{ErrorHandler, ExponentialBackoff, Hinted, RetryHint, RetryPolicy};
+/// use std::time::Duration;
+///
+/// struct TooManyRequests(Option<Duration>);
+///
+/// impl RetryHint for TooManyRequests {
+///     fn retry_after(&self) -> Option<Duration> {
+///         self.0
+///     }
+/// }
+///
+/// let mut handler = Hinted::new(ExponentialBackoff::new(Duration::from_millis(100)))
+///     .max_hint(Duration::from_secs(30));
+/// let hinted = TooManyRequests(Some(Duration::from_secs(5)));
+/// assert!(matches!(handler.handle(1, hinted), RetryPolicy::WaitRetry(d) if d.as_secs() == 5));
+/// let plain = TooManyRequests(None);
+/// assert!(matches!(handler.handle(2, plain), RetryPolicy::WaitRetry(d) if d.as_millis() == 200));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Hinted<H> {
+    inner: H,
+    max_hint: Option<Duration>,
+}
+
+impl<H> Hinted<H> {
+    /// Wraps an error handler.
+    pub fn new(inner: H) -> Self {
+        Self {
+            inner,
+            max_hint: None,
+        }
+    }
+
+    /// Sets an upper limit for the hinted delays.
+    pub fn max_hint(mut self, max_hint: Duration) -> Self {
+        self.max_hint = Some(max_hint);
+        self
+    }
+}
+
+impl<E, H> ErrorHandler<E> for Hinted<H>
+where
+    E: RetryHint,
+    H: ErrorHandler<E>,
+{
+    type OutError = H::OutError;
+
+    fn handle(&mut self, attempt: usize, e: E) -> RetryPolicy<H::OutError> {
+        let hint = e.retry_after();
+        let hint = hint.map(|hint| match self.max_hint {
+            Some(max_hint) => hint.min(max_hint),
+            None => hint,
+        });
+        match (self.inner.handle(attempt, e), hint) {
+            (RetryPolicy::ForwardError(e), _) => RetryPolicy::ForwardError(e),
+            (RetryPolicy::RetryAs { attempt, .. }, Some(delay)) => {
+                RetryPolicy::RetryAs { attempt, delay }
+            }
+            (_, Some(hint)) => RetryPolicy::WaitRetry(hint),
+            (policy, None) => policy,
+        }
+    }
+
+    fn ok(&mut self, attempt: usize) {
+        self.inner.ok(attempt);
+    }
+
+    fn ok_with<V: ?Sized>(&mut self, attempt: usize, value: &V) {
+        self.inner.ok_with(attempt, value);
+    }
+
+    fn exhausted(&mut self, attempt: usize, error: &Self::OutError) {
+        self.inner.exhausted(attempt, error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Hint(Option<u64>);
+
+    impl RetryHint for Hint {
+        fn retry_after(&self) -> Option<Duration> {
+            self.0.map(Duration::from_secs)
+        }
+    }
+
+    #[test]
+    fn hints() {
+        let mut handler = Hinted::new(|e: Hint| match e.0 {
+            Some(0) => RetryPolicy::ForwardError(()),
+            _ => RetryPolicy::Repeat,
+        })
+        .max_hint(Duration::from_secs(10));
+        assert_eq!(
+            RetryPolicy::WaitRetry(Duration::from_secs(3)),
+            handler.handle(1, Hint(Some(3)))
+        );
+        assert_eq!(
+            RetryPolicy::WaitRetry(Duration::from_secs(10)),
+            handler.handle(2, Hint(Some(60)))
+        );
+        assert_eq!(RetryPolicy::Repeat, handler.handle(3, Hint(None)));
+        assert_eq!(
+            RetryPolicy::ForwardError(()),
+            handler.handle(4, Hint(Some(0)))
+        );
+    }
+}
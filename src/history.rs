@@ -0,0 +1,188 @@
+use crate::{ErrorHandler, RetryPolicy};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+#[derive(Debug)]
+struct Ring<E> {
+    errors: VecDeque<E>,
+    cap: usize,
+    total: usize,
+}
+
+/// A shared log of the errors seen by a retry loop, for diagnostics.
+///
+/// The log is a ring of a fixed capacity: the buffer is allocated once, upfront, and the oldest
+/// error is dropped to make room for a new one, so recording doesn't allocate however many errors
+/// a long-lived stream runs into. The errors are cloned into the log, so for the errors that are
+/// expensive to clone consider retrying with `Arc<E>` errors, which are shared instead.
+///
+/// Clones share the same log, so one clone might be given to a retry loop (through
+/// [`handler`](#method.handler)) while another one is used to inspect it.
+///
+/// ```
+/// use futures_retry::{ErrorHistory, RetryPolicy, StreamRetryExt};
+/// use futures::{stream, TryStreamExt};
+/// use std::sync::Arc;
+///
+/// # #[tokio::main] async fn main() {
+/// let history = ErrorHistory::new(2);
+/// let items = stream::iter(vec![Err("a"), Ok(1), Err("b"), Err("c"), Ok(2)])
+///     .map_err(Arc::new)
+///     .retry(history.handler(|_| RetryPolicy::Repeat::<()>))
+///     .try_collect::<Vec<_>>()
+///     .await
+///     .unwrap();
+/// assert_eq!(vec![(1, 2), (2, 3)], items);
+/// assert_eq!(vec![Arc::new("b"), Arc::new("c")], history.errors());
+/// assert_eq!(3, history.total());
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ErrorHistory<E> {
+    ring: Arc<Mutex<Ring<E>>>,
+}
+
+impl<E> Clone for ErrorHistory<E> {
+    fn clone(&self) -> Self {
+        Self {
+            ring: Arc::clone(&self.ring),
+        }
+    }
+}
+
+impl<E> ErrorHistory<E> {
+    /// Creates a log that keeps only the last `cap` errors.
+    pub fn new(cap: usize) -> Self {
+        Self {
+            ring: Arc::new(Mutex::new(Ring {
+                errors: VecDeque::with_capacity(cap),
+                cap,
+                total: 0,
+            })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Ring<E>> {
+        self.ring.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Wraps an error handler so the errors it handles are recorded in this log.
+    pub fn handler<H>(&self, inner: H) -> HistoryHandler<H, E> {
+        HistoryHandler {
+            inner,
+            history: self.clone(),
+        }
+    }
+
+    /// Records an error.
+    pub fn push(&self, e: E) {
+        let mut ring = self.lock();
+        ring.total += 1;
+        if ring.cap == 0 {
+            return;
+        }
+        if ring.errors.len() == ring.cap {
+            ring.errors.pop_front();
+        }
+        ring.errors.push_back(e);
+    }
+
+    /// Returns the errors kept in the log, the oldest first.
+    pub fn errors(&self) -> Vec<E>
+    where
+        E: Clone,
+    {
+        self.lock().errors.iter().cloned().collect()
+    }
+
+    /// Returns the last recorded error.
+    pub fn last(&self) -> Option<E>
+    where
+        E: Clone,
+    {
+        self.lock().errors.back().cloned()
+    }
+
+    /// Returns how many errors are kept in the log.
+    pub fn len(&self) -> usize {
+        self.lock().errors.len()
+    }
+
+    /// Checks whether the log keeps no errors.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns how many errors have been recorded in total, the dropped ones included.
+    pub fn total(&self) -> usize {
+        self.lock().total
+    }
+
+    /// Drops all the kept errors; the total count is kept.
+    pub fn clear(&self) {
+        self.lock().errors.clear();
+    }
+}
+
+/// An error handler that records the errors in an [`ErrorHistory`](struct.ErrorHistory.html)
+/// before passing them to an inner one.
+///
+/// Created by [`ErrorHistory::handler`](struct.ErrorHistory.html#method.handler).
+#[derive(Debug, Clone)]
+pub struct HistoryHandler<H, E> {
+    inner: H,
+    history: ErrorHistory<E>,
+}
+
+impl<E, H> ErrorHandler<E> for HistoryHandler<H, E>
+where
+    E: Clone,
+    H: ErrorHandler<E>,
+{
+    type OutError = H::OutError;
+
+    fn handle(&mut self, attempt: usize, e: E) -> RetryPolicy<H::OutError> {
+        self.history.push(e.clone());
+        self.inner.handle(attempt, e)
+    }
+
+    fn ok(&mut self, attempt: usize) {
+        self.inner.ok(attempt);
+    }
+
+    fn ok_with<V: ?Sized>(&mut self, attempt: usize, value: &V) {
+        self.inner.ok_with(attempt, value);
+    }
+
+    fn exhausted(&mut self, attempt: usize, error: &Self::OutError) {
+        self.inner.exhausted(attempt, error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_does_not_grow() {
+        let history = ErrorHistory::new(3);
+        let capacity = history.lock().errors.capacity();
+        (0..3).for_each(|e| history.push(e));
+        assert_eq!(vec![0, 1, 2], history.errors());
+        // Past the capacity the oldest errors are evicted.
+        (3..100).for_each(|e| history.push(e));
+        assert_eq!(vec![97, 98, 99], history.errors());
+        assert_eq!(capacity, history.lock().errors.capacity());
+        assert_eq!((Some(99), 100), (history.last(), history.total()));
+    }
+
+    #[test]
+    fn zero_cap() {
+        let history = ErrorHistory::new(0);
+        history.push(1);
+        assert!(history.is_empty());
+        assert_eq!(1, history.total());
+    }
+}
@@ -0,0 +1,211 @@
+use crate::FutureFactory;
+use futures::TryFuture;
+use std::{
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::Arc,
+};
+
+/// A marker for the requests that are safe to send more than once, required by
+/// [`retry_request`](fn.retry_request.html).
+///
+/// Reads and the writes that replace a whole resource are usually idempotent, while e.g. a
+/// payment or an appended row is not, unless it carries an
+/// [`IdempotencyKey`](struct.IdempotencyKey.html), which is why a request paired with a key is
+/// idempotent. A request that is known to be unsafe to retry but should be retried anyway might
+/// be wrapped with [`allow_non_idempotent`](fn.allow_non_idempotent.html), so the decision is
+/// explicit in the code.
+pub trait Idempotent {}
+
+impl<T: Idempotent + ?Sized> Idempotent for &T {}
+
+impl<T: Idempotent + ?Sized> Idempotent for Box<T> {}
+
+impl<T: Idempotent + ?Sized> Idempotent for Arc<T> {}
+
+impl<T> Idempotent for (IdempotencyKey, T) {}
+
+/// A request that is retried even though it is not idempotent.
+///
+/// Created by [`allow_non_idempotent`](fn.allow_non_idempotent.html); dereferences to the request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NonIdempotent<T>(pub T);
+
+impl<T> NonIdempotent<T> {
+    /// Returns the request.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for NonIdempotent<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for NonIdempotent<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T> Idempotent for NonIdempotent<T> {}
+
+/// Marks a request as one to retry even though it is not [`Idempotent`](trait.Idempotent.html),
+/// an explicit escape hatch for the cases the caller has checked, e.g. when a server deduplicates
+/// the requests on its own.
+pub fn allow_non_idempotent<T>(request: T) -> NonIdempotent<T> {
+    NonIdempotent(request)
+}
+
+/// A key that identifies a logical operation, as opposed to an attempt to perform it, so a server
+/// might recognize a retried write (e.g. a payment) and apply it only once.
+///
+/// A new key is a random UUID (version 4) string, which is what most APIs expect in their
+/// `Idempotency-Key` headers; a key might also be restored from a string, e.g. after a restart.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct IdempotencyKey(String);
+
+impl IdempotencyKey {
+    /// Generates a random key.
+    pub fn new() -> Self {
+        let bits = fastrand::u128(..);
+        // Set the version (4) and the variant (RFC 4122) bits.
+        let bits = (bits & !(0xf << 76) | (0x4 << 76)) & !(0x3 << 62) | (0x2 << 62);
+        let hex = format!("{:032x}", bits);
+        Self(format!(
+            "{}-{}-{}-{}-{}",
+            &hex[..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..]
+        ))
+    }
+
+    /// Returns the key as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Default for IdempotencyKey {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<String> for IdempotencyKey {
+    fn from(key: String) -> Self {
+        Self(key)
+    }
+}
+
+impl From<&str> for IdempotencyKey {
+    fn from(key: &str) -> Self {
+        Self(key.to_owned())
+    }
+}
+
+impl fmt::Display for IdempotencyKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A factory that passes the same [`IdempotencyKey`](struct.IdempotencyKey.html) to every
+/// attempt, so the retried requests carry the key of the operation.
+///
+/// ```
+/// use futures_retry::{FutureRetry, IdempotencyKey, KeyedFactory, RetryPolicy};
+///
+/// async fn charge(key: IdempotencyKey, cents: u64) -> Result<IdempotencyKey, &'static str> {
+///     // Send the `key` in the `Idempotency-Key` header...
+///     Ok(key)
+/// }
+///
+/// # #[tokio::main] async fn main() {
+/// let factory = KeyedFactory::new(|key: &IdempotencyKey| charge(key.clone(), 1000));
+/// let key = factory.key().clone();
+/// let (charged, _) = FutureRetry::new(factory, |_| RetryPolicy::Repeat::<&str>).await.unwrap();
+/// assert_eq!(key, charged);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct KeyedFactory<F> {
+    key: IdempotencyKey,
+    factory: F,
+}
+
+impl<F> KeyedFactory<F> {
+    /// Wraps a factory, generating a new key for the operation.
+    pub fn new(factory: F) -> Self {
+        Self::with_key(IdempotencyKey::new(), factory)
+    }
+
+    /// Wraps a factory, reusing a key, e.g. one persisted before a restart.
+    pub fn with_key(key: IdempotencyKey, factory: F) -> Self {
+        Self { key, factory }
+    }
+
+    /// Returns the key of the operation.
+    pub fn key(&self) -> &IdempotencyKey {
+        &self.key
+    }
+}
+
+impl<F> fmt::Debug for KeyedFactory<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("KeyedFactory")
+            .field("key", &self.key)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F, Fut> FutureFactory for KeyedFactory<F>
+where
+    F: FnMut(&IdempotencyKey) -> Fut,
+    Fut: TryFuture,
+{
+    type FutureItem = Fut;
+
+    #[allow(clippy::new_ret_no_self)]
+    fn new(&mut self) -> Fut {
+        (self.factory)(&self.key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FutureRetry, RetryPolicy};
+    use futures::future::ready;
+
+    #[test]
+    fn uuid_format() {
+        let key = IdempotencyKey::new();
+        let groups: Vec<_> = key.as_str().split('-').map(str::len).collect();
+        assert_eq!(vec![8, 4, 4, 4, 12], groups);
+        assert_eq!(Some('4'), key.as_str().chars().nth(14));
+        assert!(matches!(
+            key.as_str().chars().nth(19),
+            Some('8'..='9' | 'a'..='b')
+        ));
+        assert_ne!(key, IdempotencyKey::new());
+    }
+
+    #[tokio::test]
+    async fn same_key_for_every_attempt() {
+        let mut keys = Vec::new();
+        let factory = KeyedFactory::with_key("order-42".into(), |key: &IdempotencyKey| {
+            keys.push(key.to_string());
+            ready(if keys.len() < 3 { Err(()) } else { Ok(()) })
+        });
+        let result = FutureRetry::new(factory, |_| RetryPolicy::Repeat::<()>).await;
+        assert_eq!(Ok(((), 3)), result);
+        assert_eq!(vec!["order-42"; 3], keys);
+    }
+}
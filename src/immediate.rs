@@ -0,0 +1,205 @@
+use crate::{ErrorHandler, FutureFactory, RetryPolicy};
+use futures::{ready, TryFuture};
+use pin_project_lite::pin_project;
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+pin_project! {
+    /// A leaner [`FutureRetry`](struct.FutureRetry.html) for the handlers that never wait between
+    /// the attempts, e.g. the ones retrying an in-memory operation.
+    ///
+    /// The future has no timer slot in its state at all, so it is as large as the attempt it runs
+    /// plus the factory and the handler. The handler must not ask to wait: a `WaitRetry` or a
+    /// `RetryAs` policy is only accepted with a zero delay, and a longer one is a bug, which is
+    /// caught by a debug assertion (and the next attempt is made right away in the release
+    /// builds).
+    ///
+    /// ```
+    /// use futures_retry::{ImmediateRetry, RetryPolicy};
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    ///
+    /// # #[tokio::main] async fn main() {
+    /// let version = AtomicUsize::new(0);
+    /// let retry = ImmediateRetry::new(
+    ///     || {
+    ///         let seen = version.load(Ordering::SeqCst);
+    ///         let result = version
+    ///             .compare_exchange(seen, seen + 1, Ordering::SeqCst, Ordering::SeqCst)
+    ///             .map(|_| seen + 1);
+    ///         futures::future::ready(result)
+    ///     },
+    ///     |_| RetryPolicy::Repeat::<usize>,
+    /// );
+    /// assert_eq!(Ok((1, 1)), retry.await);
+    /// # }
+    /// ```
+    pub struct ImmediateRetry<F, R>
+    where
+        F: FutureFactory,
+    {
+        factory: F,
+        error_action: R,
+        attempt: usize,
+        #[pin]
+        future: Option<F::FutureItem>,
+    }
+}
+
+impl<F: FutureFactory, R> ImmediateRetry<F, R> {
+    /// Creates an `ImmediateRetry` using a provided factory and an error handler, just like
+    /// [`FutureRetry::new`](struct.FutureRetry.html#method.new).
+    pub fn new(factory: F, error_action: R) -> Self {
+        Self {
+            factory,
+            error_action,
+            attempt: 1,
+            future: None,
+        }
+    }
+
+    /// Returns the number of the current attempt.
+    pub fn attempt(&self) -> usize {
+        self.attempt
+    }
+}
+
+impl<F: FutureFactory, R> fmt::Debug for ImmediateRetry<F, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ImmediateRetry")
+            .field("attempt", &self.attempt)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F: FutureFactory, R> Future for ImmediateRetry<F, R>
+where
+    R: ErrorHandler<<F::FutureItem as TryFuture>::Error>,
+{
+    type Output =
+        Result<(<<F as FutureFactory>::FutureItem as TryFuture>::Ok, usize), (R::OutError, usize)>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut this = self.project();
+        loop {
+            let future = match this.future.as_mut().as_pin_mut() {
+                Some(future) => future,
+                None => {
+                    this.future.set(Some(this.factory.new()));
+                    continue;
+                }
+            };
+            let attempt = *this.attempt;
+            match ready!(future.try_poll(cx)) {
+                Ok(x) => {
+                    this.future.set(None);
+                    this.error_action.ok_with(attempt, &x);
+                    *this.attempt = 1;
+                    return Poll::Ready(Ok((x, attempt)));
+                }
+                Err(e) => {
+                    this.future.set(None);
+                    let policy = this.error_action.handle(attempt, e);
+                    *this.attempt = policy.next_attempt(attempt);
+                    match policy {
+                        RetryPolicy::ForwardError(e) => {
+                            this.error_action.exhausted(attempt, &e);
+                            return Poll::Ready(Err((e, attempt)));
+                        }
+                        RetryPolicy::Repeat | RetryPolicy::RepeatWithoutCounting => {}
+                        RetryPolicy::WaitRetry(delay) | RetryPolicy::RetryAs { delay, .. } => {
+                            debug_assert!(
+                                delay.is_zero(),
+                                "ImmediateRetry can't wait {:?} before a retry",
+                                delay
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handler::tests::Successes;
+    use crate::FutureRetry;
+    use futures::future::{ready, Ready};
+    use std::{mem::size_of, time::Duration};
+
+    fn failing(errors: u8) -> impl FnMut() -> Ready<Result<(), u8>> {
+        let mut errors = errors + 1;
+        move || {
+            errors -= 1;
+            ready(if errors == 0 { Ok(()) } else { Err(errors) })
+        }
+    }
+
+    #[tokio::test]
+    async fn zero_waits_are_repeats() {
+        let retry =
+            ImmediateRetry::new(failing(2), |_| RetryPolicy::WaitRetry::<u8>(Duration::ZERO));
+        assert_eq!(Ok(((), 3)), retry.await);
+    }
+
+    #[tokio::test]
+    async fn counts_like_future_retry() {
+        let retry = ImmediateRetry::new(failing(2), |_| RetryPolicy::RepeatWithoutCounting::<u8>);
+        assert_eq!(Ok(((), 1)), retry.await);
+        let retry = ImmediateRetry::new(failing(2), |e| RetryPolicy::RetryAs::<u8> {
+            attempt: 10 * e as usize,
+            delay: Duration::ZERO,
+        });
+        // The second error is the last one, and moves the attempt to 10.
+        assert_eq!(Ok(((), 10)), retry.await);
+    }
+
+    #[cfg(debug_assertions)]
+    #[tokio::test]
+    #[should_panic(expected = "ImmediateRetry can't wait 1s before a retry")]
+    async fn rejects_waits() {
+        let retry = ImmediateRetry::new(failing(1), |_| {
+            RetryPolicy::WaitRetry::<u8>(Duration::from_secs(1))
+        });
+        let _ = retry.await;
+    }
+
+    #[cfg(debug_assertions)]
+    #[tokio::test]
+    #[should_panic(expected = "ImmediateRetry can't wait 1s before a retry")]
+    async fn rejects_delayed_retry_as() {
+        let retry = ImmediateRetry::new(failing(1), |_| RetryPolicy::RetryAs::<u8> {
+            attempt: 1,
+            delay: Duration::from_secs(1),
+        });
+        let _ = retry.await;
+    }
+
+    #[tokio::test]
+    async fn forwards() {
+        let retry = ImmediateRetry::new(|| ready(Err::<(), _>(1)), RetryPolicy::ForwardError);
+        assert_eq!(Err((1, 1)), retry.await);
+    }
+
+    #[tokio::test]
+    async fn passes_success_values() {
+        let successes = Successes::new(|_| RetryPolicy::Repeat::<u8>);
+        let retry = ImmediateRetry::new(|| ready(Ok::<_, u8>(7u64)), successes.clone());
+        assert_eq!(Ok((7, 1)), retry.await);
+        assert_eq!(vec![(1, "u64")], successes.seen());
+    }
+    #[test]
+    fn smaller_than_future_retry() {
+        type Factory = fn() -> Ready<Result<(), ()>>;
+        type Handler = fn(()) -> RetryPolicy<()>;
+        assert!(
+            size_of::<ImmediateRetry<Factory, Handler>>()
+                < size_of::<FutureRetry<Factory, Handler>>()
+        );
+    }
+}
@@ -0,0 +1,66 @@
+use crate::{ErrorHandler, FutureFactory, FutureRetry};
+use futures::{
+    future::{try_join_all, TryJoinAll},
+    TryFuture,
+};
+
+/// Runs a retry loop for each of the factories, all of them concurrently and each with its own
+/// clone of the `error_action`, resolving into all the values (along with the attempt numbers) in
+/// the order of the factories.
+///
+/// As soon as any loop gives up its error is returned and the rest of the loops are dropped.
+///
+/// ```
+/// use futures::future::ready;
+/// use futures_retry::{join_all_with_retry, ExponentialBackoff};
+/// use std::time::Duration;
+///
+/// # #[tokio::main] async fn main() {
+/// let backoff = ExponentialBackoff::new(Duration::from_millis(1)).max_attempts(3);
+/// let shards = (0..3).map(|shard| move || ready(Ok::<_, ()>(shard * 10)));
+/// let results = join_all_with_retry(shards, backoff).await;
+/// assert_eq!(Ok(vec![(0, 1), (10, 1), (20, 1)]), results);
+/// # }
+/// ```
+pub fn join_all_with_retry<I, R>(
+    factories: I,
+    error_action: R,
+) -> TryJoinAll<FutureRetry<I::Item, R>>
+where
+    I: IntoIterator,
+    I::Item: FutureFactory,
+    R: ErrorHandler<<<I::Item as FutureFactory>::FutureItem as TryFuture>::Error> + Clone,
+{
+    try_join_all(
+        factories
+            .into_iter()
+            .map(|factory| FutureRetry::new(factory, error_action.clone())),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RetryPolicy;
+    use futures::future::{ready, Ready};
+
+    #[tokio::test]
+    async fn retries_each_leg() {
+        let legs = (0..3u8).map(|leg| {
+            let mut calls = 0;
+            move || -> Ready<Result<u8, u8>> {
+                calls += 1;
+                ready(if calls > usize::from(leg) {
+                    Ok(leg)
+                } else {
+                    Err(leg)
+                })
+            }
+        });
+        let results = join_all_with_retry(legs, |_| RetryPolicy::Repeat::<u8>).await;
+        assert_eq!(Ok(vec![(0, 1), (1, 2), (2, 3)]), results);
+        let legs = vec![|| ready(Ok::<u8, u8>(1)), || ready(Err(2))];
+        let result = join_all_with_retry(legs, RetryPolicy::ForwardError).await;
+        assert_eq!(Err((2, 1)), result);
+    }
+}
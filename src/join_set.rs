@@ -0,0 +1,157 @@
+use crate::{ErrorHandler, FutureFactory, FutureRetry};
+use futures::{stream::FuturesUnordered, Stream, StreamExt, TryFuture};
+use std::{
+    collections::VecDeque,
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+type JobError<F> = <<F as FutureFactory>::FutureItem as TryFuture>::Error;
+
+/// A pool of jobs that are run concurrently, each one in its own retry loop.
+///
+/// Every job is a [`FutureFactory`](trait.FutureFactory.html) and gets its own clone of the error
+/// handler, so a failed job is retried (with a backoff, for example) without holding up the rest
+/// of the pool and only surfaces once its handler gives up. With a [`limit`](#method.limit) at
+/// most that many jobs are run (or waiting for a retry) at once, the rest are queued.
+///
+/// The set is a `Stream` of the outcomes of the jobs in the order they finish. Like
+/// `FuturesUnordered`, the stream ends whenever there are no jobs left, and it can be polled again
+/// once new jobs are pushed. Unlike a [`RetryManager`](struct.RetryManager.html) the jobs are not
+/// keyed.
+///
+/// ```
+/// use futures::{future::ready, StreamExt};
+/// use futures_retry::{RetryPolicy, RetryingJoinSet};
+///
+/// # #[tokio::main] async fn main() {
+/// let mut set = RetryingJoinSet::new(|_| RetryPolicy::Repeat::<()>).limit(2);
+/// for job in 0..4 {
+///     set.push(move || ready(Ok::<_, ()>(job)));
+/// }
+/// let mut results: Vec<_> = set.map(|result| result.unwrap().0).collect().await;
+/// results.sort_unstable();
+/// assert_eq!(vec![0, 1, 2, 3], results);
+/// # }
+/// ```
+pub struct RetryingJoinSet<F: FutureFactory, R> {
+    error_action: R,
+    running: FuturesUnordered<FutureRetry<F, R>>,
+    queued: VecDeque<F>,
+    limit: Option<usize>,
+    waker: Option<Waker>,
+}
+
+// Nothing is pinned structurally: the retry loops live in a `FuturesUnordered`, which keeps them
+// on the heap.
+impl<F: FutureFactory, R> Unpin for RetryingJoinSet<F, R> {}
+
+impl<F: FutureFactory, R> fmt::Debug for RetryingJoinSet<F, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RetryingJoinSet")
+            .field("running", &self.running.len())
+            .field("queued", &self.queued.len())
+            .field("limit", &self.limit)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F: FutureFactory, R: Clone> RetryingJoinSet<F, R> {
+    /// Creates an empty set that gives a clone of the `error_action` to every job.
+    pub fn new(error_action: R) -> Self {
+        Self {
+            error_action,
+            running: FuturesUnordered::new(),
+            queued: VecDeque::new(),
+            limit: None,
+            waker: None,
+        }
+    }
+
+    /// Sets how many jobs might be run at once.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit.max(1));
+        self
+    }
+
+    /// Adds a job. It is started on the next poll, unless the limit is reached.
+    pub fn push(&mut self, job: F) {
+        self.queued.push_back(job);
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Returns the number of jobs, both running and queued.
+    pub fn len(&self) -> usize {
+        self.running.len() + self.queued.len()
+    }
+
+    /// Checks whether there are no jobs at all.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Waits for the next job to finish. Returns `None` if there are no jobs left.
+    pub async fn join_next(&mut self) -> Option<<Self as Stream>::Item>
+    where
+        R: ErrorHandler<JobError<F>>,
+    {
+        self.next().await
+    }
+
+    fn start_queued(&mut self) {
+        while self.limit.is_none_or(|limit| self.running.len() < limit) {
+            match self.queued.pop_front() {
+                Some(job) => self
+                    .running
+                    .push(FutureRetry::new(job, self.error_action.clone())),
+                None => break,
+            }
+        }
+    }
+}
+
+impl<F, R> Stream for RetryingJoinSet<F, R>
+where
+    F: FutureFactory,
+    R: ErrorHandler<JobError<F>> + Clone,
+{
+    type Item = <FutureRetry<F, R> as Future>::Output;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        this.waker = Some(cx.waker().clone());
+        this.start_queued();
+        this.running.poll_next_unpin(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RetryPolicy;
+    use futures::future::{ready, Ready};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn retries_per_job() {
+        let mut set = RetryingJoinSet::new(|e: u8| match e {
+            0 => RetryPolicy::WaitRetry(Duration::from_millis(10)),
+            e => RetryPolicy::ForwardError(e),
+        })
+        .limit(1);
+        let flaky = |mut results: Vec<Result<u8, u8>>| {
+            move || -> Ready<Result<u8, u8>> { ready(results.remove(0)) }
+        };
+        set.push(flaky(vec![Err(0), Ok(1)]));
+        set.push(flaky(vec![Err(2)]));
+        assert_eq!(2, set.len());
+        assert_eq!(Some(Ok((1, 2))), set.join_next().await);
+        assert_eq!(Some(Err((2, 1))), set.join_next().await);
+        assert_eq!(None, set.join_next().await);
+        assert!(set.is_empty());
+    }
+}
@@ -0,0 +1,262 @@
+use crate::{ErrorHandler, RetryPolicy};
+use std::{collections::HashMap, fmt, hash::Hash, io, time::Duration};
+
+/// An error that falls into one of a few kinds, like an `io::Error` does, so the errors might be
+/// handled per kind by [`KindRules`](struct.KindRules.html).
+pub trait HasKind {
+    /// The kind of the error.
+    type Kind: PartialEq;
+
+    /// Returns the kind of the error.
+    fn kind(&self) -> Self::Kind;
+}
+
+impl HasKind for io::Error {
+    type Kind = io::ErrorKind;
+
+    fn kind(&self) -> io::ErrorKind {
+        io::Error::kind(self)
+    }
+}
+
+/// What [`KindRules`](struct.KindRules.html) does with the errors of a kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KindAction {
+    /// Retry right away, as long as the inner handler would retry at all.
+    Repeat,
+    /// Forward the error.
+    Forward,
+    /// Let the inner handler decide, the same as for the errors of the kinds with no rules.
+    Backoff,
+}
+
+/// An error handler that picks a [`KindAction`](enum.KindAction.html) by the kind of an error,
+/// and passes the errors of the other kinds to an inner handler.
+///
+/// The rules are usually declared with the [`policy!`](macro.policy.html) macro.
+///
+/// ```
+/// use futures_retry::{ErrorHandler, ExponentialBackoff, KindAction, KindRules, RetryPolicy};
+/// use std::{io, time::Duration};
+///
+/// let mut handler = KindRules::new(ExponentialBackoff::new(Duration::from_millis(10)))
+///     .on(io::ErrorKind::Interrupted, KindAction::Repeat)
+///     .on(io::ErrorKind::PermissionDenied, KindAction::Forward);
+/// let interrupted = io::Error::from(io::ErrorKind::Interrupted);
+/// assert!(matches!(handler.handle(1, interrupted), RetryPolicy::Repeat));
+/// let reset = io::Error::from(io::ErrorKind::ConnectionReset);
+/// assert!(matches!(handler.handle(2, reset), RetryPolicy::WaitRetry(_)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct KindRules<K, H> {
+    inner: H,
+    rules: Vec<(K, KindAction)>,
+}
+
+impl<K, H> KindRules<K, H> {
+    /// Wraps an error handler, with no rules yet.
+    pub fn new(inner: H) -> Self {
+        Self {
+            inner,
+            rules: Vec::new(),
+        }
+    }
+
+    /// Adds a rule for the errors of a kind. The earlier rules take precedence.
+    pub fn on(mut self, kind: K, action: KindAction) -> Self {
+        self.rules.push((kind, action));
+        self
+    }
+
+    /// Returns a reference to the inner handler.
+    pub fn get_ref(&self) -> &H {
+        &self.inner
+    }
+}
+
+impl<E, H> ErrorHandler<E> for KindRules<E::Kind, H>
+where
+    E: HasKind,
+    H: ErrorHandler<E>,
+    H::OutError: From<E>,
+{
+    type OutError = H::OutError;
+
+    fn handle(&mut self, attempt: usize, e: E) -> RetryPolicy<H::OutError> {
+        let kind = e.kind();
+        let action = self
+            .rules
+            .iter()
+            .find(|(rule, _)| *rule == kind)
+            .map_or(KindAction::Backoff, |(_, action)| *action);
+        match action {
+            KindAction::Forward => RetryPolicy::ForwardError(e.into()),
+            KindAction::Backoff => self.inner.handle(attempt, e),
+            // The inner handler still enforces its limits, only the delay is skipped.
+            KindAction::Repeat => match self.inner.handle(attempt, e) {
+                RetryPolicy::WaitRetry(_) => RetryPolicy::Repeat,
+                RetryPolicy::RetryAs { attempt, .. } => RetryPolicy::RetryAs {
+                    attempt,
+                    delay: Duration::ZERO,
+                },
+                policy => policy,
+            },
+        }
+    }
+
+    fn ok(&mut self, attempt: usize) {
+        self.inner.ok(attempt);
+    }
+
+    fn ok_with<V: ?Sized>(&mut self, attempt: usize, value: &V) {
+        self.inner.ok_with(attempt, value);
+    }
+
+    fn exhausted(&mut self, attempt: usize, error: &Self::OutError) {
+        self.inner.exhausted(attempt, error);
+    }
+}
+
+/// An error handler that counts the consecutive failures of each class of errors on its own, so
+/// e.g. a long series of `WouldBlock`s doesn't mask an escalating series of `ConnectionReset`s,
+/// nor the other way round.
+///
+/// The inner handler is given the number of the failures of the class the error belongs to (as
+/// told by the `classify` function) instead of the number of the attempt. A success resets all the
+/// counters. Created by
+/// [`StreamRetryExt::retry_per_kind`](trait.StreamRetryExt.html#method.retry_per_kind) as well.
+///
+/// ```
+/// use futures_retry::{ErrorHandler, ExponentialBackoff, KindCounters, RetryPolicy};
+/// use std::{io, time::Duration};
+///
+/// let backoff = ExponentialBackoff::new(Duration::from_millis(10)).max_attempts(3);
+/// let mut handler = KindCounters::new(io::Error::kind, backoff);
+/// for attempt in 1..=10 {
+///     let would_block = io::Error::from(io::ErrorKind::WouldBlock);
+///     assert!(matches!(handler.handle(attempt, would_block), RetryPolicy::WaitRetry(_)));
+///     handler.ok(attempt);
+/// }
+/// let reset = || io::Error::from(io::ErrorKind::ConnectionReset);
+/// assert!(matches!(handler.handle(1, reset()), RetryPolicy::WaitRetry(_)));
+/// let would_block = io::Error::from(io::ErrorKind::WouldBlock);
+/// assert!(matches!(handler.handle(2, would_block), RetryPolicy::WaitRetry(_)));
+/// // The second reset in a row waits twice as long as the first one.
+/// assert!(matches!(
+///     handler.handle(3, reset()),
+///     RetryPolicy::WaitRetry(delay) if delay == Duration::from_millis(20)
+/// ));
+/// assert_eq!(2, handler.failures(&io::ErrorKind::ConnectionReset));
+/// ```
+#[derive(Clone)]
+pub struct KindCounters<C, K, H> {
+    classify: C,
+    inner: H,
+    failures: HashMap<K, usize>,
+}
+
+impl<C, K, H> KindCounters<C, K, H> {
+    /// Wraps an error handler, telling the classes of the errors apart with `classify`.
+    pub fn new(classify: C, inner: H) -> Self {
+        Self {
+            classify,
+            inner,
+            failures: HashMap::new(),
+        }
+    }
+
+    /// Returns the number of the consecutive failures of a class.
+    pub fn failures(&self, class: &K) -> usize
+    where
+        K: Eq + Hash,
+    {
+        self.failures.get(class).copied().unwrap_or(0)
+    }
+
+    /// Returns a reference to the inner handler.
+    pub fn get_ref(&self) -> &H {
+        &self.inner
+    }
+}
+
+impl<C, K: fmt::Debug, H: fmt::Debug> fmt::Debug for KindCounters<C, K, H> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("KindCounters")
+            .field("inner", &self.inner)
+            .field("failures", &self.failures)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<E, C, K, H> ErrorHandler<E> for KindCounters<C, K, H>
+where
+    C: FnMut(&E) -> K,
+    K: Eq + Hash,
+    H: ErrorHandler<E>,
+{
+    type OutError = H::OutError;
+
+    fn handle(&mut self, _attempt: usize, e: E) -> RetryPolicy<H::OutError> {
+        let failures = self.failures.entry((self.classify)(&e)).or_insert(0);
+        *failures += 1;
+        self.inner.handle(*failures, e)
+    }
+
+    fn ok(&mut self, attempt: usize) {
+        self.failures.clear();
+        self.inner.ok(attempt);
+    }
+
+    fn ok_with<V: ?Sized>(&mut self, attempt: usize, value: &V) {
+        self.failures.clear();
+        self.inner.ok_with(attempt, value);
+    }
+
+    fn exhausted(&mut self, attempt: usize, error: &Self::OutError) {
+        self.inner.exhausted(attempt, error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ExponentialBackoff;
+
+    #[test]
+    fn repeat_keeps_the_limits() {
+        let backoff = ExponentialBackoff::new(Duration::from_millis(10)).max_attempts(2);
+        let mut handler =
+            KindRules::new(backoff).on(io::ErrorKind::Interrupted, KindAction::Repeat);
+        let interrupted = || io::Error::from(io::ErrorKind::Interrupted);
+        assert!(matches!(
+            handler.handle(1, interrupted()),
+            RetryPolicy::Repeat
+        ));
+        assert!(matches!(
+            handler.handle(2, interrupted()),
+            RetryPolicy::ForwardError(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn stream_counts_per_kind() {
+        use crate::StreamRetryExt;
+        use futures::{stream, TryStreamExt};
+
+        let errors = vec![
+            io::ErrorKind::ConnectionReset,
+            io::ErrorKind::WouldBlock,
+            io::ErrorKind::WouldBlock,
+            io::ErrorKind::ConnectionReset,
+        ];
+        let items = errors.into_iter().map(|kind| Err(io::Error::from(kind)));
+        let backoff = ExponentialBackoff::new(Duration::from_millis(1)).max_attempts(2);
+        let result: Result<Vec<((), usize)>, _> = stream::iter(items)
+            .retry_per_kind(io::Error::kind, backoff)
+            .try_collect()
+            .await;
+        let (e, attempt) = result.unwrap_err();
+        // The second `WouldBlock` in a row has used up its attempts, not the resets.
+        assert_eq!((io::ErrorKind::WouldBlock, 3), (e.kind(), attempt));
+    }
+}
@@ -0,0 +1,186 @@
+use crate::{ErrorHandler, RetryPolicy, Sleeper};
+use futures::{TryFuture, TryFutureExt};
+
+/// A factory whose futures might borrow from the factory itself, e.g. from a large request body
+/// that would otherwise have to be cloned (or put into an `Arc`) for every attempt.
+///
+/// Such futures can't be kept in a [`FutureRetry`](struct.FutureRetry.html) along with the
+/// factory they borrow from, so the factories are driven by [`retry_lending`](fn.retry_lending.html)
+/// instead.
+///
+/// ```
+/// use futures::future::BoxFuture;
+/// use futures_retry::{retry_lending, LendingFactory, RetryPolicy, TokioSleeper};
+///
+/// struct Upload {
+///     body: Vec<u8>,
+///     failures: usize,
+/// }
+///
+/// async fn send(body: &[u8]) -> Result<usize, &'static str> {
+///     Ok(body.len())
+/// }
+///
+/// impl LendingFactory for Upload {
+///     type Ok = usize;
+///     type Error = &'static str;
+///     type FutureItem<'a> = BoxFuture<'a, Result<usize, &'static str>>;
+///
+///     fn new(&mut self) -> Self::FutureItem<'_> {
+///         if self.failures > 0 {
+///             self.failures -= 1;
+///             return Box::pin(async { Err("connection reset") });
+///         }
+///         Box::pin(send(&self.body))
+///     }
+/// }
+///
+/// # #[tokio::main] async fn main() {
+/// let upload = Upload { body: vec![0; 1024], failures: 2 };
+/// let sent = retry_lending(upload, |_| RetryPolicy::Repeat::<&str>, TokioSleeper).await;
+/// assert_eq!(Ok((1024, 3)), sent);
+/// # }
+/// ```
+pub trait LendingFactory {
+    /// The value the futures resolve into on success.
+    type Ok;
+    /// The error the futures fail with.
+    type Error;
+    /// A future that is created by the `new` method and might borrow from the factory.
+    type FutureItem<'a>: TryFuture<Ok = Self::Ok, Error = Self::Error>
+    where
+        Self: 'a;
+
+    /// Creates a new future, which is polled to completion before the next one is created.
+    #[allow(clippy::wrong_self_convention, clippy::new_ret_no_self)]
+    fn new(&mut self) -> Self::FutureItem<'_>;
+}
+
+/// Retries the futures of a [`LendingFactory`](trait.LendingFactory.html) the same way a
+/// [`FutureRetry`](struct.FutureRetry.html) does, resolving into the value (or the error) along
+/// with the number of the attempt. The delays between the attempts are timed by the `sleeper`.
+pub async fn retry_lending<F, R, S>(
+    mut factory: F,
+    mut error_action: R,
+    sleeper: S,
+) -> Result<(F::Ok, usize), (R::OutError, usize)>
+where
+    F: LendingFactory,
+    R: ErrorHandler<F::Error>,
+    S: Sleeper,
+{
+    let mut attempt = 1;
+    loop {
+        match factory.new().into_future().await {
+            Ok(x) => {
+                error_action.ok_with(attempt, &x);
+                return Ok((x, attempt));
+            }
+            Err(e) => {
+                let policy = error_action.handle(attempt, e);
+                let next = policy.next_attempt(attempt);
+                match policy {
+                    RetryPolicy::ForwardError(e) => {
+                        error_action.exhausted(attempt, &e);
+                        return Err((e, attempt));
+                    }
+                    RetryPolicy::Repeat | RetryPolicy::RepeatWithoutCounting => {}
+                    RetryPolicy::WaitRetry(delay) | RetryPolicy::RetryAs { delay, .. } => {
+                        sleeper.sleep(delay).await
+                    }
+                }
+                attempt = next;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error_handler::tests::Successes, TokioSleeper};
+    use futures::future::{ready, Ready};
+    use std::time::Duration;
+
+    struct Borrowing {
+        payload: String,
+        calls: usize,
+    }
+
+    impl LendingFactory for Borrowing {
+        type Ok = usize;
+        type Error = &'static str;
+        type FutureItem<'a> = Ready<Result<usize, &'static str>>;
+
+        fn new(&mut self) -> Self::FutureItem<'_> {
+            self.calls += 1;
+            ready(match self.calls {
+                1 => Err("retry"),
+                2 => Err("give up"),
+                _ => Ok(self.payload.len()),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn retries() {
+        let factory = Borrowing {
+            payload: "hello".into(),
+            calls: 0,
+        };
+        let handler = |_| RetryPolicy::WaitRetry::<()>(Duration::from_millis(1));
+        assert_eq!(
+            Ok((5, 3)),
+            retry_lending(factory, handler, TokioSleeper).await
+        );
+        let factory = Borrowing {
+            payload: String::new(),
+            calls: 0,
+        };
+        let handler = |e| match e {
+            "retry" => RetryPolicy::Repeat,
+            e => RetryPolicy::ForwardError(e),
+        };
+        assert_eq!(
+            Err(("give up", 2)),
+            retry_lending(factory, handler, TokioSleeper).await
+        );
+    }
+
+    #[cfg(feature = "test_util")]
+    #[tokio::test]
+    async fn waits_on_the_sleeper() {
+        let factory = Borrowing {
+            payload: "hello".into(),
+            calls: 0,
+        };
+        let sleeper = crate::test_util::MockSleeper::new();
+        let hour = Duration::from_secs(3600);
+        let retry = retry_lending(
+            factory,
+            |_| RetryPolicy::WaitRetry::<()>(hour),
+            sleeper.clone(),
+        );
+        tokio::pin!(retry);
+        assert!(futures::poll!(retry.as_mut()).is_pending());
+        sleeper.advance(hour);
+        assert!(futures::poll!(retry.as_mut()).is_pending());
+        sleeper.advance(hour);
+        assert_eq!(Ok((5, 3)), retry.await);
+        assert_eq!(vec![hour, hour], sleeper.requested());
+    }
+
+    #[tokio::test]
+    async fn passes_success_values() {
+        let factory = Borrowing {
+            payload: "hello".into(),
+            calls: 0,
+        };
+        let successes = Successes::new(|_| RetryPolicy::Repeat::<()>);
+        assert_eq!(
+            Ok((5, 3)),
+            retry_lending(factory, successes.clone(), TokioSleeper).await
+        );
+        assert_eq!(vec![(3, "usize")], successes.seen());
+    }
+}
@@ -91,18 +91,170 @@
 
 use std::time::Duration;
 
+mod adaptive;
+mod backoff;
+mod batch;
+mod budget;
+mod builder;
+mod bulkhead;
+mod cached;
+mod cancel;
+mod circuit_breaker;
+mod classify;
+mod config;
+mod coordinator;
+mod copy;
+mod deadline;
 mod error_handler;
+mod failover;
+mod fault;
 mod future;
+mod hint;
+mod history;
+mod idempotency;
+mod immediate;
+mod join;
+mod join_set;
+mod kind_rules;
+mod lending;
+mod long_poll;
+mod macros;
+mod manager;
+mod middleware;
+mod option;
+mod phase;
+mod presets;
+mod reconnect;
+mod reconnect_io;
+mod retry_error;
+mod retry_read;
+mod retry_write;
+mod schedule;
+mod simulate;
+mod sink;
+mod sleeper;
+mod snapshot;
+mod stats;
 mod stream;
+mod supervisor;
+mod switch;
+mod timeout;
+mod unfold;
+mod value_handler;
+mod waiter;
+
+pub mod prelude;
+
+#[cfg(feature = "macros")]
+pub mod attr;
+#[cfg(feature = "codec")]
+pub mod codec;
+#[cfg(feature = "etcd")]
+pub mod etcd;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+#[cfg(feature = "nats")]
+pub mod nats;
+#[cfg(feature = "net")]
+pub mod net;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+#[cfg(feature = "process")]
+pub mod process;
+#[cfg(feature = "proptest")]
+pub mod proptest;
+#[cfg(feature = "redis")]
+pub mod redis;
+#[cfg(feature = "test_util")]
+pub mod test_util;
+#[cfg(feature = "tonic")]
+pub mod tonic;
+#[cfg(feature = "tower")]
+pub mod tower;
+#[cfg(feature = "tracing-error")]
+pub mod tracing_error;
 
 pub use crate::{
-    error_handler::ErrorHandler,
-    future::{FutureFactory, FutureRetry},
-    stream::{StreamRetry, StreamRetryExt},
+    adaptive::AdaptiveBackoff,
+    backoff::{BackoffFn, ExponentialBackoff, Jitter},
+    batch::{retry_batches, BatchRetry},
+    budget::{BudgetHandler, RetryBudget},
+    builder::{FutureRetryBuilder, Observed},
+    bulkhead::{Bulkhead, BulkheadFuture},
+    cached::{CacheRefresh, CachedRetry},
+    cancel::{CancellableRetry, Cancelled},
+    circuit_breaker::{CircuitBreaker, CircuitBreakerHandler, CircuitState, HalfOpenConfig},
+    classify::Classifier,
+    config::{ConfigError, RetryConfig},
+    coordinator::{BackoffCoordinator, CoordinatedHandler},
+    copy::copy_with_retry,
+    deadline::{current_deadline, with_deadline, DeadlineHandler},
+    error_handler::{ErrorHandler, Indirect, IndirectTarget, MaxAttempts},
+    failover::{Failover, FailoverFuture},
+    fault::FaultInjector,
+    future::{AttemptFailed, AttemptStream, FutureFactory, FutureRetry},
+    hint::{Hinted, RetryHint},
+    history::{ErrorHistory, HistoryHandler},
+    idempotency::{allow_non_idempotent, IdempotencyKey, Idempotent, KeyedFactory, NonIdempotent},
+    immediate::ImmediateRetry,
+    join::join_all_with_retry,
+    join_set::RetryingJoinSet,
+    kind_rules::{HasKind, KindAction, KindCounters, KindRules},
+    lending::{retry_lending, LendingFactory},
+    long_poll::{long_poll, LongPoll},
+    manager::{DeadLetter, RetryManager},
+    middleware::{retry_request, Cloning, RetryMiddleware},
+    option::{retry_some, NoValue, SomeFactory},
+    phase::{PhaseGuard, PhaseTracker, Phased},
+    presets::{default_http, default_io, exponential, safe_defaults, Profile},
+    reconnect::{ReconnectingStream, Resume, ResumeFuture, ResumeStream},
+    reconnect_io::{Connector, Handshake, ReconnectingIo},
+    retry_error::{Enriched, RetryError, WrapError},
+    retry_read::{ReaderFactory, RetryRead},
+    retry_write::{RetryWrite, WriterFactory},
+    schedule::{RetrySchedule, Scheduled, TimeWindows},
+    simulate::simulate,
+    sink::{Overflow, SinkRetry},
+    sleeper::{Sleeper, TokioSleeper},
+    snapshot::{RetrySnapshot, SnapshotHandler},
+    stats::{AttemptWait, RetryStats, WithStats},
+    stream::{ErrorCap, RecoveryDeadline, StreamRetry, StreamRetryExt},
+    supervisor::{Supervisor, TaskExit, TaskStatus},
+    switch::{disable_retries, retries_disabled, NoRetry, Switch, DISABLE_RETRIES_ENV},
+    timeout::{AttemptError, AttemptTimedOut, Timeout, TimeoutFuture, TimeoutHandler},
+    unfold::retry_unfold,
+    value_handler::{Stable, Until, ValueHandler, ValuePolicy},
+    waiter::Waiter,
 };
 
+#[cfg(feature = "backoff")]
+pub use crate::backoff::BackoffHandler;
+
+// Lets the `#[retry]` attribute, which refers to `::futures_retry`, be tested in this crate.
+#[cfg(all(test, feature = "macros"))]
+extern crate self as futures_retry;
+
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::macros::{parse_backoff, BackoffSpec};
+    pub use futures;
+}
+
+/// What a retry loop is busy with, as reported by
+/// [`FutureRetry::state`](struct.FutureRetry.html#method.state) and
+/// [`StreamRetry::state`](struct.StreamRetry.html#method.state).
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum RetryStatus {
+    /// Nothing is running: the loop hasn't been polled yet or has already resolved.
+    Idle,
+    /// An attempt is running.
+    InFlight,
+    /// The loop is waiting before the next attempt.
+    BackingOff,
+}
+
 /// What to do when a future returns an error. Used in `FutureRetry::new` and `StreamRetry::new`.
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum RetryPolicy<E> {
     /// Create and poll a new future immediately.
     ///
@@ -111,8 +263,34 @@ pub enum RetryPolicy<E> {
     /// Please be careful when using this variant since it might lead to a high (actually 100%) CPU
     /// usage in case a future instantly resolves into an error every time.
     Repeat,
+    /// Like `Repeat`, but the attempt counter is not incremented, so the failure doesn't count
+    /// towards any limit. Meant for the errors that are expected and benign, like `EINTR`.
+    ///
+    /// The same CPU usage caveat applies, and then some: a handler limited by the attempts never
+    /// gives up on such errors.
+    RepeatWithoutCounting,
     /// Wait for a given duration and make another attempt then.
     WaitRetry(Duration),
     /// Don't give it another try, just pass the error further to the user.
     ForwardError(E),
+    /// Like `WaitRetry`, but the attempt counter is overwritten: the next attempt gets the given
+    /// number. It might be reset to 1, e.g. after a successful re-authentication, or moved ahead to
+    /// give up sooner.
+    RetryAs {
+        /// The number of the next attempt; zero is treated as 1.
+        attempt: usize,
+        /// The delay before the next attempt.
+        delay: Duration,
+    },
+}
+
+impl<E> RetryPolicy<E> {
+    /// Returns the number of the attempt that follows the failed `attempt` under this policy.
+    pub(crate) fn next_attempt(&self, attempt: usize) -> usize {
+        match self {
+            RetryPolicy::RepeatWithoutCounting => attempt,
+            RetryPolicy::RetryAs { attempt, .. } => (*attempt).max(1),
+            _ => attempt + 1,
+        }
+    }
 }
@@ -0,0 +1,255 @@
+use crate::{ErrorHandler, FutureFactory, RetryPolicy, Sleeper};
+use futures::{ready, Stream, TryFuture};
+use pin_project_lite::pin_project;
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+type Response<F> = <<F as FutureFactory>::FutureItem as TryFuture>::Ok;
+type Items<F> = <Response<F> as IntoIterator>::IntoIter;
+
+pin_project! {
+    /// A stream that polls a server over and over, yielding the items of every response.
+    ///
+    /// Created by [`long_poll`](fn.long_poll.html).
+    pub struct LongPoll<F, R, S>
+    where
+        F: FutureFactory,
+        Response<F>: IntoIterator,
+        S: Sleeper,
+    {
+        factory: F,
+        error_action: R,
+        idle_delay: Duration,
+        attempt: usize,
+        items: Option<(Items<F>, usize)>,
+        sleeper: S,
+        #[pin]
+        state: PollState<F::FutureItem, S::Sleep>,
+    }
+}
+
+pin_project! {
+    #[project = PollStateProj]
+    enum PollState<F, D> {
+        NotStarted,
+        WaitingForFuture { #[pin] future: F },
+        TimerActive { #[pin] delay: D },
+    }
+}
+
+/// Creates a stream that repeatedly polls a server with futures created by the `factory`.
+///
+/// Each future resolves into a response, i.e. a collection of items. The items of a non-empty
+/// response are yielded right away, and the next request is made as soon as they are consumed.
+/// After an empty response the stream waits for the
+/// [idle delay](struct.LongPoll.html#method.idle_delay) (none by default, since a long-polling
+/// server holds a request until it has something to say), and errors are passed to the
+/// `error_action`, which decides how long to back off. Both waits are timed by the `sleeper`.
+///
+/// Every item is yielded along with the number of the attempt that has fetched it. A forwarded
+/// error is yielded as well, and then the polling goes on.
+///
+/// ```
+/// use futures::{StreamExt, TryStreamExt};
+/// use futures_retry::{long_poll, RetryPolicy, TokioSleeper};
+/// use std::time::Duration;
+///
+/// # #[tokio::main] async fn main() {
+/// let mut responses = vec![Ok(vec![1, 2]), Ok(vec![]), Err(()), Ok(vec![3])].into_iter();
+/// let items: Vec<_> = long_poll(
+///     || futures::future::ready(responses.next().unwrap()),
+///     |_| RetryPolicy::WaitRetry::<()>(Duration::from_millis(10)),
+///     TokioSleeper,
+/// )
+/// .idle_delay(Duration::from_millis(1))
+/// .take(3)
+/// .try_collect()
+/// .await
+/// .unwrap();
+/// assert_eq!(vec![(1, 1), (2, 1), (3, 2)], items);
+/// # }
+/// ```
+pub fn long_poll<F, R, S>(factory: F, error_action: R, sleeper: S) -> LongPoll<F, R, S>
+where
+    F: FutureFactory,
+    Response<F>: IntoIterator,
+    S: Sleeper,
+{
+    LongPoll {
+        factory,
+        error_action,
+        idle_delay: Duration::from_secs(0),
+        attempt: 1,
+        items: None,
+        sleeper,
+        state: PollState::NotStarted,
+    }
+}
+
+impl<F, R, S> LongPoll<F, R, S>
+where
+    F: FutureFactory,
+    Response<F>: IntoIterator,
+    S: Sleeper,
+{
+    /// Sets how long to wait after an empty response before polling again.
+    pub fn idle_delay(mut self, idle_delay: Duration) -> Self {
+        self.idle_delay = idle_delay;
+        self
+    }
+}
+
+impl<F, R, S> fmt::Debug for LongPoll<F, R, S>
+where
+    F: FutureFactory,
+    Response<F>: IntoIterator,
+    S: Sleeper,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LongPoll")
+            .field("attempt", &self.attempt)
+            .field("idle_delay", &self.idle_delay)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F, R, S> Stream for LongPoll<F, R, S>
+where
+    F: FutureFactory,
+    Response<F>: IntoIterator,
+    R: ErrorHandler<<F::FutureItem as TryFuture>::Error>,
+    S: Sleeper,
+{
+    type Item = Result<(<Response<F> as IntoIterator>::Item, usize), (R::OutError, usize)>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        loop {
+            let this = self.as_mut().project();
+            if let Some((items, attempt)) = this.items {
+                match items.next() {
+                    Some(item) => return Poll::Ready(Some(Ok((item, *attempt)))),
+                    None => *this.items = None,
+                }
+            }
+            let attempt = *this.attempt;
+            let new_state = match this.state.project() {
+                PollStateProj::NotStarted => PollState::WaitingForFuture {
+                    future: this.factory.new(),
+                },
+                PollStateProj::TimerActive { delay } => {
+                    ready!(delay.poll(cx));
+                    PollState::WaitingForFuture {
+                        future: this.factory.new(),
+                    }
+                }
+                PollStateProj::WaitingForFuture { future } => match ready!(future.try_poll(cx)) {
+                    Ok(response) => {
+                        this.error_action.ok_with(attempt, &response);
+                        *this.attempt = 1;
+                        let mut items = response.into_iter();
+                        match items.next() {
+                            Some(item) => {
+                                *this.items = Some((items, attempt));
+                                self.as_mut().project().state.set(PollState::NotStarted);
+                                return Poll::Ready(Some(Ok((item, attempt))));
+                            }
+                            None => PollState::TimerActive {
+                                delay: this.sleeper.sleep(*this.idle_delay),
+                            },
+                        }
+                    }
+                    Err(e) => {
+                        let policy = this.error_action.handle(attempt, e);
+                        *this.attempt = policy.next_attempt(attempt);
+                        match policy {
+                            RetryPolicy::ForwardError(e) => {
+                                this.error_action.exhausted(attempt, &e);
+                                *this.attempt = 1;
+                                self.as_mut().project().state.set(PollState::NotStarted);
+                                return Poll::Ready(Some(Err((e, attempt))));
+                            }
+                            RetryPolicy::Repeat | RetryPolicy::RepeatWithoutCounting => {
+                                PollState::WaitingForFuture {
+                                    future: this.factory.new(),
+                                }
+                            }
+                            RetryPolicy::WaitRetry(duration)
+                            | RetryPolicy::RetryAs {
+                                delay: duration, ..
+                            } => PollState::TimerActive {
+                                delay: this.sleeper.sleep(duration),
+                            },
+                        }
+                    }
+                },
+            };
+            self.as_mut().project().state.set(new_state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error_handler::tests::Successes, TokioSleeper};
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn forwarded_errors_dont_stop_polling() {
+        let mut responses = vec![Err(1u8), Ok(vec![]), Ok(vec!["a"])].into_iter();
+        let items: Vec<_> = long_poll(
+            || futures::future::ready(responses.next().unwrap()),
+            RetryPolicy::ForwardError,
+            TokioSleeper,
+        )
+        .take(2)
+        .collect()
+        .await;
+        assert_eq!(vec![Err((1, 1)), Ok(("a", 1))], items);
+    }
+
+    #[cfg(feature = "test_util")]
+    #[tokio::test]
+    async fn waits_on_the_sleeper() {
+        let mut responses = vec![Ok(vec![]), Err(1u8), Ok(vec!["a"])].into_iter();
+        let sleeper = crate::test_util::MockSleeper::new();
+        let (minute, hour) = (Duration::from_secs(60), Duration::from_secs(3600));
+        let poll = long_poll(
+            || futures::future::ready(responses.next().unwrap()),
+            |_| RetryPolicy::WaitRetry::<u8>(hour),
+            sleeper.clone(),
+        )
+        .idle_delay(minute);
+        tokio::pin!(poll);
+        let next = poll.next();
+        tokio::pin!(next);
+        assert!(futures::poll!(next.as_mut()).is_pending());
+        sleeper.advance(minute);
+        assert!(futures::poll!(next.as_mut()).is_pending());
+        sleeper.advance(hour);
+        assert_eq!(Some(Ok(("a", 2))), next.await);
+        assert_eq!(vec![minute, hour], sleeper.requested());
+    }
+
+    #[tokio::test]
+    async fn passes_success_values() {
+        let successes = Successes::new(RetryPolicy::ForwardError);
+        let mut responses = vec![Err(1u8), Ok(vec!["a"])].into_iter();
+        let items: Vec<_> = long_poll(
+            || futures::future::ready(responses.next().unwrap()),
+            successes.clone(),
+            TokioSleeper,
+        )
+        .take(2)
+        .collect()
+        .await;
+        assert_eq!(vec![Err((1, 1)), Ok(("a", 1))], items);
+        let response = std::any::type_name::<Vec<&str>>();
+        assert_eq!(vec![(1, response)], successes.seen());
+    }
+}
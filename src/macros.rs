@@ -0,0 +1,390 @@
+use crate::ExponentialBackoff;
+use std::time::Duration;
+
+/// Retries an async block in place.
+///
+/// `retry!(error_action, { ... })` expands to a [`FutureRetry`](struct.FutureRetry.html) that
+/// runs the block anew on every attempt, so there is no need to write a factory for a one-off
+/// call site. The block borrows its environment, hence it should only read the captured
+/// variables; use a factory if an attempt needs to mutate its environment.
+///
+/// ```
+/// use futures_retry::{retry, RetryPolicy};
+/// use std::time::Duration;
+///
+/// # async fn fetch(url: &str) -> Result<String, std::io::Error> { Ok(url.to_owned()) }
+/// # #[tokio::main] async fn main() {
+/// let url = "https://example.com";
+/// let (body, _attempt) = retry!(
+///     |_| RetryPolicy::WaitRetry::<std::io::Error>(Duration::from_millis(10)),
+///     { fetch(url).await }
+/// )
+/// .await
+/// .unwrap();
+/// assert_eq!(url, body);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! retry {
+    ($error_action:expr, $body:block $(,)?) => {
+        $crate::FutureRetry::new(|| async $body, $error_action)
+    };
+}
+
+/// Runs a retry loop for each of the factories concurrently, like
+/// [`join_all_with_retry`](fn.join_all_with_retry.html), but for a fixed number of factories that
+/// might produce values of different types.
+///
+/// `try_join_with_retry!(error_action, factory, ...)` expands to a future that resolves into a
+/// tuple of all the values (along with the attempt numbers). Every leg gets its own clone of the
+/// `error_action`; once any leg gives up, its error is returned and the other legs are dropped.
+///
+/// ```
+/// use futures::future::ready;
+/// use futures_retry::{try_join_with_retry, RetryPolicy};
+///
+/// # #[tokio::main] async fn main() {
+/// let (user, orders) = try_join_with_retry!(
+///     |_| RetryPolicy::Repeat::<&str>,
+///     || ready(Ok::<_, &str>("alice")),
+///     || ready(Ok::<_, &str>(vec![1, 2, 3])),
+/// )
+/// .await
+/// .unwrap();
+/// assert_eq!((("alice", 1), (vec![1, 2, 3], 1)), (user, orders));
+/// # }
+/// ```
+#[macro_export]
+macro_rules! try_join_with_retry {
+    (@legs $error_action:ident; [$($leg:ident)*];) => {
+        async move { $crate::__private::futures::try_join!($($leg),*) }
+    };
+    (@legs $error_action:ident; [$($leg:ident)*]; $factory:expr $(, $rest:expr)*) => {{
+        // Every expansion introduces its own (hygienic) `leg` binding.
+        let leg = $crate::FutureRetry::new(
+            $factory,
+            ::core::clone::Clone::clone(&$error_action),
+        );
+        $crate::try_join_with_retry!(@legs $error_action; [$($leg)* leg]; $($rest),*)
+    }};
+    ($error_action:expr, $($factory:expr),+ $(,)?) => {{
+        let error_action = $error_action;
+        $crate::try_join_with_retry!(@legs error_action; []; $($factory),+)
+    }};
+}
+
+/// Declares an error handler in a compact form, so a policy might be reviewed at a glance.
+///
+/// ```text
+/// policy! {
+///     max_attempts: <expr>,                   // optional, unlimited by default
+///     backoff: exponential(<min>..<max>),     // or exponential(<min>), or constant(<delay>)
+///     jitter: none | full | equal,            // optional, none by default
+///     on: [<kind> => repeat | forward | backoff, ...], // optional
+/// }
+/// ```
+///
+/// The delays are written as a number and a unit, one of `ns`, `us`, `ms`, `s`, `m` and `h`
+/// (e.g. `100ms` or `1.5s`), and are checked at compile time. The `#[retry]` attribute (the
+/// `macros` feature) accepts the very same backoffs as strings. The macro expands to an
+/// [`ExponentialBackoff`](struct.ExponentialBackoff.html), wrapped into
+/// [`KindRules`](struct.KindRules.html) when there are any `on` rules.
+///
+/// ```
+/// use futures_retry::{policy, ErrorHandler, RetryPolicy};
+/// use std::{io, time::Duration};
+///
+/// let mut handler = policy! {
+///     max_attempts: 5,
+///     backoff: exponential(100ms..10s),
+///     jitter: none,
+///     on: [
+///         io::ErrorKind::Interrupted => repeat,
+///         io::ErrorKind::PermissionDenied => forward,
+///     ],
+/// };
+/// let reset = io::Error::from(io::ErrorKind::ConnectionReset);
+/// assert!(matches!(
+///     handler.handle(2, reset),
+///     RetryPolicy::WaitRetry(delay) if delay == Duration::from_millis(200)
+/// ));
+/// let interrupted = io::Error::from(io::ErrorKind::Interrupted);
+/// assert!(matches!(handler.handle(3, interrupted), RetryPolicy::Repeat));
+/// ```
+#[macro_export]
+macro_rules! policy {
+    (@backoff $($backoff:tt)+) => {{
+        const BACKOFF: $crate::__private::BackoffSpec =
+            $crate::__private::parse_backoff(::core::stringify!($($backoff)+));
+        BACKOFF.build()
+    }};
+    (@jitter none) => { $crate::Jitter::None };
+    (@jitter full) => { $crate::Jitter::Full };
+    (@jitter equal) => { $crate::Jitter::Equal };
+    (@action repeat) => { $crate::KindAction::Repeat };
+    (@action forward) => { $crate::KindAction::Forward };
+    (@action backoff) => { $crate::KindAction::Backoff };
+    (@rules $backoff:ident;) => { $backoff };
+    (@rules $backoff:ident; [$($kind:path => $action:ident),*]) => {
+        $crate::KindRules::new($backoff)
+            $(.on($kind, $crate::policy!(@action $action)))*
+    };
+    (
+        $(max_attempts: $max_attempts:expr,)?
+        backoff: $curve:ident($($delays:tt)+)
+        $(, jitter: $jitter:ident)?
+        $(, on: [$($kind:path => $action:ident),* $(,)?])?
+        $(,)?
+    ) => {{
+        let backoff = $crate::policy!(@backoff $curve($($delays)+));
+        $(let backoff = backoff.max_attempts($max_attempts);)?
+        $(let backoff = backoff.jitter($crate::policy!(@jitter $jitter));)?
+        $crate::policy!(@rules backoff; $([$($kind => $action),*])?)
+    }};
+}
+
+/// A backoff curve written as `exponential(<min>)`, `exponential(<min>..<max>)` or
+/// `constant(<delay>)`, shared by the [`policy!`](macro.policy.html) macro and the `#[retry]`
+/// attribute.
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackoffSpec {
+    initial_delay: Duration,
+    factor: u32,
+    max_delay: Option<Duration>,
+}
+
+impl BackoffSpec {
+    /// Creates a backoff following the curve.
+    pub fn build(self) -> ExponentialBackoff {
+        let backoff = ExponentialBackoff::new(self.initial_delay).factor(self.factor);
+        match self.max_delay {
+            Some(max_delay) => backoff.max_delay(max_delay),
+            None => backoff,
+        }
+    }
+}
+
+/// Parses a backoff curve, e.g. `exponential(100ms..10s)`, panicking (at compile time when
+/// evaluated in a constant) if it is malformed.
+#[doc(hidden)]
+pub const fn parse_backoff(backoff: &str) -> BackoffSpec {
+    let (curve, args) = match find(backoff.as_bytes(), b"(") {
+        Some(open) => backoff.as_bytes().split_at(open),
+        None => panic!("a backoff should look like `exponential(100ms..10s)`"),
+    };
+    let args = match args.trim_ascii() {
+        [b'(', args @ .., b')'] => args,
+        _ => panic!("a backoff should look like `exponential(100ms..10s)`"),
+    };
+    let curve = curve.trim_ascii();
+    if eq(curve, b"exponential") {
+        match find(args, b"..") {
+            Some(range) => {
+                let (min, max) = args.split_at(range);
+                BackoffSpec {
+                    initial_delay: parse_duration(min),
+                    factor: 2,
+                    max_delay: Some(parse_duration(max.split_at(2).1)),
+                }
+            }
+            None => BackoffSpec {
+                initial_delay: parse_duration(args),
+                factor: 2,
+                max_delay: None,
+            },
+        }
+    } else if eq(curve, b"constant") {
+        BackoffSpec {
+            initial_delay: parse_duration(args),
+            factor: 1,
+            max_delay: None,
+        }
+    } else {
+        panic!("a backoff should be `exponential(<min>)`, `exponential(<min>..<max>)` or `constant(<delay>)`")
+    }
+}
+
+/// Parses a delay, e.g. `100ms` or `1.5s`.
+const fn parse_duration(delay: &[u8]) -> Duration {
+    let bytes = delay.trim_ascii();
+    let mut i = 0;
+    let mut whole: u128 = 0;
+    let mut fraction: u128 = 0;
+    let mut scale: u128 = 1;
+    while i < bytes.len() && bytes[i].is_ascii_digit() {
+        whole = whole * 10 + (bytes[i] - b'0') as u128;
+        i += 1;
+    }
+    if i == 0 {
+        panic!("a delay should start with a number");
+    }
+    if i < bytes.len() && bytes[i] == b'.' {
+        i += 1;
+        while i < bytes.len() && bytes[i].is_ascii_digit() {
+            fraction = fraction * 10 + (bytes[i] - b'0') as u128;
+            scale *= 10;
+            i += 1;
+        }
+    }
+    let unit: u128 = match bytes.split_at(i).1 {
+        b"s" => 1_000_000_000,
+        b"m" => 60_000_000_000,
+        b"h" => 3_600_000_000_000,
+        b"ns" => 1,
+        b"us" => 1_000,
+        b"ms" => 1_000_000,
+        _ => panic!("a delay should end with a unit: ns, us, ms, s, m or h"),
+    };
+    let nanos = whole * unit + fraction * unit / scale;
+    if nanos > u64::MAX as u128 {
+        panic!("the delay is too long");
+    }
+    Duration::from_nanos(nanos as u64)
+}
+
+/// Returns the position of the first occurrence of the `needle` in the `haystack`.
+const fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    let mut i = 0;
+    while i + needle.len() <= haystack.len() {
+        if eq(haystack.split_at(i).1.split_at(needle.len()).0, needle) {
+            return Some(i);
+        }
+        i += 1;
+    }
+    None
+}
+
+const fn eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ErrorHandler, RetryPolicy};
+    use std::{
+        io,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    #[tokio::test]
+    async fn retry() {
+        let calls = AtomicUsize::new(0);
+        let result = retry!(|_| RetryPolicy::Repeat::<()>, {
+            match calls.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err(()),
+                call => Ok(call),
+            }
+        })
+        .await;
+        assert_eq!(Ok((2, 3)), result);
+    }
+
+    #[tokio::test]
+    async fn try_join_with_retry() {
+        use futures::future::{pending, ready};
+        let calls = AtomicUsize::new(0);
+        let result = try_join_with_retry!(
+            |e| match e {
+                "again" => RetryPolicy::Repeat,
+                e => RetryPolicy::ForwardError(e),
+            },
+            || ready(Ok::<_, &str>(1u8)),
+            || ready(match calls.fetch_add(1, Ordering::SeqCst) {
+                0 => Err("again"),
+                _ => Ok("two"),
+            }),
+        )
+        .await;
+        assert_eq!(Ok(((1, 1), ("two", 2))), result);
+        let result =
+            try_join_with_retry!(RetryPolicy::ForwardError, pending::<Result<(), u8>>, || {
+                ready(Err::<(), u8>(3))
+            },)
+            .await;
+        assert_eq!(Err((3, 1)), result);
+    }
+
+    #[test]
+    fn policy() {
+        assert_eq!(Duration::from_millis(1500), super::parse_duration(b"1.5s"));
+        assert_eq!(Duration::from_nanos(250), super::parse_duration(b" 250ns"));
+        assert_eq!(
+            super::parse_backoff("exponential(1.5s..2m)"),
+            super::parse_backoff(" exponential( 1.5s .. 2m ) ")
+        );
+        assert_eq!(
+            Duration::from_secs(3),
+            super::parse_backoff("constant(3s)").build().delay(5)
+        );
+        let mut handler = policy! {
+            backoff: constant(2m),
+            on: [io::ErrorKind::NotFound => forward],
+        };
+        let not_found = io::Error::from(io::ErrorKind::NotFound);
+        assert!(matches!(
+            handler.handle(1, not_found),
+            RetryPolicy::ForwardError(_)
+        ));
+        let mut backoff = policy!(max_attempts: 2, backoff: exponential(10us), jitter: full);
+        assert!(matches!(
+            backoff.handle(1, ()),
+            RetryPolicy::WaitRetry(delay) if delay <= Duration::from_micros(10)
+        ));
+        assert_eq!(RetryPolicy::ForwardError(()), backoff.handle(2, ()));
+    }
+
+    #[cfg(feature = "macros")]
+    #[tokio::test(start_paused = true)]
+    async fn same_backoffs_as_the_attribute() {
+        use crate::attr::retry;
+        use std::future::Future;
+        use tokio::time::Instant;
+
+        #[retry(max_attempts = 4, backoff = "exponential(10ms..25ms)")]
+        async fn bounded() -> Result<(), ()> {
+            Err(())
+        }
+
+        #[retry(max_attempts = 4, backoff = "exponential(1.5s)")]
+        async fn unbounded() -> Result<(), ()> {
+            Err(())
+        }
+
+        #[retry(max_attempts = 4, backoff = "constant(2m)")]
+        async fn constant() -> Result<(), ()> {
+            Err(())
+        }
+
+        async fn elapsed(retry: impl Future<Output = Result<(), ()>>) -> Duration {
+            let start = Instant::now();
+            assert_eq!(Err(()), retry.await);
+            start.elapsed()
+        }
+
+        let total = |backoff: crate::ExponentialBackoff| backoff.schedule().sum::<Duration>();
+        assert_eq!(
+            total(policy!(max_attempts: 4, backoff: exponential(10ms..25ms))),
+            elapsed(bounded()).await
+        );
+        assert_eq!(
+            total(policy!(max_attempts: 4, backoff: exponential(1.5s))),
+            elapsed(unbounded()).await
+        );
+        assert_eq!(
+            total(policy!(max_attempts: 4, backoff: constant(2m))),
+            elapsed(constant()).await
+        );
+    }
+}
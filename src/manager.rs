@@ -0,0 +1,468 @@
+use crate::{ErrorHandler, FutureFactory, RetryPolicy};
+use futures::{ready, stream::FuturesUnordered, Stream, StreamExt, TryFuture};
+use pin_project_lite::pin_project;
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    future::Future,
+    hash::Hash,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+use tokio::{sync::mpsc::UnboundedSender, time::Instant};
+use tokio_util::time::{delay_queue, DelayQueue};
+
+/// The longest a job waits for a retry, as the `DelayQueue` can't time much longer waits; the
+/// longer delays, e.g. a `Duration::MAX` one meaning forever, are cut to it.
+const MAX_DELAY: Duration = Duration::from_secs(86400 * 365);
+
+type JobOk<J> = <<J as FutureFactory>::FutureItem as TryFuture>::Ok;
+type JobError<J> = <<J as FutureFactory>::FutureItem as TryFuture>::Error;
+
+enum JobState {
+    Running,
+    Waiting {
+        queue_key: delay_queue::Key,
+        retry_at: Instant,
+    },
+}
+
+struct Job<J> {
+    job: J,
+    id: u64,
+    attempt: usize,
+    state: JobState,
+    errors: Vec<String>,
+}
+
+/// A job that has been given up on, handed to the dead-letter sink of a
+/// [`RetryManager`](struct.RetryManager.html).
+#[derive(Debug, Clone)]
+pub struct DeadLetter<K, J> {
+    /// The key the job was submitted under.
+    pub key: K,
+    /// The job itself, so it can be inspected or resubmitted later.
+    pub job: J,
+    /// The number of attempts that have been made.
+    pub attempts: usize,
+    /// Descriptions of the errors of all the attempts, the last one included.
+    pub errors: Vec<String>,
+}
+
+pin_project! {
+    struct Attempt<K, Fut> {
+        key: Option<K>,
+        id: u64,
+        #[pin]
+        future: Fut,
+    }
+}
+
+impl<K, Fut: TryFuture> Future for Attempt<K, Fut> {
+    type Output = (K, u64, Result<Fut::Ok, Fut::Error>);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.project();
+        let result = ready!(this.future.try_poll(cx));
+        let key = this.key.take().expect("Attempt polled after completion");
+        Poll::Ready((key, *this.id, result))
+    }
+}
+
+/// A queue of keyed jobs that are run concurrently and retried independently of each other.
+///
+/// Each job is a [`FutureFactory`](trait.FutureFactory.html) submitted under a unique key, for
+/// example an outbound webhook identified by its delivery id. A failed attempt is passed to the
+/// manager's error handler together with the job's own attempt counter; the job is then either
+/// restarted right away, scheduled on an internal `DelayQueue` (for a year at most), or given up
+/// on.
+///
+/// The manager is a `Stream` of finished jobs: it yields the key along with either the result
+/// and the number of attempts, or the forwarded error. Like `FuturesUnordered`, the stream ends
+/// whenever there are no jobs left, and it can be polled again once new jobs are submitted. Jobs
+/// that have been given up on might also be passed to a [dead-letter sink](#method.dead_letter).
+///
+/// ```
+/// use futures::StreamExt;
+/// use futures_retry::{RetryManager, RetryPolicy};
+/// use std::time::Duration;
+///
+/// # #[tokio::main] async fn main() {
+/// let mut manager = RetryManager::new(|_| {
+///     RetryPolicy::WaitRetry::<()>(Duration::from_millis(10))
+/// });
+/// let job = |id: u32| move || async move { Ok::<_, ()>(id * 10) };
+/// for id in 0..3 {
+///     manager.submit(id, job(id));
+/// }
+/// // Deduplicated: a job with this key is already pending.
+/// assert!(!manager.submit(0, job(0)));
+/// let mut finished: Vec<_> = manager.collect().await;
+/// finished.sort_by_key(|(id, _)| *id);
+/// assert_eq!(vec![(0, Ok((0, 1))), (1, Ok((10, 1))), (2, Ok((20, 1)))], finished);
+/// # }
+/// ```
+pub struct RetryManager<K, J: FutureFactory, R> {
+    error_action: R,
+    jobs: HashMap<K, Job<J>>,
+    running: FuturesUnordered<Attempt<K, J::FutureItem>>,
+    queue: DelayQueue<K>,
+    next_id: u64,
+    waker: Option<Waker>,
+    dead_letter: Option<DeadLetterSink<K, J>>,
+}
+
+struct DeadLetterSink<K, J: FutureFactory> {
+    describe: fn(&JobError<J>) -> String,
+    sink: Box<dyn FnMut(DeadLetter<K, J>) + Send>,
+}
+
+// Nothing is pinned structurally: the attempts live in a `FuturesUnordered`, which keeps them on
+// the heap.
+impl<K, J: FutureFactory, R> Unpin for RetryManager<K, J, R> {}
+
+impl<K: fmt::Debug, J: FutureFactory, R> fmt::Debug for RetryManager<K, J, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RetryManager")
+            .field("jobs", &self.jobs.keys().collect::<Vec<_>>())
+            .field("running", &self.running.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<K, J, R> RetryManager<K, J, R>
+where
+    K: Clone + Eq + Hash,
+    J: FutureFactory,
+{
+    /// Creates an empty manager that consults the `error_action` about every failed attempt.
+    pub fn new(error_action: R) -> Self {
+        Self {
+            error_action,
+            jobs: HashMap::new(),
+            running: FuturesUnordered::new(),
+            queue: DelayQueue::new(),
+            next_id: 0,
+            waker: None,
+            dead_letter: None,
+        }
+    }
+
+    /// Hands every job that the error handler gives up on to the `sink`, along with the errors of
+    /// all its attempts, so exhausted work is not silently lost.
+    ///
+    /// The forwarded error is still yielded by the stream as usual.
+    pub fn dead_letter<S>(mut self, sink: S) -> Self
+    where
+        S: FnMut(DeadLetter<K, J>) + Send + 'static,
+        JobError<J>: Display,
+    {
+        self.dead_letter = Some(DeadLetterSink {
+            describe: |e| e.to_string(),
+            sink: Box::new(sink),
+        });
+        self
+    }
+
+    /// Sends every job that the error handler gives up on into a channel, see
+    /// [`dead_letter`](#method.dead_letter).
+    pub fn dead_letter_channel(self, sender: UnboundedSender<DeadLetter<K, J>>) -> Self
+    where
+        K: Send + 'static,
+        J: Send + 'static,
+        JobError<J>: Display,
+    {
+        self.dead_letter(move |letter| {
+            // Nobody is interested in the dead letters if the receiver is gone.
+            let _ = sender.send(letter);
+        })
+    }
+
+    /// Submits a job and starts its first attempt right away.
+    ///
+    /// Returns `false` and drops the job if a job with the same key is already running or waiting
+    /// for a retry.
+    pub fn submit(&mut self, key: K, mut job: J) -> bool {
+        if self.jobs.contains_key(&key) {
+            return false;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.running.push(Attempt {
+            key: Some(key.clone()),
+            id,
+            future: job.new(),
+        });
+        self.jobs.insert(
+            key,
+            Job {
+                job,
+                id,
+                attempt: 1,
+                state: JobState::Running,
+                errors: Vec::new(),
+            },
+        );
+        self.wake();
+        true
+    }
+
+    /// Removes a job from the manager and returns it. An attempt that is currently running is left
+    /// to finish in the background, but its result is discarded.
+    pub fn cancel(&mut self, key: &K) -> Option<J> {
+        let job = self.jobs.remove(key)?;
+        if let JobState::Waiting { queue_key, .. } = &job.state {
+            self.queue.remove(queue_key);
+        }
+        Some(job.job)
+    }
+
+    /// Checks whether a job with the given key is running or waiting for a retry.
+    pub fn contains(&self, key: &K) -> bool {
+        self.jobs.contains_key(key)
+    }
+
+    /// Returns the number of the attempt that a job is running or waiting for.
+    pub fn attempt(&self, key: &K) -> Option<usize> {
+        self.jobs.get(key).map(|job| job.attempt)
+    }
+
+    /// Returns when a job is going to be retried, if it is waiting for a retry.
+    pub fn next_retry(&self, key: &K) -> Option<Instant> {
+        match self.jobs.get(key)?.state {
+            JobState::Running => None,
+            JobState::Waiting { retry_at, .. } => Some(retry_at),
+        }
+    }
+
+    /// Iterates over the jobs waiting for a retry, along with the numbers of their next attempts
+    /// and when they are going to be made.
+    pub fn pending_retries(&self) -> impl Iterator<Item = (&K, usize, Instant)> {
+        self.jobs.iter().filter_map(|(key, job)| match job.state {
+            JobState::Running => None,
+            JobState::Waiting { retry_at, .. } => Some((key, job.attempt, retry_at)),
+        })
+    }
+
+    /// Returns the number of jobs, both running and waiting for a retry.
+    pub fn len(&self) -> usize {
+        self.jobs.len()
+    }
+
+    /// Checks whether there are no jobs at all.
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    /// Returns a reference to the error handler.
+    pub fn error_action(&self) -> &R {
+        &self.error_action
+    }
+
+    fn start(&mut self, key: K) {
+        if let Some(job) = self.jobs.get_mut(&key) {
+            job.state = JobState::Running;
+            self.running.push(Attempt {
+                key: Some(key),
+                id: job.id,
+                future: job.job.new(),
+            });
+        }
+    }
+
+    fn schedule(&mut self, key: K, delay: Duration) {
+        if let Some(job) = self.jobs.get_mut(&key) {
+            let retry_at = Instant::now() + delay.min(MAX_DELAY);
+            job.state = JobState::Waiting {
+                queue_key: self.queue.insert_at(key, retry_at),
+                retry_at,
+            };
+        }
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<K, J, R> Stream for RetryManager<K, J, R>
+where
+    K: Clone + Eq + Hash,
+    J: FutureFactory,
+    R: ErrorHandler<JobError<J>>,
+{
+    type Item = (K, Result<(JobOk<J>, usize), (R::OutError, usize)>);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        this.waker = Some(cx.waker().clone());
+        loop {
+            while let Poll::Ready(Some(expired)) = this.queue.poll_expired(cx) {
+                this.start(expired.into_inner());
+            }
+            let (key, id, result) = match this.running.poll_next_unpin(cx) {
+                Poll::Ready(Some(finished)) => finished,
+                Poll::Ready(None) if this.jobs.is_empty() => return Poll::Ready(None),
+                Poll::Ready(None) | Poll::Pending => return Poll::Pending,
+            };
+            let job = match this.jobs.get_mut(&key) {
+                Some(job) if job.id == id => job,
+                // The job has been cancelled while the attempt was running.
+                _ => continue,
+            };
+            let attempt = job.attempt;
+            match result {
+                Ok(item) => {
+                    this.jobs.remove(&key);
+                    this.error_action.ok_with(attempt, &item);
+                    return Poll::Ready(Some((key, Ok((item, attempt)))));
+                }
+                Err(e) => {
+                    if let Some(dead_letter) = &this.dead_letter {
+                        job.errors.push((dead_letter.describe)(&e));
+                    }
+                    let policy = this.error_action.handle(attempt, e);
+                    let next = policy.next_attempt(attempt);
+                    match policy {
+                        RetryPolicy::ForwardError(e) => {
+                            this.error_action.exhausted(attempt, &e);
+                            let job = this.jobs.remove(&key).expect("The job has just been found");
+                            if let Some(dead_letter) = &mut this.dead_letter {
+                                (dead_letter.sink)(DeadLetter {
+                                    key: key.clone(),
+                                    job: job.job,
+                                    attempts: attempt,
+                                    errors: job.errors,
+                                });
+                            }
+                            return Poll::Ready(Some((key, Err((e, attempt)))));
+                        }
+                        RetryPolicy::Repeat | RetryPolicy::RepeatWithoutCounting => {
+                            job.attempt = next;
+                            this.start(key);
+                        }
+                        RetryPolicy::WaitRetry(delay) | RetryPolicy::RetryAs { delay, .. } => {
+                            job.attempt = next;
+                            this.schedule(key, delay);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handler::tests::Successes;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use tokio::time::timeout;
+
+    fn flaky(failures: usize) -> impl FnMut() -> futures::future::Ready<Result<usize, usize>> {
+        let calls = Arc::new(AtomicUsize::new(0));
+        move || {
+            let call = calls.fetch_add(1, Ordering::SeqCst);
+            futures::future::ready(if call < failures { Err(call) } else { Ok(call) })
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_per_job() {
+        let mut manager = RetryManager::new(|e: usize| {
+            if e < 2 {
+                RetryPolicy::WaitRetry(Duration::from_millis(20))
+            } else {
+                RetryPolicy::ForwardError(e)
+            }
+        });
+        assert!(manager.submit("ok", flaky(0)));
+        assert!(manager.submit("flaky", flaky(2)));
+        assert!(manager.submit("broken", flaky(5)));
+        assert_eq!(Some(("ok", Ok((0, 1)))), manager.next().await);
+        // Let the manager process the failed attempts of the other jobs.
+        assert!(timeout(Duration::from_millis(5), manager.next())
+            .await
+            .is_err());
+        assert_eq!(Some(2), manager.attempt(&"flaky"));
+        let mut pending: Vec<_> = manager.pending_retries().map(|(key, ..)| *key).collect();
+        pending.sort_unstable();
+        assert_eq!(vec!["broken", "flaky"], pending);
+        let mut finished = vec![manager.next().await, manager.next().await];
+        finished.sort();
+        assert_eq!(
+            vec![Some(("broken", Err((2, 3)))), Some(("flaky", Ok((2, 3))))],
+            finished
+        );
+        assert_eq!(None, manager.next().await);
+        assert!(manager.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dead_letter() {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let mut manager = RetryManager::new(|e: usize| {
+            if e < 1 {
+                RetryPolicy::Repeat
+            } else {
+                RetryPolicy::ForwardError(e)
+            }
+        })
+        .dead_letter_channel(sender);
+        manager.submit("ok", flaky(1));
+        manager.submit("broken", flaky(2));
+        let mut finished = vec![manager.next().await, manager.next().await];
+        finished.sort();
+        assert_eq!(
+            vec![Some(("broken", Err((1, 2)))), Some(("ok", Ok((1, 2))))],
+            finished
+        );
+        let letter = receiver.recv().await.unwrap();
+        assert_eq!(("broken", 2), (letter.key, letter.attempts));
+        assert_eq!(vec!["0", "1"], letter.errors);
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancel() {
+        let mut manager =
+            RetryManager::new(|_: usize| RetryPolicy::WaitRetry::<usize>(Duration::from_secs(60)));
+        manager.submit(1, flaky(1));
+        manager.submit(2, flaky(0));
+        assert_eq!(Some((2, Ok((0, 1)))), manager.next().await);
+        assert!(timeout(Duration::from_millis(5), manager.next())
+            .await
+            .is_err());
+        assert!(manager.next_retry(&1).is_some());
+        assert!(manager.cancel(&1).is_some());
+        assert!(!manager.contains(&1));
+        assert_eq!(None, manager.next().await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn clamps_long_delays() {
+        let started = tokio::time::Instant::now();
+        let mut manager =
+            RetryManager::new(|_: usize| RetryPolicy::WaitRetry::<usize>(Duration::MAX));
+        manager.submit(1, flaky(1));
+        assert!(timeout(Duration::from_millis(5), manager.next())
+            .await
+            .is_err());
+        assert_eq!(Some(started + MAX_DELAY), manager.next_retry(&1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn passes_success_values() {
+        let successes = Successes::new(|_: usize| RetryPolicy::Repeat::<usize>);
+        let mut manager = RetryManager::new(successes.clone());
+        manager.submit("flaky", flaky(1));
+        assert_eq!(Some(("flaky", Ok((1, 2)))), manager.next().await);
+        assert_eq!(vec![(2, "usize")], successes.seen());
+    }
+}
@@ -0,0 +1,228 @@
+use crate::{ErrorHandler, Idempotent, RetryPolicy, Sleeper, TokioSleeper};
+use futures::{future::IntoFuture, TryFuture, TryFutureExt};
+use std::{fmt, future::Future};
+
+/// A request/response client whose requests might be sent again, so the RPC stacks that don't
+/// build on `tower` might still be retried with any of the error handlers.
+///
+/// A request is consumed by [`send`](#tymethod.send), so before every attempt a fresh copy is
+/// made from the original one by [`rebuild`](#tymethod.rebuild): usually a clone, but a request
+/// that can't be cloned (e.g. one with a streamed body) might be built again from scratch, and a
+/// retried request might be marked as such. The requests are driven by
+/// [`retry_request`](fn.retry_request.html), which applies the decisions of an error handler.
+///
+/// ```
+/// use futures::future::{ready, Ready};
+/// use futures_retry::{retry_request, Idempotent, RetryMiddleware, RetryPolicy};
+///
+/// struct Request {
+///     payload: Vec<u8>,
+///     retry: bool,
+/// }
+///
+/// // The server stores the payload under a fixed name, so storing it twice does no harm.
+/// impl Idempotent for Request {}
+///
+/// struct Client {
+///     failures: usize,
+/// }
+///
+/// impl RetryMiddleware<Request, usize> for Client {
+///     type Error = &'static str;
+///     type Future = Ready<Result<usize, &'static str>>;
+///
+///     fn send(&mut self, request: Request) -> Self::Future {
+///         if self.failures > 0 {
+///             self.failures -= 1;
+///             return ready(Err("unavailable"));
+///         }
+///         assert!(request.retry);
+///         ready(Ok(request.payload.len()))
+///     }
+///
+///     fn rebuild(&mut self, request: &Request, attempt: usize) -> Request {
+///         Request { payload: request.payload.clone(), retry: attempt > 1 }
+///     }
+/// }
+///
+/// # #[tokio::main] async fn main() {
+/// let mut client = Client { failures: 2 };
+/// let request = Request { payload: vec![0; 16], retry: false };
+/// let sent = retry_request(&mut client, request, |_| RetryPolicy::Repeat::<&str>).await;
+/// assert_eq!(Ok((16, 3)), sent);
+/// # }
+/// ```
+pub trait RetryMiddleware<Req, Res> {
+    /// The error the requests fail with.
+    type Error;
+    /// A future that resolves into the response.
+    type Future: Future<Output = Result<Res, Self::Error>>;
+
+    /// Sends a request once.
+    fn send(&mut self, request: Req) -> Self::Future;
+
+    /// Makes a copy of the original request to be sent by the given attempt.
+    fn rebuild(&mut self, request: &Req, attempt: usize) -> Req;
+}
+
+impl<Req, Res, M: RetryMiddleware<Req, Res> + ?Sized> RetryMiddleware<Req, Res> for &mut M {
+    type Error = M::Error;
+    type Future = M::Future;
+
+    fn send(&mut self, request: Req) -> Self::Future {
+        (**self).send(request)
+    }
+
+    fn rebuild(&mut self, request: &Req, attempt: usize) -> Req {
+        (**self).rebuild(request, attempt)
+    }
+}
+
+/// A [`RetryMiddleware`](trait.RetryMiddleware.html) made of a closure that sends the requests,
+/// which are simply cloned for every attempt.
+///
+/// ```
+/// use futures_retry::{allow_non_idempotent, retry_request, Cloning, NonIdempotent, RetryPolicy};
+///
+/// # #[tokio::main] async fn main() {
+/// let mut calls = 0;
+/// let mut client = Cloning::new(|request: NonIdempotent<String>| {
+///     calls += 1;
+///     futures::future::ready(if calls < 3 { Err("busy") } else { Ok(request.len()) })
+/// });
+/// let request = allow_non_idempotent("ping".to_owned());
+/// let sent = retry_request(&mut client, request, |_| RetryPolicy::Repeat::<&str>);
+/// assert_eq!(Ok((4, 3)), sent.await);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Cloning<F> {
+    send: F,
+}
+
+impl<F> Cloning<F> {
+    /// Wraps a closure that sends a request once.
+    pub fn new(send: F) -> Self {
+        Self { send }
+    }
+}
+
+impl<F> fmt::Debug for Cloning<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Cloning").finish_non_exhaustive()
+    }
+}
+
+impl<F, Fut, Req> RetryMiddleware<Req, Fut::Ok> for Cloning<F>
+where
+    F: FnMut(Req) -> Fut,
+    Fut: TryFuture,
+    Req: Clone,
+{
+    type Error = Fut::Error;
+    type Future = IntoFuture<Fut>;
+
+    fn send(&mut self, request: Req) -> Self::Future {
+        (self.send)(request).into_future()
+    }
+
+    fn rebuild(&mut self, request: &Req, _attempt: usize) -> Req {
+        request.clone()
+    }
+}
+
+/// Sends a request through a [`RetryMiddleware`](trait.RetryMiddleware.html) until it succeeds
+/// or the error handler gives up, resolving into the response (or the error) along with the
+/// number of the attempt, the same way a [`FutureRetry`](struct.FutureRetry.html) does.
+///
+/// Only the [`Idempotent`](trait.Idempotent.html) requests are retried, so a mutation isn't
+/// applied twice by accident.
+pub async fn retry_request<M, Req, Res, H>(
+    mut middleware: M,
+    request: Req,
+    mut error_action: H,
+) -> Result<(Res, usize), (H::OutError, usize)>
+where
+    M: RetryMiddleware<Req, Res>,
+    Req: Idempotent,
+    H: ErrorHandler<M::Error>,
+{
+    let mut attempt = 1;
+    loop {
+        let copy = middleware.rebuild(&request, attempt);
+        match middleware.send(copy).await {
+            Ok(response) => {
+                error_action.ok_with(attempt, &response);
+                return Ok((response, attempt));
+            }
+            Err(e) => {
+                let policy = error_action.handle(attempt, e);
+                let next = policy.next_attempt(attempt);
+                match policy {
+                    RetryPolicy::ForwardError(e) => {
+                        error_action.exhausted(attempt, &e);
+                        return Err((e, attempt));
+                    }
+                    RetryPolicy::Repeat | RetryPolicy::RepeatWithoutCounting => {}
+                    RetryPolicy::WaitRetry(delay) | RetryPolicy::RetryAs { delay, .. } => {
+                        TokioSleeper.sleep(delay).await
+                    }
+                }
+                attempt = next;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::{ready, Ready};
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct Recording {
+        attempts: Vec<usize>,
+    }
+
+    impl Idempotent for u8 {}
+
+    impl RetryMiddleware<u8, u8> for Recording {
+        type Error = u8;
+        type Future = Ready<Result<u8, u8>>;
+
+        fn send(&mut self, request: u8) -> Self::Future {
+            ready(match self.attempts.len() {
+                1 | 2 => Err(request),
+                _ => Ok(request),
+            })
+        }
+
+        fn rebuild(&mut self, request: &u8, attempt: usize) -> u8 {
+            self.attempts.push(attempt);
+            *request + 1
+        }
+    }
+
+    #[tokio::test]
+    async fn rebuilds_every_attempt() {
+        let mut middleware = Recording::default();
+        let handler = |_| RetryPolicy::WaitRetry::<()>(Duration::from_millis(1));
+        assert_eq!(Ok((2, 3)), retry_request(&mut middleware, 1, handler).await);
+        assert_eq!(vec![1, 2, 3], middleware.attempts);
+    }
+
+    #[tokio::test]
+    async fn forwards_errors() {
+        let mut middleware = Recording::default();
+        let handler = |e| match e {
+            2 => RetryPolicy::ForwardError(e),
+            _ => RetryPolicy::Repeat,
+        };
+        assert_eq!(
+            Err((2, 1)),
+            retry_request(&mut middleware, 1, handler).await
+        );
+        assert_eq!(vec![1], middleware.attempts);
+    }
+}
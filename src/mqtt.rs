@@ -0,0 +1,136 @@
+//! Helpers for [`rumqttc`](https://docs.rs/rumqttc) event loops.
+//!
+//! Available with the `mqtt` feature.
+
+use crate::{ErrorHandler, RetryPolicy};
+use futures::{stream, Stream};
+use rumqttc::{ConnectionError, Event, EventLoop, Packet, QoS, Request, Subscribe};
+use tokio::time;
+
+/// Drives a `rumqttc` event loop, pacing reconnections with an error handler and replaying
+/// subscriptions once a connection is (re)established.
+///
+/// An `EventLoop` reconnects to the broker by itself the next time it is polled after an error, so
+/// without any throttling a client that has lost its broker starts reconnecting in a tight loop.
+/// This type passes every `ConnectionError` to an error handler instead, which decides whether to
+/// reconnect right away, wait a bit (preferably with a [`Jitter`](../enum.Jitter.html) so a fleet
+/// of clients doesn't hammer a restarted broker at once) or give up.
+///
+/// Subscriptions registered with [`subscribe`](#method.subscribe) are sent to the broker on every
+/// connection acknowledgement unless the broker reports that the session is still present.
+///
+/// ```no_run
+/// use futures_retry::{mqtt::EventLoopRetry, ExponentialBackoff, Jitter};
+/// use rumqttc::{AsyncClient, MqttOptions, QoS};
+/// use std::time::Duration;
+///
+/// # async fn run() {
+/// let options = MqttOptions::new("gateway-17", "broker.local", 1883);
+/// let (_client, eventloop) = AsyncClient::new(options, 10);
+/// let backoff = ExponentialBackoff::new(Duration::from_millis(100))
+///     .max_delay(Duration::from_secs(30))
+///     .jitter(Jitter::Full);
+/// let mut eventloop = EventLoopRetry::new(eventloop, backoff)
+///     .subscribe("sensors/+/temperature", QoS::AtLeastOnce);
+/// while let Ok((event, _attempt)) = eventloop.poll().await {
+///     println!("{:?}", event);
+/// }
+/// # }
+/// ```
+pub struct EventLoopRetry<R> {
+    eventloop: EventLoop,
+    subscriptions: Vec<(String, QoS)>,
+    error_action: R,
+    attempt: usize,
+}
+
+impl<R> EventLoopRetry<R> {
+    /// Wraps an `eventloop`, using `error_action` to handle connection errors.
+    pub fn new(eventloop: EventLoop, error_action: R) -> Self {
+        Self {
+            eventloop,
+            subscriptions: Vec::new(),
+            error_action,
+            attempt: 1,
+        }
+    }
+
+    /// Registers a subscription that will be (re)sent to the broker after each connection.
+    pub fn subscribe<S: Into<String>>(mut self, topic: S, qos: QoS) -> Self {
+        self.subscriptions.push((topic.into(), qos));
+        self
+    }
+
+    /// Returns a reference to the wrapped event loop.
+    pub fn eventloop(&self) -> &EventLoop {
+        &self.eventloop
+    }
+
+    /// Returns a mutable reference to the wrapped event loop.
+    pub fn eventloop_mut(&mut self) -> &mut EventLoop {
+        &mut self.eventloop
+    }
+
+    /// Unwraps the event loop.
+    pub fn into_inner(self) -> EventLoop {
+        self.eventloop
+    }
+
+    fn replay_subscriptions(&mut self) {
+        let requests = self
+            .subscriptions
+            .iter()
+            .map(|(topic, qos)| Request::Subscribe(Subscribe::new(topic.as_str(), *qos)));
+        self.eventloop.pending.extend(requests);
+    }
+}
+
+impl<R> EventLoopRetry<R>
+where
+    R: ErrorHandler<ConnectionError>,
+{
+    /// Polls the event loop for the next event, reconnecting if required.
+    ///
+    /// Just like with the other types of this crate, a successful result carries the attempt
+    /// number it took to get the event, and an error carries an attempt number of the failure.
+    pub async fn poll(&mut self) -> Result<(Event, usize), (R::OutError, usize)> {
+        loop {
+            let attempt = self.attempt;
+            match self.eventloop.poll().await {
+                Ok(event) => {
+                    if let Event::Incoming(Packet::ConnAck(connack)) = &event {
+                        if !connack.session_present {
+                            self.replay_subscriptions();
+                        }
+                    }
+                    self.attempt = 1;
+                    self.error_action.ok_with(attempt, &event);
+                    return Ok((event, attempt));
+                }
+                Err(e) => {
+                    let policy = self.error_action.handle(attempt, e);
+                    self.attempt = policy.next_attempt(attempt);
+                    match policy {
+                        RetryPolicy::ForwardError(e) => {
+                            self.error_action.exhausted(attempt, &e);
+                            return Err((e, attempt));
+                        }
+                        RetryPolicy::Repeat | RetryPolicy::RepeatWithoutCounting => {}
+                        RetryPolicy::WaitRetry(duration)
+                        | RetryPolicy::RetryAs {
+                            delay: duration, ..
+                        } => time::sleep(duration).await,
+                    }
+                }
+            }
+        }
+    }
+
+    /// Converts the event loop into an endless stream of events.
+    pub fn into_stream(self) -> impl Stream<Item = Result<(Event, usize), (R::OutError, usize)>> {
+        stream::unfold(self, |mut this| async move {
+            let item = this.poll().await;
+            Some((item, this))
+        })
+    }
+}
@@ -0,0 +1,89 @@
+//! Helpers for [`async-nats`](https://docs.rs/async-nats) subscriptions.
+//!
+//! Available with the `nats` feature.
+
+use crate::{FutureFactory, ReconnectingStream};
+use async_nats::{Client, Message, Subject, SubscribeError, Subscriber, ToSubject};
+use futures::stream::{self, SelectAll, StreamExt};
+use std::{future::Future, pin::Pin, sync::Arc};
+
+/// A stream of messages received on all the subscribed subjects.
+pub type Subscription =
+    stream::Map<SelectAll<Subscriber>, fn(Message) -> Result<Message, SubscribeError>>;
+
+/// A factory that subscribes a client to a set of subjects.
+///
+/// Used by [`subscribe`](fn.subscribe.html) to resubscribe to all the subjects once a subscription
+/// is lost.
+#[derive(Clone)]
+pub struct Resubscriber {
+    client: Client,
+    subjects: Arc<[Subject]>,
+}
+
+impl Resubscriber {
+    /// Creates a factory that subscribes the `client` to every subject from `subjects`.
+    pub fn new<I>(client: Client, subjects: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: ToSubject,
+    {
+        Self {
+            client,
+            subjects: subjects.into_iter().map(|s| s.to_subject()).collect(),
+        }
+    }
+}
+
+impl FutureFactory for Resubscriber {
+    type FutureItem = Pin<Box<dyn Future<Output = Result<Subscription, SubscribeError>> + Send>>;
+
+    fn new(&mut self) -> Self::FutureItem {
+        let client = self.client.clone();
+        let subjects = Arc::clone(&self.subjects);
+        Box::pin(async move {
+            let mut subscribers = Vec::with_capacity(subjects.len());
+            for subject in subjects.iter() {
+                subscribers.push(client.subscribe(subject.clone()).await?);
+            }
+            Ok(stream::select_all(subscribers).map(Ok as fn(_) -> _))
+        })
+    }
+}
+
+/// Subscribes the `client` to the given `subjects` and keeps the subscription alive.
+///
+/// Whenever a subscription fails or ends (for example, when the server has dropped it), the
+/// client is resubscribed to all the `subjects`, and `error_action` decides how to pace the
+/// attempts. Messages from all the subjects are merged into a single stream.
+///
+/// ```no_run
+/// use futures::TryStreamExt;
+/// use futures_retry::RetryPolicy;
+/// use std::time::Duration;
+///
+/// # async fn run() -> Result<(), async_nats::Error> {
+/// let client = async_nats::connect("nats://127.0.0.1:4222").await?;
+/// futures_retry::nats::subscribe(client, ["orders.*", "invoices.*"], |_| {
+///     RetryPolicy::WaitRetry::<async_nats::SubscribeError>(Duration::from_secs(1))
+/// })
+/// .try_for_each(|(message, _attempt)| async move {
+///     println!("Got a message on {}", message.subject);
+///     Ok(())
+/// })
+/// .await
+/// .map_err(|(e, _attempt)| e)?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn subscribe<I, R>(
+    client: Client,
+    subjects: I,
+    error_action: R,
+) -> ReconnectingStream<Resubscriber, R>
+where
+    I: IntoIterator,
+    I::Item: ToSubject,
+{
+    ReconnectingStream::new(Resubscriber::new(client, subjects), error_action)
+}
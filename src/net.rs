@@ -0,0 +1,534 @@
+//! Reconnecting TCP and Unix domain streams.
+//!
+//! Available with the `net` feature.
+
+use crate::{ErrorHandler, ReconnectingIo, StreamRetry};
+use futures::{future::BoxFuture, stream::FuturesUnordered, Stream, StreamExt};
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::{
+    fmt, io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, Mutex, PoisonError},
+    task::{Context, Poll},
+    time::Duration,
+};
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio::{
+    net::{lookup_host, TcpListener, TcpStream},
+    time::{timeout, Instant},
+};
+
+/// A TCP stream that transparently reconnects to its address whenever it breaks, see
+/// [`ReconnectingIo`](../struct.ReconnectingIo.html).
+pub type RetryTcpStream<H, C = TcpConnector> = ReconnectingIo<C, H>;
+
+#[derive(Debug)]
+struct Resolved {
+    addrs: Vec<SocketAddr>,
+    at: Instant,
+    uses: usize,
+}
+
+/// Connects to a TCP address, trying all the addresses the host name resolves into in turn.
+///
+/// By default the host name is resolved anew on every connection, so the retries fail over to
+/// whatever healthy hosts the DNS points at by then. The resolution might be cached for a number
+/// of connections ([`resolve_every`](#method.resolve_every)) or for a period of time
+/// ([`resolution_ttl`](#method.resolution_ttl)) instead; with both set, the host name is resolved
+/// again as soon as either limit is reached. Clones share the cache.
+#[derive(Clone)]
+pub struct TcpConnector {
+    addr: Arc<str>,
+    nodelay: bool,
+    happy_eyeballs: Option<Duration>,
+    resolve_every: Option<usize>,
+    resolution_ttl: Option<Duration>,
+    resolved: Arc<Mutex<Option<Resolved>>>,
+}
+
+impl TcpConnector {
+    /// Creates a connector to the `addr`, a `host:port` string.
+    pub fn new(addr: impl Into<String>) -> Self {
+        Self {
+            addr: addr.into().into(),
+            nodelay: false,
+            happy_eyeballs: None,
+            resolve_every: None,
+            resolution_ttl: None,
+            resolved: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Sets the `TCP_NODELAY` option on every new connection.
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = nodelay;
+        self
+    }
+
+    /// Races the connections to the resolved addresses Happy Eyeballs style (RFC 8305), instead of
+    /// trying them one after another: the IPv6 and IPv4 addresses are interleaved, and the next
+    /// connection is started as soon as the previous one fails or after the `stagger` (250 ms is
+    /// the recommended one), whichever comes first. The first established connection wins, so a
+    /// broken route of one family doesn't add a connection timeout to every attempt.
+    pub fn happy_eyeballs(mut self, stagger: Duration) -> Self {
+        self.happy_eyeballs = Some(stagger);
+        self
+    }
+
+    /// Resolves the host name only on every `connections`-th connection, reusing the addresses in
+    /// between.
+    pub fn resolve_every(mut self, connections: usize) -> Self {
+        self.resolve_every = Some(connections.max(1));
+        self
+    }
+
+    /// Reuses the resolved addresses until the `ttl` expires.
+    pub fn resolution_ttl(mut self, ttl: Duration) -> Self {
+        self.resolution_ttl = Some(ttl);
+        self
+    }
+
+    /// Returns the cached addresses, if they are still fresh, counting one more use of them.
+    fn cached(&self) -> Option<Vec<SocketAddr>> {
+        if self.resolve_every.is_none() && self.resolution_ttl.is_none() {
+            return None;
+        }
+        let mut resolved = self.resolved.lock().unwrap_or_else(PoisonError::into_inner);
+        let resolved = resolved.as_mut()?;
+        let used_up = self
+            .resolve_every
+            .is_some_and(|every| resolved.uses >= every);
+        let expired = self
+            .resolution_ttl
+            .is_some_and(|ttl| resolved.at.elapsed() >= ttl);
+        if used_up || expired {
+            return None;
+        }
+        resolved.uses += 1;
+        Some(resolved.addrs.clone())
+    }
+}
+
+impl fmt::Debug for TcpConnector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TcpConnector")
+            .field("addr", &self.addr)
+            .field("nodelay", &self.nodelay)
+            .field("happy_eyeballs", &self.happy_eyeballs)
+            .field("resolve_every", &self.resolve_every)
+            .field("resolution_ttl", &self.resolution_ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+impl crate::Connector for TcpConnector {
+    type Io = TcpStream;
+    type Future = BoxFuture<'static, io::Result<TcpStream>>;
+
+    fn connect(&mut self) -> Self::Future {
+        let cached = self.cached();
+        let (addr, nodelay, stagger) = (Arc::clone(&self.addr), self.nodelay, self.happy_eyeballs);
+        let resolved = Arc::clone(&self.resolved);
+        Box::pin(async move {
+            let addrs = match cached {
+                Some(addrs) => addrs,
+                None => {
+                    let addrs: Vec<_> = lookup_host(&*addr).await?.collect();
+                    *resolved.lock().unwrap_or_else(PoisonError::into_inner) = Some(Resolved {
+                        addrs: addrs.clone(),
+                        at: Instant::now(),
+                        uses: 1,
+                    });
+                    addrs
+                }
+            };
+            let stream = match stagger {
+                Some(stagger) => race(interleave(addrs), stagger).await?,
+                None => connect_in_turn(addrs).await?,
+            };
+            stream.set_nodelay(nodelay)?;
+            Ok(stream)
+        })
+    }
+}
+
+fn no_addresses() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "could not resolve to any address",
+    )
+}
+
+async fn connect_in_turn(addrs: Vec<SocketAddr>) -> io::Result<TcpStream> {
+    let mut last_error = None;
+    for addr in addrs {
+        match TcpStream::connect(addr).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(no_addresses))
+}
+
+/// Alternates the address families, starting with IPv6.
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let (v6, v4): (Vec<_>, Vec<_>) = addrs.into_iter().partition(SocketAddr::is_ipv6);
+    let (mut v6, mut v4) = (v6.into_iter(), v4.into_iter());
+    let mut interleaved = Vec::with_capacity(v6.len() + v4.len());
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => return interleaved,
+            (v6, v4) => interleaved.extend(v6.into_iter().chain(v4)),
+        }
+    }
+}
+
+async fn race(addrs: Vec<SocketAddr>, stagger: Duration) -> io::Result<TcpStream> {
+    let mut pending = addrs.into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut last_error = None;
+    loop {
+        match pending.next() {
+            Some(addr) => attempts.push(TcpStream::connect(addr)),
+            None if attempts.is_empty() => return Err(last_error.unwrap_or_else(no_addresses)),
+            None => {}
+        }
+        // Wait for an attempt to finish, but only for the stagger if there are more addresses.
+        let finished = if pending.len() > 0 {
+            match timeout(stagger, attempts.next()).await {
+                Ok(finished) => finished,
+                Err(_) => continue,
+            }
+        } else {
+            attempts.next().await
+        };
+        match finished {
+            Some(Ok(stream)) => return Ok(stream),
+            Some(Err(e)) => last_error = Some(e),
+            None => {}
+        }
+    }
+}
+
+impl<H> ReconnectingIo<TcpConnector, H> {
+    /// Creates a TCP stream that connects to the `addr`, a `host:port` string, on the first use.
+    ///
+    /// ```
+    /// use futures_retry::{net::RetryTcpStream, RetryPolicy};
+    /// use std::{io, time::Duration};
+    /// use tokio::io::AsyncWriteExt;
+    ///
+    /// # async fn run() -> io::Result<()> {
+    /// let stream = RetryTcpStream::tcp("localhost:6379", |_| {
+    ///     RetryPolicy::WaitRetry::<io::Error>(Duration::from_secs(1))
+    /// });
+    /// tokio::pin!(stream);
+    /// stream.write_all(b"PING\r\n").await?;
+    /// # Ok(()) }
+    /// ```
+    pub fn tcp(addr: impl Into<String>, error_action: H) -> Self {
+        Self::new(TcpConnector::new(addr), error_action)
+    }
+}
+
+/// Accepts the connections of a TCP listener, passing every accept error to the `error_action`
+/// instead of ending the stream on the first one.
+///
+/// Accepting fails transiently all the time on a busy server: a client might give up on the
+/// connection before it is accepted (`ECONNABORTED`), or the process might run out of file
+/// descriptors (`EMFILE`) until some connections are closed. The `error_action` decides which of
+/// the errors are worth a (possibly delayed) retry and which should end the stream.
+///
+/// ```no_run
+/// use futures::TryStreamExt;
+/// use futures_retry::{net::retrying_incoming, RetryPolicy};
+/// use std::{io, time::Duration};
+/// use tokio::net::TcpListener;
+///
+/// # async fn run() -> io::Result<()> {
+/// let listener = TcpListener::bind("127.0.0.1:8080").await?;
+/// retrying_incoming(listener, |e: io::Error| match e.kind() {
+///     io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionReset => RetryPolicy::Repeat,
+///     io::ErrorKind::PermissionDenied => RetryPolicy::ForwardError(e),
+///     // Running out of file descriptors among others: wait for some connections to close.
+///     _ => RetryPolicy::WaitRetry(Duration::from_millis(100)),
+/// })
+/// .map_err(|(e, _attempt)| e)
+/// .try_for_each(|(stream, _attempt)| async move {
+///     tokio::spawn(async move { drop(stream) });
+///     Ok(())
+/// })
+/// .await
+/// # }
+/// ```
+pub fn retrying_incoming<H>(
+    listener: TcpListener,
+    error_action: H,
+) -> impl Stream<Item = Result<(TcpStream, usize), (H::OutError, usize)>>
+where
+    H: ErrorHandler<io::Error>,
+{
+    StreamRetry::new(Incoming { listener }, error_action)
+}
+
+/// A listener the connections are accepted from, so the tests might inject the accept errors.
+trait Accept {
+    type Stream;
+
+    fn poll_accept(&self, cx: &mut Context) -> Poll<io::Result<Self::Stream>>;
+}
+
+impl Accept for TcpListener {
+    type Stream = TcpStream;
+
+    fn poll_accept(&self, cx: &mut Context) -> Poll<io::Result<TcpStream>> {
+        TcpListener::poll_accept(self, cx).map_ok(|(stream, _)| stream)
+    }
+}
+
+/// The connections accepted by a listener, which is kept across the accept errors, unlike the
+/// state of a `try_unfold`.
+struct Incoming<L> {
+    listener: L,
+}
+
+impl<L: Accept> Stream for Incoming<L> {
+    type Item = io::Result<L::Stream>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        self.listener.poll_accept(cx).map(Some)
+    }
+}
+
+/// A Unix domain stream that transparently reconnects to its socket whenever it breaks, e.g. when
+/// the local daemon listening on it restarts; see [`ReconnectingIo`](../struct.ReconnectingIo.html).
+#[cfg(unix)]
+pub type RetryUnixStream<H, C = UnixConnector> = ReconnectingIo<C, H>;
+
+/// Connects to a Unix domain socket.
+#[cfg(unix)]
+#[derive(Debug, Clone)]
+pub struct UnixConnector {
+    path: Arc<Path>,
+}
+
+#[cfg(unix)]
+impl UnixConnector {
+    /// Creates a connector to the socket at the `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into().into(),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl crate::Connector for UnixConnector {
+    type Io = UnixStream;
+    type Future = BoxFuture<'static, io::Result<UnixStream>>;
+
+    fn connect(&mut self) -> Self::Future {
+        let path = Arc::clone(&self.path);
+        Box::pin(async move { UnixStream::connect(path).await })
+    }
+}
+
+#[cfg(unix)]
+impl<H> ReconnectingIo<UnixConnector, H> {
+    /// Creates a Unix domain stream that connects to the socket at the `path` on the first use.
+    ///
+    /// ```
+    /// use futures_retry::{net::RetryUnixStream, RetryPolicy};
+    /// use std::{io, time::Duration};
+    /// use tokio::io::AsyncWriteExt;
+    ///
+    /// # async fn run() -> io::Result<()> {
+    /// let stream = RetryUnixStream::unix("/var/run/docker.sock", |_| {
+    ///     RetryPolicy::WaitRetry::<io::Error>(Duration::from_secs(1))
+    /// });
+    /// tokio::pin!(stream);
+    /// stream.write_all(b"GET /_ping HTTP/1.0\r\n\r\n").await?;
+    /// # Ok(()) }
+    /// ```
+    pub fn unix(path: impl Into<PathBuf>, error_action: H) -> Self {
+        Self::new(UnixConnector::new(path), error_action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Connector, RetryPolicy};
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    #[tokio::test]
+    async fn reconnects() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        // Nobody listens on the address until the server is up.
+        drop(listener);
+        let server = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let listener = TcpListener::bind(addr).await.unwrap();
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut received = [0; 4];
+            stream.read_exact(&mut received).await.unwrap();
+            received
+        });
+        let stream = RetryTcpStream::tcp(addr.to_string(), |e: io::Error| match e.kind() {
+            io::ErrorKind::ConnectionRefused => RetryPolicy::WaitRetry(Duration::from_millis(10)),
+            _ => RetryPolicy::ForwardError(e),
+        });
+        tokio::pin!(stream);
+        stream.write_all(b"ping").await.unwrap();
+        assert_eq!(b"ping", &server.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn caches_resolution() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut connector = TcpConnector::new(format!("localhost:{}", port)).resolve_every(2);
+        let uses = |connector: &TcpConnector| {
+            connector
+                .resolved
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map(|resolved| resolved.uses)
+        };
+        assert_eq!(None, uses(&connector));
+        for expected in [1, 2, 1] {
+            // The listener might only be bound to one of the addresses `localhost` resolves to.
+            let _ = connector.connect().await;
+            assert_eq!(Some(expected), uses(&connector));
+        }
+        let mut connector = TcpConnector::new(format!("127.0.0.1:{}", port));
+        connector.connect().await.unwrap();
+        assert_eq!(None, connector.cached());
+    }
+
+    #[tokio::test]
+    async fn accepts_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let incoming = retrying_incoming(listener, |e: io::Error| RetryPolicy::ForwardError(e));
+        tokio::pin!(incoming);
+        for _ in 0..2 {
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            client.write_all(b"ping").await.unwrap();
+            let (mut stream, attempt) = incoming.next().await.unwrap().unwrap();
+            assert_eq!(1, attempt);
+            let mut received = [0; 4];
+            stream.read_exact(&mut received).await.unwrap();
+            assert_eq!(b"ping", &received);
+        }
+    }
+
+    /// Fails the first accepts before passing them to the real listener.
+    struct Flaky {
+        listener: TcpListener,
+        failures: AtomicUsize,
+    }
+
+    impl Accept for Flaky {
+        type Stream = TcpStream;
+
+        fn poll_accept(&self, cx: &mut Context) -> Poll<io::Result<TcpStream>> {
+            if self.failures.load(Ordering::Relaxed) > 0 {
+                self.failures.fetch_sub(1, Ordering::Relaxed);
+                return Poll::Ready(Err(io::ErrorKind::ConnectionAborted.into()));
+            }
+            Accept::poll_accept(&self.listener, cx)
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_accepting_after_errors() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let flaky = Flaky {
+            listener,
+            failures: AtomicUsize::new(2),
+        };
+        let incoming = StreamRetry::new(Incoming { listener: flaky }, |e: io::Error| {
+            match e.kind() {
+                io::ErrorKind::ConnectionAborted => RetryPolicy::Repeat,
+                _ => RetryPolicy::ForwardError(e),
+            }
+        });
+        tokio::pin!(incoming);
+        let _client = TcpStream::connect(addr).await.unwrap();
+        let (_, attempt) = incoming.next().await.unwrap().unwrap();
+        assert_eq!(3, attempt);
+        let _client = TcpStream::connect(addr).await.unwrap();
+        let (_, attempt) = incoming.next().await.unwrap().unwrap();
+        assert_eq!(1, attempt);
+    }
+
+    #[test]
+    fn interleaves() {
+        let addrs: Vec<SocketAddr> = ["1.1.1.1:1", "1.1.1.2:1", "[::1]:1", "1.1.1.3:1", "[::2]:1"]
+            .iter()
+            .map(|addr| addr.parse().unwrap())
+            .collect();
+        let interleaved: Vec<_> = interleave(addrs)
+            .into_iter()
+            .map(|addr| addr.ip().to_string())
+            .collect();
+        assert_eq!(
+            vec!["::1", "1.1.1.1", "::2", "1.1.1.2", "1.1.1.3"],
+            interleaved
+        );
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_ipv4() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // Nothing listens on the IPv6 loopback (if there is one at all).
+        let addrs = vec![
+            format!("[::1]:{}", port).parse().unwrap(),
+            format!("127.0.0.1:{}", port).parse().unwrap(),
+        ];
+        let stream = race(addrs, Duration::from_millis(250)).await.unwrap();
+        assert!(stream.peer_addr().unwrap().is_ipv4());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn reconnects_unix() {
+        let path = std::env::temp_dir().join(format!("futures-retry-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let stream = RetryUnixStream::unix(&path, |e: io::Error| match e.kind() {
+            io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused => {
+                RetryPolicy::WaitRetry(Duration::from_millis(10))
+            }
+            _ => RetryPolicy::ForwardError(e),
+        });
+        let server_path = path.clone();
+        let server = tokio::spawn(async move {
+            // The daemon comes up a bit later.
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let listener = tokio::net::UnixListener::bind(server_path).unwrap();
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut received = [0; 4];
+            stream.read_exact(&mut received).await.unwrap();
+            received
+        });
+        tokio::pin!(stream);
+        stream.write_all(b"ping").await.unwrap();
+        assert_eq!(b"ping", &server.await.unwrap());
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,81 @@
+use crate::{FutureFactory, FutureRetry};
+use futures::{future::Map, FutureExt};
+use std::{error::Error, fmt, future::Future};
+
+/// An error that is passed to the error handler of [`retry_some`](fn.retry_some.html) when a
+/// future resolves into `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoValue;
+
+impl fmt::Display for NoValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("no value yet")
+    }
+}
+
+impl Error for NoValue {}
+
+/// A factory adapter that turns futures resolving into `Option<T>` into futures resolving into
+/// `Result<T, NoValue>`.
+///
+/// Created by [`retry_some`](fn.retry_some.html).
+#[derive(Clone)]
+pub struct SomeFactory<F>(F);
+
+impl<F> fmt::Debug for SomeFactory<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SomeFactory").finish_non_exhaustive()
+    }
+}
+
+impl<F, Fut, T> FutureFactory for SomeFactory<F>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Option<T>>,
+{
+    type FutureItem = Map<Fut, fn(Option<T>) -> Result<T, NoValue>>;
+
+    fn new(&mut self) -> Self::FutureItem {
+        (self.0)().map(|value| value.ok_or(NoValue))
+    }
+}
+
+/// Retries a future until it resolves into `Some` value.
+///
+/// Polling APIs often report "nothing yet" with an empty value rather than an error. This helper
+/// treats `None` as a [`NoValue`](struct.NoValue.html) error, so the usual error handlers (a
+/// backoff for example) decide how long to wait before the next attempt and when to give up.
+///
+/// ```
+/// use futures_retry::{retry_some, ExponentialBackoff};
+/// use std::time::Duration;
+///
+/// # #[tokio::main] async fn main() {
+/// let mut messages = vec![None, None, Some("hello")].into_iter();
+/// let backoff = ExponentialBackoff::new(Duration::from_millis(1)).max_attempts(5);
+/// let message = retry_some(|| futures::future::ready(messages.next().unwrap()), backoff);
+/// assert_eq!(Ok(("hello", 3)), message.await);
+/// # }
+/// ```
+pub fn retry_some<F, Fut, T, R>(factory: F, error_action: R) -> FutureRetry<SomeFactory<F>, R>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Option<T>>,
+{
+    FutureRetry::new(SomeFactory(factory), error_action)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RetryPolicy;
+    use futures::future::ready;
+
+    #[tokio::test]
+    async fn gives_up_on_none() {
+        let retry = retry_some(|| ready(None::<u8>), RetryPolicy::ForwardError);
+        assert_eq!(Err((NoValue, 1)), retry.await);
+        let retry = retry_some(|| ready(Some(1)), RetryPolicy::ForwardError);
+        assert_eq!(Ok((1, 1)), retry.await);
+    }
+}
@@ -0,0 +1,227 @@
+use crate::{ErrorHandler, RetryPolicy};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// Tells a [`Phased`](struct.Phased.html) handler which phase of an operation an attempt is in.
+///
+/// A tracker is shared between the handler and the factory (it is cheap to clone), and the
+/// factory marks the phases either with [`set`](#method.set) or with the guards returned by
+/// [`enter`](#method.enter). Every attempt starts with no phase, i.e. in the default one.
+#[derive(Debug, Clone, Default)]
+pub struct PhaseTracker {
+    current: Arc<Mutex<Option<&'static str>>>,
+}
+
+impl PhaseTracker {
+    /// Creates a tracker, in the default phase.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes the `phase` the active one.
+    pub fn set(&self, phase: &'static str) {
+        *self.lock() = Some(phase);
+    }
+
+    /// Makes the `phase` the active one until the returned guard is
+    /// [`complete`](struct.PhaseGuard.html#method.complete)d.
+    pub fn enter(&self, phase: &'static str) -> PhaseGuard {
+        let previous = self.lock().replace(phase);
+        PhaseGuard {
+            tracker: self.clone(),
+            previous,
+        }
+    }
+
+    /// Returns the active phase, or `None` for the default one.
+    pub fn current(&self) -> Option<&'static str> {
+        *self.lock()
+    }
+
+    fn take(&self) -> Option<&'static str> {
+        self.lock().take()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<&'static str>> {
+        self.current.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A phase entered with [`PhaseTracker::enter`](struct.PhaseTracker.html#method.enter).
+///
+/// A phase that is left without completing it, e.g. by returning early with `?`, stays active, so
+/// the error it has failed with is handled by the policy of the phase.
+#[derive(Debug)]
+#[must_use = "a phase stays active until its guard is completed"]
+pub struct PhaseGuard {
+    tracker: PhaseTracker,
+    previous: Option<&'static str>,
+}
+
+impl PhaseGuard {
+    /// Leaves the phase, making the previous one active again.
+    pub fn complete(self) {
+        *self.tracker.lock() = self.previous;
+    }
+}
+
+/// A composite error handler with an own sub-handler for each named phase of an operation, e.g.
+/// looser rules for connecting than for executing a request.
+///
+/// A failure is passed to the handler of the phase it has happened in, as reported to the
+/// [`tracker`](#method.tracker), or to the default handler when the phase has no handler. Every
+/// phase counts its own attempts, so the attempts spent on connecting don't use up the ones of
+/// the request; a success resets all the counters.
+///
+/// ```
+/// use futures_retry::{ExponentialBackoff, FutureRetry, Phased};
+/// use std::time::Duration;
+///
+/// # #[tokio::main] async fn main() {
+/// let backoff = |max_attempts| {
+///     ExponentialBackoff::new(Duration::from_millis(1)).max_attempts(max_attempts)
+/// };
+/// let handler = Phased::new(backoff(1))
+///     .phase("connect", backoff(10))
+///     .phase("request", backoff(2));
+/// let tracker = handler.tracker();
+/// let mut attempts = 0;
+/// let result = FutureRetry::new(
+///     || {
+///         attempts += 1;
+///         let tracker = tracker.clone();
+///         async move {
+///             let connecting = tracker.enter("connect");
+///             // Connecting succeeds on the fifth attempt only.
+///             if attempts < 5 {
+///                 return Err("connection refused");
+///             }
+///             connecting.complete();
+///             tracker.set("request");
+///             Err::<(), _>("internal server error")
+///         }
+///     },
+///     handler,
+/// )
+/// .await;
+/// // Four failed connections, then two failed requests.
+/// assert_eq!(Err(("internal server error", 6)), result);
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Phased<H> {
+    fallback: (H, usize),
+    phases: Vec<(&'static str, H, usize)>,
+    tracker: PhaseTracker,
+    // The index of the phase of the last failure, `None` for the default one.
+    last: Option<usize>,
+}
+
+impl<H> Phased<H> {
+    /// Creates a handler for the failures of the default phase, and of the phases with no
+    /// handlers.
+    pub fn new(fallback: H) -> Self {
+        Self {
+            fallback: (fallback, 0),
+            phases: Vec::new(),
+            tracker: PhaseTracker::new(),
+            last: None,
+        }
+    }
+
+    /// Sets a handler for the failures of a phase.
+    pub fn phase(mut self, phase: &'static str, handler: H) -> Self {
+        match self.phases.iter_mut().find(|(name, ..)| *name == phase) {
+            Some(entry) => entry.1 = handler,
+            None => self.phases.push((phase, handler, 0)),
+        }
+        self
+    }
+
+    /// Returns the tracker the phases of the attempts should be reported to.
+    pub fn tracker(&self) -> PhaseTracker {
+        self.tracker.clone()
+    }
+
+    fn reset(&mut self) {
+        self.fallback.1 = 0;
+        for (_, _, failures) in &mut self.phases {
+            *failures = 0;
+        }
+    }
+}
+
+impl<E, H: ErrorHandler<E>> ErrorHandler<E> for Phased<H> {
+    type OutError = H::OutError;
+
+    fn handle(&mut self, _attempt: usize, e: E) -> RetryPolicy<H::OutError> {
+        // The next attempt starts in the default phase again.
+        let phase = self.tracker.take();
+        self.last =
+            phase.and_then(|phase| self.phases.iter().position(|(name, ..)| *name == phase));
+        let (handler, failures) = match self.last {
+            Some(index) => {
+                let (_, handler, failures) = &mut self.phases[index];
+                (handler, failures)
+            }
+            None => (&mut self.fallback.0, &mut self.fallback.1),
+        };
+        *failures += 1;
+        handler.handle(*failures, e)
+    }
+
+    fn ok(&mut self, attempt: usize) {
+        self.tracker.take();
+        self.reset();
+        self.fallback.0.ok(attempt);
+        for (_, handler, _) in &mut self.phases {
+            handler.ok(attempt);
+        }
+    }
+
+    fn ok_with<V: ?Sized>(&mut self, attempt: usize, value: &V) {
+        self.tracker.take();
+        self.reset();
+        self.fallback.0.ok_with(attempt, value);
+        for (_, handler, _) in &mut self.phases {
+            handler.ok_with(attempt, value);
+        }
+    }
+
+    fn exhausted(&mut self, _attempt: usize, error: &H::OutError) {
+        match self.last {
+            Some(index) => {
+                let (_, handler, failures) = &mut self.phases[index];
+                handler.exhausted(*failures, error)
+            }
+            None => self.fallback.0.exhausted(self.fallback.1, error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ExponentialBackoff;
+    use std::time::Duration;
+
+    #[test]
+    fn counts_per_phase() {
+        let backoff =
+            |max_attempts| ExponentialBackoff::new(Duration::ZERO).max_attempts(max_attempts);
+        let mut handler = Phased::new(backoff(1)).phase("connect", backoff(3));
+        let tracker = handler.tracker();
+        tracker.set("connect");
+        assert!(matches!(handler.handle(1, ()), RetryPolicy::WaitRetry(_)));
+        // An unknown phase falls back to the default handler.
+        tracker.set("read-body");
+        assert_eq!(RetryPolicy::ForwardError(()), handler.handle(2, ()));
+        assert_eq!(None, tracker.current());
+        tracker.enter("connect").complete();
+        assert_eq!(None, tracker.current());
+        let _connecting = tracker.enter("connect");
+        assert!(matches!(handler.handle(3, ()), RetryPolicy::WaitRetry(_)));
+        ErrorHandler::<()>::ok(&mut handler, 4);
+        tracker.set("connect");
+        assert!(matches!(handler.handle(1, ()), RetryPolicy::WaitRetry(_)));
+    }
+}
@@ -0,0 +1,110 @@
+//! Helpers for [`tokio-postgres`](https://docs.rs/tokio-postgres) transactions.
+//!
+//! Available with the `postgres` feature.
+
+use crate::{ErrorHandler, RetryPolicy, Sleeper};
+use std::{future::Future, pin::Pin};
+use tokio_postgres::{error::SqlState, Client, Error, IsolationLevel, Transaction};
+
+/// Checks whether an error is a serialization failure (`40001`) or a deadlock (`40P01`), i.e.
+/// whether the whole transaction should be retried.
+pub fn is_serialization_failure(e: &Error) -> bool {
+    matches!(
+        e.code(),
+        Some(&SqlState::T_R_SERIALIZATION_FAILURE) | Some(&SqlState::T_R_DEADLOCK_DETECTED)
+    )
+}
+
+/// Runs a transaction, re-running it from scratch each time the database reports a serialization
+/// failure or a deadlock, as recommended by the PostgreSQL documentation.
+///
+/// On each attempt a new transaction with the given `isolation_level` is started, the
+/// `transaction` closure is executed within it and then the transaction is committed. If any of
+/// these steps fails due to a serialization failure or a deadlock (see
+/// [`is_serialization_failure`](fn.is_serialization_failure.html)), the error is passed to the
+/// `error_action` which decides whether to try again, wait and then try, or give up. Any other
+/// error is returned immediately. The delays between the attempts are timed by the `sleeper`.
+///
+/// Keep in mind that the closure might be called several times, so it must not have side effects
+/// outside of the transaction.
+///
+/// ```no_run
+/// use futures_retry::{postgres::retry_transaction, ExponentialBackoff, Jitter, TokioSleeper};
+/// use std::time::Duration;
+/// use tokio_postgres::IsolationLevel;
+///
+/// # async fn run(client: &mut tokio_postgres::Client) -> Result<(), tokio_postgres::Error> {
+/// let backoff = ExponentialBackoff::new(Duration::from_millis(10))
+///     .max_attempts(5)
+///     .jitter(Jitter::Full);
+/// let (balance, _attempt) = retry_transaction(
+///     client,
+///     IsolationLevel::Serializable,
+///     backoff,
+///     |tx| {
+///         Box::pin(async move {
+///             tx.execute("UPDATE accounts SET balance = balance - 10 WHERE id = 1", &[])
+///                 .await?;
+///             let row = tx.query_one("SELECT balance FROM accounts WHERE id = 1", &[]).await?;
+///             Ok(row.get::<_, i64>(0))
+///         })
+///     },
+///     TokioSleeper,
+/// )
+/// .await
+/// .map_err(|(e, _attempt)| e)?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn retry_transaction<F, T, R, S>(
+    client: &mut Client,
+    isolation_level: IsolationLevel,
+    mut error_action: R,
+    mut transaction: F,
+    sleeper: S,
+) -> Result<(T, usize), (Error, usize)>
+where
+    F: for<'a> FnMut(
+        &'a mut Transaction<'_>,
+    ) -> Pin<Box<dyn Future<Output = Result<T, Error>> + Send + 'a>>,
+    R: ErrorHandler<Error, OutError = Error>,
+    S: Sleeper,
+{
+    let mut attempt = 1;
+    loop {
+        let result = async {
+            let mut tx = client
+                .build_transaction()
+                .isolation_level(isolation_level)
+                .start()
+                .await?;
+            let value = transaction(&mut tx).await?;
+            tx.commit().await?;
+            Ok(value)
+        }
+        .await;
+        match result {
+            Ok(value) => {
+                error_action.ok_with(attempt, &value);
+                return Ok((value, attempt));
+            }
+            Err(e) if is_serialization_failure(&e) => {
+                let policy = error_action.handle(attempt, e);
+                let next = policy.next_attempt(attempt);
+                match policy {
+                    RetryPolicy::ForwardError(e) => {
+                        error_action.exhausted(attempt, &e);
+                        return Err((e, attempt));
+                    }
+                    RetryPolicy::Repeat | RetryPolicy::RepeatWithoutCounting => {}
+                    RetryPolicy::WaitRetry(duration)
+                    | RetryPolicy::RetryAs {
+                        delay: duration, ..
+                    } => sleeper.sleep(duration).await,
+                }
+                attempt = next;
+            }
+            Err(e) => return Err((e, attempt)),
+        }
+    }
+}
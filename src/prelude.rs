@@ -0,0 +1,17 @@
+//! The items most retry loops need, to be imported all at once.
+//!
+//! ```
+//! use futures_retry::prelude::*;
+//! use std::time::Duration;
+//!
+//! # #[tokio::main] async fn main() {
+//! let backoff = exponential(Duration::from_millis(10), 3);
+//! let result = FutureRetry::new(|| async { Ok::<_, std::io::Error>(42) }, backoff).await;
+//! assert_eq!(42, result.unwrap().0);
+//! # }
+//! ```
+
+pub use crate::{
+    default_http, default_io, exponential, safe_defaults, ErrorHandler, ExponentialBackoff,
+    FutureRetry, Jitter, Profile, RetryPolicy, StreamRetry, StreamRetryExt,
+};
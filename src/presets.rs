@@ -0,0 +1,202 @@
+use crate::{BudgetHandler, Classifier, ExponentialBackoff, Hinted, Jitter, RetryBudget};
+use std::{sync::Arc, time::Duration};
+
+/// Creates an exponential backoff starting at the `base` delay and doubling after each failure,
+/// with full jitter, that gives up after `max_attempts` attempts.
+pub fn exponential(base: Duration, max_attempts: usize) -> ExponentialBackoff {
+    ExponentialBackoff::new(base)
+        .max_attempts(max_attempts)
+        .jitter(Jitter::Full)
+}
+
+/// Creates a handler for `io::Error`s that retries the transient ones (refused, reset or aborted
+/// connections, timeouts, interruptions, broken pipes) and forwards the others right away.
+///
+/// The retries back off exponentially from 50 ms up to 5 s, with full jitter, and the handler
+/// gives up after 5 attempts.
+///
+/// ```
+/// use futures_retry::prelude::*;
+/// use std::io;
+///
+/// # #[tokio::main] async fn main() {
+/// let result = FutureRetry::new(
+///     || async { Err::<(), _>(io::Error::from(io::ErrorKind::PermissionDenied)) },
+///     default_io(),
+/// )
+/// .await;
+/// assert!(matches!(result, Err((e, 1)) if e.kind() == io::ErrorKind::PermissionDenied));
+/// # }
+/// ```
+pub fn default_io() -> Classifier<ExponentialBackoff> {
+    let backoff = exponential(Duration::from_millis(50), 5).max_delay(Duration::from_secs(5));
+    Classifier::new(backoff)
+}
+
+/// Creates a handler for HTTP-like errors that honours the delays the server asks for (see
+/// [`RetryHint`](trait.RetryHint.html), e.g. a `Retry-After` header), up to a minute.
+///
+/// Otherwise the retries back off exponentially from 200 ms up to 30 s, with full jitter, and the
+/// handler gives up after 4 attempts. Whether a status is worth retrying at all is left to the
+/// caller: wrap the handler or forward the non-retryable errors before it.
+pub fn default_http() -> Hinted<ExponentialBackoff> {
+    let backoff = exponential(Duration::from_millis(200), 4).max_delay(Duration::from_secs(30));
+    Hinted::new(backoff).max_hint(Duration::from_secs(60))
+}
+
+/// A named set of retry settings for a kind of workload, so a policy might be picked in a word.
+///
+/// | Profile        | Workload    | Delays         | Jitter | Attempts | Budget                 |
+/// |----------------|-------------|----------------|--------|----------|------------------------|
+/// | `Aggressive`   | interactive | 25 ms to 1 s   | full   | 3        | 10%, 20 retries, 5/s   |
+/// | `Standard`     | background  | 100 ms to 10 s | full   | 5        | 20%, 100 retries, 10/s |
+/// | `Conservative` | batch       | 1 s to 2 min   | equal  | 10       | 50%, 200 retries, 1/s  |
+///
+/// The budgets are given as the share of extra load the retries might add, the number of retries
+/// a budget holds, and how many retries it refills every second.
+///
+/// ```
+/// use futures_retry::{FutureRetry, Profile};
+/// use std::sync::Arc;
+///
+/// # #[tokio::main] async fn main() {
+/// let profile = Profile::Aggressive;
+/// let budget = Arc::new(profile.budget());
+/// let handler = profile.handler(&budget);
+/// let retry = FutureRetry::new(|| async { Ok::<_, std::io::Error>(()) }, handler);
+/// # retry.await.unwrap();
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Profile {
+    /// Fails fast for the requests a user is waiting for.
+    Aggressive,
+    /// Suits the background work, the same as [`safe_defaults`](fn.safe_defaults.html).
+    Standard,
+    /// Waits a backend out for the batch jobs that would rather finish late than fail.
+    Conservative,
+}
+
+impl Profile {
+    /// Creates the backoff of the profile, without a budget.
+    pub fn backoff(self) -> ExponentialBackoff {
+        let (base, max_delay, jitter, max_attempts) = match self {
+            Profile::Aggressive => (
+                Duration::from_millis(25),
+                Duration::from_secs(1),
+                Jitter::Full,
+                3,
+            ),
+            Profile::Standard => (
+                Duration::from_millis(100),
+                Duration::from_secs(10),
+                Jitter::Full,
+                5,
+            ),
+            Profile::Conservative => (
+                Duration::from_secs(1),
+                Duration::from_secs(120),
+                Jitter::Equal,
+                10,
+            ),
+        };
+        ExponentialBackoff::new(base)
+            .max_delay(max_delay)
+            .max_attempts(max_attempts)
+            .jitter(jitter)
+    }
+
+    /// Creates a budget sized for the profile, to be shared by the handlers of a backend.
+    pub fn budget(self) -> RetryBudget {
+        match self {
+            Profile::Aggressive => RetryBudget::new(0.1, 20).reserve_per_second(5),
+            Profile::Standard => RetryBudget::default(),
+            Profile::Conservative => RetryBudget::new(0.5, 200).reserve_per_second(1),
+        }
+    }
+
+    /// Creates the handler of the profile, paying for the retries from the `budget`.
+    pub fn handler(self, budget: &Arc<RetryBudget>) -> BudgetHandler<ExponentialBackoff> {
+        RetryBudget::handler(budget, self.backoff())
+    }
+}
+
+/// Creates a handler with responsible defaults that protect a backend from retry storms.
+///
+/// The handler combines:
+///
+/// * an exponential backoff starting at 100 ms, doubling after each failure and capped at 10 s,
+/// * full jitter, so the clients don't retry in lockstep,
+/// * at most 5 attempts,
+/// * a shared retry `budget`, so retries stop altogether once they make up too much of the load.
+///
+/// Any error is considered retryable; wrap the handler (or pick the building blocks yourself) if
+/// some errors should be forwarded right away.
+///
+/// ```
+/// use futures_retry::{safe_defaults, FutureRetry, RetryBudget};
+/// use std::sync::Arc;
+///
+/// # #[tokio::main] async fn main() {
+/// // Share the budget among all the operations that talk to the same backend.
+/// let budget = Arc::new(RetryBudget::default());
+/// let retry = FutureRetry::new(|| async { Ok::<_, std::io::Error>(()) }, safe_defaults(&budget));
+/// # retry.await.unwrap();
+/// # }
+/// ```
+pub fn safe_defaults(budget: &Arc<RetryBudget>) -> BudgetHandler<ExponentialBackoff> {
+    Profile::Standard.handler(budget)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorHandler, RetryPolicy};
+
+    #[test]
+    fn io_forwards_fatal_errors() {
+        use std::io;
+
+        let mut handler = default_io();
+        let reset = io::Error::from(io::ErrorKind::ConnectionReset);
+        assert!(matches!(
+            handler.handle(1, reset),
+            RetryPolicy::WaitRetry(_)
+        ));
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        match handler.handle(2, denied) {
+            RetryPolicy::ForwardError(e) => assert_eq!(io::ErrorKind::PermissionDenied, e.kind()),
+            policy => panic!("Unexpected policy {:?}", policy),
+        }
+    }
+
+    #[test]
+    fn gives_up() {
+        let budget = Arc::new(RetryBudget::default());
+        let mut handler = safe_defaults(&budget);
+        for attempt in 1..5 {
+            match handler.handle(attempt, ()) {
+                RetryPolicy::WaitRetry(delay) => assert!(delay <= Duration::from_secs(10)),
+                policy => panic!("Unexpected policy {:?}", policy),
+            }
+        }
+        assert_eq!(RetryPolicy::ForwardError(()), handler.handle(5, ()));
+    }
+
+    #[test]
+    fn profiles_escalate() {
+        let attempts = |profile: Profile| profile.backoff().schedule().count();
+        assert!(attempts(Profile::Aggressive) < attempts(Profile::Standard));
+        assert!(attempts(Profile::Standard) < attempts(Profile::Conservative));
+        let budget = Arc::new(Profile::Aggressive.budget());
+        let mut handler = Profile::Aggressive.handler(&budget);
+        for attempt in 1..3 {
+            match handler.handle(attempt, ()) {
+                RetryPolicy::WaitRetry(delay) => assert!(delay <= Duration::from_secs(1)),
+                policy => panic!("Unexpected policy {:?}", policy),
+            }
+        }
+        assert_eq!(RetryPolicy::ForwardError(()), handler.handle(3, ()));
+        assert_eq!(18, budget.available());
+    }
+}
@@ -0,0 +1,350 @@
+//! Respawning child processes.
+//!
+//! Available with the `process` feature.
+
+use crate::{ErrorHandler, RetryPolicy};
+use futures::{
+    stream::{self, BoxStream},
+    Stream, StreamExt,
+};
+use std::{
+    error::Error,
+    fmt, io, mem,
+    pin::Pin,
+    process::ExitStatus,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    process::{Child, Command},
+    time::{sleep, Instant},
+};
+
+/// Why a child process has to be respawned.
+#[derive(Debug)]
+pub enum ProcessError {
+    /// The process couldn't be spawned or waited for.
+    Io(io::Error),
+    /// The process has exited, with whatever status.
+    Exited(ExitStatus),
+}
+
+impl fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProcessError::Io(e) => write!(f, "failed to run the process: {}", e),
+            ProcessError::Exited(status) => write!(f, "the process has exited: {}", status),
+        }
+    }
+}
+
+impl Error for ProcessError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ProcessError::Io(e) => Some(e),
+            ProcessError::Exited(_) => None,
+        }
+    }
+}
+
+/// What has happened to a process supervised by a [`Respawn`](struct.Respawn.html) stream.
+#[derive(Debug)]
+pub enum Lifecycle<E> {
+    /// The process has been spawned.
+    Spawned {
+        /// The OS identifier of the process.
+        pid: Option<u32>,
+        /// The number of the attempt.
+        attempt: usize,
+    },
+    /// The process has exited.
+    Exited {
+        /// The exit status of the process.
+        status: ExitStatus,
+        /// The number of the attempt.
+        attempt: usize,
+    },
+    /// The process is going to be spawned again after the `delay`.
+    Restarting {
+        /// The delay before the next spawn; zero when it is made right away.
+        delay: Duration,
+    },
+    /// The error handler has given up; this is the last event.
+    GaveUp {
+        /// The forwarded error.
+        error: E,
+        /// The number of the failed attempt.
+        attempt: usize,
+    },
+}
+
+enum Step {
+    Spawn,
+    Wait(Child, Instant),
+    Failed(ProcessError),
+    TimerActive(Duration),
+    Done,
+}
+
+struct Supervision<C, H> {
+    command: C,
+    error_action: H,
+    healthy_after: Option<Duration>,
+    attempt: usize,
+    step: Step,
+}
+
+impl<C, H> Supervision<C, H>
+where
+    C: FnMut() -> Command,
+    H: ErrorHandler<ProcessError>,
+{
+    fn fail(&mut self, e: ProcessError) -> Lifecycle<H::OutError> {
+        let attempt = self.attempt;
+        let policy = self.error_action.handle(attempt, e);
+        self.attempt = policy.next_attempt(attempt);
+        match policy {
+            RetryPolicy::ForwardError(error) => {
+                self.error_action.exhausted(attempt, &error);
+                self.step = Step::Done;
+                Lifecycle::GaveUp { error, attempt }
+            }
+            RetryPolicy::Repeat | RetryPolicy::RepeatWithoutCounting => {
+                self.step = Step::Spawn;
+                Lifecycle::Restarting {
+                    delay: Duration::ZERO,
+                }
+            }
+            RetryPolicy::WaitRetry(delay) | RetryPolicy::RetryAs { delay, .. } => {
+                self.step = Step::TimerActive(delay);
+                Lifecycle::Restarting { delay }
+            }
+        }
+    }
+
+    async fn next_event(&mut self) -> Option<Lifecycle<H::OutError>> {
+        loop {
+            match mem::replace(&mut self.step, Step::Done) {
+                Step::Done => return None,
+                Step::TimerActive(delay) => {
+                    sleep(delay).await;
+                    self.step = Step::Spawn;
+                }
+                Step::Spawn => {
+                    return Some(match (self.command)().kill_on_drop(true).spawn() {
+                        Ok(child) => {
+                            let pid = child.id();
+                            self.step = Step::Wait(child, Instant::now());
+                            Lifecycle::Spawned {
+                                pid,
+                                attempt: self.attempt,
+                            }
+                        }
+                        Err(e) => self.fail(ProcessError::Io(e)),
+                    })
+                }
+                Step::Wait(mut child, started) => {
+                    let status = match child.wait().await {
+                        Ok(status) => status,
+                        Err(e) => return Some(self.fail(ProcessError::Io(e))),
+                    };
+                    let attempt = self.attempt;
+                    if self
+                        .healthy_after
+                        .is_some_and(|after| started.elapsed() >= after)
+                    {
+                        self.error_action.ok_with(attempt, &status);
+                        self.attempt = 1;
+                    }
+                    self.step = Step::Failed(ProcessError::Exited(status));
+                    return Some(Lifecycle::Exited { status, attempt });
+                }
+                Step::Failed(e) => return Some(self.fail(e)),
+            }
+        }
+    }
+}
+
+/// A stream of the lifecycle events of a child process that is respawned whenever it exits (or
+/// fails to spawn), as the error handler decides.
+///
+/// Every exit is treated as a failure, with the exit status passed to the error handler as a
+/// [`ProcessError::Exited`](enum.ProcessError.html); a handler might forward the error on a
+/// successful exit to stop respawning. The process is killed once the stream is dropped.
+///
+/// The attempt counter grows with every respawn unless the process is considered
+/// [healthy](#method.healthy_after) once it has been running for long enough.
+///
+/// ```
+/// use futures::StreamExt;
+/// use futures_retry::{
+///     process::{Lifecycle, ProcessError, Respawn},
+///     ErrorHandler, ExponentialBackoff,
+/// };
+/// use std::time::Duration;
+/// use tokio::process::Command;
+///
+/// # async fn run() {
+/// let backoff = ExponentialBackoff::new(Duration::from_millis(100)).max_attempts(5);
+/// let mut sidecar = Respawn::new(|| Command::new("envoy"), backoff)
+///     .healthy_after(Duration::from_secs(60));
+/// while let Some(event) = sidecar.next().await {
+///     match event {
+///         Lifecycle::GaveUp { error, .. } => eprintln!("The sidecar is down: {}", error),
+///         event => println!("{:?}", event),
+///     }
+/// }
+/// # }
+/// ```
+pub struct Respawn<C, H>
+where
+    H: ErrorHandler<ProcessError>,
+{
+    supervision: Option<Supervision<C, H>>,
+    events: Option<BoxStream<'static, Lifecycle<H::OutError>>>,
+}
+
+impl<C, H> Respawn<C, H>
+where
+    C: FnMut() -> Command + Send + 'static,
+    H: ErrorHandler<ProcessError> + Send + 'static,
+    H::OutError: Send,
+{
+    /// Creates a stream that spawns the processes built by the `command` closure on the first
+    /// poll.
+    pub fn new(command: C, error_action: H) -> Self {
+        Self {
+            supervision: Some(Supervision {
+                command,
+                error_action,
+                healthy_after: None,
+                attempt: 1,
+                step: Step::Spawn,
+            }),
+            events: None,
+        }
+    }
+
+    /// Resets the attempt counter once a process exits after running for at least the `period`.
+    pub fn healthy_after(mut self, period: Duration) -> Self {
+        if let Some(supervision) = &mut self.supervision {
+            supervision.healthy_after = Some(period);
+        }
+        self
+    }
+}
+
+impl<C, H: ErrorHandler<ProcessError>> fmt::Debug for Respawn<C, H> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Respawn")
+            .field("started", &self.events.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+// Nothing is pinned structurally: the events are produced by a boxed stream.
+impl<C, H: ErrorHandler<ProcessError>> Unpin for Respawn<C, H> {}
+
+impl<C, H> Stream for Respawn<C, H>
+where
+    C: FnMut() -> Command + Send + 'static,
+    H: ErrorHandler<ProcessError> + Send + 'static,
+    H::OutError: Send,
+{
+    type Item = Lifecycle<H::OutError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if let Some(supervision) = this.supervision.take() {
+            this.events = Some(
+                stream::unfold(supervision, |mut supervision| async move {
+                    let event = supervision.next_event().await?;
+                    Some((event, supervision))
+                })
+                .boxed(),
+            );
+        }
+        match &mut this.events {
+            Some(events) => events.poll_next_unpin(cx),
+            None => Poll::Ready(None),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::error_handler::tests::Successes;
+
+    #[tokio::test]
+    async fn respawns() {
+        let command = || {
+            let mut command = Command::new("sh");
+            command.args(["-c", "exit 3"]);
+            command
+        };
+        let events: Vec<_> = Respawn::new(command, |e| match e {
+            ProcessError::Exited(status) if status.code() == Some(3) => RetryPolicy::Repeat,
+            e => RetryPolicy::ForwardError(e),
+        })
+        .take(6)
+        .collect()
+        .await;
+        let kinds: Vec<_> = events
+            .iter()
+            .map(|event| match event {
+                Lifecycle::Spawned { attempt, .. } => format!("spawned {}", attempt),
+                Lifecycle::Exited { status, attempt } => {
+                    format!("exited {} {:?}", attempt, status.code())
+                }
+                Lifecycle::Restarting { delay } => format!("restarting {:?}", delay),
+                Lifecycle::GaveUp { attempt, .. } => format!("gave up {}", attempt),
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                "spawned 1",
+                "exited 1 Some(3)",
+                "restarting 0ns",
+                "spawned 2",
+                "exited 2 Some(3)",
+                "restarting 0ns"
+            ],
+            kinds
+        );
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_spawn_errors() {
+        let mut respawn = Respawn::new(
+            || Command::new("/nonexistent/futures-retry"),
+            |e: ProcessError| RetryPolicy::ForwardError(e.to_string()),
+        );
+        match respawn.next().await {
+            Some(Lifecycle::GaveUp { error, attempt: 1 }) => {
+                assert!(error.starts_with("failed to run the process"))
+            }
+            event => panic!("Unexpected event {:?}", event),
+        }
+        assert!(respawn.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn passes_success_values() {
+        let command = || {
+            let mut command = Command::new("sh");
+            command.args(["-c", "exit 3"]);
+            command
+        };
+        let successes = Successes::new(RetryPolicy::ForwardError);
+        let events: Vec<_> = Respawn::new(command, successes.clone())
+            .healthy_after(Duration::ZERO)
+            .take(2)
+            .collect()
+            .await;
+        assert!(matches!(events[1], Lifecycle::Exited { attempt: 1, .. }));
+        // The exit status of the healthy run.
+        let status = std::any::type_name::<std::process::ExitStatus>();
+        assert_eq!(vec![(1, status)], successes.seen());
+    }
+}
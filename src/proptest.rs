@@ -0,0 +1,125 @@
+//! Property-testing helpers for error handlers, built on
+//! [`proptest`](https://docs.rs/proptest).
+//!
+//! Available with the `proptest` feature.
+//!
+//! The strategies generate error sequences and backoff configurations, while the `check_*`
+//! functions feed a sequence to a handler (like [`simulate`](../fn.simulate.html) does) and fail
+//! the test case if an invariant doesn't hold.
+//!
+//! ```
+//! use futures_retry::proptest::{backoffs, check_capped, check_terminates, errors};
+//! use proptest::prelude::*;
+//! use std::time::Duration;
+//!
+//! proptest!(|(backoff in backoffs(), errors in errors(any::<u8>(), 20))| {
+//!     let backoff = backoff.max_delay(Duration::from_secs(1)).max_attempts(10);
+//!     check_capped(backoff.clone(), errors.clone(), Duration::from_secs(1))?;
+//!     check_terminates(backoff, errors, 10)?;
+//! });
+//! ```
+
+use crate::{simulate, ErrorHandler, ExponentialBackoff, Jitter, RetryPolicy};
+use proptest::{prelude::*, test_runner::TestCaseError};
+use std::time::Duration;
+
+/// Generates sequences of up to `max_len` errors.
+pub fn errors<S: Strategy>(error: S, max_len: usize) -> impl Strategy<Value = Vec<S::Value>> {
+    proptest::collection::vec(error, 0..=max_len)
+}
+
+/// Generates exponential backoffs with an initial delay of up to a second, a factor of up to 4
+/// and any jitter, yet without limits on the delay or the attempts.
+pub fn backoffs() -> impl Strategy<Value = ExponentialBackoff> {
+    let jitter = prop_oneof![Just(Jitter::None), Just(Jitter::Full), Just(Jitter::Equal)];
+    (1u64..=1000, 1u32..=4, jitter, any::<u64>()).prop_map(|(initial, factor, jitter, seed)| {
+        ExponentialBackoff::new(Duration::from_millis(initial))
+            .factor(factor)
+            .jitter(jitter)
+            .seed(seed)
+    })
+}
+
+fn delays<E, H: ErrorHandler<E>>(handler: H, errors: Vec<E>) -> Vec<Duration> {
+    simulate(handler, errors)
+        .into_iter()
+        .filter_map(|policy| match policy {
+            RetryPolicy::WaitRetry(delay) | RetryPolicy::RetryAs { delay, .. } => Some(delay),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Checks that the delays picked by the `handler` never decrease. Note that the jittered delays
+/// usually do.
+pub fn check_monotonic<E, H>(handler: H, errors: Vec<E>) -> Result<(), TestCaseError>
+where
+    H: ErrorHandler<E>,
+{
+    let delays = delays(handler, errors);
+    for pair in delays.windows(2) {
+        prop_assert!(
+            pair[0] <= pair[1],
+            "delay decreased from {:?} to {:?}",
+            pair[0],
+            pair[1]
+        );
+    }
+    Ok(())
+}
+
+/// Checks that no delay picked by the `handler` exceeds the `cap`.
+pub fn check_capped<E, H>(handler: H, errors: Vec<E>, cap: Duration) -> Result<(), TestCaseError>
+where
+    H: ErrorHandler<E>,
+{
+    for delay in delays(handler, errors) {
+        prop_assert!(delay <= cap, "delay {:?} exceeds the cap {:?}", delay, cap);
+    }
+    Ok(())
+}
+
+/// Checks that the `handler` gives up no later than on the `max_attempts`-th attempt, provided
+/// that the attempts keep failing with the `errors` (repeated over and over).
+pub fn check_terminates<E, H>(
+    handler: H,
+    errors: Vec<E>,
+    max_attempts: usize,
+) -> Result<(), TestCaseError>
+where
+    E: Clone,
+    H: ErrorHandler<E>,
+{
+    if errors.is_empty() {
+        return Ok(());
+    }
+    let errors = errors.into_iter().cycle().take(max_attempts);
+    let forwarded = simulate(handler, errors)
+        .iter()
+        .position(|policy| matches!(policy, RetryPolicy::ForwardError(_)));
+    prop_assert!(
+        forwarded.is_some(),
+        "still retrying after {} attempts",
+        max_attempts
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn plain_backoffs_are_monotonic(backoff in backoffs(), errors in errors(Just(()), 30)) {
+            check_monotonic(backoff.jitter(Jitter::None), errors)?;
+        }
+    }
+
+    #[test]
+    fn endless_retries_fail() {
+        let backoff = ExponentialBackoff::new(Duration::from_millis(1));
+        assert!(check_terminates(backoff.clone(), vec![()], 5).is_err());
+        assert!(check_terminates(backoff.max_attempts(5), vec![()], 5).is_ok());
+    }
+}
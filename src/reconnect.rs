@@ -0,0 +1,517 @@
+use crate::{ErrorHandler, ExponentialBackoff, FutureFactory, RetryPolicy, Sleeper, TokioSleeper};
+use futures::{ready, Stream, TryFuture, TryStream};
+use pin_project_lite::pin_project;
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+pin_project! {
+    /// A stream that (re)creates an underlying stream using a factory whenever the current one
+    /// fails or ends.
+    ///
+    /// Unlike [`StreamRetry`](struct.StreamRetry.html), which keeps polling the very same stream
+    /// after an error, this type assumes that an error renders the stream unusable (a dropped
+    /// connection, a closed subscription and so on), so it throws the stream away and asks the
+    /// factory for a new one. Errors of both the factory futures and the streams they produce are
+    /// passed to the error handler, which decides whether to reconnect immediately, wait a bit or
+    /// give up.
+    ///
+    /// The attempt counter is shared between connection and stream errors, and is reset back to
+    /// `1` once an item is successfully received.
+    ///
+    /// By default a stream that ends gracefully is reconnected after a delay, starting at 100ms
+    /// and doubling with each consecutive end up to 30 seconds, so a server that keeps closing
+    /// connections right away isn't hammered. Receiving an item resets the delay. Use
+    /// [`end_backoff`](#method.end_backoff) to pick other delays or to give up after a few ends,
+    /// and [`stop_on_end`](#method.stop_on_end) for streams that signal completion by ending.
+    pub struct ReconnectingStream<F, R, S = TokioSleeper>
+    where
+        F: FutureFactory,
+        S: Sleeper,
+    {
+        factory: F,
+        error_action: R,
+        attempt: usize,
+        reconnect_on_end: bool,
+        end_backoff: ExponentialBackoff,
+        ends: usize,
+        sleeper: S,
+        #[pin]
+        state: ReconnectState<F::FutureItem, <F::FutureItem as TryFuture>::Ok, S::Sleep>,
+    }
+}
+
+pin_project! {
+    #[project = ReconnectStateProj]
+    enum ReconnectState<F, S, D> {
+        NotStarted,
+        Connecting { #[pin] future: F },
+        Streaming { #[pin] stream: S },
+        TimerActive { #[pin] delay: D },
+        Finished,
+    }
+}
+
+impl<F: FutureFactory, R> ReconnectingStream<F, R> {
+    /// Creates a `ReconnectingStream` using a provided factory of streams and an object of
+    /// `ErrorHandler` type that decides on a retry-policy depending on an encountered error.
+    ///
+    /// # Arguments
+    ///
+    /// * `factory`: a factory that creates futures which resolve into streams,
+    /// * `error_action`: a type that handles an error and decides which route to take: simply
+    ///   reconnect, wait and then reconnect, or give up.
+    pub fn new(factory: F, error_action: R) -> Self {
+        Self::with_sleeper(factory, error_action, TokioSleeper)
+    }
+}
+
+impl<F: FutureFactory, R, S: Sleeper> ReconnectingStream<F, R, S> {
+    /// Like a `new` method, but the delays before reconnecting are timed by a custom
+    /// [`Sleeper`](trait.Sleeper.html).
+    pub fn with_sleeper(factory: F, error_action: R, sleeper: S) -> Self {
+        Self {
+            factory,
+            error_action,
+            attempt: 1,
+            reconnect_on_end: true,
+            end_backoff: ExponentialBackoff::new(Duration::from_millis(100))
+                .max_delay(Duration::from_secs(30)),
+            ends: 0,
+            sleeper,
+            state: ReconnectState::NotStarted,
+        }
+    }
+
+    /// Sets the delays before reconnecting after an underlying stream ends gracefully.
+    ///
+    /// The backoff is asked for the delay after the `n`-th end in a row, and the stream ends once
+    /// the backoff's [`max_attempts`](struct.ExponentialBackoff.html#method.max_attempts) are
+    /// exhausted.
+    pub fn end_backoff(mut self, end_backoff: ExponentialBackoff) -> Self {
+        self.end_backoff = end_backoff;
+        self
+    }
+
+    /// Makes the stream end as soon as an underlying stream ends, instead of reconnecting.
+    pub fn stop_on_end(mut self) -> Self {
+        self.reconnect_on_end = false;
+        self
+    }
+}
+
+impl<F, P, T, R> ReconnectingStream<Resume<F, P, T>, R>
+where
+    Resume<F, P, T>: FutureFactory,
+{
+    /// Creates a `ReconnectingStream` that resumes new streams from a position of the last
+    /// received item.
+    ///
+    /// Each time a stream has to be (re)created, the `factory` is called with the last known
+    /// position (`None` if no position is known yet). The `position` closure extracts a position
+    /// from every successfully received item; if it returns `None`, the previous position is kept.
+    ///
+    /// ```
+    /// use futures::{future, stream, StreamExt};
+    /// use futures_retry::{ReconnectingStream, RetryPolicy};
+    ///
+    /// # #[tokio::main] async fn main() {
+    /// let retry = ReconnectingStream::resuming(
+    ///     |last: Option<&u32>| {
+    ///         let start = last.map_or(0, |last| last + 1);
+    ///         // Pretend that the connection breaks after every two items.
+    ///         let items = (start..start + 2).map(Ok).chain(Some(Err("Connection reset")));
+    ///         future::ok::<_, &str>(stream::iter(items))
+    ///     },
+    ///     |item: &u32| Some(*item),
+    ///     |_| RetryPolicy::Repeat::<&str>,
+    /// );
+    /// let items: Vec<_> = retry.take(5).map(|item| item.unwrap().0).collect().await;
+    /// assert_eq!(vec![0, 1, 2, 3, 4], items);
+    /// # }
+    /// ```
+    pub fn resuming(factory: F, position: P, error_action: R) -> Self {
+        Self::new(
+            Resume {
+                factory,
+                tracker: Arc::new(Mutex::new(Tracker {
+                    position,
+                    last: None,
+                })),
+            },
+            error_action,
+        )
+    }
+}
+
+impl<F: FutureFactory, R, S: Sleeper> fmt::Debug for ReconnectingStream<F, R, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ReconnectingStream")
+            .field("attempt", &self.attempt)
+            .field("reconnect_on_end", &self.reconnect_on_end)
+            .field("end_backoff", &self.end_backoff)
+            .field("ends", &self.ends)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F, R, S, T> Stream for ReconnectingStream<F, R, T>
+where
+    F: FutureFactory,
+    F::FutureItem: TryFuture<Ok = S>,
+    S: TryStream<Error = <F::FutureItem as TryFuture>::Error>,
+    R: ErrorHandler<S::Error>,
+    T: Sleeper,
+{
+    type Item = Result<(S::Ok, usize), (R::OutError, usize)>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        loop {
+            let this = self.as_mut().project();
+            let attempt = *this.attempt;
+            let error = match this.state.project() {
+                ReconnectStateProj::NotStarted => None,
+                ReconnectStateProj::TimerActive { delay } => {
+                    ready!(delay.poll(cx));
+                    None
+                }
+                ReconnectStateProj::Connecting { future } => match ready!(future.try_poll(cx)) {
+                    Ok(stream) => {
+                        self.as_mut()
+                            .project()
+                            .state
+                            .set(ReconnectState::Streaming { stream });
+                        continue;
+                    }
+                    Err(e) => Some(e),
+                },
+                ReconnectStateProj::Streaming { stream } => {
+                    match ready!(stream.try_poll_next(cx)) {
+                        Some(Ok(x)) => {
+                            *this.attempt = 1;
+                            *this.ends = 0;
+                            this.error_action.ok_with(attempt, &x);
+                            return Poll::Ready(Some(Ok((x, attempt))));
+                        }
+                        Some(Err(e)) => Some(e),
+                        None if *this.reconnect_on_end => {
+                            *this.ends += 1;
+                            let policy = ErrorHandler::handle(this.end_backoff, *this.ends, ());
+                            if let RetryPolicy::WaitRetry(delay) = policy {
+                                let delay = this.sleeper.sleep(delay);
+                                self.as_mut()
+                                    .project()
+                                    .state
+                                    .set(ReconnectState::TimerActive { delay });
+                                continue;
+                            }
+                            self.as_mut().project().state.set(ReconnectState::Finished);
+                            return Poll::Ready(None);
+                        }
+                        None => {
+                            self.as_mut().project().state.set(ReconnectState::Finished);
+                            return Poll::Ready(None);
+                        }
+                    }
+                }
+                ReconnectStateProj::Finished => return Poll::Ready(None),
+            };
+            let this = self.as_mut().project();
+            let new_state = match error {
+                None => ReconnectState::Connecting {
+                    future: this.factory.new(),
+                },
+                Some(e) => {
+                    let policy = this.error_action.handle(attempt, e);
+                    *this.attempt = policy.next_attempt(attempt);
+                    match policy {
+                        RetryPolicy::ForwardError(e) => {
+                            this.error_action.exhausted(attempt, &e);
+                            return Poll::Ready(Some(Err((e, attempt))));
+                        }
+                        RetryPolicy::Repeat | RetryPolicy::RepeatWithoutCounting => {
+                            ReconnectState::Connecting {
+                                future: this.factory.new(),
+                            }
+                        }
+                        RetryPolicy::WaitRetry(duration)
+                        | RetryPolicy::RetryAs {
+                            delay: duration, ..
+                        } => ReconnectState::TimerActive {
+                            delay: this.sleeper.sleep(duration),
+                        },
+                    }
+                }
+            };
+            self.as_mut().project().state.set(new_state);
+        }
+    }
+}
+
+struct Tracker<P, T> {
+    position: P,
+    last: Option<T>,
+}
+
+fn lock<P, T>(tracker: &Mutex<Tracker<P, T>>) -> MutexGuard<'_, Tracker<P, T>> {
+    tracker.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A factory of streams that resume from a position of the last received item.
+///
+/// Created by [`ReconnectingStream::resuming`](struct.ReconnectingStream.html#method.resuming).
+pub struct Resume<F, P, T> {
+    factory: F,
+    tracker: Arc<Mutex<Tracker<P, T>>>,
+}
+
+impl<F, P, T, Fut> FutureFactory for Resume<F, P, T>
+where
+    F: FnMut(Option<&T>) -> Fut,
+    Fut: TryFuture,
+    Fut::Ok: TryStream,
+    P: FnMut(&<Fut::Ok as TryStream>::Ok) -> Option<T>,
+{
+    type FutureItem = ResumeFuture<Fut, P, T>;
+
+    fn new(&mut self) -> Self::FutureItem {
+        let future = (self.factory)(lock(&self.tracker).last.as_ref());
+        ResumeFuture {
+            future,
+            tracker: Arc::clone(&self.tracker),
+        }
+    }
+}
+
+pin_project! {
+    /// A future that resolves into a [`ResumeStream`](struct.ResumeStream.html).
+    pub struct ResumeFuture<Fut, P, T> {
+        #[pin]
+        future: Fut,
+        tracker: Arc<Mutex<Tracker<P, T>>>,
+    }
+}
+
+impl<Fut, P, T> Future for ResumeFuture<Fut, P, T>
+where
+    Fut: TryFuture,
+{
+    type Output = Result<ResumeStream<Fut::Ok, P, T>, Fut::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.project();
+        let stream = ready!(this.future.try_poll(cx))?;
+        Poll::Ready(Ok(ResumeStream {
+            stream,
+            tracker: Arc::clone(this.tracker),
+        }))
+    }
+}
+
+pin_project! {
+    /// A stream that remembers a position of the last received item.
+    pub struct ResumeStream<S, P, T> {
+        #[pin]
+        stream: S,
+        tracker: Arc<Mutex<Tracker<P, T>>>,
+    }
+}
+
+impl<S, P, T> Stream for ResumeStream<S, P, T>
+where
+    S: TryStream,
+    P: FnMut(&S::Ok) -> Option<T>,
+{
+    type Item = Result<S::Ok, S::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let item = ready!(this.stream.try_poll_next(cx));
+        if let Some(Ok(x)) = &item {
+            let mut tracker = lock(this.tracker);
+            if let Some(position) = (tracker.position)(x) {
+                tracker.last = Some(position);
+            }
+        }
+        Poll::Ready(item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handler::tests::Successes;
+    use futures::{future, pin_mut, stream, StreamExt};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn reconnect_on_error() {
+        let mut connections = vec![
+            Err(1u8),
+            Ok(stream::iter(vec![Ok(10u8), Err(2u8)])),
+            Ok(stream::iter(vec![Ok(20u8)])),
+        ]
+        .into_iter();
+        let retry = ReconnectingStream::new(
+            move || future::ready(connections.next().expect("No more connections!")),
+            |_| RetryPolicy::Repeat::<u8>,
+        )
+        .take(2);
+        assert_eq!(
+            vec![Ok((10, 2)), Ok((20, 2))],
+            retry.collect::<Vec<_>>().await
+        );
+    }
+
+    #[tokio::test]
+    async fn passes_success_values() {
+        let mut connections = vec![Err(1u8), Ok(stream::iter(vec![Ok(10u8)]))].into_iter();
+        let successes = Successes::new(|_| RetryPolicy::Repeat::<u8>);
+        let retry = ReconnectingStream::new(
+            move || future::ready(connections.next().expect("No more connections!")),
+            successes.clone(),
+        )
+        .take(1);
+        assert_eq!(vec![Ok((10, 2))], retry.collect::<Vec<_>>().await);
+        assert_eq!(vec![(2, "u8")], successes.seen());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reconnect_on_end() {
+        let mut connections = vec![stream::iter(vec![Ok(1u8)]), stream::iter(vec![Ok(2u8)])]
+            .into_iter()
+            .map(Ok::<_, u8>);
+        let retry = ReconnectingStream::new(
+            move || future::ready(connections.next().expect("No more connections!")),
+            RetryPolicy::ForwardError,
+        )
+        .take(2);
+        assert_eq!(
+            vec![Ok((1, 1)), Ok((2, 1))],
+            retry.collect::<Vec<_>>().await
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backs_off_on_end() {
+        let connections = Arc::new(Mutex::new(0));
+        let counter = Arc::clone(&connections);
+        let retry = ReconnectingStream::new(
+            move || {
+                *counter.lock().unwrap() += 1;
+                future::ok::<_, u8>(stream::empty::<Result<u8, u8>>())
+            },
+            RetryPolicy::ForwardError,
+        );
+        pin_mut!(retry);
+        // The server keeps closing the connections: reconnect after 100ms, then after 200ms more.
+        let timeout = tokio::time::timeout(Duration::from_millis(350), retry.next()).await;
+        assert!(timeout.is_err());
+        assert_eq!(3, *connections.lock().unwrap());
+    }
+
+    #[cfg(feature = "test_util")]
+    #[tokio::test]
+    async fn waits_on_the_sleeper() {
+        let mut connections = vec![
+            Err(1u8),
+            Ok(stream::iter(vec![])),
+            Ok(stream::iter(vec![Ok(2u8)])),
+        ]
+        .into_iter();
+        let sleeper = crate::test_util::MockSleeper::new();
+        let hour = Duration::from_secs(3600);
+        let retry = ReconnectingStream::with_sleeper(
+            move || future::ready(connections.next().expect("No more connections!")),
+            |_| RetryPolicy::WaitRetry::<u8>(hour),
+            sleeper.clone(),
+        );
+        pin_mut!(retry);
+        let next = retry.next();
+        pin_mut!(next);
+        assert!(futures::poll!(next.as_mut()).is_pending());
+        sleeper.advance(hour);
+        // The stream ends right away, so the next connection is made after an end backoff.
+        assert!(futures::poll!(next.as_mut()).is_pending());
+        sleeper.advance(hour);
+        assert_eq!(Some(Ok((2, 2))), next.await);
+        assert_eq!(vec![hour, Duration::from_millis(100)], sleeper.requested());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_after_ends() {
+        let mut connections = 0;
+        let retry = ReconnectingStream::new(
+            || {
+                connections += 1;
+                future::ok::<_, u8>(stream::empty::<Result<u8, u8>>())
+            },
+            RetryPolicy::ForwardError,
+        )
+        .end_backoff(ExponentialBackoff::new(Duration::from_millis(10)).max_attempts(3));
+        assert_eq!(0, retry.count().await);
+        assert_eq!(3, connections);
+    }
+
+    #[tokio::test]
+    async fn stop_on_end() {
+        let retry = ReconnectingStream::new(
+            || future::ok::<_, u8>(stream::iter(vec![Ok(1u8), Ok(2u8)])),
+            RetryPolicy::ForwardError,
+        )
+        .stop_on_end();
+        pin_mut!(retry);
+        assert_eq!(Some(Ok((1, 1))), retry.next().await);
+        assert_eq!(Some(Ok((2, 1))), retry.next().await);
+        assert_eq!(None, retry.next().await);
+        assert_eq!(None, retry.next().await);
+    }
+
+    #[tokio::test]
+    async fn wait_and_forward() {
+        let mut connections = vec![Err(1u8), Err(2u8)].into_iter();
+        let retry = ReconnectingStream::new(
+            move || {
+                future::ready(
+                    connections
+                        .next()
+                        .expect("No more connections!")
+                        .map(|()| stream::empty::<Result<u8, u8>>()),
+                )
+            },
+            |e| match e {
+                1 => RetryPolicy::WaitRetry(Duration::from_millis(10)),
+                e => RetryPolicy::ForwardError(e),
+            },
+        );
+        pin_mut!(retry);
+        assert_eq!(Some(Err((2, 2))), retry.next().await);
+    }
+
+    #[tokio::test]
+    async fn resume() {
+        let mut positions = Vec::new();
+        let retry = ReconnectingStream::resuming(
+            |last: Option<&usize>| {
+                positions.push(last.copied());
+                let start = last.map_or(0, |last| last + 1);
+                future::ok(stream::iter(
+                    vec![Ok(start), Ok(start + 1), Err(start)].into_iter(),
+                ))
+            },
+            |item: &usize| Some(*item).filter(|item| item.is_multiple_of(2)),
+            |_| RetryPolicy::Repeat::<usize>,
+        )
+        .take(4);
+        assert_eq!(
+            vec![Ok((0, 1)), Ok((1, 1)), Ok((1, 2)), Ok((2, 1))],
+            retry.collect::<Vec<_>>().await
+        );
+        assert_eq!(vec![None, Some(0)], positions);
+    }
+}
@@ -0,0 +1,332 @@
+use crate::{ErrorHandler, RetryPolicy, Sleeper, TokioSleeper};
+use futures::{future::AndThen, ready, TryFutureExt};
+use pin_project_lite::pin_project;
+use std::{
+    fmt,
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Establishes the connections for a [`ReconnectingIo`](struct.ReconnectingIo.html).
+///
+/// Implemented for any closure that returns a future resolving into a connection.
+pub trait Connector {
+    /// The connection, e.g. a `TcpStream`.
+    type Io;
+    /// A future that resolves into a connection.
+    type Future: Future<Output = io::Result<Self::Io>>;
+
+    /// Starts connecting.
+    fn connect(&mut self) -> Self::Future;
+}
+
+impl<F, Fut, T> Connector for F
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = io::Result<T>>,
+{
+    type Io = T;
+    type Future = Fut;
+
+    fn connect(&mut self) -> Fut {
+        (self)()
+    }
+}
+
+/// A connector that runs a handshake on every new connection before handing it out.
+///
+/// Created by [`ReconnectingIo::with_handshake`](struct.ReconnectingIo.html#method.with_handshake).
+#[derive(Debug, Clone)]
+pub struct Handshake<C, F> {
+    connector: C,
+    handshake: F,
+}
+
+impl<C, F, Fut> Connector for Handshake<C, F>
+where
+    C: Connector,
+    F: FnMut(C::Io) -> Fut + Clone,
+    Fut: Future<Output = io::Result<C::Io>>,
+{
+    type Io = C::Io;
+    type Future = AndThen<C::Future, Fut, F>;
+
+    fn connect(&mut self) -> Self::Future {
+        self.connector.connect().and_then(self.handshake.clone())
+    }
+}
+
+pin_project! {
+    #[project = IoStateProj]
+    enum IoState<Fut, T, D> {
+        Reconnect,
+        Connecting { #[pin] future: Fut },
+        TimerActive { #[pin] delay: D },
+        Connected { #[pin] io: T },
+    }
+}
+
+pin_project! {
+    /// A connection that transparently reconnects whenever it breaks.
+    ///
+    /// The connection is established lazily, on the first read or write, by a
+    /// [`Connector`](trait.Connector.html). Every error, of connecting, reading or writing, is
+    /// passed to the error handler, which decides whether to reconnect (right away or after a
+    /// delay) or to give up and return the error; the next read or write after that starts over
+    /// with reconnecting. The attempt counter is reset once a read or a write succeeds.
+    ///
+    /// Only the connection is restored: the data that has been in flight when it broke is lost,
+    /// so the protocol spoken over it should tolerate that (or use a
+    /// [handshake](#method.with_handshake) to resynchronize).
+    ///
+    /// ```
+    /// use futures_retry::{ReconnectingIo, RetryPolicy};
+    /// use std::{io, time::Duration};
+    /// use tokio::{io::AsyncWriteExt, net::TcpStream};
+    ///
+    /// # async fn run() -> io::Result<()> {
+    /// let connection = ReconnectingIo::new(
+    ///     || TcpStream::connect("127.0.0.1:6379"),
+    ///     |e: io::Error| match e.kind() {
+    ///         io::ErrorKind::InvalidInput => RetryPolicy::ForwardError(e),
+    ///         _ => RetryPolicy::WaitRetry(Duration::from_secs(1)),
+    ///     },
+    /// );
+    /// tokio::pin!(connection);
+    /// connection.write_all(b"PING\r\n").await?;
+    /// # Ok(()) }
+    /// ```
+    pub struct ReconnectingIo<C, H, S = TokioSleeper>
+    where
+        C: Connector,
+        S: Sleeper,
+    {
+        connector: C,
+        error_action: H,
+        sleeper: S,
+        attempt: usize,
+        #[pin]
+        state: IoState<C::Future, C::Io, S::Sleep>,
+    }
+}
+
+impl<C: Connector, H> ReconnectingIo<C, H> {
+    /// Creates a connection that is established with the `connector` on the first use.
+    pub fn new(connector: C, error_action: H) -> Self {
+        Self::with_sleeper(connector, error_action, TokioSleeper)
+    }
+}
+
+impl<C: Connector, H, S: Sleeper> ReconnectingIo<C, H, S> {
+    /// Like a `new` method, but the delays between the attempts are timed by a custom
+    /// [`Sleeper`](trait.Sleeper.html).
+    pub fn with_sleeper(connector: C, error_action: H, sleeper: S) -> Self {
+        Self {
+            connector,
+            error_action,
+            sleeper,
+            attempt: 1,
+            state: IoState::Reconnect,
+        }
+    }
+
+    /// Runs the `handshake` on every new connection before using it, e.g. to authenticate or to
+    /// select a database. A failed handshake is handled like a failed connection.
+    pub fn with_handshake<F, Fut>(self, handshake: F) -> ReconnectingIo<Handshake<C, F>, H, S>
+    where
+        F: FnMut(C::Io) -> Fut + Clone,
+        Fut: Future<Output = io::Result<C::Io>>,
+    {
+        ReconnectingIo::with_sleeper(
+            Handshake {
+                connector: self.connector,
+                handshake,
+            },
+            self.error_action,
+            self.sleeper,
+        )
+    }
+
+    /// Checks whether the connection is currently established.
+    pub fn is_connected(&self) -> bool {
+        matches!(self.state, IoState::Connected { .. })
+    }
+}
+
+impl<C: Connector, H, S: Sleeper> fmt::Debug for ReconnectingIo<C, H, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ReconnectingIo")
+            .field("attempt", &self.attempt)
+            .field("connected", &self.is_connected())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<C, H, S> ReconnectingIo<C, H, S>
+where
+    C: Connector,
+    S: Sleeper,
+    H: ErrorHandler<io::Error, OutError = io::Error>,
+{
+    /// Runs an operation on the connection, reconnecting as needed.
+    fn poll_with<T>(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        mut operation: impl FnMut(Pin<&mut C::Io>, &mut Context) -> Poll<io::Result<T>>,
+    ) -> Poll<io::Result<T>> {
+        let mut this = self.project();
+        loop {
+            let e = match this.state.as_mut().project() {
+                IoStateProj::Reconnect => {
+                    let future = this.connector.connect();
+                    this.state.set(IoState::Connecting { future });
+                    continue;
+                }
+                IoStateProj::Connecting { future } => match ready!(future.poll(cx)) {
+                    Ok(io) => {
+                        this.state.set(IoState::Connected { io });
+                        continue;
+                    }
+                    Err(e) => e,
+                },
+                IoStateProj::TimerActive { delay } => {
+                    ready!(delay.poll(cx));
+                    this.state.set(IoState::Reconnect);
+                    continue;
+                }
+                IoStateProj::Connected { io } => match ready!(operation(io, cx)) {
+                    Ok(x) => {
+                        if *this.attempt > 1 {
+                            this.error_action.ok_with(*this.attempt, &x);
+                            *this.attempt = 1;
+                        }
+                        return Poll::Ready(Ok(x));
+                    }
+                    Err(e) => e,
+                },
+            };
+            let attempt = *this.attempt;
+            let policy = this.error_action.handle(attempt, e);
+            *this.attempt = policy.next_attempt(attempt);
+            match policy {
+                RetryPolicy::ForwardError(e) => {
+                    this.error_action.exhausted(attempt, &e);
+                    this.state.set(IoState::Reconnect);
+                    *this.attempt = 1;
+                    return Poll::Ready(Err(e));
+                }
+                RetryPolicy::Repeat | RetryPolicy::RepeatWithoutCounting => {
+                    this.state.set(IoState::Reconnect)
+                }
+                RetryPolicy::WaitRetry(duration)
+                | RetryPolicy::RetryAs {
+                    delay: duration, ..
+                } => this.state.set(IoState::TimerActive {
+                    delay: this.sleeper.sleep(duration),
+                }),
+            }
+        }
+    }
+}
+
+impl<C, H, S> AsyncRead for ReconnectingIo<C, H, S>
+where
+    C: Connector,
+    S: Sleeper,
+    C::Io: AsyncRead,
+    H: ErrorHandler<io::Error, OutError = io::Error>,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut ReadBuf,
+    ) -> Poll<io::Result<()>> {
+        self.poll_with(cx, |io, cx| io.poll_read(cx, buf))
+    }
+}
+
+impl<C, H, S> AsyncWrite for ReconnectingIo<C, H, S>
+where
+    C: Connector,
+    S: Sleeper,
+    C::Io: AsyncWrite,
+    H: ErrorHandler<io::Error, OutError = io::Error>,
+{
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.poll_with(cx, |io, cx| io.poll_write(cx, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.poll_with(cx, |io, cx| io.poll_flush(cx))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.poll_with(cx, |io, cx| io.poll_shutdown(cx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handler::tests::Successes;
+    use futures::future::{ready, Ready};
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+    #[tokio::test]
+    async fn reconnects_with_handshake() {
+        let (first, peer) = duplex(64);
+        drop(peer);
+        let (second, mut peer) = duplex(64);
+        let mut connections = vec![first, second].into_iter();
+        let successes = Successes::new(|e: io::Error| match e.kind() {
+            io::ErrorKind::BrokenPipe => RetryPolicy::Repeat,
+            _ => RetryPolicy::ForwardError(e),
+        });
+        let connection = ReconnectingIo::new(
+            move || -> Ready<io::Result<DuplexStream>> { ready(Ok(connections.next().unwrap())) },
+            successes.clone(),
+        )
+        .with_handshake(|mut io: DuplexStream| async move {
+            io.write_all(b"HELLO ").await?;
+            Ok(io)
+        });
+        tokio::pin!(connection);
+        assert!(!connection.is_connected());
+        // The handshake on the first connection fails, so the second one is used.
+        connection.write_all(b"world").await.unwrap();
+        assert!(connection.is_connected());
+        let mut received = [0; 11];
+        peer.read_exact(&mut received).await.unwrap();
+        assert_eq!(b"HELLO world", &received);
+        // The write that has succeeded after the reconnect.
+        assert_eq!(vec![(2, "usize")], successes.seen());
+    }
+
+    #[cfg(feature = "test_util")]
+    #[tokio::test]
+    async fn waits_on_the_sleeper() {
+        let hour = std::time::Duration::from_secs(3600);
+        let (connection, mut peer) = duplex(64);
+        let mut connections =
+            vec![Err(io::ErrorKind::ConnectionRefused.into()), Ok(connection)].into_iter();
+        let sleeper = crate::test_util::MockSleeper::new();
+        let connection = ReconnectingIo::with_sleeper(
+            move || -> Ready<io::Result<DuplexStream>> { ready(connections.next().unwrap()) },
+            |_| RetryPolicy::WaitRetry(hour),
+            sleeper.clone(),
+        );
+        tokio::pin!(connection);
+        let write = connection.write_all(b"PING");
+        tokio::pin!(write);
+        assert!(futures::poll!(write.as_mut()).is_pending());
+        sleeper.advance(hour);
+        write.await.unwrap();
+        let mut received = [0; 4];
+        peer.read_exact(&mut received).await.unwrap();
+        assert_eq!(b"PING", &received);
+        assert_eq!(vec![hour], sleeper.requested());
+    }
+}
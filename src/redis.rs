@@ -0,0 +1,273 @@
+//! Helpers for coordinating retries among replicas via [`redis`](https://docs.rs/redis).
+//!
+//! Available with the `redis` feature.
+
+use futures::{
+    future::{select, Either},
+    pin_mut,
+};
+use redis::{aio::ConnectionLike, ErrorKind, RedisError, RedisResult, Script};
+use std::{future::Future, time::Duration};
+use tokio::time;
+
+const RELEASE: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("DEL", KEYS[1])
+end
+return 0
+"#;
+
+const EXTEND: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("PEXPIRE", KEYS[1], ARGV[2])
+end
+return 0
+"#;
+
+/// What has happened to a keyed retry run through a
+/// [`RedisCoordinator`](struct.RedisCoordinator.html).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Outcome<T> {
+    /// This replica has performed the retry and got the given output.
+    Performed(T),
+    /// Another replica has been performing the retry, and has finished (or died) while this one
+    /// was observing it.
+    Observed,
+}
+
+/// Makes sure that only one replica of a service performs a given keyed retry at a time.
+///
+/// Before starting a retry loop, a replica takes a lease on the key with `SET NX PX`. The lease
+/// is extended in the background while the loop is running and released as soon as it is over;
+/// should the replica crash, the lease expires after the `ttl`. The replicas that fail to take
+/// the lease observe it instead, until it is released or expires.
+///
+/// ```no_run
+/// use futures_retry::{
+///     redis::{Outcome, RedisCoordinator},
+///     FutureRetry, RetryPolicy,
+/// };
+/// use std::time::Duration;
+///
+/// # async fn run() -> redis::RedisResult<()> {
+/// let client = redis::Client::open("redis://127.0.0.1/")?;
+/// let connection = client.get_multiplexed_async_connection().await?;
+/// let coordinator = RedisCoordinator::new(connection, Duration::from_secs(10));
+/// let retry = FutureRetry::new(
+///     || async { Ok::<_, std::io::Error>("delivered") },
+///     |_| RetryPolicy::WaitRetry::<std::io::Error>(Duration::from_secs(1)),
+/// );
+/// match coordinator.run("webhook:42", retry).await? {
+///     Outcome::Performed(result) => println!("Performed: {:?}", result),
+///     Outcome::Observed => println!("Another replica took care of it"),
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct RedisCoordinator<C> {
+    connection: C,
+    ttl: Duration,
+    poll_interval: Duration,
+    token: String,
+}
+
+impl<C: ConnectionLike + Clone> RedisCoordinator<C> {
+    /// Creates a coordinator that takes leases for the `ttl`.
+    ///
+    /// Each coordinator identifies itself with a random token, so clones of a coordinator share
+    /// their leases, while coordinators created separately (e.g. by different replicas) exclude
+    /// each other.
+    pub fn new(connection: C, ttl: Duration) -> Self {
+        Self {
+            connection,
+            ttl,
+            poll_interval: ttl / 4,
+            token: format!("{:032x}", fastrand::u128(..)),
+        }
+    }
+
+    /// Sets how often an observing replica checks whether the lease is still held. Defaults to a
+    /// quarter of the `ttl`.
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Tries to take the lease on the `key`. Returns `false` if it is held by someone else.
+    pub async fn try_lead(&self, key: &str) -> RedisResult<bool> {
+        let reply: Option<String> = redis::cmd("SET")
+            .arg(key)
+            .arg(&self.token)
+            .arg("NX")
+            .arg("PX")
+            .arg(self.ttl_millis())
+            .query_async(&mut self.connection.clone())
+            .await?;
+        Ok(reply.is_some())
+    }
+
+    /// Releases the lease on the `key`, if it is held by this coordinator.
+    pub async fn release(&self, key: &str) -> RedisResult<()> {
+        Script::new(RELEASE)
+            .key(key)
+            .arg(&self.token)
+            .invoke_async::<i64>(&mut self.connection.clone())
+            .await?;
+        Ok(())
+    }
+
+    /// Runs the `retry` future if the lease on the `key` can be taken, or observes the replica
+    /// that holds it otherwise.
+    ///
+    /// A Redis error is returned as soon as it happens; if it happens while the lease is being
+    /// extended, the `retry` future is dropped, since other replicas might take over once the
+    /// lease expires. The same goes for a lease that has already expired or has been taken over
+    /// by another replica when it is being extended, which is reported as a
+    /// [`Client`](https://docs.rs/redis/1/redis/enum.ErrorKind.html#variant.Client) error.
+    pub async fn run<F: Future>(&self, key: &str, retry: F) -> RedisResult<Outcome<F::Output>> {
+        if !self.try_lead(key).await? {
+            self.observe(key).await?;
+            return Ok(Outcome::Observed);
+        }
+        let output = self.lead(key, retry).await;
+        self.release(key).await?;
+        output.map(Outcome::Performed)
+    }
+
+    async fn lead<F: Future>(&self, key: &str, retry: F) -> RedisResult<F::Output> {
+        pin_mut!(retry);
+        loop {
+            let renew = time::sleep(self.ttl / 3);
+            pin_mut!(renew);
+            match select(retry, renew).await {
+                Either::Left((output, _)) => return Ok(output),
+                Either::Right(((), unfinished)) => {
+                    retry = unfinished;
+                    let extended = Script::new(EXTEND)
+                        .key(key)
+                        .arg(&self.token)
+                        .arg(self.ttl_millis())
+                        .invoke_async::<i64>(&mut self.connection.clone())
+                        .await?;
+                    if extended == 0 {
+                        return Err(RedisError::from((
+                            ErrorKind::Client,
+                            "Lost the lease",
+                            key.to_owned(),
+                        )));
+                    }
+                }
+            }
+        }
+    }
+
+    async fn observe(&self, key: &str) -> RedisResult<()> {
+        loop {
+            let held: bool = redis::cmd("EXISTS")
+                .arg(key)
+                .query_async(&mut self.connection.clone())
+                .await?;
+            if !held {
+                return Ok(());
+            }
+            time::sleep(self.poll_interval).await;
+        }
+    }
+
+    fn ttl_millis(&self) -> u64 {
+        self.ttl.as_millis().max(1) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::{self, BoxFuture};
+    use redis::{Arg, Cmd, Pipeline, Value};
+    use std::sync::{Arc, Mutex};
+
+    /// A connection to a single key, understanding just enough commands for the coordinator.
+    #[derive(Clone, Default)]
+    struct Lease {
+        holder: Arc<Mutex<Option<Vec<u8>>>>,
+    }
+
+    impl Lease {
+        fn reply(&self, cmd: &Cmd) -> Value {
+            let args: Vec<_> = cmd
+                .args_iter()
+                .filter_map(|arg| match arg {
+                    Arg::Simple(arg) => Some(arg.to_vec()),
+                    _ => None,
+                })
+                .collect();
+            let mut holder = self.holder.lock().unwrap();
+            match (&args[0][..], &args[..]) {
+                (b"SET", [_, _, token, ..]) if holder.is_none() => {
+                    *holder = Some(token.clone());
+                    Value::Okay
+                }
+                (b"SET", _) => Value::Nil,
+                (b"EXISTS", _) => Value::Int(holder.is_some().into()),
+                // EVALSHA sha 1 key token [ttl]
+                (b"EVALSHA", [_, _, _, _, token, _]) => {
+                    Value::Int((holder.as_ref() == Some(token)).into())
+                }
+                (b"EVALSHA", [_, _, _, _, token]) if holder.as_ref() == Some(token) => {
+                    *holder = None;
+                    Value::Int(1)
+                }
+                (b"EVALSHA", _) => Value::Int(0),
+                (command, _) => panic!("Unexpected command {:?}", String::from_utf8_lossy(command)),
+            }
+        }
+    }
+
+    impl ConnectionLike for Lease {
+        fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> BoxFuture<'a, RedisResult<Value>> {
+            Box::pin(future::ok(self.reply(cmd)))
+        }
+
+        fn req_packed_commands<'a>(
+            &'a mut self,
+            _: &'a Pipeline,
+            _: usize,
+            _: usize,
+        ) -> BoxFuture<'a, RedisResult<Vec<Value>>> {
+            unimplemented!()
+        }
+
+        fn get_db(&self) -> i64 {
+            0
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn performs_and_releases() {
+        let lease = Lease::default();
+        let coordinator = RedisCoordinator::new(lease.clone(), Duration::from_millis(30));
+        let retry = time::sleep(Duration::from_millis(100));
+        assert_eq!(
+            Outcome::Performed(()),
+            coordinator.run("key", retry).await.unwrap()
+        );
+        assert_eq!(None, *lease.holder.lock().unwrap());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stops_after_losing_the_lease() {
+        let lease = Lease::default();
+        let coordinator = RedisCoordinator::new(lease.clone(), Duration::from_millis(30));
+        let holder = Arc::clone(&lease.holder);
+        let retry = async move {
+            // The lease expires and another replica takes it over between renewals.
+            time::sleep(Duration::from_millis(15)).await;
+            *holder.lock().unwrap() = Some(b"another".to_vec());
+            time::sleep(Duration::from_secs(60 * 60)).await;
+        };
+        let error = coordinator.run("key", retry).await.unwrap_err();
+        assert_eq!(ErrorKind::Client, error.kind());
+        assert_eq!(Some(b"another".to_vec()), *lease.holder.lock().unwrap());
+    }
+}
@@ -0,0 +1,257 @@
+use crate::{ErrorHandler, RetryPolicy};
+use futures::ready;
+use pin_project_lite::pin_project;
+use std::{
+    error::Error,
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+/// The final error of a retry loop along with how many attempts it has taken and how long it has
+/// been running.
+///
+/// Unlike a bare `(E, usize)` tuple it implements `Error` (with the inner error as its
+/// `source()`), so it works with `?` and the error-reporting crates. Created by
+/// [`FutureRetry::wrap_error`](struct.FutureRetry.html#method.wrap_error), or right by the error
+/// handler when it is wrapped in an [`Enriched`](struct.Enriched.html) one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryError<E> {
+    /// The error the loop has given up on.
+    pub error: E,
+    /// The number of the attempt that has failed with the error.
+    pub attempts: usize,
+    /// How long the loop has been running, from its first poll until it gave up.
+    pub elapsed: Duration,
+    /// The delay before the last attempt, if it was a retry and the delay is known.
+    pub last_delay: Option<Duration>,
+}
+
+impl<E> RetryError<E> {
+    /// Returns the inner error.
+    pub fn into_inner(self) -> E {
+        self.error
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for RetryError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} (gave up after {} attempts in {:?})",
+            self.error, self.attempts, self.elapsed
+        )
+    }
+}
+
+impl<E: Error + 'static> Error for RetryError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+pin_project! {
+    /// A future that turns the outcome of a retry loop into a `Result<T, RetryError<E>>`.
+    ///
+    /// Created by [`FutureRetry::wrap_error`](struct.FutureRetry.html#method.wrap_error).
+    #[derive(Debug)]
+    pub struct WrapError<Fut> {
+        #[pin]
+        future: Fut,
+        started: Option<Instant>,
+    }
+}
+
+impl<Fut> WrapError<Fut> {
+    pub(crate) fn new(future: Fut) -> Self {
+        Self {
+            future,
+            started: None,
+        }
+    }
+}
+
+impl<Fut, T, E> Future for WrapError<Fut>
+where
+    Fut: Future<Output = Result<(T, usize), (E, usize)>>,
+{
+    type Output = Result<T, RetryError<E>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.project();
+        let started = *this.started.get_or_insert_with(Instant::now);
+        Poll::Ready(match ready!(this.future.poll(cx)) {
+            Ok((x, _)) => Ok(x),
+            Err((error, attempts)) => Err(RetryError {
+                error,
+                attempts,
+                elapsed: started.elapsed(),
+                last_delay: None,
+            }),
+        })
+    }
+}
+
+/// An error handler that wraps the errors forwarded by an inner one into a
+/// [`RetryError`](struct.RetryError.html), so the report tells how hard the loop has tried.
+///
+/// Unlike [`FutureRetry::wrap_error`](struct.FutureRetry.html#method.wrap_error) it works with
+/// any retry loop, streams included, and it also knows the last delay. The time is counted from
+/// the creation of the handler or from the last success, whichever is later, so for a stream it is
+/// the time since the last item.
+///
+/// ```
+/// use futures_retry::{Enriched, ErrorHandler, ExponentialBackoff, RetryPolicy};
+/// use std::time::Duration;
+///
+/// let backoff = ExponentialBackoff::new(Duration::from_millis(10)).max_attempts(2);
+/// let mut handler = Enriched::new(backoff);
+/// assert_eq!(RetryPolicy::WaitRetry(Duration::from_millis(10)), handler.handle(1, "refused"));
+/// match handler.handle(2, "refused") {
+///     RetryPolicy::ForwardError(e) => {
+///         assert_eq!((2, Some(Duration::from_millis(10))), (e.attempts, e.last_delay));
+///         assert!(e.to_string().starts_with("refused (gave up after 2 attempts in "));
+///     }
+///     policy => panic!("Unexpected policy {:?}", policy),
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Enriched<H> {
+    inner: H,
+    started: Instant,
+    last_delay: Option<Duration>,
+}
+
+impl<H> Enriched<H> {
+    /// Wraps an error handler.
+    pub fn new(inner: H) -> Self {
+        Self {
+            inner,
+            started: Instant::now(),
+            last_delay: None,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.started = Instant::now();
+        self.last_delay = None;
+    }
+}
+
+impl<E, H: ErrorHandler<E>> ErrorHandler<E> for Enriched<H> {
+    type OutError = RetryError<H::OutError>;
+
+    fn handle(&mut self, attempt: usize, e: E) -> RetryPolicy<Self::OutError> {
+        match self.inner.handle(attempt, e) {
+            RetryPolicy::ForwardError(error) => {
+                let e = RetryError {
+                    error,
+                    attempts: attempt,
+                    elapsed: self.started.elapsed(),
+                    last_delay: self.last_delay,
+                };
+                self.reset();
+                RetryPolicy::ForwardError(e)
+            }
+            RetryPolicy::Repeat => {
+                self.last_delay = Some(Duration::ZERO);
+                RetryPolicy::Repeat
+            }
+            RetryPolicy::RepeatWithoutCounting => {
+                self.last_delay = Some(Duration::ZERO);
+                RetryPolicy::RepeatWithoutCounting
+            }
+            RetryPolicy::WaitRetry(delay) => {
+                self.last_delay = Some(delay);
+                RetryPolicy::WaitRetry(delay)
+            }
+            RetryPolicy::RetryAs { attempt, delay } => {
+                self.last_delay = Some(delay);
+                RetryPolicy::RetryAs { attempt, delay }
+            }
+        }
+    }
+
+    fn ok(&mut self, attempt: usize) {
+        self.reset();
+        self.inner.ok(attempt);
+    }
+
+    fn ok_with<V: ?Sized>(&mut self, attempt: usize, value: &V) {
+        self.reset();
+        self.inner.ok_with(attempt, value);
+    }
+
+    fn exhausted(&mut self, attempt: usize, error: &Self::OutError) {
+        self.inner.exhausted(attempt, &error.error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FutureRetry, RetryPolicy};
+    use futures::future::{err, ok};
+    use std::io;
+
+    #[tokio::test]
+    async fn wraps() {
+        let mut errors = 0;
+        let result = FutureRetry::new(
+            || err::<(), _>(io::Error::other("nope")),
+            move |e| {
+                errors += 1;
+                match errors {
+                    1 | 2 => RetryPolicy::Repeat,
+                    _ => RetryPolicy::ForwardError(e),
+                }
+            },
+        )
+        .wrap_error()
+        .await;
+        let e = result.unwrap_err();
+        assert_eq!(3, e.attempts);
+        assert!(e
+            .to_string()
+            .starts_with("nope (gave up after 3 attempts in "));
+        assert_eq!("nope", e.source().unwrap().to_string());
+    }
+
+    #[tokio::test]
+    async fn passes_values() {
+        let result = FutureRetry::new(|| ok::<_, u8>(1), RetryPolicy::ForwardError)
+            .wrap_error()
+            .await;
+        assert_eq!(Ok(1), result);
+    }
+
+    #[tokio::test]
+    async fn enriches_stream_errors() {
+        use crate::StreamRetryExt;
+        use futures::{stream, StreamExt};
+
+        let mut errors = 0;
+        let results: Vec<_> = stream::iter(vec![Err("a"), Err("b"), Ok(1), Err("c")])
+            .retry(Enriched::new(move |e| {
+                errors += 1;
+                match errors {
+                    1 => RetryPolicy::WaitRetry(Duration::from_millis(1)),
+                    _ => RetryPolicy::ForwardError(e),
+                }
+            }))
+            .collect()
+            .await;
+        let errors: Vec<_> = results
+            .into_iter()
+            .filter_map(|result| result.err())
+            .map(|(e, _)| (e.error, e.attempts, e.last_delay))
+            .collect();
+        assert_eq!(
+            vec![("b", 2, Some(Duration::from_millis(1))), ("c", 1, None)],
+            errors
+        );
+    }
+}
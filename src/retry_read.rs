@@ -0,0 +1,336 @@
+use crate::{ErrorHandler, RetryPolicy, Sleeper, TokioSleeper};
+use futures::{ready, TryFuture};
+use pin_project_lite::pin_project;
+use std::{
+    fmt,
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, ReadBuf};
+
+pin_project! {
+    #[project = ReadStateProj]
+    enum ReadState<R, Fut, D> {
+        Reopen,
+        Reopening { #[pin] future: Fut },
+        TimerActive { #[pin] delay: D },
+        Reading { #[pin] reader: R },
+    }
+}
+
+pin_project! {
+    /// A reader that survives transient read errors: the failed reader is dropped and a new one is
+    /// requested from a factory, which is given the number of bytes read so far, so a streaming
+    /// download continues where it has stopped instead of from the byte zero (e.g. with an HTTP
+    /// range request).
+    ///
+    /// Both the read errors and the errors of reopening the reader are passed to the error handler,
+    /// which paces the attempts. Once it gives up, the error is returned from the read, and the
+    /// next read starts over with reopening the reader.
+    ///
+    /// ```
+    /// use futures_retry::{RetryPolicy, RetryRead};
+    /// use std::io;
+    /// use tokio::io::AsyncReadExt;
+    ///
+    /// # #[tokio::main] async fn main() -> io::Result<()> {
+    /// let file = b"hello, world";
+    /// let reader = RetryRead::open(
+    ///     |offset: u64| async move { Ok::<_, io::Error>(&file[offset as usize..]) },
+    ///     |e: io::Error| match e.kind() {
+    ///         io::ErrorKind::ConnectionReset => RetryPolicy::Repeat,
+    ///         _ => RetryPolicy::ForwardError(e),
+    ///     },
+    /// );
+    /// let mut body = String::new();
+    /// tokio::pin!(reader);
+    /// reader.read_to_string(&mut body).await?;
+    /// assert_eq!("hello, world", body);
+    /// # Ok(()) }
+    /// ```
+    pub struct RetryRead<R, F, H, S = TokioSleeper>
+    where
+        F: ReaderFactory,
+        S: Sleeper,
+    {
+        factory: F,
+        error_action: H,
+        sleeper: S,
+        offset: u64,
+        attempt: usize,
+        #[pin]
+        state: ReadState<R, F::Future, S::Sleep>,
+    }
+}
+
+/// A factory of the readers for a [`RetryRead`](struct.RetryRead.html), implemented for any
+/// closure that takes the offset to resume from and returns a future resolving into a reader.
+pub trait ReaderFactory {
+    /// A future that resolves into a reader.
+    type Future: TryFuture<Error = io::Error>;
+
+    /// Requests a reader that starts at the `offset` byte.
+    fn reopen(&mut self, offset: u64) -> Self::Future;
+}
+
+impl<F, Fut> ReaderFactory for F
+where
+    F: FnMut(u64) -> Fut,
+    Fut: TryFuture<Error = io::Error>,
+{
+    type Future = Fut;
+
+    fn reopen(&mut self, offset: u64) -> Fut {
+        (self)(offset)
+    }
+}
+
+impl<R, F: ReaderFactory, H> RetryRead<R, F, H> {
+    /// Wraps a reader that is already open; the factory is only used after an error.
+    pub fn new(reader: R, factory: F, error_action: H) -> Self {
+        Self::with_state(
+            factory,
+            error_action,
+            TokioSleeper,
+            ReadState::Reading { reader },
+        )
+    }
+
+    /// Creates a reader that opens the first underlying reader with the factory on the first read.
+    pub fn open(factory: F, error_action: H) -> Self {
+        Self::with_sleeper(factory, error_action, TokioSleeper)
+    }
+}
+
+impl<R, F: ReaderFactory, H, S: Sleeper> RetryRead<R, F, H, S> {
+    /// Like [`open`](#method.open), but the delays between the attempts are timed by a custom
+    /// [`Sleeper`](trait.Sleeper.html).
+    pub fn with_sleeper(factory: F, error_action: H, sleeper: S) -> Self {
+        Self::with_state(factory, error_action, sleeper, ReadState::Reopen)
+    }
+
+    fn with_state(
+        factory: F,
+        error_action: H,
+        sleeper: S,
+        state: ReadState<R, F::Future, S::Sleep>,
+    ) -> Self {
+        Self {
+            factory,
+            error_action,
+            sleeper,
+            offset: 0,
+            attempt: 1,
+            state,
+        }
+    }
+
+    /// Returns the number of bytes read so far.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+impl<R, F: ReaderFactory, H, S: Sleeper> fmt::Debug for RetryRead<R, F, H, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RetryRead")
+            .field("offset", &self.offset)
+            .field("attempt", &self.attempt)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<R, F, H, S> AsyncRead for RetryRead<R, F, H, S>
+where
+    R: AsyncRead,
+    F: ReaderFactory,
+    S: Sleeper,
+    F::Future: TryFuture<Ok = R>,
+    H: ErrorHandler<io::Error, OutError = io::Error>,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut ReadBuf,
+    ) -> Poll<io::Result<()>> {
+        let mut this = self.project();
+        loop {
+            let e = match this.state.as_mut().project() {
+                ReadStateProj::Reopen => {
+                    let future = this.factory.reopen(*this.offset);
+                    this.state.set(ReadState::Reopening { future });
+                    continue;
+                }
+                ReadStateProj::Reopening { future } => match ready!(future.try_poll(cx)) {
+                    Ok(reader) => {
+                        this.state.set(ReadState::Reading { reader });
+                        continue;
+                    }
+                    Err(e) => e,
+                },
+                ReadStateProj::TimerActive { delay } => {
+                    ready!(delay.poll(cx));
+                    this.state.set(ReadState::Reopen);
+                    continue;
+                }
+                ReadStateProj::Reading { reader } => {
+                    let filled = buf.filled().len();
+                    match ready!(reader.poll_read(cx, buf)) {
+                        Ok(()) => {
+                            let read = buf.filled().len() - filled;
+                            if read > 0 && *this.attempt > 1 {
+                                this.error_action
+                                    .ok_with(*this.attempt, &buf.filled()[filled..]);
+                                *this.attempt = 1;
+                            }
+                            *this.offset += read as u64;
+                            return Poll::Ready(Ok(()));
+                        }
+                        Err(e) => e,
+                    }
+                }
+            };
+            let attempt = *this.attempt;
+            let policy = this.error_action.handle(attempt, e);
+            *this.attempt = policy.next_attempt(attempt);
+            match policy {
+                RetryPolicy::ForwardError(e) => {
+                    this.error_action.exhausted(attempt, &e);
+                    this.state.set(ReadState::Reopen);
+                    *this.attempt = 1;
+                    return Poll::Ready(Err(e));
+                }
+                RetryPolicy::Repeat | RetryPolicy::RepeatWithoutCounting => {
+                    this.state.set(ReadState::Reopen)
+                }
+                RetryPolicy::WaitRetry(duration)
+                | RetryPolicy::RetryAs {
+                    delay: duration, ..
+                } => this.state.set(ReadState::TimerActive {
+                    delay: this.sleeper.sleep(duration),
+                }),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handler::tests::Successes;
+    use futures::future::{ready, Ready};
+    use std::time::Duration;
+    use tokio::io::AsyncReadExt;
+
+    /// Reads a chunk of the data and then fails with a connection reset.
+    struct Flaky<'a> {
+        data: &'a [u8],
+        chunk: usize,
+    }
+
+    impl AsyncRead for Flaky<'_> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context,
+            buf: &mut ReadBuf,
+        ) -> Poll<io::Result<()>> {
+            if self.chunk == 0 && !self.data.is_empty() {
+                return Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()));
+            }
+            let len = self.chunk.min(self.data.len()).min(buf.remaining());
+            buf.put_slice(&self.data[..len]);
+            self.data = &self.data[len..];
+            self.chunk -= len;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn resumes() {
+        let data = b"0123456789";
+        let mut offsets = Vec::new();
+        let factory = |offset: u64| -> Ready<io::Result<Flaky>> {
+            offsets.push(offset);
+            ready(Ok(Flaky {
+                data: &data[offset as usize..],
+                chunk: 4,
+            }))
+        };
+        let reader = RetryRead::open(factory, |_| {
+            RetryPolicy::WaitRetry(Duration::from_millis(1))
+        });
+        tokio::pin!(reader);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).await.unwrap();
+        assert_eq!(&data[..], &read[..]);
+        assert_eq!(10, reader.offset());
+        assert_eq!(vec![0, 4, 8], offsets);
+    }
+
+    #[tokio::test]
+    async fn gives_up() {
+        let flaky = Flaky {
+            data: b"abc",
+            chunk: 1,
+        };
+        let reader = RetryRead::new(
+            flaky,
+            |_| ready(Err::<Flaky, _>(io::ErrorKind::NotFound.into())),
+            |e: io::Error| match e.kind() {
+                io::ErrorKind::ConnectionReset => RetryPolicy::Repeat,
+                _ => RetryPolicy::ForwardError(e),
+            },
+        );
+        tokio::pin!(reader);
+        let mut read = Vec::new();
+        let e = reader.read_to_end(&mut read).await.unwrap_err();
+        assert_eq!(io::ErrorKind::NotFound, e.kind());
+        assert_eq!(b"a", &read[..]);
+    }
+
+    #[cfg(feature = "test_util")]
+    #[tokio::test]
+    async fn waits_on_the_sleeper() {
+        let data = b"0123456789";
+        let factory = |offset: u64| -> Ready<io::Result<Flaky>> {
+            ready(Ok(Flaky {
+                data: &data[offset as usize..],
+                chunk: 8,
+            }))
+        };
+        let sleeper = crate::test_util::MockSleeper::new();
+        let reader = RetryRead::with_sleeper(
+            factory,
+            |_| RetryPolicy::WaitRetry(Duration::from_secs(3600)),
+            sleeper.clone(),
+        );
+        tokio::pin!(reader);
+        let mut read = Vec::new();
+        let read_to_end = reader.read_to_end(&mut read);
+        tokio::pin!(read_to_end);
+        assert!(futures::poll!(read_to_end.as_mut()).is_pending());
+        sleeper.advance(Duration::from_secs(3600));
+        assert_eq!(10, read_to_end.await.unwrap());
+        assert_eq!(vec![Duration::from_secs(3600)], sleeper.requested());
+    }
+
+    #[tokio::test]
+    async fn passes_success_values() {
+        let data = b"0123456789";
+        let factory = |offset: u64| -> Ready<io::Result<Flaky>> {
+            ready(Ok(Flaky {
+                data: &data[offset as usize..],
+                chunk: 4,
+            }))
+        };
+        let successes = Successes::new(|_| RetryPolicy::Repeat);
+        let reader = RetryRead::open(factory, successes.clone());
+        tokio::pin!(reader);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).await.unwrap();
+        // The first reads after the two resets, each one given the bytes it has read.
+        assert_eq!(vec![(2, "[u8]"), (2, "[u8]")], successes.seen());
+    }
+}
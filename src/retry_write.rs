@@ -0,0 +1,386 @@
+use crate::{ErrorHandler, RetryPolicy, Sleeper, TokioSleeper};
+use futures::{ready, TryFuture};
+use pin_project_lite::pin_project;
+use std::{
+    fmt,
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::AsyncWrite;
+
+pin_project! {
+    #[project = WriteStateProj]
+    enum WriteState<W, Fut, D> {
+        Reconnect,
+        Connecting { #[pin] future: Fut },
+        TimerActive { #[pin] delay: D },
+        Writing { #[pin] writer: W },
+    }
+}
+
+/// The bytes accepted by the current writer since the last successful flush.
+#[derive(Debug, Default)]
+struct Unacknowledged {
+    bytes: Vec<u8>,
+    replayed: usize,
+}
+
+pin_project! {
+    /// A writer that survives connection resets: the failed writer is dropped and a new one is
+    /// requested from a factory, which is given the number of bytes acknowledged so far, so an
+    /// upload continues where it has stopped.
+    ///
+    /// A byte is acknowledged once it has been flushed (or the writer has been shut down). The
+    /// bytes accepted by a writer but not flushed yet are kept and written to the new writer again
+    /// after a reconnect, so nothing is lost; flush regularly to keep that buffer small.
+    ///
+    /// Both the write errors and the errors of reconnecting are passed to the error handler, which
+    /// paces the attempts. Once it gives up, the error is returned from the write, and the next
+    /// write starts over with reconnecting.
+    ///
+    /// ```
+    /// use futures_retry::{RetryPolicy, RetryWrite};
+    /// use std::io;
+    /// use tokio::io::AsyncWriteExt;
+    ///
+    /// # #[tokio::main] async fn main() -> io::Result<()> {
+    /// let writer = RetryWrite::open(
+    ///     |offset: u64| async move {
+    ///         // Ask the server to append at the `offset`...
+    ///         Ok::<_, io::Error>(Vec::new())
+    ///     },
+    ///     |e: io::Error| match e.kind() {
+    ///         io::ErrorKind::ConnectionReset => RetryPolicy::Repeat,
+    ///         _ => RetryPolicy::ForwardError(e),
+    ///     },
+    /// );
+    /// tokio::pin!(writer);
+    /// writer.write_all(b"hello").await?;
+    /// writer.flush().await?;
+    /// assert_eq!(5, writer.acknowledged());
+    /// # Ok(()) }
+    /// ```
+    pub struct RetryWrite<W, F, H, S = TokioSleeper>
+    where
+        F: WriterFactory,
+        S: Sleeper,
+    {
+        factory: F,
+        error_action: H,
+        sleeper: S,
+        acknowledged: u64,
+        unacknowledged: Unacknowledged,
+        attempt: usize,
+        #[pin]
+        state: WriteState<W, F::Future, S::Sleep>,
+    }
+}
+
+/// A factory of the writers for a [`RetryWrite`](struct.RetryWrite.html), implemented for any
+/// closure that takes the offset to resume from and returns a future resolving into a writer.
+pub trait WriterFactory {
+    /// A future that resolves into a writer.
+    type Future: TryFuture<Error = io::Error>;
+
+    /// Requests a writer that continues at the `offset` byte.
+    fn reconnect(&mut self, offset: u64) -> Self::Future;
+}
+
+impl<F, Fut> WriterFactory for F
+where
+    F: FnMut(u64) -> Fut,
+    Fut: TryFuture<Error = io::Error>,
+{
+    type Future = Fut;
+
+    fn reconnect(&mut self, offset: u64) -> Fut {
+        (self)(offset)
+    }
+}
+
+impl<W, F: WriterFactory, H> RetryWrite<W, F, H> {
+    /// Wraps a writer that is already connected; the factory is only used after an error.
+    pub fn new(writer: W, factory: F, error_action: H) -> Self {
+        Self::with_state(
+            factory,
+            error_action,
+            TokioSleeper,
+            WriteState::Writing { writer },
+        )
+    }
+
+    /// Creates a writer that connects the first underlying writer with the factory on the first
+    /// write.
+    pub fn open(factory: F, error_action: H) -> Self {
+        Self::with_sleeper(factory, error_action, TokioSleeper)
+    }
+}
+
+impl<W, F: WriterFactory, H, S: Sleeper> RetryWrite<W, F, H, S> {
+    /// Like [`open`](#method.open), but the delays between the attempts are timed by a custom
+    /// [`Sleeper`](trait.Sleeper.html).
+    pub fn with_sleeper(factory: F, error_action: H, sleeper: S) -> Self {
+        Self::with_state(factory, error_action, sleeper, WriteState::Reconnect)
+    }
+
+    fn with_state(
+        factory: F,
+        error_action: H,
+        sleeper: S,
+        state: WriteState<W, F::Future, S::Sleep>,
+    ) -> Self {
+        Self {
+            factory,
+            error_action,
+            sleeper,
+            acknowledged: 0,
+            unacknowledged: Unacknowledged::default(),
+            attempt: 1,
+            state,
+        }
+    }
+
+    /// Returns the number of bytes acknowledged so far.
+    pub fn acknowledged(&self) -> u64 {
+        self.acknowledged
+    }
+}
+
+impl<W, F: WriterFactory, H, S: Sleeper> fmt::Debug for RetryWrite<W, F, H, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RetryWrite")
+            .field("acknowledged", &self.acknowledged)
+            .field("unacknowledged", &self.unacknowledged.bytes.len())
+            .field("attempt", &self.attempt)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<W, F, H, S> RetryWrite<W, F, H, S>
+where
+    W: AsyncWrite,
+    F: WriterFactory,
+    S: Sleeper,
+    F::Future: TryFuture<Ok = W>,
+    H: ErrorHandler<io::Error, OutError = io::Error>,
+{
+    /// Runs an operation on the writer, reconnecting and replaying the unacknowledged bytes as
+    /// needed.
+    fn poll_with<T>(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        mut operation: impl FnMut(
+            Pin<&mut W>,
+            &mut Context,
+            &mut Unacknowledged,
+            &mut u64,
+        ) -> Poll<io::Result<T>>,
+    ) -> Poll<io::Result<T>> {
+        let mut this = self.project();
+        loop {
+            let e = match this.state.as_mut().project() {
+                WriteStateProj::Reconnect => {
+                    let future = this.factory.reconnect(*this.acknowledged);
+                    this.state.set(WriteState::Connecting { future });
+                    continue;
+                }
+                WriteStateProj::Connecting { future } => match ready!(future.try_poll(cx)) {
+                    Ok(writer) => {
+                        this.unacknowledged.replayed = 0;
+                        this.state.set(WriteState::Writing { writer });
+                        continue;
+                    }
+                    Err(e) => e,
+                },
+                WriteStateProj::TimerActive { delay } => {
+                    ready!(delay.poll(cx));
+                    this.state.set(WriteState::Reconnect);
+                    continue;
+                }
+                WriteStateProj::Writing { mut writer } => {
+                    let unacknowledged = &mut *this.unacknowledged;
+                    if unacknowledged.replayed < unacknowledged.bytes.len() {
+                        let replay = &unacknowledged.bytes[unacknowledged.replayed..];
+                        match ready!(writer.poll_write(cx, replay)) {
+                            Ok(0) => io::ErrorKind::WriteZero.into(),
+                            Ok(written) => {
+                                unacknowledged.replayed += written;
+                                continue;
+                            }
+                            Err(e) => e,
+                        }
+                    } else {
+                        let acknowledged = &mut *this.acknowledged;
+                        match ready!(operation(writer.as_mut(), cx, unacknowledged, acknowledged)) {
+                            Ok(x) => {
+                                if *this.attempt > 1 {
+                                    this.error_action.ok_with(*this.attempt, &x);
+                                    *this.attempt = 1;
+                                }
+                                return Poll::Ready(Ok(x));
+                            }
+                            Err(e) => e,
+                        }
+                    }
+                }
+            };
+            let attempt = *this.attempt;
+            let policy = this.error_action.handle(attempt, e);
+            *this.attempt = policy.next_attempt(attempt);
+            match policy {
+                RetryPolicy::ForwardError(e) => {
+                    this.error_action.exhausted(attempt, &e);
+                    this.state.set(WriteState::Reconnect);
+                    *this.attempt = 1;
+                    return Poll::Ready(Err(e));
+                }
+                RetryPolicy::Repeat | RetryPolicy::RepeatWithoutCounting => {
+                    this.state.set(WriteState::Reconnect)
+                }
+                RetryPolicy::WaitRetry(duration)
+                | RetryPolicy::RetryAs {
+                    delay: duration, ..
+                } => this.state.set(WriteState::TimerActive {
+                    delay: this.sleeper.sleep(duration),
+                }),
+            }
+        }
+    }
+}
+
+impl<W, F, H, S> AsyncWrite for RetryWrite<W, F, H, S>
+where
+    W: AsyncWrite,
+    F: WriterFactory,
+    S: Sleeper,
+    F::Future: TryFuture<Ok = W>,
+    H: ErrorHandler<io::Error, OutError = io::Error>,
+{
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.poll_with(cx, |writer, cx, unacknowledged, _| {
+            let written = ready!(writer.poll_write(cx, buf))?;
+            unacknowledged.bytes.extend_from_slice(&buf[..written]);
+            unacknowledged.replayed += written;
+            Poll::Ready(Ok(written))
+        })
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.poll_with(cx, |writer, cx, unacknowledged, acknowledged| {
+            ready!(writer.poll_flush(cx))?;
+            *acknowledged += unacknowledged.bytes.len() as u64;
+            *unacknowledged = Unacknowledged::default();
+            Poll::Ready(Ok(()))
+        })
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.poll_with(cx, |writer, cx, unacknowledged, acknowledged| {
+            ready!(writer.poll_shutdown(cx))?;
+            *acknowledged += unacknowledged.bytes.len() as u64;
+            *unacknowledged = Unacknowledged::default();
+            Poll::Ready(Ok(()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handler::tests::Successes;
+    use futures::future::{ready, Ready};
+    use std::sync::{Arc, Mutex};
+    use tokio::io::AsyncWriteExt;
+
+    /// Appends the flushed bytes to the `server`, unless it's told to reset the connection.
+    struct Connection {
+        server: Arc<Mutex<Vec<u8>>>,
+        pending: Vec<u8>,
+        reset_on_flush: bool,
+    }
+
+    impl AsyncWrite for Connection {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.pending.extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+            if self.reset_on_flush {
+                return Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()));
+            }
+            let pending = std::mem::take(&mut self.pending);
+            self.server.lock().unwrap().extend(pending);
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+            self.poll_flush(cx)
+        }
+    }
+
+    #[tokio::test]
+    async fn replays_unacknowledged() {
+        let server = Arc::new(Mutex::new(Vec::new()));
+        let mut offsets = Vec::new();
+        let factory = |offset: u64| -> Ready<io::Result<Connection>> {
+            offsets.push(offset);
+            ready(Ok(Connection {
+                server: Arc::clone(&server),
+                pending: Vec::new(),
+                reset_on_flush: offsets.len() == 2,
+            }))
+        };
+        let successes = Successes::new(|e: io::Error| match e.kind() {
+            io::ErrorKind::ConnectionReset => RetryPolicy::Repeat,
+            _ => RetryPolicy::ForwardError(e),
+        });
+        let writer = RetryWrite::open(factory, successes.clone());
+        tokio::pin!(writer);
+        writer.write_all(b"hello, ").await.unwrap();
+        writer.flush().await.unwrap();
+        // Forces a reconnect, so the second connection is the one that resets.
+        writer.as_mut().project().state.set(WriteState::Reconnect);
+        writer.write_all(b"world").await.unwrap();
+        writer.shutdown().await.unwrap();
+        assert_eq!(12, writer.acknowledged());
+        assert_eq!(b"hello, world", &server.lock().unwrap()[..]);
+        assert_eq!(vec![0, 7, 7], offsets);
+        // The shutdown that has succeeded after the replay.
+        assert_eq!(vec![(2, "()")], successes.seen());
+    }
+
+    #[cfg(feature = "test_util")]
+    #[tokio::test]
+    async fn waits_on_the_sleeper() {
+        let hour = std::time::Duration::from_secs(3600);
+        let server = Arc::new(Mutex::new(Vec::new()));
+        let mut connections = 0;
+        let factory = |_| {
+            connections += 1;
+            ready(Ok(Connection {
+                server: Arc::clone(&server),
+                pending: Vec::new(),
+                reset_on_flush: connections == 1,
+            }))
+        };
+        let sleeper = crate::test_util::MockSleeper::new();
+        let writer =
+            RetryWrite::with_sleeper(factory, |_| RetryPolicy::WaitRetry(hour), sleeper.clone());
+        tokio::pin!(writer);
+        writer.write_all(b"hello").await.unwrap();
+        let flush = writer.flush();
+        tokio::pin!(flush);
+        assert!(futures::poll!(flush.as_mut()).is_pending());
+        sleeper.advance(hour);
+        flush.await.unwrap();
+        assert_eq!(b"hello", &server.lock().unwrap()[..]);
+        assert_eq!(vec![hour], sleeper.requested());
+    }
+}
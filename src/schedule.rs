@@ -0,0 +1,247 @@
+use crate::{ErrorHandler, RetryPolicy};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A calendar that tells when retrying is allowed, e.g. only within the maintenance windows agreed
+/// with a partner system.
+///
+/// Implemented for closures, so a cron expression parsed by a dedicated crate might be plugged in
+/// as well as the daily [`TimeWindows`](struct.TimeWindows.html).
+pub trait RetrySchedule {
+    /// Returns the earliest moment at or after `at` when an attempt is allowed.
+    fn next_allowed(&self, at: SystemTime) -> SystemTime;
+}
+
+impl<F: Fn(SystemTime) -> SystemTime> RetrySchedule for F {
+    fn next_allowed(&self, at: SystemTime) -> SystemTime {
+        self(at)
+    }
+}
+
+/// A daily schedule made of time-of-day windows, like "02:00–04:00".
+///
+/// The times are counted from midnight UTC, shifted by a fixed [`utc_offset`](#method.utc_offset)
+/// for a local time. A window might span midnight, e.g. from 22:00 to 02:00. Without any window
+/// retrying is always allowed.
+///
+/// ```
+/// use futures_retry::{RetrySchedule, TimeWindows};
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// let hour = Duration::from_secs(60 * 60);
+/// let nightly = TimeWindows::new().daily(2 * hour, 4 * hour);
+/// let noon = UNIX_EPOCH + 12 * hour;
+/// assert_eq!(UNIX_EPOCH + 26 * hour, nightly.next_allowed(noon));
+/// let three_am = UNIX_EPOCH + 3 * hour;
+/// assert_eq!(three_am, nightly.next_allowed(three_am));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TimeWindows {
+    windows: Vec<(Duration, Duration)>,
+    utc_offset: i32,
+}
+
+impl TimeWindows {
+    /// Creates a schedule without windows.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a window from the `start` till the `end`, both counted from midnight.
+    pub fn daily(mut self, start: Duration, end: Duration) -> Self {
+        self.windows.push((start.min(DAY), end.min(DAY)));
+        self
+    }
+
+    /// Sets the offset of the local time from UTC in seconds, positive to the east.
+    pub fn utc_offset(mut self, seconds: i32) -> Self {
+        self.utc_offset = seconds;
+        self
+    }
+
+    fn time_of_day(&self, at: SystemTime) -> Duration {
+        let since_epoch = at.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let seconds = (since_epoch.as_secs() as i64 + i64::from(self.utc_offset))
+            .rem_euclid(DAY.as_secs() as i64) as u64;
+        Duration::new(seconds, since_epoch.subsec_nanos())
+    }
+}
+
+impl RetrySchedule for TimeWindows {
+    fn next_allowed(&self, at: SystemTime) -> SystemTime {
+        let now = self.time_of_day(at);
+        let inside = |&(start, end): &(Duration, Duration)| {
+            if start <= end {
+                start <= now && now < end
+            } else {
+                now >= start || now < end
+            }
+        };
+        if self.windows.is_empty() || self.windows.iter().any(inside) {
+            return at;
+        }
+        let wait = self
+            .windows
+            .iter()
+            .map(|&(start, _)| {
+                if start >= now {
+                    start - now
+                } else {
+                    DAY - now + start
+                }
+            })
+            .min()
+            .unwrap_or_default();
+        at.checked_add(wait).unwrap_or(at)
+    }
+}
+
+/// An error handler that postpones the retries of an inner one until its
+/// [`RetrySchedule`](trait.RetrySchedule.html) allows them.
+///
+/// The inner handler still decides whether and how soon to retry; when the planned attempt falls
+/// outside the schedule the delay is stretched till the next allowed moment.
+///
+/// ```
+/// use futures_retry::{ErrorHandler, ExponentialBackoff, RetryPolicy, Scheduled, TimeWindows};
+/// use std::time::Duration;
+///
+/// let hour = Duration::from_secs(60 * 60);
+/// let mut handler = Scheduled::new(
+///     ExponentialBackoff::new(Duration::from_secs(1)),
+///     TimeWindows::new().daily(2 * hour, 4 * hour),
+/// );
+/// match handler.handle(1, ()) {
+///     RetryPolicy::WaitRetry(delay) => assert!(delay >= Duration::from_secs(1)),
+///     policy => panic!("Unexpected policy {:?}", policy),
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Scheduled<H, S> {
+    inner: H,
+    schedule: S,
+}
+
+impl<H, S> Scheduled<H, S> {
+    /// Wraps an error handler.
+    pub fn new(inner: H, schedule: S) -> Self {
+        Self { inner, schedule }
+    }
+}
+
+impl<H, S: RetrySchedule> Scheduled<H, S> {
+    /// Stretches the `delay` till the next allowed moment, or keeps it if the planned attempt is
+    /// too far in the future to be represented.
+    fn postpone(&self, delay: Duration) -> Duration {
+        let now = SystemTime::now();
+        match now.checked_add(delay) {
+            Some(at) => {
+                let allowed = self.schedule.next_allowed(at);
+                allowed.duration_since(now).unwrap_or_default().max(delay)
+            }
+            None => delay,
+        }
+    }
+}
+
+impl<E, H, S> ErrorHandler<E> for Scheduled<H, S>
+where
+    H: ErrorHandler<E>,
+    S: RetrySchedule,
+{
+    type OutError = H::OutError;
+
+    fn handle(&mut self, attempt: usize, e: E) -> RetryPolicy<H::OutError> {
+        match self.inner.handle(attempt, e) {
+            RetryPolicy::ForwardError(e) => RetryPolicy::ForwardError(e),
+            RetryPolicy::Repeat => match self.postpone(Duration::ZERO) {
+                delay if delay.is_zero() => RetryPolicy::Repeat,
+                delay => RetryPolicy::WaitRetry(delay),
+            },
+            RetryPolicy::RepeatWithoutCounting => match self.postpone(Duration::ZERO) {
+                delay if delay.is_zero() => RetryPolicy::RepeatWithoutCounting,
+                delay => RetryPolicy::WaitRetry(delay),
+            },
+            RetryPolicy::WaitRetry(delay) => RetryPolicy::WaitRetry(self.postpone(delay)),
+            RetryPolicy::RetryAs { attempt, delay } => RetryPolicy::RetryAs {
+                attempt,
+                delay: self.postpone(delay),
+            },
+        }
+    }
+
+    fn ok(&mut self, attempt: usize) {
+        self.inner.ok(attempt);
+    }
+
+    fn ok_with<V: ?Sized>(&mut self, attempt: usize, value: &V) {
+        self.inner.ok_with(attempt, value);
+    }
+
+    fn exhausted(&mut self, attempt: usize, error: &Self::OutError) {
+        self.inner.exhausted(attempt, error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    #[test]
+    fn windows() {
+        let windows = TimeWindows::new()
+            .daily(22 * HOUR, 2 * HOUR)
+            .daily(12 * HOUR, 13 * HOUR)
+            .utc_offset(-3600);
+        // 23:30 UTC is 22:30 local, within the window spanning midnight.
+        let late = UNIX_EPOCH + 23 * HOUR + HOUR / 2;
+        assert_eq!(late, windows.next_allowed(late));
+        // 02:00 UTC is 01:00 local.
+        let night = UNIX_EPOCH + 2 * HOUR;
+        assert_eq!(night, windows.next_allowed(night));
+        // 05:00 local waits for the noon window.
+        let morning = UNIX_EPOCH + 6 * HOUR;
+        assert_eq!(UNIX_EPOCH + 13 * HOUR, windows.next_allowed(morning));
+        // 13:00 local waits for the evening window.
+        assert_eq!(
+            UNIX_EPOCH + 23 * HOUR,
+            windows.next_allowed(UNIX_EPOCH + 14 * HOUR)
+        );
+        let always = TimeWindows::new();
+        assert_eq!(morning, always.next_allowed(morning));
+    }
+
+    #[test]
+    fn postpones() {
+        let mut handler = Scheduled::new(
+            |_| RetryPolicy::Repeat::<()>,
+            |at: SystemTime| at + Duration::from_secs(5),
+        );
+        match handler.handle(1, ()) {
+            RetryPolicy::WaitRetry(delay) => {
+                assert!(delay > Duration::from_secs(4) && delay <= Duration::from_secs(5))
+            }
+            policy => panic!("Unexpected policy {:?}", policy),
+        }
+        let mut open = Scheduled::new(|_| RetryPolicy::Repeat::<()>, TimeWindows::new());
+        assert_eq!(RetryPolicy::Repeat, open.handle(1, ()));
+        let mut forwarding = Scheduled::new(RetryPolicy::ForwardError, TimeWindows::new());
+        assert_eq!(RetryPolicy::ForwardError(7), forwarding.handle(1, 7));
+    }
+
+    #[test]
+    fn endless_delay() {
+        let endless = RetryPolicy::<()>::RetryAs {
+            attempt: 3,
+            delay: Duration::MAX,
+        };
+        let mut handler = Scheduled::new(
+            move |_| endless,
+            TimeWindows::new().daily(2 * HOUR, 4 * HOUR),
+        );
+        assert_eq!(endless, handler.handle(1, ()));
+    }
+}
@@ -0,0 +1,129 @@
+use crate::{ErrorHandler, RetryPolicy};
+
+/// Feeds a scripted sequence of errors to an error handler and returns the policies it picks,
+/// without running any futures or sleeping.
+///
+/// Every error is treated as a failure of the next attempt, starting with the first one and
+/// counted just like in [`FutureRetry`](struct.FutureRetry.html). The simulation stops at the first
+/// `ForwardError`, which is the last returned policy, or when the errors run out. Schedules become
+/// plain data that can be asserted on, or printed to document a configuration.
+///
+/// ```
+/// use futures_retry::{simulate, ExponentialBackoff, RetryPolicy};
+/// use std::time::Duration;
+///
+/// let backoff = ExponentialBackoff::new(Duration::from_millis(100))
+///     .max_delay(Duration::from_millis(300))
+///     .max_attempts(4);
+/// let policies = simulate(backoff, vec!["timeout"; 10]);
+/// assert_eq!(
+///     vec![
+///         RetryPolicy::WaitRetry(Duration::from_millis(100)),
+///         RetryPolicy::WaitRetry(Duration::from_millis(200)),
+///         RetryPolicy::WaitRetry(Duration::from_millis(300)),
+///         RetryPolicy::ForwardError("timeout"),
+///     ],
+///     policies
+/// );
+/// ```
+pub fn simulate<E, H, I>(mut handler: H, errors: I) -> Vec<RetryPolicy<H::OutError>>
+where
+    H: ErrorHandler<E>,
+    I: IntoIterator<Item = E>,
+{
+    let mut policies = Vec::new();
+    let mut attempt = 1;
+    for e in errors {
+        let policy = handler.handle(attempt, e);
+        attempt = policy.next_attempt(attempt);
+        let forwarded = matches!(policy, RetryPolicy::ForwardError(_));
+        policies.push(policy);
+        if forwarded {
+            break;
+        }
+    }
+    policies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn runs_out_of_errors() {
+        let policies = simulate(
+            |e| match e {
+                0 => RetryPolicy::Repeat,
+                e => RetryPolicy::ForwardError(e),
+            },
+            vec![0, 0],
+        );
+        assert_eq!(vec![RetryPolicy::Repeat, RetryPolicy::Repeat], policies);
+    }
+
+    #[test]
+    fn passes_attempts() {
+        struct Attempts;
+
+        impl ErrorHandler<()> for Attempts {
+            type OutError = usize;
+
+            fn handle(&mut self, attempt: usize, _: ()) -> RetryPolicy<usize> {
+                match attempt {
+                    3 => RetryPolicy::ForwardError(attempt),
+                    _ => RetryPolicy::Repeat,
+                }
+            }
+        }
+
+        let policies = simulate(Attempts, vec![(); 5]);
+        assert_eq!(
+            vec![
+                RetryPolicy::Repeat,
+                RetryPolicy::Repeat,
+                RetryPolicy::ForwardError(3)
+            ],
+            policies
+        );
+    }
+
+    #[test]
+    fn overrides_the_counter() {
+        struct Reauthenticating;
+
+        impl ErrorHandler<&'static str> for Reauthenticating {
+            type OutError = &'static str;
+
+            fn handle(&mut self, attempt: usize, e: &'static str) -> RetryPolicy<&'static str> {
+                match (attempt, e) {
+                    (3, _) => RetryPolicy::ForwardError(e),
+                    (_, "unauthorized") => RetryPolicy::RetryAs {
+                        attempt: 1,
+                        delay: Duration::ZERO,
+                    },
+                    (_, "eintr") => RetryPolicy::RepeatWithoutCounting,
+                    _ => RetryPolicy::Repeat,
+                }
+            }
+        }
+
+        // The attempts are 1, 2, then reset to 1, kept at 1, 2 and 3.
+        let errors = vec!["a", "unauthorized", "eintr", "a", "a", "a", "a"];
+        let policies = simulate(Reauthenticating, errors);
+        assert_eq!(
+            vec![
+                RetryPolicy::Repeat,
+                RetryPolicy::RetryAs {
+                    attempt: 1,
+                    delay: Duration::ZERO
+                },
+                RetryPolicy::RepeatWithoutCounting,
+                RetryPolicy::Repeat,
+                RetryPolicy::Repeat,
+                RetryPolicy::ForwardError("a"),
+            ],
+            policies
+        );
+    }
+}
@@ -0,0 +1,480 @@
+use crate::{ErrorHandler, RetryPolicy, Sleeper, TokioSleeper};
+use futures::{ready, Sink};
+use pin_project_lite::pin_project;
+use std::{
+    collections::VecDeque,
+    fmt,
+    future::Future,
+    mem,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// What a [`SinkRetry`](struct.SinkRetry.html) does with a new item once its buffer is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Overflow {
+    /// Stop accepting items: `poll_ready` stays pending until there is room in the buffer again.
+    #[default]
+    Backpressure,
+    /// Accept the item and drop the oldest buffered one.
+    DropOldest,
+    /// Accept the item and drop it right away.
+    DropNewest,
+}
+
+/// Either `Sink::poll_flush` or `Sink::poll_close`.
+type Finish<Si, Item> =
+    fn(Pin<&mut Si>, &mut Context) -> Poll<Result<(), <Si as Sink<Item>>::Error>>;
+
+pin_project! {
+    /// A sink that retries sending items into an inner sink, as the error handler decides.
+    ///
+    /// The items are buffered before they are sent, so an item the inner sink has failed to
+    /// accept (either in `poll_ready` or in `start_send`) is sent again later; that's why the
+    /// items have to be `Clone`. The buffer is bounded by a [`capacity`](#method.capacity): once
+    /// it is full, the sink stops being ready, so the producers are slowed down during an outage
+    /// instead of piling the items up in memory, unless an [`overflow`](#method.overflow)
+    /// policy says to drop the items instead.
+    ///
+    /// Once the error handler gives up, the item is dropped and the error is returned (along with
+    /// the number of the attempt), and the next items are sent as usual.
+    ///
+    /// The failures of flushing and closing the inner sink are retried as well, by the same error
+    /// handler unless a separate [`flush_handler`](#method.flush_handler) is given. A failed flush
+    /// often means that the connection is dead and resending won't help, so a flush handler might
+    /// give up right away, leaving it to the caller to reconnect. The attempts to flush are
+    /// counted apart from the attempts to send.
+    ///
+    /// ```
+    /// use futures::{channel::mpsc, SinkExt, StreamExt};
+    /// use futures_retry::{Overflow, RetryPolicy, SinkRetry};
+    /// use std::time::Duration;
+    ///
+    /// # #[tokio::main] async fn main() {
+    /// let (sender, receiver) = mpsc::channel(0);
+    /// let consumer = tokio::spawn(receiver.collect::<Vec<_>>());
+    /// let mut sink = SinkRetry::new(sender, |_| {
+    ///     RetryPolicy::WaitRetry::<mpsc::SendError>(Duration::from_millis(100))
+    /// })
+    /// .capacity(64)
+    /// .overflow(Overflow::DropOldest);
+    /// sink.send(1).await.unwrap();
+    /// sink.send(2).await.unwrap();
+    /// sink.close().await.unwrap();
+    /// assert_eq!(vec![1, 2], consumer.await.unwrap());
+    /// # }
+    /// ```
+    pub struct SinkRetry<Si, Item, H, F = H, S = TokioSleeper>
+    where
+        S: Sleeper,
+    {
+        #[pin]
+        sink: Si,
+        error_action: H,
+        flush_action: Option<F>,
+        buffer: VecDeque<Item>,
+        capacity: usize,
+        overflow: Overflow,
+        attempt: usize,
+        flush_attempt: usize,
+        sleeper: S,
+        // Boxed, so the sink is `Unpin` as long as the inner one is, the way `SinkExt` wants it.
+        delay: Option<Pin<Box<S::Sleep>>>,
+    }
+}
+
+impl<Si, Item, H> SinkRetry<Si, Item, H> {
+    /// Wraps a sink. The buffer holds a single item, so the sink is ready whenever the inner one
+    /// is, until the [`capacity`](#method.capacity) is raised.
+    pub fn new(sink: Si, error_action: H) -> Self {
+        Self::with_sleeper(sink, error_action, TokioSleeper)
+    }
+}
+
+impl<Si, Item, H, S: Sleeper> SinkRetry<Si, Item, H, H, S> {
+    /// Like a `new` method, but the delays between the attempts are timed by a custom
+    /// [`Sleeper`](trait.Sleeper.html).
+    pub fn with_sleeper(sink: Si, error_action: H, sleeper: S) -> Self {
+        Self {
+            sink,
+            error_action,
+            flush_action: None,
+            buffer: VecDeque::with_capacity(1),
+            capacity: 1,
+            overflow: Overflow::Backpressure,
+            attempt: 1,
+            flush_attempt: 1,
+            sleeper,
+            delay: None,
+        }
+    }
+}
+
+impl<Si, Item, H, F, S: Sleeper> SinkRetry<Si, Item, H, F, S> {
+    /// Sets a separate error handler for the failures of flushing and closing the inner sink.
+    pub fn flush_handler<G>(self, flush_action: G) -> SinkRetry<Si, Item, H, G, S> {
+        SinkRetry {
+            sink: self.sink,
+            error_action: self.error_action,
+            flush_action: Some(flush_action),
+            buffer: self.buffer,
+            capacity: self.capacity,
+            overflow: self.overflow,
+            attempt: self.attempt,
+            flush_attempt: 1,
+            sleeper: self.sleeper,
+            delay: self.delay,
+        }
+    }
+
+    /// Sets how many items might be buffered while the inner sink is failing; at least one.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self.buffer.reserve(self.capacity);
+        self
+    }
+
+    /// Sets what to do with the new items once the buffer is full.
+    pub fn overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Returns how many items are waiting to be sent.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Returns the number of the current attempt to send the oldest buffered item.
+    pub fn attempt(&self) -> usize {
+        self.attempt
+    }
+
+    /// Returns a reference to the inner sink.
+    pub fn get_ref(&self) -> &Si {
+        &self.sink
+    }
+
+    /// Returns the inner sink, dropping the buffered items.
+    pub fn into_inner(self) -> Si {
+        self.sink
+    }
+}
+
+impl<Si, Item, H, F, S: Sleeper> fmt::Debug for SinkRetry<Si, Item, H, F, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SinkRetry")
+            .field("buffered", &self.buffer.len())
+            .field("capacity", &self.capacity)
+            .field("overflow", &self.overflow)
+            .field("attempt", &self.attempt)
+            .field("flush_attempt", &self.flush_attempt)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<Si, Item, H, F, S> SinkRetry<Si, Item, H, F, S>
+where
+    S: Sleeper,
+    Si: Sink<Item>,
+    Item: Clone,
+    H: ErrorHandler<Si::Error>,
+    F: ErrorHandler<Si::Error, OutError = H::OutError>,
+{
+    /// Passes an error to the handler, arming the timer if it decides to wait.
+    fn retry(
+        self: Pin<&mut Self>,
+        e: Si::Error,
+        flushing: bool,
+    ) -> Result<(), (H::OutError, usize)> {
+        let this = self.project();
+        let counter = if flushing {
+            this.flush_attempt
+        } else {
+            this.attempt
+        };
+        let attempt = *counter;
+        let policy = match this.flush_action {
+            Some(flush_action) if flushing => flush_action.handle(attempt, e),
+            _ => this.error_action.handle(attempt, e),
+        };
+        *counter = policy.next_attempt(attempt);
+        match policy {
+            RetryPolicy::ForwardError(e) => {
+                match this.flush_action {
+                    Some(flush_action) if flushing => flush_action.exhausted(attempt, &e),
+                    _ => this.error_action.exhausted(attempt, &e),
+                }
+                *counter = 1;
+                return Err((e, attempt));
+            }
+            RetryPolicy::Repeat | RetryPolicy::RepeatWithoutCounting => {}
+            RetryPolicy::WaitRetry(delay) | RetryPolicy::RetryAs { delay, .. } => {
+                *this.delay = Some(Box::pin(this.sleeper.sleep(delay)))
+            }
+        }
+        Ok(())
+    }
+
+    /// Tells the handler about a success, passing it the item sent (or `()` for a flush).
+    fn succeeded<V: ?Sized>(self: Pin<&mut Self>, flushing: bool, value: &V) {
+        let this = self.project();
+        if flushing {
+            let attempt = mem::replace(this.flush_attempt, 1);
+            match this.flush_action {
+                Some(flush_action) => flush_action.ok_with(attempt, value),
+                None => this.error_action.ok_with(attempt, value),
+            }
+        } else {
+            let attempt = mem::replace(this.attempt, 1);
+            this.error_action.ok_with(attempt, value);
+        }
+    }
+
+    /// Sends the buffered items into the inner sink, retrying the failed ones.
+    fn poll_send(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<(), (H::OutError, usize)>> {
+        loop {
+            let mut this = self.as_mut().project();
+            if let Some(delay) = this.delay {
+                ready!(delay.as_mut().poll(cx));
+                *this.delay = None;
+            }
+            let item = match this.buffer.front() {
+                Some(item) => item,
+                None => return Poll::Ready(Ok(())),
+            };
+            let sent = match ready!(this.sink.as_mut().poll_ready(cx)) {
+                Ok(()) => this.sink.as_mut().start_send(item.clone()),
+                Err(e) => Err(e),
+            };
+            match sent {
+                Ok(()) => {
+                    if let Some(item) = this.buffer.pop_front() {
+                        self.as_mut().succeeded(false, &item);
+                    }
+                }
+                Err(e) => {
+                    if let Err(e) = self.as_mut().retry(e, false) {
+                        self.project().buffer.pop_front();
+                        return Poll::Ready(Err(e));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sends the buffered items and then flushes or closes the inner sink, retrying the failures.
+    fn poll_finish(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        finish: Finish<Si, Item>,
+    ) -> Poll<Result<(), (H::OutError, usize)>> {
+        loop {
+            ready!(self.as_mut().poll_send(cx))?;
+            match ready!(finish(self.as_mut().project().sink, cx)) {
+                Ok(()) => {
+                    self.succeeded(true, &());
+                    return Poll::Ready(Ok(()));
+                }
+                Err(e) => self.as_mut().retry(e, true)?,
+            }
+        }
+    }
+}
+
+impl<Si, Item, H, F, S> Sink<Item> for SinkRetry<Si, Item, H, F, S>
+where
+    S: Sleeper,
+    Si: Sink<Item>,
+    Item: Clone,
+    H: ErrorHandler<Si::Error>,
+    F: ErrorHandler<Si::Error, OutError = H::OutError>,
+{
+    type Error = (H::OutError, usize);
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        if let Poll::Ready(result) = self.as_mut().poll_send(cx) {
+            return Poll::Ready(result);
+        }
+        if self.buffer.len() < self.capacity || self.overflow != Overflow::Backpressure {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Item) -> Result<(), Self::Error> {
+        let this = self.project();
+        if this.buffer.len() >= *this.capacity {
+            match this.overflow {
+                Overflow::Backpressure => {}
+                Overflow::DropOldest => {
+                    this.buffer.pop_front();
+                    *this.attempt = 1;
+                }
+                Overflow::DropNewest => return Ok(()),
+            }
+        }
+        this.buffer.push_back(item);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.poll_finish(cx, Si::poll_flush)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.poll_finish(cx, Si::poll_close)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handler::tests::Successes;
+    use futures::{channel::mpsc, task::noop_waker_ref, SinkExt, StreamExt};
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct Flaky {
+        failures: usize,
+        flush_failures: usize,
+        items: Vec<u8>,
+    }
+
+    impl Sink<u8> for Flaky {
+        type Error = u8;
+
+        fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), u8>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn start_send(mut self: Pin<&mut Self>, item: u8) -> Result<(), u8> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(item);
+            }
+            self.items.push(item);
+            Ok(())
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), u8>> {
+            if self.flush_failures > 0 {
+                self.flush_failures -= 1;
+                return Poll::Ready(Err(0));
+            }
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), u8>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn resends_failed_items() {
+        let flaky = Flaky {
+            failures: 2,
+            ..Flaky::default()
+        };
+        let mut sink = SinkRetry::new(flaky, |_| {
+            RetryPolicy::WaitRetry::<()>(Duration::from_millis(1))
+        });
+        sink.send(1).await.unwrap();
+        sink.send(2).await.unwrap();
+        assert_eq!(vec![1, 2], sink.get_ref().items);
+        let flaky = Flaky {
+            failures: 1,
+            ..Flaky::default()
+        };
+        let mut sink = SinkRetry::new(flaky, RetryPolicy::ForwardError);
+        assert_eq!(Err((3, 1)), sink.send(3).await);
+        sink.send(4).await.unwrap();
+        assert_eq!(vec![4], sink.get_ref().items);
+    }
+
+    #[tokio::test]
+    async fn applies_backpressure() {
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let (sender, receiver) = mpsc::channel(0);
+        let mut sink = SinkRetry::new(sender, |_| RetryPolicy::Repeat::<()>).capacity(2);
+        for item in 1..=3 {
+            assert!(Pin::new(&mut sink).poll_ready(&mut cx).is_ready());
+            Pin::new(&mut sink).start_send(item).unwrap();
+        }
+        assert!(Pin::new(&mut sink).poll_ready(&mut cx).is_pending());
+        assert_eq!(2, sink.buffered());
+        let (closed, items) = futures::join!(sink.close(), receiver.collect::<Vec<_>>());
+        assert_eq!((Ok(()), vec![1, 2, 3]), (closed, items));
+    }
+
+    #[tokio::test]
+    async fn drops_on_overflow() {
+        let (sender, receiver) = mpsc::channel(0);
+        let mut sink = SinkRetry::new(sender, |_| RetryPolicy::Repeat::<()>)
+            .capacity(2)
+            .overflow(Overflow::DropOldest);
+        let mut cx = Context::from_waker(noop_waker_ref());
+        for item in 1..=5 {
+            assert!(Pin::new(&mut sink).poll_ready(&mut cx).is_ready());
+            Pin::new(&mut sink).start_send(item).unwrap();
+        }
+        let (closed, items) = futures::join!(sink.close(), receiver.collect::<Vec<_>>());
+        assert_eq!((Ok(()), vec![1, 4, 5]), (closed, items));
+    }
+
+    #[tokio::test]
+    async fn separate_flush_handler() {
+        let flaky = Flaky {
+            failures: 1,
+            flush_failures: 2,
+            ..Flaky::default()
+        };
+        let mut sink = SinkRetry::new(flaky, |_| RetryPolicy::Repeat::<&str>)
+            .flush_handler(|_| RetryPolicy::ForwardError("reconnect"));
+        assert_eq!(Err(("reconnect", 1)), sink.send(1).await);
+        assert_eq!((1, vec![1]), (sink.attempt(), sink.get_ref().items.clone()));
+        assert_eq!(Err(("reconnect", 1)), sink.flush().await);
+        sink.send(2).await.unwrap();
+        assert_eq!(vec![1, 2], sink.get_ref().items);
+    }
+
+    #[cfg(feature = "test_util")]
+    #[tokio::test]
+    async fn waits_on_the_sleeper() {
+        let hour = Duration::from_secs(3600);
+        let flaky = Flaky {
+            failures: 1,
+            ..Flaky::default()
+        };
+        let sleeper = crate::test_util::MockSleeper::new();
+        let mut sink = SinkRetry::with_sleeper(
+            flaky,
+            |_| RetryPolicy::WaitRetry::<()>(hour),
+            sleeper.clone(),
+        );
+        let mut send = sink.send(1);
+        assert!(futures::poll!(&mut send).is_pending());
+        sleeper.advance(hour);
+        send.await.unwrap();
+        assert_eq!(vec![1], sink.get_ref().items);
+        assert_eq!(vec![hour], sleeper.requested());
+    }
+
+    #[tokio::test]
+    async fn passes_success_values() {
+        let flaky = Flaky {
+            failures: 1,
+            flush_failures: 1,
+            ..Flaky::default()
+        };
+        let successes = Successes::new(|_| RetryPolicy::Repeat::<u8>);
+        let mut sink = SinkRetry::new(flaky, successes.clone());
+        sink.send(1).await.unwrap();
+        assert_eq!(vec![(2, "u8"), (2, "()")], successes.seen());
+    }
+}
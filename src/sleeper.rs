@@ -0,0 +1,69 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::time::{self, Instant};
+
+/// A source of time for the retry loops: it tells the current time and creates the timers that
+/// delay the retries.
+///
+/// The loops use [`TokioSleeper`](struct.TokioSleeper.html) by default; a custom sleeper is
+/// mostly useful in tests, see the `test_util` feature.
+pub trait Sleeper {
+    /// A future that resolves once a delay has passed.
+    type Sleep: Future<Output = ()>;
+
+    /// Creates a timer that resolves after the `duration`.
+    fn sleep(&self, duration: Duration) -> Self::Sleep;
+
+    /// Returns the current time.
+    fn now(&self) -> Instant;
+}
+
+/// How far a wait that is too long to be represented is clamped to, about 30 years, like the
+/// tokio timer does.
+const FAR_FUTURE: Duration = Duration::from_secs(86400 * 365 * 30);
+
+/// Returns when a wait of `duration` that starts `now` ends, clamping the waits that would
+/// overflow an `Instant`, e.g. a `Duration::MAX` one meaning forever.
+pub(crate) fn wait_until(now: Instant, duration: Duration) -> Instant {
+    now.checked_add(duration)
+        .unwrap_or_else(|| now + FAR_FUTURE)
+}
+
+/// Polls the timer of a retry loop that waits `until` the given time, creating the timer first if
+/// the loop has deferred that to the first poll, e.g. for an initial delay set up outside of a
+/// runtime.
+pub(crate) fn poll_timer<S: Sleeper>(
+    mut timer: Pin<&mut Option<S::Sleep>>,
+    until: Instant,
+    sleeper: &S,
+    cx: &mut Context,
+) -> Poll<()> {
+    if timer.is_none() {
+        timer.set(Some(
+            sleeper.sleep(until.saturating_duration_since(sleeper.now())),
+        ));
+    }
+    timer
+        .as_pin_mut()
+        .map_or(Poll::Ready(()), |timer| timer.poll(cx))
+}
+
+/// A sleeper backed by the tokio timer.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioSleeper;
+
+impl Sleeper for TokioSleeper {
+    type Sleep = time::Sleep;
+
+    fn sleep(&self, duration: Duration) -> time::Sleep {
+        time::sleep(duration)
+    }
+
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
@@ -0,0 +1,214 @@
+use crate::{ErrorHandler, RetryPolicy};
+use std::{
+    fmt::{self, Display},
+    time::{Duration, SystemTime},
+};
+
+/// A persistable state of a retry loop.
+///
+/// Long-running retries (think of hours or days) shouldn't start from scratch after a process
+/// restart. A [`SnapshotHandler`](struct.SnapshotHandler.html) keeps a snapshot up to date and
+/// hands it to a callback after each attempt, so it can be saved somewhere; once the process is
+/// back, the loop is resumed with
+/// [`FutureRetry::resume`](struct.FutureRetry.html#method.resume).
+///
+/// With the `serde` feature the snapshot implements `Serialize` and `Deserialize`.
+///
+/// ```
+/// use futures_retry::{ExponentialBackoff, FutureRetry, RetrySnapshot};
+/// use std::time::Duration;
+///
+/// # #[tokio::main] async fn main() {
+/// // Loaded from a file or a database, or the default one for a fresh start.
+/// let snapshot = RetrySnapshot::default();
+/// let handler = snapshot.clone().handler(
+///     ExponentialBackoff::new(Duration::from_millis(10)),
+///     |snapshot: &RetrySnapshot| {
+///         // Save the snapshot here.
+///     },
+/// );
+/// let retry = FutureRetry::resume(
+///     || async { Ok::<_, std::io::Error>(()) },
+///     handler,
+///     &snapshot,
+/// );
+/// assert_eq!(1, retry.await.unwrap().1);
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RetrySnapshot {
+    /// The number of the next attempt.
+    pub attempt: usize,
+    /// When the next attempt is due, or `None` if it should be made right away or is too far in
+    /// the future to be represented.
+    pub next_retry: Option<SystemTime>,
+    /// Descriptions of the errors of the failed attempts.
+    pub errors: Vec<String>,
+}
+
+impl Default for RetrySnapshot {
+    fn default() -> Self {
+        Self {
+            attempt: 1,
+            next_retry: None,
+            errors: Vec::new(),
+        }
+    }
+}
+
+impl RetrySnapshot {
+    /// Returns how long to wait before the next attempt, which is zero if it is already due.
+    pub fn remaining_delay(&self) -> Duration {
+        self.next_retry
+            .and_then(|at| at.duration_since(SystemTime::now()).ok())
+            .unwrap_or_default()
+    }
+
+    /// Wraps an error handler so the snapshot is updated after each attempt and passed to the
+    /// `persist` callback.
+    pub fn handler<H, P>(self, inner: H, persist: P) -> SnapshotHandler<H, P>
+    where
+        P: FnMut(&RetrySnapshot),
+    {
+        SnapshotHandler {
+            snapshot: self,
+            inner,
+            persist,
+        }
+    }
+}
+
+/// An error handler that keeps a [`RetrySnapshot`](struct.RetrySnapshot.html) of the retry loop.
+///
+/// Created by [`RetrySnapshot::handler`](struct.RetrySnapshot.html#method.handler).
+#[derive(Clone)]
+pub struct SnapshotHandler<H, P> {
+    snapshot: RetrySnapshot,
+    inner: H,
+    persist: P,
+}
+
+impl<H: fmt::Debug, P> fmt::Debug for SnapshotHandler<H, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SnapshotHandler")
+            .field("snapshot", &self.snapshot)
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<H, P> SnapshotHandler<H, P> {
+    /// Returns the current snapshot.
+    pub fn snapshot(&self) -> &RetrySnapshot {
+        &self.snapshot
+    }
+}
+
+impl<E, H, P> ErrorHandler<E> for SnapshotHandler<H, P>
+where
+    E: Display,
+    H: ErrorHandler<E>,
+    P: FnMut(&RetrySnapshot),
+{
+    type OutError = H::OutError;
+
+    fn handle(&mut self, attempt: usize, e: E) -> RetryPolicy<H::OutError> {
+        self.snapshot.errors.push(e.to_string());
+        let policy = self.inner.handle(attempt, e);
+        self.snapshot.attempt = policy.next_attempt(attempt);
+        self.snapshot.next_retry = match policy {
+            RetryPolicy::WaitRetry(delay) | RetryPolicy::RetryAs { delay, .. } => {
+                SystemTime::now().checked_add(delay)
+            }
+            RetryPolicy::Repeat | RetryPolicy::RepeatWithoutCounting => None,
+            RetryPolicy::ForwardError(_) => None,
+        };
+        (self.persist)(&self.snapshot);
+        policy
+    }
+
+    fn ok(&mut self, attempt: usize) {
+        self.snapshot = RetrySnapshot::default();
+        (self.persist)(&self.snapshot);
+        self.inner.ok(attempt);
+    }
+
+    fn ok_with<V: ?Sized>(&mut self, attempt: usize, value: &V) {
+        self.snapshot = RetrySnapshot::default();
+        (self.persist)(&self.snapshot);
+        self.inner.ok_with(attempt, value);
+    }
+
+    fn exhausted(&mut self, attempt: usize, error: &Self::OutError) {
+        self.inner.exhausted(attempt, error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FutureRetry;
+    use futures::future::{err, ok, Ready};
+    use std::sync::{Arc, Mutex};
+
+    fn failing(failures: usize) -> impl FnMut() -> Ready<Result<(), &'static str>> {
+        let mut calls = 0;
+        move || {
+            calls += 1;
+            if calls > failures {
+                ok(())
+            } else {
+                err("boom")
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn persist_and_resume() {
+        let saved = Arc::new(Mutex::new(Vec::new()));
+        let persist = {
+            let saved = Arc::clone(&saved);
+            move |snapshot: &RetrySnapshot| saved.lock().unwrap().push(snapshot.clone())
+        };
+        let handler = RetrySnapshot::default().handler(
+            |e| {
+                if e == "boom" {
+                    RetryPolicy::WaitRetry(Duration::from_millis(50))
+                } else {
+                    RetryPolicy::ForwardError(e)
+                }
+            },
+            persist,
+        );
+        // The process "crashes" while waiting for the third attempt.
+        let retry = FutureRetry::new(failing(2), handler);
+        assert!(tokio::time::timeout(Duration::from_millis(75), retry)
+            .await
+            .is_err());
+        let snapshot = saved.lock().unwrap().last().cloned().unwrap();
+        assert_eq!(3, snapshot.attempt);
+        assert_eq!(vec!["boom", "boom"], snapshot.errors);
+        assert!(snapshot.next_retry.is_some());
+
+        let handler = snapshot
+            .clone()
+            .handler(RetryPolicy::ForwardError, |_: &_| {});
+        let retry = FutureRetry::resume(failing(0), handler, &snapshot);
+        assert_eq!(Ok(((), 3)), retry.await);
+    }
+
+    #[test]
+    fn endless_delay() {
+        let mut handler = RetrySnapshot::default().handler(
+            |_| RetryPolicy::WaitRetry::<&str>(Duration::MAX),
+            |_: &_| {},
+        );
+        assert_eq!(
+            RetryPolicy::WaitRetry(Duration::MAX),
+            handler.handle(1, "boom")
+        );
+        assert_eq!(2, handler.snapshot().attempt);
+        assert_eq!(None, handler.snapshot().next_retry);
+    }
+}
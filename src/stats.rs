@@ -0,0 +1,187 @@
+use crate::{ErrorHandler, FutureFactory, FutureRetry, Sleeper, TokioSleeper};
+use futures::{ready, TryFuture};
+use pin_project_lite::pin_project;
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+/// A wait for a retry, as it was planned and as it has really happened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AttemptWait {
+    /// The number of the attempt the loop has waited for.
+    pub attempt: usize,
+    /// The delay the error handler has asked for.
+    pub requested: Duration,
+    /// The time from the failure until the loop was polled to make the attempt, which is longer
+    /// than the requested delay when the timers or the executor lag behind.
+    pub realized: Duration,
+}
+
+/// What a retry loop has gone through, reported by
+/// [`FutureRetry::with_stats`](struct.FutureRetry.html#method.with_stats).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RetryStats {
+    /// The number of attempts made, the last one included.
+    pub attempts: usize,
+    /// How long the loop has been running, from its first poll until it resolved.
+    pub elapsed: Duration,
+    /// The waits before the retries, the first one first. The retries made right away (e.g. on a
+    /// `Repeat`) have no waits.
+    pub waits: Vec<AttemptWait>,
+}
+
+impl RetryStats {
+    /// Returns the total time spent waiting for the retries.
+    pub fn waited(&self) -> Duration {
+        self.waits.iter().map(|wait| wait.realized).sum()
+    }
+}
+
+pin_project! {
+    /// A future that resolves into the outcome of a retry loop along with its
+    /// [`RetryStats`](struct.RetryStats.html).
+    ///
+    /// Created by [`FutureRetry::with_stats`](struct.FutureRetry.html#method.with_stats).
+    ///
+    /// ```
+    /// use futures::future::ready;
+    /// use futures_retry::{FutureRetry, RetryPolicy};
+    /// use std::time::Duration;
+    ///
+    /// # #[tokio::main] async fn main() {
+    /// let mut results = vec![Err("busy"), Ok("done")].into_iter();
+    /// let (result, stats) = FutureRetry::new(
+    ///     || ready(results.next().unwrap()),
+    ///     |_| RetryPolicy::WaitRetry::<&str>(Duration::from_millis(10)),
+    /// )
+    /// .with_stats()
+    /// .await;
+    /// assert_eq!(Ok(("done", 2)), result);
+    /// assert_eq!(2, stats.attempts);
+    /// assert_eq!(Duration::from_millis(10), stats.waits[0].requested);
+    /// assert!(stats.waits[0].realized >= Duration::from_millis(10));
+    /// # }
+    /// ```
+    pub struct WithStats<F, R, S = TokioSleeper>
+    where
+        F: FutureFactory,
+        S: Sleeper,
+    {
+        #[pin]
+        retry: FutureRetry<F, R, S>,
+        started: Option<Instant>,
+        pending: Option<(usize, Instant, Duration)>,
+        waits: Vec<AttemptWait>,
+    }
+}
+
+impl<F: FutureFactory, R, S: Sleeper> WithStats<F, R, S> {
+    pub(crate) fn new(retry: FutureRetry<F, R, S>) -> Self {
+        Self {
+            retry,
+            started: None,
+            pending: None,
+            waits: Vec::new(),
+        }
+    }
+}
+
+impl<F: FutureFactory, R, S: Sleeper> fmt::Debug for WithStats<F, R, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WithStats")
+            .field("retry", &self.retry)
+            .field("waits", &self.waits)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F: FutureFactory, R, S: Sleeper> Future for WithStats<F, R, S>
+where
+    R: ErrorHandler<<F::FutureItem as TryFuture>::Error>,
+{
+    type Output = (<FutureRetry<F, R, S> as Future>::Output, RetryStats);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut this = self.project();
+        // A timer that has fired has done so before this poll, so this is when the loop got to
+        // make the next attempt.
+        let now = this.retry.now();
+        let started = *this.started.get_or_insert(now);
+        let result = this.retry.as_mut().poll(cx);
+        let window = this.retry.backoff_window();
+        if let Some((attempt, since, requested)) = *this.pending {
+            if window != Some((since, requested)) {
+                this.waits.push(AttemptWait {
+                    attempt,
+                    requested,
+                    realized: now.saturating_duration_since(since),
+                });
+            }
+        }
+        *this.pending = window.map(|(since, wait)| (this.retry.attempt(), since, wait));
+        let result = ready!(result);
+        let attempts = match &result {
+            Ok((_, attempt)) | Err((_, attempt)) => *attempt,
+        };
+        let stats = RetryStats {
+            attempts,
+            elapsed: this.retry.now().saturating_duration_since(started),
+            waits: std::mem::take(this.waits),
+        };
+        Poll::Ready((result, stats))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RetryPolicy;
+    use futures::future::{err, ok};
+
+    #[tokio::test]
+    async fn records_waits() {
+        let mut results = vec![ok(()), err(2), err(1)].into_iter();
+        let (result, stats) = FutureRetry::new(
+            move || results.next_back().unwrap(),
+            |e: u64| RetryPolicy::WaitRetry::<()>(Duration::from_millis(e * 5)),
+        )
+        .with_stats()
+        .await;
+        assert_eq!(Ok(((), 3)), result);
+        let waits: Vec<_> = stats
+            .waits
+            .iter()
+            .map(|wait| (wait.attempt, wait.requested.as_millis()))
+            .collect();
+        assert_eq!(vec![(2, 5), (3, 10)], waits);
+        assert!(stats
+            .waits
+            .iter()
+            .all(|wait| wait.realized >= wait.requested));
+        assert!(stats.elapsed >= stats.waited());
+    }
+
+    #[tokio::test]
+    async fn no_waits_on_repeat() {
+        let mut errors = 0;
+        let (result, stats) = FutureRetry::new(
+            || err::<(), _>(()),
+            move |e| {
+                errors += 1;
+                match errors {
+                    1 => RetryPolicy::Repeat,
+                    _ => RetryPolicy::ForwardError(e),
+                }
+            },
+        )
+        .with_stats()
+        .await;
+        assert_eq!(Err(((), 2)), result);
+        assert_eq!((2, Vec::new()), (stats.attempts, stats.waits));
+    }
+}
@@ -1,12 +1,17 @@
-use crate::{ErrorHandler, RetryPolicy};
+use crate::{
+    sleeper::{poll_timer, wait_until},
+    Classifier, ErrorHandler, Jitter, KindCounters, RetryPolicy, RetryStatus, Sleeper,
+    TokioSleeper,
+};
 use futures::{ready, Stream, TryStream};
 use pin_project_lite::pin_project;
 use std::{
-    future::Future,
+    fmt, mem,
     pin::Pin,
     task::{Context, Poll},
+    time::Duration,
 };
-use tokio::time;
+use tokio::time::Instant;
 
 pin_project! {
     /// Provides a way to handle errors during a `Stream` execution, i.e. it gives you an ability to
@@ -24,13 +29,17 @@ pin_project! {
     ///
     /// Also have a look at [`StreamRetryExt`](trait.StreamRetryExt.html) trait for a more convenient
     /// usage.
-    pub struct StreamRetry<F, S> {
+    pub struct StreamRetry<F, S, T = TokioSleeper>
+    where
+        T: Sleeper,
+    {
         error_action: F,
         #[pin]
         stream: S,
+        sleeper: T,
         attempt: usize,
         #[pin]
-        state: RetryState,
+        state: RetryState<T::Sleep>,
     }
 }
 
@@ -89,15 +98,39 @@ pub trait StreamRetryExt: TryStream {
     {
         StreamRetry::new(self, error_action)
     }
+
+    /// Converts the stream into a **retry stream** that retries the transient errors (as told by
+    /// a [`Classifier`](struct.Classifier.html)) with a backoff strategy, e.g. an
+    /// [`ExponentialBackoff`](struct.ExponentialBackoff.html), and forwards the others right away.
+    fn retry_with_backoff<B>(self, backoff: B) -> StreamRetry<Classifier<B>, Self>
+    where
+        Self: Sized,
+    {
+        StreamRetry::new(self, Classifier::new(backoff))
+    }
+
+    /// Converts the stream into a **retry stream** that counts the consecutive failures of each
+    /// class of errors (as told by `classify`) on its own, passing them to the `error_action`
+    /// instead of the number of the attempt. See [`KindCounters`](struct.KindCounters.html).
+    fn retry_per_kind<C, K, F>(
+        self,
+        classify: C,
+        error_action: F,
+    ) -> StreamRetry<KindCounters<C, K, F>, Self>
+    where
+        Self: Sized,
+    {
+        StreamRetry::new(self, KindCounters::new(classify, error_action))
+    }
 }
 
 impl<S: ?Sized> StreamRetryExt for S where S: TryStream {}
 
 pin_project! {
     #[project = RetryStateProj]
-    enum RetryState {
+    enum RetryState<D> {
         WaitingForStream,
-        TimerActive { #[pin] delay: time::Sleep },
+        TimerActive { #[pin] delay: Option<D>, until: Instant },
     }
 }
 
@@ -113,8 +146,8 @@ impl<F, S> StreamRetry<F, S> {
     ///
     /// * `stream`: a stream of future items,
     /// * `error_action`: a type that handles an error and decides which route to take: simply
-    ///                   try again, wait and then try, or give up (on a critical error for
-    ///                   exapmle).
+    ///   try again, wait and then try, or give up (on a critical error for
+    ///   exapmle).
     pub fn new(stream: S, error_action: F) -> Self
     where
         S: TryStream,
@@ -124,55 +157,357 @@ impl<F, S> StreamRetry<F, S> {
 
     /// Like a `new` method, but a custom attempt counter initial value might be provided.
     pub fn with_counter(stream: S, error_action: F, attempt_counter: usize) -> Self {
+        let mut retry = Self::with_sleeper(stream, error_action, TokioSleeper);
+        retry.attempt = attempt_counter;
+        retry
+    }
+}
+
+impl<F, S, T: Sleeper> StreamRetry<F, S, T> {
+    /// Like a `new` method, but the delays between the attempts are timed by a custom
+    /// [`Sleeper`](trait.Sleeper.html).
+    pub fn with_sleeper(stream: S, error_action: F, sleeper: T) -> Self {
         Self {
             error_action,
             stream,
-            attempt: attempt_counter,
+            sleeper,
+            attempt: 1,
             state: RetryState::WaitingForStream,
         }
     }
+
+    /// Delays polling the underlying stream for the first time, e.g. for a stream that is resumed
+    /// right after a known failure. The wait is reported the same way as a wait for a retry; if
+    /// the stream is already waiting, the longer wait is kept. The timer is only created on the
+    /// first poll, so the stream might be built outside of a runtime.
+    pub fn initial_delay(mut self, delay: Duration) -> Self {
+        let until = wait_until(self.sleeper.now(), delay);
+        if self.next_retry_at().is_none_or(|at| at < until) {
+            self.state = RetryState::TimerActive { until, delay: None };
+        }
+        self
+    }
+
+    /// Delays polling the underlying stream for the first time by a random time within the
+    /// `window`, to spread the clients that all start at the same moment. Like
+    /// [`initial_delay`](#method.initial_delay), with the delay picked with a
+    /// [`Jitter::Full`](enum.Jitter.html#variant.Full).
+    pub fn start_splay(self, window: Duration) -> Self {
+        self.initial_delay(Jitter::Full.apply(window))
+    }
+
+    /// Like [`start_splay`](#method.start_splay), but the delay is drawn from the given random
+    /// number generator, so a seeded generator yields a reproducible start.
+    pub fn start_splay_with(self, window: Duration, rng: &mut fastrand::Rng) -> Self {
+        self.initial_delay(Jitter::Full.apply_with(window, rng))
+    }
+
+    /// Returns the number of the current attempt, i.e. of the one that is running, or of the one
+    /// that the loop is waiting for.
+    pub fn attempt(&self) -> usize {
+        self.attempt
+    }
+
+    /// Returns what the stream is busy with: waiting for the next item of the underlying stream,
+    /// or waiting for a retry.
+    pub fn state(&self) -> RetryStatus {
+        match self.state {
+            RetryState::WaitingForStream => RetryStatus::InFlight,
+            RetryState::TimerActive { .. } => RetryStatus::BackingOff,
+        }
+    }
+
+    /// Returns when the next attempt is going to be made, if the loop is currently waiting for a
+    /// retry.
+    pub fn next_retry_at(&self) -> Option<Instant> {
+        match &self.state {
+            RetryState::TimerActive { until, .. } => Some(*until),
+            _ => None,
+        }
+    }
+
+    /// Returns how much time is left until the next attempt, if the loop is currently waiting for
+    /// a retry.
+    pub fn time_until_next_retry(&self) -> Option<Duration> {
+        self.next_retry_at()
+            .map(|at| at.saturating_duration_since(self.sleeper.now()))
+    }
+
+    /// Gives up once `max_errors` errors have been encountered over the whole lifetime of the
+    /// stream, no matter how many items have been received in between, so a source that keeps
+    /// failing on every other item isn't retried forever. The consecutive counter the handler gets
+    /// stays as it is. See [`ErrorCap`](struct.ErrorCap.html).
+    ///
+    /// ```
+    /// use futures::{stream, StreamExt};
+    /// use futures_retry::{RetryPolicy, StreamRetryExt};
+    ///
+    /// # #[tokio::main] async fn main() {
+    /// let flaky = stream::iter(vec![Err("flake"), Ok(1), Err("flake"), Ok(2), Err("flake")]);
+    /// let results: Vec<_> = flaky
+    ///     .retry(|_| RetryPolicy::Repeat::<&str>)
+    ///     .max_total_errors(2)
+    ///     .collect()
+    ///     .await;
+    /// // The second error and all the later ones are forwarded.
+    /// assert_eq!(
+    ///     vec![Ok((1, 2)), Err(("flake", 1)), Ok((2, 2)), Err(("flake", 1))],
+    ///     results
+    /// );
+    /// # }
+    /// ```
+    pub fn max_total_errors(self, max_errors: usize) -> StreamRetry<ErrorCap<F>, S, T> {
+        StreamRetry {
+            error_action: ErrorCap::new(self.error_action, max_errors),
+            stream: self.stream,
+            sleeper: self.sleeper,
+            attempt: self.attempt,
+            state: self.state,
+        }
+    }
+
+    /// Gives up once the stream has been failing for `recovery_time` without producing an item,
+    /// e.g. to alert someone after reconnecting for ten minutes, which is hard to express with the
+    /// numbers of attempts. See [`RecoveryDeadline`](struct.RecoveryDeadline.html).
+    ///
+    /// ```
+    /// use futures::{stream, StreamExt};
+    /// use futures_retry::{RetryPolicy, StreamRetryExt};
+    /// use std::time::Duration;
+    ///
+    /// # #[tokio::main] async fn main() {
+    /// let down = stream::repeat(Err::<(), _>("connection refused"))
+    ///     .retry(|_| RetryPolicy::WaitRetry::<&str>(Duration::from_millis(10)))
+    ///     .recovery_deadline(Duration::from_millis(50));
+    /// let first: Vec<_> = down.take(1).collect().await;
+    /// let (e, attempt) = first[0].unwrap_err();
+    /// assert_eq!("connection refused", e);
+    /// assert!(attempt > 1);
+    /// # }
+    /// ```
+    pub fn recovery_deadline(
+        self,
+        recovery_time: Duration,
+    ) -> StreamRetry<RecoveryDeadline<F, T>, S, T>
+    where
+        T: Clone,
+    {
+        StreamRetry {
+            error_action: RecoveryDeadline::with_sleeper(
+                self.error_action,
+                recovery_time,
+                self.sleeper.clone(),
+            ),
+            stream: self.stream,
+            sleeper: self.sleeper,
+            attempt: self.attempt,
+            state: self.state,
+        }
+    }
+}
+
+impl<F, S, T: Sleeper> fmt::Debug for StreamRetry<F, S, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StreamRetry")
+            .field("attempt", &self.attempt)
+            .field("state", &self.state())
+            .field("next_retry_at", &self.next_retry_at())
+            .finish_non_exhaustive()
+    }
+}
+
+/// An error handler that forwards every error once a number of errors have been handled in total,
+/// as opposed to the consecutive failures the inner handler is told about, which a success resets.
+///
+/// Created by [`StreamRetry::max_total_errors`](struct.StreamRetry.html#method.max_total_errors).
+#[derive(Debug, Clone)]
+pub struct ErrorCap<H> {
+    inner: H,
+    max_errors: usize,
+    errors: usize,
+}
+
+impl<H> ErrorCap<H> {
+    /// Wraps an error handler, giving up after `max_errors` errors in total.
+    pub fn new(inner: H, max_errors: usize) -> Self {
+        Self {
+            inner,
+            max_errors,
+            errors: 0,
+        }
+    }
+
+    /// Returns how many errors have been handled in total.
+    pub fn errors(&self) -> usize {
+        self.errors
+    }
+
+    /// Returns a reference to the inner handler.
+    pub fn get_ref(&self) -> &H {
+        &self.inner
+    }
+}
+
+impl<E, H> ErrorHandler<E> for ErrorCap<H>
+where
+    H: ErrorHandler<E>,
+    H::OutError: From<E>,
+{
+    type OutError = H::OutError;
+
+    fn handle(&mut self, attempt: usize, e: E) -> RetryPolicy<H::OutError> {
+        self.errors += 1;
+        if self.errors >= self.max_errors {
+            RetryPolicy::ForwardError(e.into())
+        } else {
+            self.inner.handle(attempt, e)
+        }
+    }
+
+    fn ok(&mut self, attempt: usize) {
+        self.inner.ok(attempt);
+    }
+
+    fn ok_with<V: ?Sized>(&mut self, attempt: usize, value: &V) {
+        self.inner.ok_with(attempt, value);
+    }
+
+    fn exhausted(&mut self, attempt: usize, error: &H::OutError) {
+        self.inner.exhausted(attempt, error);
+    }
+}
+
+/// An error handler that forwards an error once the errors have kept coming for a while with no
+/// success in between, the waits being cut to end at that moment at the latest.
+///
+/// The time is counted from the first error after a success; once an error is forwarded, the
+/// counting starts over, so a stream that stays down is given up on once per `recovery_time`.
+/// The time is told by the same [`Sleeper`](trait.Sleeper.html) that times the retries.
+/// Created by [`StreamRetry::recovery_deadline`](struct.StreamRetry.html#method.recovery_deadline).
+#[derive(Debug, Clone)]
+pub struct RecoveryDeadline<H, T = TokioSleeper> {
+    inner: H,
+    recovery_time: Duration,
+    sleeper: T,
+    failing_since: Option<Instant>,
+}
+
+impl<H> RecoveryDeadline<H> {
+    /// Wraps an error handler, giving up after `recovery_time` of failures.
+    pub fn new(inner: H, recovery_time: Duration) -> Self {
+        Self::with_sleeper(inner, recovery_time, TokioSleeper)
+    }
+}
+
+impl<H, T: Sleeper> RecoveryDeadline<H, T> {
+    /// Like a `new` method, but the time is told by a custom [`Sleeper`](trait.Sleeper.html).
+    pub fn with_sleeper(inner: H, recovery_time: Duration, sleeper: T) -> Self {
+        Self {
+            inner,
+            recovery_time,
+            sleeper,
+            failing_since: None,
+        }
+    }
+
+    /// Returns when the current series of failures has started, if there is one.
+    pub fn failing_since(&self) -> Option<Instant> {
+        self.failing_since
+    }
+
+    /// Returns a reference to the inner handler.
+    pub fn get_ref(&self) -> &H {
+        &self.inner
+    }
 }
 
-impl<F, S> Stream for StreamRetry<F, S>
+impl<E, H, T> ErrorHandler<E> for RecoveryDeadline<H, T>
 where
+    H: ErrorHandler<E>,
+    T: Sleeper,
+    H::OutError: From<E>,
+{
+    type OutError = H::OutError;
+
+    fn handle(&mut self, attempt: usize, e: E) -> RetryPolicy<H::OutError> {
+        let now = self.sleeper.now();
+        let since = *self.failing_since.get_or_insert(now);
+        let remaining = wait_until(since, self.recovery_time).saturating_duration_since(now);
+        if remaining.as_nanos() == 0 {
+            self.failing_since = None;
+            return RetryPolicy::ForwardError(e.into());
+        }
+        match self.inner.handle(attempt, e) {
+            RetryPolicy::WaitRetry(delay) => RetryPolicy::WaitRetry(delay.min(remaining)),
+            RetryPolicy::RetryAs { attempt, delay } => RetryPolicy::RetryAs {
+                attempt,
+                delay: delay.min(remaining),
+            },
+            policy @ RetryPolicy::ForwardError(_) => {
+                self.failing_since = None;
+                policy
+            }
+            policy => policy,
+        }
+    }
+
+    fn ok(&mut self, attempt: usize) {
+        self.failing_since = None;
+        self.inner.ok(attempt);
+    }
+
+    fn ok_with<V: ?Sized>(&mut self, attempt: usize, value: &V) {
+        self.failing_since = None;
+        self.inner.ok_with(attempt, value);
+    }
+
+    fn exhausted(&mut self, attempt: usize, error: &H::OutError) {
+        self.inner.exhausted(attempt, error);
+    }
+}
+
+impl<F, S, T> Stream for StreamRetry<F, S, T>
+where
+    T: Sleeper,
     S: TryStream,
     F: ErrorHandler<S::Error>,
 {
     type Item = Result<(S::Ok, usize), (F::OutError, usize)>;
 
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
         loop {
-            let this = self.as_mut().project();
-            let attempt = *this.attempt;
-            let new_state = match this.state.project() {
-                RetryStateProj::TimerActive { delay } => {
-                    ready!(delay.poll(cx));
-                    RetryState::WaitingForStream
+            if let RetryStateProj::TimerActive { delay, until } = this.state.as_mut().project() {
+                ready!(poll_timer(delay, *until, this.sleeper, cx));
+                this.state.set(RetryState::WaitingForStream);
+            }
+            match ready!(this.stream.as_mut().try_poll_next(cx)) {
+                Some(Ok(x)) => {
+                    let attempt = mem::replace(this.attempt, 1);
+                    this.error_action.ok_with(attempt, &x);
+                    return Poll::Ready(Some(Ok((x, attempt))));
                 }
-                RetryStateProj::WaitingForStream => match ready!(this.stream.try_poll_next(cx)) {
-                    Some(Ok(x)) => {
-                        *this.attempt = 1;
-                        this.error_action.ok(attempt);
-                        return Poll::Ready(Some(Ok((x, attempt))));
-                    }
-                    None => {
-                        return Poll::Ready(None);
-                    }
-                    Some(Err(e)) => {
-                        *this.attempt += 1;
-                        match this.error_action.handle(attempt, e) {
-                            RetryPolicy::ForwardError(e) => {
-                                return Poll::Ready(Some(Err((e, attempt))))
-                            }
-                            RetryPolicy::Repeat => RetryState::WaitingForStream,
-                            RetryPolicy::WaitRetry(duration) => RetryState::TimerActive {
-                                delay: time::sleep(duration),
-                            },
+                None => return Poll::Ready(None),
+                Some(Err(e)) => {
+                    let attempt = *this.attempt;
+                    let policy = this.error_action.handle(attempt, e);
+                    *this.attempt = policy.next_attempt(attempt);
+                    match policy {
+                        RetryPolicy::ForwardError(e) => {
+                            this.error_action.exhausted(attempt, &e);
+                            return Poll::Ready(Some(Err((e, attempt))));
                         }
+                        RetryPolicy::Repeat | RetryPolicy::RepeatWithoutCounting => {}
+                        RetryPolicy::WaitRetry(duration)
+                        | RetryPolicy::RetryAs {
+                            delay: duration, ..
+                        } => this.state.set(RetryState::TimerActive {
+                            until: wait_until(this.sleeper.now(), duration),
+                            delay: Some(this.sleeper.sleep(duration)),
+                        }),
                     }
-                },
-            };
-            self.as_mut().project().state.set(new_state);
+                }
+            }
         }
     }
 }
@@ -181,7 +516,6 @@ where
 mod test {
     use super::*;
     use futures::{pin_mut, prelude::*};
-    use std::time::Duration;
 
     #[tokio::test]
     async fn naive() {
@@ -221,4 +555,141 @@ mod test {
         pin_mut!(retry);
         assert_eq!(Some(Err((17u8, 1))), retry.next().await,);
     }
+
+    #[tokio::test]
+    async fn with_backoff() {
+        use crate::ExponentialBackoff;
+        use std::io;
+
+        let stream = stream::iter(vec![
+            Err::<u8, io::Error>(io::ErrorKind::ConnectionReset.into()),
+            Ok(1),
+            Err(io::ErrorKind::PermissionDenied.into()),
+        ]);
+        let backoff = ExponentialBackoff::new(Duration::from_millis(1));
+        let results: Vec<_> = stream.retry_with_backoff(backoff).collect().await;
+        assert!(matches!(results[0], Ok((1, 2))));
+        assert!(matches!(&results[1], Err((e, 1)) if e.kind() == io::ErrorKind::PermissionDenied));
+    }
+
+    #[tokio::test]
+    async fn introspection() {
+        let stream = stream::iter(vec![Err(1), Ok(2)]);
+        let retry = StreamRetry::new(stream, |_| {
+            RetryPolicy::WaitRetry::<()>(Duration::from_millis(100))
+        });
+        pin_mut!(retry);
+        assert!(futures::poll!(retry.next()).is_pending());
+        assert_eq!(
+            (2, RetryStatus::BackingOff),
+            (retry.attempt(), retry.state())
+        );
+        assert!(retry.next_retry_at().is_some());
+        assert_eq!(Some(Ok((2, 2))), retry.next().await);
+        assert_eq!(None, retry.time_until_next_retry());
+        assert_eq!(RetryStatus::InFlight, retry.state());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn seeded_start_splay() {
+        let window = Duration::from_secs(60);
+        let splayed = |seed| {
+            StreamRetry::new(
+                stream::iter(vec![Ok::<_, u8>(1)]),
+                RetryPolicy::ForwardError,
+            )
+            .start_splay_with(window, &mut fastrand::Rng::with_seed(seed))
+        };
+        let splay = Jitter::Full.apply_with(window, &mut fastrand::Rng::with_seed(3));
+        assert_eq!(Some(splay), splayed(3).time_until_next_retry());
+        assert_eq!(Ok(vec![(1, 1)]), splayed(3).try_collect::<Vec<_>>().await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn initial_delay_keeps_longer_wait() {
+        let started = tokio::time::Instant::now();
+        let retry = StreamRetry::new(
+            stream::iter(vec![Ok::<_, u8>(1)]),
+            RetryPolicy::ForwardError,
+        )
+        .initial_delay(Duration::from_millis(50))
+        .initial_delay(Duration::from_millis(10));
+        assert_eq!(
+            Some(Duration::from_millis(50)),
+            retry.time_until_next_retry()
+        );
+        assert_eq!(Ok(vec![(1, 1)]), retry.try_collect::<Vec<_>>().await);
+        assert_eq!(Duration::from_millis(50), started.elapsed());
+    }
+
+    #[test]
+    fn initial_delay_outside_of_runtime() {
+        let retry = StreamRetry::new(
+            stream::iter(vec![Ok::<_, u8>(1)]),
+            RetryPolicy::ForwardError,
+        )
+        .initial_delay(Duration::from_millis(10));
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()
+            .unwrap();
+        assert_eq!(
+            Ok(vec![(1, 1)]),
+            runtime.block_on(retry.try_collect::<Vec<_>>())
+        );
+    }
+
+    #[tokio::test]
+    async fn total_error_cap() {
+        // Fails on every third item, so the consecutive counter never exceeds one.
+        let items = (1..=9).map(|i| if i % 3 == 0 { Err(i) } else { Ok(i) });
+        let retry = stream::iter(items)
+            .retry(|_| RetryPolicy::Repeat::<u8>)
+            .max_total_errors(3);
+        let results: Vec<_> = retry.collect().await;
+        assert_eq!(
+            vec![
+                Ok((1, 1)),
+                Ok((2, 1)),
+                Ok((4, 2)),
+                Ok((5, 1)),
+                Ok((7, 2)),
+                Ok((8, 1))
+            ],
+            results[..6]
+        );
+        assert_eq!(vec![Err((9, 1))], results[6..]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn recovery_deadline_expires() {
+        let started = tokio::time::Instant::now();
+        let down = stream::repeat(Err::<(), _>(1u8))
+            .retry(|_| RetryPolicy::WaitRetry::<u8>(Duration::from_millis(15)))
+            .recovery_deadline(Duration::from_millis(50));
+        let first: Vec<_> = down.take(1).collect().await;
+        // The fourth wait is cut to end at the deadline.
+        assert_eq!(vec![Err((1, 5))], first);
+        assert_eq!(Duration::from_millis(50), started.elapsed());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn recovery_deadline_restarts() {
+        let mut handler = RecoveryDeadline::new(
+            |_| RetryPolicy::WaitRetry::<u8>(Duration::from_secs(5)),
+            Duration::from_millis(20),
+        );
+        match handler.handle(1, 1) {
+            RetryPolicy::WaitRetry(delay) => assert!(delay <= Duration::from_millis(20)),
+            policy => panic!("Unexpected policy {:?}", policy),
+        }
+        let since = handler.failing_since();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(RetryPolicy::ForwardError(2), handler.handle(2, 2));
+        assert_eq!(None, handler.failing_since());
+        // A new series of failures gets the whole time again.
+        assert!(matches!(handler.handle(3, 3), RetryPolicy::WaitRetry(_)));
+        assert!(handler.failing_since() > since);
+    }
 }
@@ -0,0 +1,388 @@
+use crate::{ErrorHandler, FutureFactory, RetryPolicy};
+use futures::{future::poll_fn, FutureExt, TryFuture, TryFutureExt};
+use std::{
+    fmt,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{Arc, Mutex, PoisonError},
+    task::Poll,
+    time::Duration,
+};
+use tokio::{
+    task::{self, JoinHandle},
+    time::{sleep, Instant},
+};
+
+/// Why a supervised task has stopped, as passed to its error handler.
+#[derive(Debug)]
+pub enum TaskExit<E> {
+    /// The task has failed with an error.
+    Failed(E),
+    /// The task has finished successfully, which is unexpected for a long-running one.
+    Finished,
+    /// The task has panicked.
+    Panicked,
+}
+
+/// What a task owned by a [`Supervisor`](struct.Supervisor.html) is busy with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskStatus {
+    /// The task is running.
+    Running {
+        /// The number of the current attempt.
+        attempt: usize,
+    },
+    /// The task has stopped and is going to be restarted.
+    BackingOff {
+        /// The number of the next attempt.
+        attempt: usize,
+        /// When the task is restarted.
+        until: Instant,
+    },
+    /// The error handler has given up on the task.
+    GaveUp {
+        /// The number of the failed attempt.
+        attempt: usize,
+    },
+    /// The task has been shut down.
+    Stopped,
+}
+
+struct Task<E> {
+    name: String,
+    status: Arc<Mutex<TaskStatus>>,
+    handle: Option<JoinHandle<E>>,
+}
+
+fn set_status(status: &Mutex<TaskStatus>, new: TaskStatus) {
+    *status.lock().unwrap_or_else(PoisonError::into_inner) = new;
+}
+
+async fn supervise<F, H>(
+    mut factory: F,
+    mut error_action: H,
+    status: Arc<Mutex<TaskStatus>>,
+    healthy_after: Option<Duration>,
+) -> H::OutError
+where
+    F: FutureFactory,
+    H: ErrorHandler<TaskExit<<F::FutureItem as TryFuture>::Error>>,
+{
+    let mut attempt = 1;
+    loop {
+        set_status(&status, TaskStatus::Running { attempt });
+        let started = Instant::now();
+        // The exit is borrowed in a block of its own, so the borrow doesn't make the future hold
+        // the (possibly not `Send`) exit across the awaits below.
+        let exit = {
+            let exit = match AssertUnwindSafe(factory.new().into_future())
+                .catch_unwind()
+                .await
+            {
+                Ok(Ok(_)) => TaskExit::Finished,
+                Ok(Err(e)) => TaskExit::Failed(e),
+                Err(_) => TaskExit::Panicked,
+            };
+            if healthy_after.is_some_and(|after| started.elapsed() >= after) {
+                error_action.ok_with(attempt, &exit);
+                attempt = 1;
+            }
+            exit
+        };
+        let failed = attempt;
+        let policy = error_action.handle(failed, exit);
+        attempt = policy.next_attempt(failed);
+        match policy {
+            RetryPolicy::ForwardError(e) => {
+                error_action.exhausted(failed, &e);
+                set_status(&status, TaskStatus::GaveUp { attempt: failed });
+                return e;
+            }
+            // A task that keeps stopping right away mustn't starve the rest of the runtime.
+            RetryPolicy::Repeat | RetryPolicy::RepeatWithoutCounting => task::yield_now().await,
+            RetryPolicy::WaitRetry(delay) | RetryPolicy::RetryAs { delay, .. } => {
+                let until = Instant::now() + delay;
+                set_status(&status, TaskStatus::BackingOff { attempt, until });
+                sleep(delay).await;
+            }
+        }
+    }
+}
+
+/// Owns a set of long-running tasks and restarts the ones that fail, panic or finish
+/// unexpectedly, each one as its own error handler decides, Erlang style.
+///
+/// Every task is a [`FutureFactory`](trait.FutureFactory.html) spawned on the tokio runtime under
+/// a name, and its handler is given a [`TaskExit`](enum.TaskExit.html) whenever the task stops. A
+/// task is only surfaced by [`join_next`](#method.join_next) once its handler gives up. The tasks
+/// are [shut down](#method.shutdown) (or aborted, when the supervisor is dropped) all at once.
+///
+/// The attempt counter of a task grows with every restart unless the task is considered
+/// [healthy](#method.healthy_after) once it has been running for long enough.
+///
+/// ```
+/// use futures_retry::{RetryPolicy, Supervisor, TaskExit, TaskStatus};
+/// use std::time::Duration;
+///
+/// async fn consume(queue: &str) -> Result<(), String> {
+///     // ...
+/// #   Err(format!("{} is gone", queue))
+/// }
+///
+/// # #[tokio::main] async fn main() {
+/// let mut supervisor = Supervisor::new().healthy_after(Duration::from_secs(60));
+/// supervisor.spawn(
+///     "orders",
+///     || consume("orders"),
+///     |exit: TaskExit<String>| match exit {
+///         TaskExit::Failed(e) => RetryPolicy::ForwardError(e),
+///         _ => RetryPolicy::WaitRetry(Duration::from_secs(1)),
+///     },
+/// );
+/// let (name, error) = supervisor.join_next().await.unwrap();
+/// assert_eq!(("orders", "orders is gone"), (name.as_str(), error.as_str()));
+/// assert_eq!(Some(TaskStatus::GaveUp { attempt: 1 }), supervisor.status("orders"));
+/// supervisor.shutdown().await;
+/// # }
+/// ```
+pub struct Supervisor<E> {
+    tasks: Vec<Task<E>>,
+    healthy_after: Option<Duration>,
+}
+
+impl<E> Default for Supervisor<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E> Supervisor<E> {
+    /// Creates a supervisor without tasks.
+    pub fn new() -> Self {
+        Self {
+            tasks: Vec::new(),
+            healthy_after: None,
+        }
+    }
+
+    /// Resets the attempt counter of a task once it stops after running for at least the
+    /// `period`. Applies to the tasks spawned afterwards.
+    pub fn healthy_after(mut self, period: Duration) -> Self {
+        self.healthy_after = Some(period);
+        self
+    }
+
+    /// Spawns a task under the `name`; a task already spawned under the same name is aborted.
+    ///
+    /// Must be called within a tokio runtime.
+    pub fn spawn<F, H>(&mut self, name: impl Into<String>, factory: F, error_action: H)
+    where
+        F: FutureFactory + Send + 'static,
+        F::FutureItem: Send,
+        H: ErrorHandler<TaskExit<<F::FutureItem as TryFuture>::Error>, OutError = E>
+            + Send
+            + 'static,
+        E: Send + 'static,
+    {
+        let name = name.into();
+        let status = Arc::new(Mutex::new(TaskStatus::Running { attempt: 1 }));
+        let handle = tokio::spawn(supervise(
+            factory,
+            error_action,
+            Arc::clone(&status),
+            self.healthy_after,
+        ));
+        let task = Task {
+            name,
+            status,
+            handle: Some(handle),
+        };
+        match self.tasks.iter_mut().find(|old| old.name == task.name) {
+            Some(old) => {
+                if let Some(handle) = old.handle.take() {
+                    handle.abort();
+                }
+                *old = task;
+            }
+            None => self.tasks.push(task),
+        }
+    }
+
+    /// Returns the status of the task spawned under the `name`.
+    pub fn status(&self, name: &str) -> Option<TaskStatus> {
+        self.tasks
+            .iter()
+            .find(|task| task.name == name)
+            .map(|task| *task.status.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Returns the names and the statuses of all the tasks, in the order they were spawned.
+    pub fn statuses(&self) -> impl Iterator<Item = (&str, TaskStatus)> + '_ {
+        self.tasks.iter().map(|task| {
+            let status = *task.status.lock().unwrap_or_else(PoisonError::into_inner);
+            (task.name.as_str(), status)
+        })
+    }
+
+    /// Returns how many tasks are still being supervised, i.e. haven't been given up on.
+    pub fn running(&self) -> usize {
+        self.tasks
+            .iter()
+            .filter(|task| task.handle.is_some())
+            .count()
+    }
+
+    /// Waits for a task to be given up on and returns its name with the forwarded error, or
+    /// `None` once no task is supervised anymore.
+    ///
+    /// Panics if an error handler has panicked.
+    pub async fn join_next(&mut self) -> Option<(String, E)> {
+        poll_fn(|cx| {
+            let mut supervised = false;
+            for task in &mut self.tasks {
+                let handle = match &mut task.handle {
+                    Some(handle) => handle,
+                    None => continue,
+                };
+                let result = match Pin::new(handle).poll(cx) {
+                    Poll::Ready(result) => result,
+                    Poll::Pending => {
+                        supervised = true;
+                        continue;
+                    }
+                };
+                task.handle = None;
+                match result {
+                    Ok(e) => return Poll::Ready(Some((task.name.clone(), e))),
+                    Err(e) if e.is_panic() => panic::resume_unwind(e.into_panic()),
+                    Err(_) => {}
+                }
+            }
+            if supervised {
+                Poll::Pending
+            } else {
+                Poll::Ready(None)
+            }
+        })
+        .await
+    }
+
+    /// Stops all the tasks and waits for them to be dropped.
+    pub async fn shutdown(&mut self) {
+        for task in &mut self.tasks {
+            if let Some(handle) = task.handle.take() {
+                handle.abort();
+                let _ = handle.await;
+            }
+            set_status(&task.status, TaskStatus::Stopped);
+        }
+    }
+}
+
+impl<E> Drop for Supervisor<E> {
+    fn drop(&mut self) {
+        self.tasks
+            .iter()
+            .filter_map(|task| task.handle.as_ref())
+            .for_each(JoinHandle::abort);
+    }
+}
+
+impl<E> fmt::Debug for Supervisor<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Supervisor")
+            .field("statuses", &self.statuses().collect::<Vec<_>>())
+            .field("healthy_after", &self.healthy_after)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handler::tests::Successes;
+    use futures::future::{pending, ready};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn restarts_until_given_up() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&runs);
+        let mut supervisor = Supervisor::new();
+        supervisor.spawn(
+            "flaky",
+            move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                ready(Ok::<_, ()>(()))
+            },
+            |exit| match exit {
+                TaskExit::Finished => RetryPolicy::Repeat,
+                _ => RetryPolicy::ForwardError("unexpected"),
+            },
+        );
+        let mut attempts = 0;
+        supervisor.spawn(
+            "limited",
+            || ready(Err::<(), _>(7)),
+            move |exit| {
+                attempts += 1;
+                match (exit, attempts) {
+                    (TaskExit::Failed(_), 1 | 2) => {
+                        RetryPolicy::WaitRetry(Duration::from_millis(5))
+                    }
+                    _ => RetryPolicy::ForwardError("failed"),
+                }
+            },
+        );
+        assert_eq!(
+            Some(("limited".to_owned(), "failed")),
+            supervisor.join_next().await
+        );
+        assert_eq!(
+            Some(TaskStatus::GaveUp { attempt: 3 }),
+            supervisor.status("limited")
+        );
+        assert_eq!(1, supervisor.running());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(runs.load(Ordering::SeqCst) > 1);
+        supervisor.shutdown().await;
+        assert_eq!(
+            vec![
+                ("flaky", TaskStatus::Stopped),
+                ("limited", TaskStatus::Stopped)
+            ],
+            supervisor.statuses().collect::<Vec<_>>()
+        );
+        assert_eq!(None, supervisor.join_next().await);
+    }
+
+    #[tokio::test]
+    async fn replaces_tasks() {
+        let mut supervisor = Supervisor::<()>::new();
+        supervisor.spawn("task", pending::<Result<(), ()>>, |_| {
+            RetryPolicy::ForwardError(())
+        });
+        supervisor.spawn(
+            "task",
+            || ready(Err::<(), _>(())),
+            |_| RetryPolicy::ForwardError(()),
+        );
+        assert_eq!(Some(("task".to_owned(), ())), supervisor.join_next().await);
+        assert_eq!(None, supervisor.join_next().await);
+    }
+
+    #[tokio::test]
+    async fn passes_success_values() {
+        let successes = Successes::new(|_| RetryPolicy::ForwardError("done"));
+        let mut supervisor = Supervisor::new().healthy_after(Duration::ZERO);
+        supervisor.spawn("task", || ready(Ok::<_, ()>(())), successes.clone());
+        assert_eq!(
+            Some(("task".to_owned(), "done")),
+            supervisor.join_next().await
+        );
+        // The exit of the healthy run.
+        let exit = std::any::type_name::<TaskExit<()>>();
+        assert_eq!(vec![(1, exit)], successes.seen());
+    }
+}
@@ -0,0 +1,130 @@
+use crate::{ErrorHandler, RetryPolicy};
+use std::{
+    env,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+};
+
+/// The environment variable that disables the retries of every [`Switch`](struct.Switch.html)
+/// when set to `1` or `true`.
+pub const DISABLE_RETRIES_ENV: &str = "FUTURES_RETRY_DISABLE";
+
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Turns the retries of every [`Switch`](struct.Switch.html) off (or back on), unless a switch is
+/// [`disabled`](struct.Switch.html#method.disabled) explicitly.
+pub fn disable_retries(disabled: bool) {
+    DISABLED.store(disabled, Ordering::Relaxed);
+}
+
+/// Checks whether the retries are disabled globally, either by
+/// [`disable_retries`](fn.disable_retries.html) or by the
+/// [`FUTURES_RETRY_DISABLE`](constant.DISABLE_RETRIES_ENV.html) environment variable.
+///
+/// The environment variable is only read once.
+pub fn retries_disabled() -> bool {
+    static FROM_ENV: OnceLock<bool> = OnceLock::new();
+    DISABLED.load(Ordering::Relaxed)
+        || *FROM_ENV.get_or_init(|| {
+            env::var(DISABLE_RETRIES_ENV)
+                .map(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+                .unwrap_or(false)
+        })
+}
+
+/// An error handler that never retries: every error is forwarded right away.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NoRetry;
+
+impl<E> ErrorHandler<E> for NoRetry {
+    type OutError = E;
+
+    fn handle(&mut self, _attempt: usize, e: E) -> RetryPolicy<E> {
+        RetryPolicy::ForwardError(e)
+    }
+}
+
+/// An error handler that might be switched to a [`NoRetry`](struct.NoRetry.html) one, so the
+/// failures surface instantly while debugging or in CI instead of being masked by the retries.
+///
+/// By default it follows the global switch, see [`retries_disabled`](fn.retries_disabled.html);
+/// the [`disabled`](#method.disabled) method overrides it for a single handler.
+///
+/// ```
+/// use futures_retry::{ErrorHandler, RetryPolicy, Switch};
+///
+/// let mut handler = Switch::new(|_| RetryPolicy::Repeat).disabled(true);
+/// assert_eq!(RetryPolicy::ForwardError("boom"), handler.handle(1, "boom"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Switch<H> {
+    inner: H,
+    disabled: Option<bool>,
+}
+
+impl<H> Switch<H> {
+    /// Wraps an error handler.
+    pub fn new(inner: H) -> Self {
+        Self {
+            inner,
+            disabled: None,
+        }
+    }
+
+    /// Disables (or enables) the retries of this handler regardless of the global switch.
+    pub fn disabled(mut self, disabled: bool) -> Self {
+        self.disabled = Some(disabled);
+        self
+    }
+
+    /// Checks whether the errors are currently forwarded without retrying.
+    pub fn is_disabled(&self) -> bool {
+        self.disabled.unwrap_or_else(retries_disabled)
+    }
+}
+
+impl<E, H> ErrorHandler<E> for Switch<H>
+where
+    H: ErrorHandler<E, OutError = E>,
+{
+    type OutError = E;
+
+    fn handle(&mut self, attempt: usize, e: E) -> RetryPolicy<E> {
+        if self.is_disabled() {
+            RetryPolicy::ForwardError(e)
+        } else {
+            self.inner.handle(attempt, e)
+        }
+    }
+
+    fn ok(&mut self, attempt: usize) {
+        self.inner.ok(attempt);
+    }
+
+    fn ok_with<V: ?Sized>(&mut self, attempt: usize, value: &V) {
+        self.inner.ok_with(attempt, value);
+    }
+
+    fn exhausted(&mut self, attempt: usize, error: &Self::OutError) {
+        self.inner.exhausted(attempt, error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn switches() {
+        let mut enabled = Switch::new(|_| RetryPolicy::Repeat).disabled(false);
+        let mut following = Switch::new(|_| RetryPolicy::Repeat);
+        assert_eq!(RetryPolicy::Repeat, enabled.handle(1, 1));
+        disable_retries(true);
+        assert_eq!(RetryPolicy::ForwardError(2), following.handle(1, 2));
+        assert_eq!(RetryPolicy::Repeat, enabled.handle(2, 3));
+        disable_retries(false);
+        assert_eq!(RetryPolicy::ForwardError(4), NoRetry.handle(1, 4));
+    }
+}
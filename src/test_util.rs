@@ -0,0 +1,442 @@
+//! Utilities for testing the retry logic without sleeping for real.
+//!
+//! [`MockSleeper`](struct.MockSleeper.html) is a clock that only moves when it is told to, so a
+//! test controls exactly when the retries are due, without relying on tokio's global
+//! `time::pause`. [`Decisions`](struct.Decisions.html) records what an error handler has decided
+//! on every attempt and asserts on the sequence afterwards.
+//!
+//! ```
+//! use futures_retry::{
+//!     test_util::{Decision, Decisions, MockSleeper},
+//!     FutureRetry, RetryPolicy,
+//! };
+//! use std::time::Duration;
+//!
+//! # #[tokio::main] async fn main() {
+//! let sleeper = MockSleeper::new();
+//! let decisions = Decisions::new();
+//! let mut failures = 2;
+//! let retry = FutureRetry::with_sleeper(
+//!     move || {
+//!         failures -= 1;
+//!         futures::future::ready(if failures < 0 { Ok(()) } else { Err("oops") })
+//!     },
+//!     decisions.handler(|_| RetryPolicy::WaitRetry::<&str>(Duration::from_secs(60))),
+//!     sleeper.clone(),
+//! );
+//! futures::pin_mut!(retry);
+//! assert!(futures::poll!(retry.as_mut()).is_pending());
+//! sleeper.advance(Duration::from_secs(60));
+//! assert!(futures::poll!(retry.as_mut()).is_pending());
+//! sleeper.advance(Duration::from_secs(60));
+//! assert_eq!(Ok(((), 3)), retry.await);
+//! decisions.assert_eq(&[
+//!     Decision::WaitRetry(1, Duration::from_secs(60)),
+//!     Decision::WaitRetry(2, Duration::from_secs(60)),
+//!     Decision::Ok(3),
+//! ]);
+//! assert_eq!(Duration::from_secs(120), sleeper.elapsed());
+//! # }
+//! ```
+
+use crate::{ErrorHandler, RetryPolicy, Sleeper, StreamRetry};
+use futures::{stream, task::noop_waker_ref, Stream};
+use std::{
+    fmt,
+    future::Future,
+    mem,
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    task::{Context, Poll, Waker},
+    time::Duration,
+    vec,
+};
+use tokio::time::Instant;
+
+#[derive(Debug)]
+struct Clock {
+    start: Instant,
+    elapsed: Duration,
+    requested: Vec<Duration>,
+    wakers: Vec<Waker>,
+}
+
+/// A controllable [`Sleeper`](../trait.Sleeper.html): its time stands still until it is
+/// [advanced](#method.advance).
+///
+/// Clones share the same clock, so one clone might be given to a retry loop while the test keeps
+/// another one.
+#[derive(Debug, Clone)]
+pub struct MockSleeper {
+    clock: Arc<Mutex<Clock>>,
+}
+
+impl Default for MockSleeper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockSleeper {
+    /// Creates a clock that starts at the current time.
+    pub fn new() -> Self {
+        Self {
+            clock: Arc::new(Mutex::new(Clock {
+                start: Instant::now(),
+                elapsed: Duration::from_secs(0),
+                requested: Vec::new(),
+                wakers: Vec::new(),
+            })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Clock> {
+        self.clock.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Moves the time forward and wakes up the timers; the ones that are due resolve the next time
+    /// they are polled.
+    pub fn advance(&self, duration: Duration) {
+        let wakers = {
+            let mut clock = self.lock();
+            clock.elapsed += duration;
+            mem::take(&mut clock.wakers)
+        };
+        wakers.into_iter().for_each(Waker::wake);
+    }
+
+    /// Returns how far the time has been advanced.
+    pub fn elapsed(&self) -> Duration {
+        self.lock().elapsed
+    }
+
+    /// Returns the delays of all the timers created so far, in order.
+    pub fn requested(&self) -> Vec<Duration> {
+        self.lock().requested.clone()
+    }
+}
+
+impl Sleeper for MockSleeper {
+    type Sleep = MockSleep;
+
+    fn sleep(&self, duration: Duration) -> MockSleep {
+        let deadline = {
+            let mut clock = self.lock();
+            clock.requested.push(duration);
+            clock.start + clock.elapsed + duration
+        };
+        MockSleep {
+            sleeper: self.clone(),
+            deadline,
+        }
+    }
+
+    fn now(&self) -> Instant {
+        let clock = self.lock();
+        clock.start + clock.elapsed
+    }
+}
+
+/// A timer created by a [`MockSleeper`](struct.MockSleeper.html).
+#[derive(Debug)]
+pub struct MockSleep {
+    sleeper: MockSleeper,
+    deadline: Instant,
+}
+
+impl Future for MockSleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let mut clock = self.sleeper.lock();
+        if clock.start + clock.elapsed >= self.deadline {
+            Poll::Ready(())
+        } else {
+            clock.wakers.push(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+/// A decision taken by an error handler, along with the attempt it has been taken on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// The attempt has succeeded.
+    Ok(usize),
+    /// The attempt has failed and is repeated right away.
+    Repeat(usize),
+    /// The attempt has failed and is repeated right away, without counting the failure.
+    RepeatWithoutCounting(usize),
+    /// The attempt has failed and is retried after a delay.
+    WaitRetry(usize, Duration),
+    /// The attempt has failed and is retried after a delay as the attempt of the given number.
+    RetryAs(usize, usize, Duration),
+    /// The attempt has failed and the error is forwarded.
+    ForwardError(usize),
+}
+
+/// A shared log of the [`Decision`](enum.Decision.html)s taken by the error handlers created with
+/// [`handler`](#method.handler).
+#[derive(Debug, Clone, Default)]
+pub struct Decisions {
+    log: Arc<Mutex<Vec<Decision>>>,
+}
+
+impl Decisions {
+    /// Creates an empty log.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wraps an error handler so its decisions are recorded in this log.
+    pub fn handler<H>(&self, inner: H) -> Recorder<H> {
+        Recorder {
+            inner,
+            decisions: self.clone(),
+        }
+    }
+
+    fn push(&self, decision: Decision) {
+        self.log
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(decision);
+    }
+
+    /// Returns the decisions recorded so far.
+    pub fn get(&self) -> Vec<Decision> {
+        self.log
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Asserts that exactly the `expected` decisions have been recorded so far.
+    #[track_caller]
+    pub fn assert_eq(&self, expected: &[Decision]) {
+        assert_eq!(
+            expected,
+            self.get().as_slice(),
+            "unexpected retry decisions"
+        );
+    }
+}
+
+/// An error handler that records the decisions of an inner one, created by
+/// [`Decisions::handler`](struct.Decisions.html#method.handler).
+#[derive(Debug, Clone)]
+pub struct Recorder<H> {
+    inner: H,
+    decisions: Decisions,
+}
+
+impl<E, H: ErrorHandler<E>> ErrorHandler<E> for Recorder<H> {
+    type OutError = H::OutError;
+
+    fn handle(&mut self, attempt: usize, e: E) -> RetryPolicy<H::OutError> {
+        let policy = self.inner.handle(attempt, e);
+        self.decisions.push(match &policy {
+            RetryPolicy::Repeat => Decision::Repeat(attempt),
+            RetryPolicy::RepeatWithoutCounting => Decision::RepeatWithoutCounting(attempt),
+            RetryPolicy::WaitRetry(delay) => Decision::WaitRetry(attempt, *delay),
+            RetryPolicy::RetryAs {
+                attempt: next,
+                delay,
+            } => Decision::RetryAs(attempt, *next, *delay),
+            RetryPolicy::ForwardError(_) => Decision::ForwardError(attempt),
+        });
+        policy
+    }
+
+    fn ok(&mut self, attempt: usize) {
+        self.decisions.push(Decision::Ok(attempt));
+        self.inner.ok(attempt);
+    }
+
+    fn ok_with<V: ?Sized>(&mut self, attempt: usize, value: &V) {
+        self.decisions.push(Decision::Ok(attempt));
+        self.inner.ok_with(attempt, value);
+    }
+
+    fn exhausted(&mut self, attempt: usize, error: &Self::OutError) {
+        self.inner.exhausted(attempt, error);
+    }
+}
+
+type ScriptedRetry<T, E, H> =
+    StreamRetry<Recorder<H>, stream::Iter<vec::IntoIter<Result<T, E>>>, MockSleeper>;
+
+/// A step-by-step verifier of a [`StreamRetry`](../struct.StreamRetry.html) fed with a scripted
+/// stream, driven by a [`MockSleeper`](struct.MockSleeper.html).
+///
+/// Every `expect_*` step polls the stream once (without any runtime) and panics if the outcome is
+/// not the expected one. The decisions of the error handler are recorded along the way.
+///
+/// ```
+/// use futures_retry::{test_util::StreamVerifier, RetryPolicy};
+/// use std::time::Duration;
+///
+/// let delay = Duration::from_secs(1);
+/// StreamVerifier::new(
+///     vec![Err("busy"), Ok(1), Err("busy"), Err("busy"), Ok(2), Err("fatal")],
+///     move |e| match e {
+///         "busy" => RetryPolicy::WaitRetry(delay),
+///         e => RetryPolicy::ForwardError(e),
+///     },
+/// )
+/// .expect_wait(delay)
+/// .expect_item(1, 2)
+/// .expect_wait(delay)
+/// .expect_wait(delay)
+/// .expect_item(2, 3)
+/// .expect_error("fatal", 1)
+/// .expect_end();
+/// ```
+pub struct StreamVerifier<T, E, H> {
+    retry: Pin<Box<ScriptedRetry<T, E, H>>>,
+    sleeper: MockSleeper,
+    decisions: Decisions,
+}
+
+impl<T, E, H> StreamVerifier<T, E, H>
+where
+    T: fmt::Debug + PartialEq + 'static,
+    H: ErrorHandler<E>,
+    H::OutError: fmt::Debug + PartialEq,
+{
+    /// Creates a verifier of a stream that yields the `script` items, handling the errors with the
+    /// `error_action`.
+    pub fn new(script: Vec<Result<T, E>>, error_action: H) -> Self {
+        let sleeper = MockSleeper::new();
+        let decisions = Decisions::new();
+        let retry = StreamRetry::with_sleeper(
+            stream::iter(script),
+            decisions.handler(error_action),
+            sleeper.clone(),
+        );
+        Self {
+            retry: Box::pin(retry),
+            sleeper,
+            decisions,
+        }
+    }
+
+    /// Returns the clock driving the stream.
+    pub fn sleeper(&self) -> &MockSleeper {
+        &self.sleeper
+    }
+
+    /// Returns the decisions taken by the error handler so far.
+    pub fn decisions(&self) -> &Decisions {
+        &self.decisions
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn poll(&mut self) -> Poll<Option<Result<(T, usize), (H::OutError, usize)>>> {
+        let mut cx = Context::from_waker(noop_waker_ref());
+        self.retry.as_mut().poll_next(&mut cx)
+    }
+
+    /// Expects the stream to wait for the `delay` before the next attempt, and advances the clock
+    /// by it.
+    #[track_caller]
+    pub fn expect_wait(mut self, delay: Duration) -> Self {
+        match self.poll() {
+            Poll::Pending => {}
+            Poll::Ready(item) => panic!("expected a wait of {:?}, got {:?}", delay, item),
+        }
+        assert_eq!(
+            Some(delay),
+            self.retry.time_until_next_retry(),
+            "unexpected delay"
+        );
+        self.sleeper.advance(delay);
+        self
+    }
+
+    /// Expects the stream to yield an `item` on the `attempt`.
+    #[track_caller]
+    pub fn expect_item(mut self, item: T, attempt: usize) -> Self {
+        assert_eq!(Poll::Ready(Some(Ok((item, attempt)))), self.poll());
+        self
+    }
+
+    /// Expects the stream to forward an `error` that has occurred on the `attempt`.
+    #[track_caller]
+    pub fn expect_error(mut self, error: H::OutError, attempt: usize) -> Self {
+        assert_eq!(Poll::Ready(Some(Err((error, attempt)))), self.poll());
+        self
+    }
+
+    /// Expects the stream to end.
+    #[track_caller]
+    pub fn expect_end(mut self) -> Self {
+        assert_eq!(Poll::Ready(None), self.poll());
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{poll, StreamExt};
+
+    #[tokio::test]
+    async fn stream_waits_for_the_mock_clock() {
+        let sleeper = MockSleeper::new();
+        let decisions = Decisions::new();
+        let retry = StreamRetry::with_sleeper(
+            stream::iter(vec![Err(1), Ok(2), Err(3)]),
+            decisions.handler(|e| match e {
+                1 => RetryPolicy::WaitRetry(Duration::from_secs(3600)),
+                e => RetryPolicy::ForwardError(e),
+            }),
+            sleeper.clone(),
+        );
+        futures::pin_mut!(retry);
+        assert!(poll!(retry.next()).is_pending());
+        assert_eq!(
+            Some(Duration::from_secs(3600)),
+            retry.time_until_next_retry()
+        );
+        sleeper.advance(Duration::from_secs(1800));
+        assert!(poll!(retry.next()).is_pending());
+        assert_eq!(
+            Some(Duration::from_secs(1800)),
+            retry.time_until_next_retry()
+        );
+        sleeper.advance(Duration::from_secs(1800));
+        assert_eq!(Some(Ok((2, 2))), retry.next().await);
+        assert_eq!(Some(Err((3, 1))), retry.next().await);
+        decisions.assert_eq(&[
+            Decision::WaitRetry(1, Duration::from_secs(3600)),
+            Decision::Ok(2),
+            Decision::ForwardError(1),
+        ]);
+        assert_eq!(vec![Duration::from_secs(3600)], sleeper.requested());
+    }
+
+    #[test]
+    fn resets_the_counter() {
+        let verifier = StreamVerifier::new(vec![Err(()), Ok(1), Err(()), Ok(2)], |_| {
+            RetryPolicy::Repeat::<()>
+        })
+        .expect_item(1, 2)
+        .expect_item(2, 2)
+        .expect_end();
+        verifier.decisions().assert_eq(&[
+            Decision::Repeat(1),
+            Decision::Ok(2),
+            Decision::Repeat(1),
+            Decision::Ok(2),
+        ]);
+        assert!(verifier.sleeper().requested().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "expected a wait")]
+    fn unexpected_item() {
+        StreamVerifier::new(vec![Ok::<_, ()>(1)], RetryPolicy::ForwardError)
+            .expect_wait(Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,229 @@
+use crate::{ErrorHandler, FutureFactory, RetryPolicy};
+use futures::{future::IntoFuture, ready, TryFuture, TryFutureExt};
+use pin_project_lite::pin_project;
+use std::{
+    error::Error,
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::time;
+
+/// An error of an attempt that hasn't finished in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AttemptTimedOut {
+    /// The timeout that has elapsed.
+    pub timeout: Duration,
+}
+
+impl fmt::Display for AttemptTimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "attempt timed out after {:?}", self.timeout)
+    }
+}
+
+impl Error for AttemptTimedOut {}
+
+/// An error of an attempt run with a [`Timeout`](struct.Timeout.html).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttemptError<E> {
+    /// The attempt hasn't finished in time.
+    TimedOut(AttemptTimedOut),
+    /// The attempt has failed on its own.
+    Failed(E),
+}
+
+impl<E: fmt::Display> fmt::Display for AttemptError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AttemptError::TimedOut(e) => e.fmt(f),
+            AttemptError::Failed(e) => e.fmt(f),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for AttemptError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AttemptError::TimedOut(e) => Some(e),
+            AttemptError::Failed(e) => Some(e),
+        }
+    }
+}
+
+/// A factory adapter that limits how long each attempt might take.
+///
+/// An attempt that doesn't finish within the timeout is dropped and fails with
+/// [`AttemptError::TimedOut`](enum.AttemptError.html#variant.TimedOut), so the error handler can
+/// tell a slow attempt from a failed one. A [`TimeoutHandler`](struct.TimeoutHandler.html) makes
+/// it easy to handle the two cases separately.
+///
+/// ```
+/// use futures_retry::{FutureRetry, RetryPolicy, Timeout, TimeoutHandler};
+/// use std::time::Duration;
+///
+/// # #[tokio::main] async fn main() {
+/// let mut delays = vec![Duration::from_secs(10), Duration::from_millis(1)].into_iter();
+/// let factory = Timeout::new(
+///     move || {
+///         let delay = delays.next().unwrap();
+///         async move {
+///             tokio::time::sleep(delay).await;
+///             Ok::<_, std::io::Error>("done")
+///         }
+///     },
+///     Duration::from_millis(50),
+/// );
+/// let handler = TimeoutHandler::new(
+///     RetryPolicy::ForwardError,
+///     |_| RetryPolicy::Repeat::<std::io::Error>,
+/// );
+/// assert_eq!("done", FutureRetry::new(factory, handler).await.unwrap().0);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Timeout<F> {
+    factory: F,
+    timeout: Duration,
+}
+
+impl<F> Timeout<F> {
+    /// Wraps a `factory` so every attempt times out after the `timeout`.
+    pub fn new(factory: F, timeout: Duration) -> Self {
+        Self { factory, timeout }
+    }
+}
+
+impl<F> fmt::Debug for Timeout<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Timeout")
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F: FutureFactory> FutureFactory for Timeout<F> {
+    type FutureItem = TimeoutFuture<F::FutureItem>;
+
+    fn new(&mut self) -> Self::FutureItem {
+        TimeoutFuture {
+            timeout: self.timeout,
+            future: time::timeout(self.timeout, self.factory.new().into_future()),
+        }
+    }
+}
+
+pin_project! {
+    /// A future that fails if it doesn't finish within a timeout.
+    ///
+    /// Created by [`Timeout`](struct.Timeout.html).
+    pub struct TimeoutFuture<Fut> {
+        timeout: Duration,
+        #[pin]
+        future: time::Timeout<IntoFuture<Fut>>,
+    }
+}
+
+impl<Fut> fmt::Debug for TimeoutFuture<Fut> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TimeoutFuture")
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<Fut: TryFuture> Future for TimeoutFuture<Fut> {
+    type Output = Result<Fut::Ok, AttemptError<Fut::Error>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.project();
+        Poll::Ready(match ready!(this.future.poll(cx)) {
+            Ok(result) => result.map_err(AttemptError::Failed),
+            Err(_) => Err(AttemptError::TimedOut(AttemptTimedOut {
+                timeout: *this.timeout,
+            })),
+        })
+    }
+}
+
+/// An error handler that handles the timed out attempts and the failed ones separately.
+///
+/// Meant to be used along with a [`Timeout`](struct.Timeout.html) factory: failures are passed to
+/// the `inner` handler, and timeouts are passed to the `on_timeout` handler.
+#[derive(Debug, Clone)]
+pub struct TimeoutHandler<H, T> {
+    inner: H,
+    on_timeout: T,
+}
+
+impl<H, T> TimeoutHandler<H, T> {
+    /// Creates a handler out of a handler of failures and a handler of timeouts.
+    pub fn new(inner: H, on_timeout: T) -> Self {
+        Self { inner, on_timeout }
+    }
+}
+
+impl<E, H, T> ErrorHandler<AttemptError<E>> for TimeoutHandler<H, T>
+where
+    H: ErrorHandler<E>,
+    T: ErrorHandler<AttemptTimedOut, OutError = H::OutError>,
+{
+    type OutError = H::OutError;
+
+    fn handle(&mut self, attempt: usize, e: AttemptError<E>) -> RetryPolicy<H::OutError> {
+        match e {
+            AttemptError::TimedOut(e) => self.on_timeout.handle(attempt, e),
+            AttemptError::Failed(e) => self.inner.handle(attempt, e),
+        }
+    }
+
+    fn ok(&mut self, attempt: usize) {
+        self.inner.ok(attempt);
+        self.on_timeout.ok(attempt);
+    }
+
+    fn ok_with<V: ?Sized>(&mut self, attempt: usize, value: &V) {
+        self.inner.ok_with(attempt, value);
+        self.on_timeout.ok_with(attempt, value);
+    }
+
+    fn exhausted(&mut self, attempt: usize, error: &H::OutError) {
+        self.inner.exhausted(attempt, error);
+        self.on_timeout.exhausted(attempt, error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FutureRetry;
+    use futures::future::{err, pending, ready, Either};
+
+    #[tokio::test]
+    async fn timeouts_are_distinct() {
+        let mut attempts = 0;
+        let factory = Timeout::new(
+            move || {
+                attempts += 1;
+                match attempts {
+                    1 => Either::Left(pending::<Result<(), u8>>()),
+                    _ => Either::Right(err(7)),
+                }
+            },
+            Duration::from_millis(10),
+        );
+        let handler = TimeoutHandler::new(RetryPolicy::ForwardError, |e: AttemptTimedOut| {
+            assert_eq!(Duration::from_millis(10), e.timeout);
+            RetryPolicy::Repeat
+        });
+        assert_eq!(Err((7, 2)), FutureRetry::new(factory, handler).await);
+    }
+
+    #[tokio::test]
+    async fn in_time() {
+        let mut factory = Timeout::new(|| ready(Ok::<_, ()>(1)), Duration::from_millis(10));
+        assert_eq!(Ok(1), factory.new().await);
+    }
+}
@@ -0,0 +1,235 @@
+//! Helpers for [`tonic`](https://docs.rs/tonic) streaming RPCs.
+//!
+//! Available with the `tonic` feature.
+
+use crate::{ErrorHandler, Hinted, ReconnectingStream, RetryHint, RetryPolicy};
+use futures::{Stream, TryFutureExt, TryStream};
+use std::{convert::TryFrom, future::Future, time::Duration};
+use tonic::{Response, Status};
+
+/// The trailer a gRPC server tells the clients how long to wait before a retry with.
+pub const PUSHBACK_METADATA: &str = "grpc-retry-pushback-ms";
+
+/// Reads the pushback of a status: `None` if there is none, `Some(None)` if the server asks not
+/// to retry (with a negative or a malformed value), and `Some(Some(delay))` otherwise.
+fn pushback(status: &Status) -> Option<Option<Duration>> {
+    let value = status.metadata().get(PUSHBACK_METADATA)?;
+    let millis = value
+        .to_str()
+        .ok()
+        .and_then(|value| value.trim().parse::<i64>().ok());
+    Some(
+        millis
+            .and_then(|millis| u64::try_from(millis).ok())
+            .map(Duration::from_millis),
+    )
+}
+
+/// The delay a server has pushed back with, so a [`Hinted`](../struct.Hinted.html) handler waits
+/// as long as the server asks to.
+impl RetryHint for Status {
+    fn retry_after(&self) -> Option<Duration> {
+        pushback(self).flatten()
+    }
+}
+
+/// An error handler that honors the retry pushback of gRPC servers, as the
+/// [gRPC retry design](https://github.com/grpc/proposal/blob/master/A6-client-retries.md)
+/// requires: the delay of the `grpc-retry-pushback-ms` trailer replaces the one of the inner
+/// handler, and a negative (or a malformed) pushback means the error is forwarded right away.
+///
+/// The inner handler is still asked about every error, so its limits are respected.
+///
+/// ```
+/// use futures_retry::{tonic::Pushback, ErrorHandler, ExponentialBackoff, RetryPolicy};
+/// use std::time::Duration;
+/// use tonic::Status;
+///
+/// let mut handler = Pushback::new(ExponentialBackoff::new(Duration::from_millis(10)));
+/// let mut status = Status::unavailable("overloaded");
+/// status.metadata_mut().insert("grpc-retry-pushback-ms", "2500".parse().unwrap());
+/// assert!(matches!(
+///     handler.handle(1, status),
+///     RetryPolicy::WaitRetry(delay) if delay == Duration::from_millis(2500)
+/// ));
+/// let mut status = Status::unavailable("go away");
+/// status.metadata_mut().insert("grpc-retry-pushback-ms", "-1".parse().unwrap());
+/// assert!(matches!(handler.handle(2, status), RetryPolicy::ForwardError(_)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Pushback<H> {
+    inner: Hinted<H>,
+}
+
+impl<H> Pushback<H> {
+    /// Wraps an error handler.
+    pub fn new(inner: H) -> Self {
+        Self {
+            inner: Hinted::new(inner),
+        }
+    }
+
+    /// Sets an upper limit for the pushback delays.
+    pub fn max_pushback(self, max_pushback: Duration) -> Self {
+        Self {
+            inner: self.inner.max_hint(max_pushback),
+        }
+    }
+}
+
+impl<H> ErrorHandler<Status> for Pushback<H>
+where
+    H: ErrorHandler<Status>,
+    H::OutError: From<Status>,
+{
+    type OutError = H::OutError;
+
+    fn handle(&mut self, attempt: usize, e: Status) -> RetryPolicy<H::OutError> {
+        match pushback(&e) {
+            Some(None) => RetryPolicy::ForwardError(e.into()),
+            _ => self.inner.handle(attempt, e),
+        }
+    }
+
+    fn ok(&mut self, attempt: usize) {
+        self.inner.ok(attempt);
+    }
+
+    fn ok_with<V: ?Sized>(&mut self, attempt: usize, value: &V) {
+        self.inner.ok_with(attempt, value);
+    }
+
+    fn exhausted(&mut self, attempt: usize, error: &H::OutError) {
+        self.inner.exhausted(attempt, error);
+    }
+}
+
+/// Surfaces a server-streaming RPC as one continuous stream, re-issuing the request whenever the
+/// response stream fails.
+///
+/// The `factory` is called with the last known resume position (`None` on the very first call)
+/// and is expected to issue a request that continues from that position. The `position` closure
+/// extracts a position from every received message; if it returns `None`, the previous position is
+/// kept. Every `Status` error, be it of the call itself or of the response stream, is passed to the
+/// `error_action`.
+///
+/// A response stream that ends gracefully (with an `OK` status) means that the call is complete,
+/// so the resulting stream ends as well.
+///
+/// ```no_run
+/// use futures::TryStreamExt;
+/// use futures_retry::ExponentialBackoff;
+/// use std::time::Duration;
+///
+/// # #[derive(Clone)] struct LogClient;
+/// # #[derive(Debug)] struct LogEntry { offset: u64 }
+/// # impl LogClient {
+/// #     async fn tail(&mut self, _: u64)
+/// #         -> Result<tonic::Response<tonic::Streaming<LogEntry>>, tonic::Status> { todo!() }
+/// # }
+/// # async fn run(client: LogClient) -> Result<(), tonic::Status> {
+/// futures_retry::tonic::resume_streaming(
+///     move |offset: Option<&u64>| {
+///         let mut client = client.clone();
+///         let offset = offset.map_or(0, |offset| offset + 1);
+///         async move { client.tail(offset).await }
+///     },
+///     |entry: &LogEntry| Some(entry.offset),
+///     ExponentialBackoff::new(Duration::from_millis(50)).max_attempts(5),
+/// )
+/// .try_for_each(|(entry, _attempt)| async move {
+///     println!("{:?}", entry);
+///     Ok(())
+/// })
+/// .await
+/// .map_err(|(e, _attempt)| e)
+/// # }
+/// ```
+pub fn resume_streaming<F, Fut, S, P, T, R>(
+    mut factory: F,
+    position: P,
+    error_action: R,
+) -> impl Stream<Item = Result<(S::Ok, usize), (R::OutError, usize)>>
+where
+    F: FnMut(Option<&T>) -> Fut,
+    Fut: Future<Output = Result<Response<S>, Status>>,
+    S: TryStream<Error = Status>,
+    P: FnMut(&S::Ok) -> Option<T>,
+    R: ErrorHandler<Status>,
+{
+    ReconnectingStream::resuming(
+        move |last: Option<&T>| factory(last).map_ok(Response::into_inner),
+        position,
+        error_action,
+    )
+    .stop_on_end()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RetryPolicy;
+    use futures::{future, stream, StreamExt};
+    use tonic::Code;
+
+    #[tokio::test]
+    async fn resume_and_finish() {
+        let retry = resume_streaming(
+            |last: Option<&u32>| {
+                let items = match last {
+                    None => vec![Ok(1), Err(Status::unavailable("Going away"))],
+                    Some(last) => vec![Ok(last + 1)],
+                };
+                future::ok(Response::new(stream::iter(items)))
+            },
+            |item: &u32| Some(*item),
+            |e: Status| match e.code() {
+                Code::Unavailable => RetryPolicy::Repeat,
+                _ => RetryPolicy::ForwardError(e),
+            },
+        );
+        let items: Vec<_> = retry
+            .map(|item| item.map_err(|(e, _)| e.code()))
+            .collect()
+            .await;
+        assert_eq!(vec![Ok((1, 1)), Ok((2, 2))], items);
+    }
+
+    #[test]
+    fn pushback_overrides_the_backoff() {
+        let pushed_back = |value: &str| {
+            let mut status = Status::resource_exhausted("slow down");
+            status
+                .metadata_mut()
+                .insert(PUSHBACK_METADATA, value.parse().unwrap());
+            status
+        };
+        let mut handler = Pushback::new(|e: Status| match e {
+            e if e.code() == Code::InvalidArgument => RetryPolicy::ForwardError(e),
+            _ => RetryPolicy::Repeat,
+        })
+        .max_pushback(Duration::from_secs(1));
+        assert_eq!(
+            Some(Duration::from_secs(1)),
+            match handler.handle(1, pushed_back("60000")) {
+                RetryPolicy::WaitRetry(delay) => Some(delay),
+                _ => None,
+            }
+        );
+        assert!(matches!(
+            handler.handle(2, pushed_back("soon")),
+            RetryPolicy::ForwardError(_)
+        ));
+        assert!(matches!(
+            handler.handle(3, Status::unavailable("no pushback")),
+            RetryPolicy::Repeat
+        ));
+        // The inner handler still decides when to give up.
+        let metadata = pushed_back("10").metadata().clone();
+        let invalid = Status::with_metadata(Code::InvalidArgument, "bad request", metadata);
+        assert!(matches!(
+            handler.handle(4, invalid),
+            RetryPolicy::ForwardError(_)
+        ));
+    }
+}
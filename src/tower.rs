@@ -0,0 +1,277 @@
+//! Retrying connection establishment for [`tower`](https://docs.rs/tower) connectors.
+//!
+//! Available with the `tower` feature.
+
+use crate::{ErrorHandler, RetryPolicy, Sleeper, TokioSleeper};
+use ::tower::{make::MakeConnection, Service};
+use futures::ready;
+use pin_project_lite::pin_project;
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// A connector that retries establishing a connection with an inner
+/// [`MakeConnection`](https://docs.rs/tower/latest/tower/make/trait.MakeConnection.html), as the
+/// error handler decides.
+///
+/// It is a `Service` from a target (e.g. a `Uri`) to a connection itself, so it might be used
+/// wherever the inner connector is, covering hyper, tonic and custom transports alike. Every call
+/// gets its own clones of the inner connector and of the error handler, so the attempts of the
+/// concurrent calls are counted separately.
+///
+/// ```
+/// use futures_retry::{tower::RetryConnector, ExponentialBackoff};
+/// use std::{io, time::Duration};
+/// use tower::Service;
+///
+/// # #[derive(Clone)] struct Connector;
+/// # impl Service<&'static str> for Connector {
+/// #     type Response = tokio::io::DuplexStream;
+/// #     type Error = io::Error;
+/// #     type Future = futures::future::Ready<io::Result<Self::Response>>;
+/// #     fn poll_ready(&mut self, _: &mut std::task::Context) -> std::task::Poll<io::Result<()>> {
+/// #         std::task::Poll::Ready(Ok(()))
+/// #     }
+/// #     fn call(&mut self, _: &'static str) -> Self::Future {
+/// #         futures::future::ready(Ok(tokio::io::duplex(64).0))
+/// #     }
+/// # }
+/// # #[tokio::main] async fn main() -> io::Result<()> {
+/// let backoff = ExponentialBackoff::new(Duration::from_millis(100)).max_attempts(5);
+/// let mut connector = RetryConnector::new(Connector, backoff);
+/// let connection = connector.call("db.internal:5432").await?;
+/// # Ok(()) }
+/// ```
+#[derive(Debug, Clone)]
+pub struct RetryConnector<C, H, S = TokioSleeper> {
+    connector: C,
+    error_action: H,
+    sleeper: S,
+}
+
+impl<C, H> RetryConnector<C, H> {
+    /// Wraps a connector.
+    pub fn new(connector: C, error_action: H) -> Self {
+        Self::with_sleeper(connector, error_action, TokioSleeper)
+    }
+}
+
+impl<C, H, S> RetryConnector<C, H, S> {
+    /// Like a `new` method, but the delays between the attempts are timed by a custom
+    /// [`Sleeper`](../trait.Sleeper.html).
+    pub fn with_sleeper(connector: C, error_action: H, sleeper: S) -> Self {
+        Self {
+            connector,
+            error_action,
+            sleeper,
+        }
+    }
+
+    /// Returns a reference to the inner connector.
+    pub fn get_ref(&self) -> &C {
+        &self.connector
+    }
+}
+
+impl<C, H, T, S> Service<T> for RetryConnector<C, H, S>
+where
+    C: MakeConnection<T> + Clone,
+    H: ErrorHandler<C::Error> + Clone,
+    T: Clone,
+    S: Sleeper + Clone,
+{
+    type Response = C::Connection;
+    type Error = H::OutError;
+    type Future = RetryConnect<C, H, T, S>;
+
+    fn poll_ready(&mut self, _cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        // The readiness of the inner connector is awaited (and retried) by the futures.
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        RetryConnect {
+            connector: self.connector.clone(),
+            error_action: self.error_action.clone(),
+            target,
+            attempt: 1,
+            sleeper: self.sleeper.clone(),
+            state: ConnectState::PollReady,
+        }
+    }
+}
+
+pin_project! {
+    #[project = ConnectStateProj]
+    enum ConnectState<F, D> {
+        PollReady,
+        Connecting { #[pin] future: F },
+        TimerActive { #[pin] delay: D },
+    }
+}
+
+pin_project! {
+    /// A future that establishes a connection, retrying on errors.
+    ///
+    /// Created by [`RetryConnector`](struct.RetryConnector.html).
+    pub struct RetryConnect<C, H, T, S = TokioSleeper>
+    where
+        C: MakeConnection<T>,
+        S: Sleeper,
+    {
+        connector: C,
+        error_action: H,
+        target: T,
+        attempt: usize,
+        sleeper: S,
+        #[pin]
+        state: ConnectState<C::Future, S::Sleep>,
+    }
+}
+
+impl<C: MakeConnection<T>, H, T, S: Sleeper> fmt::Debug for RetryConnect<C, H, T, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RetryConnect")
+            .field("attempt", &self.attempt)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<C, H, T, S> Future for RetryConnect<C, H, T, S>
+where
+    C: MakeConnection<T>,
+    H: ErrorHandler<C::Error>,
+    T: Clone,
+    S: Sleeper,
+{
+    type Output = Result<C::Connection, H::OutError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut this = self.project();
+        loop {
+            let e = match this.state.as_mut().project() {
+                ConnectStateProj::PollReady => match ready!(this.connector.poll_ready(cx)) {
+                    Ok(()) => {
+                        let future = this.connector.make_connection(this.target.clone());
+                        this.state.set(ConnectState::Connecting { future });
+                        continue;
+                    }
+                    Err(e) => e,
+                },
+                ConnectStateProj::Connecting { future } => match ready!(future.poll(cx)) {
+                    Ok(connection) => {
+                        this.error_action.ok_with(*this.attempt, &connection);
+                        return Poll::Ready(Ok(connection));
+                    }
+                    Err(e) => e,
+                },
+                ConnectStateProj::TimerActive { delay } => {
+                    ready!(delay.poll(cx));
+                    this.state.set(ConnectState::PollReady);
+                    continue;
+                }
+            };
+            let attempt = *this.attempt;
+            let policy = this.error_action.handle(attempt, e);
+            *this.attempt = policy.next_attempt(attempt);
+            match policy {
+                RetryPolicy::ForwardError(e) => {
+                    this.error_action.exhausted(attempt, &e);
+                    return Poll::Ready(Err(e));
+                }
+                RetryPolicy::Repeat | RetryPolicy::RepeatWithoutCounting => {
+                    this.state.set(ConnectState::PollReady)
+                }
+                RetryPolicy::WaitRetry(duration)
+                | RetryPolicy::RetryAs {
+                    delay: duration, ..
+                } => this.state.set(ConnectState::TimerActive {
+                    delay: this.sleeper.sleep(duration),
+                }),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handler::tests::Successes;
+    use futures::future::{ready, Ready};
+    use std::{
+        io,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+    use tokio::io::{duplex, DuplexStream};
+
+    #[derive(Clone, Default)]
+    struct Flaky(Arc<AtomicUsize>);
+
+    impl Service<u16> for Flaky {
+        type Response = DuplexStream;
+        type Error = io::Error;
+        type Future = Ready<io::Result<DuplexStream>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, port: u16) -> Self::Future {
+            ready(match self.0.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err(io::ErrorKind::ConnectionRefused.into()),
+                _ if port == 0 => Err(io::ErrorKind::InvalidInput.into()),
+                _ => Ok(duplex(8).0),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn retries() {
+        let flaky = Flaky::default();
+        let mut connector = RetryConnector::new(flaky.clone(), |e: io::Error| match e.kind() {
+            io::ErrorKind::ConnectionRefused => RetryPolicy::WaitRetry(Duration::from_millis(1)),
+            _ => RetryPolicy::ForwardError(e),
+        });
+        assert!(connector.call(80).await.is_ok());
+        assert_eq!(3, flaky.0.load(Ordering::SeqCst));
+        let e = connector.call(0).await.unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, e.kind());
+    }
+
+    #[cfg(feature = "test_util")]
+    #[tokio::test]
+    async fn waits_on_the_sleeper() {
+        let sleeper = crate::test_util::MockSleeper::new();
+        let hour = Duration::from_secs(3600);
+        let mut connector = RetryConnector::with_sleeper(
+            Flaky::default(),
+            |_| RetryPolicy::WaitRetry::<io::Error>(hour),
+            sleeper.clone(),
+        );
+        let connect = connector.call(80);
+        tokio::pin!(connect);
+        assert!(futures::poll!(connect.as_mut()).is_pending());
+        sleeper.advance(hour);
+        assert!(futures::poll!(connect.as_mut()).is_pending());
+        sleeper.advance(hour);
+        assert!(connect.await.is_ok());
+        assert_eq!(vec![hour, hour], sleeper.requested());
+    }
+
+    #[tokio::test]
+    async fn passes_success_values() {
+        let successes =
+            Successes::new(|_| RetryPolicy::WaitRetry::<io::Error>(Duration::from_millis(1)));
+        let mut connector = RetryConnector::new(Flaky::default(), successes.clone());
+        assert!(connector.call(80).await.is_ok());
+        let connection = std::any::type_name::<DuplexStream>();
+        assert_eq!(vec![(3, connection)], successes.seen());
+    }
+}
@@ -0,0 +1,162 @@
+//! Capturing the span context of the failed attempts with
+//! [`tracing-error`](https://docs.rs/tracing-error).
+//!
+//! The [`SpanTraced`](struct.SpanTraced.html) handler captures a `SpanTrace` whenever an attempt
+//! fails, so once the loop gives up the forwarded error tells where each attempt has been made,
+//! the first one included. The traces are only captured when the subscriber has a
+//! `tracing_error::ErrorLayer`.
+//!
+//! Available with the `tracing-error` feature.
+
+use crate::{ErrorHandler, RetryPolicy};
+use ::tracing_error::{SpanTrace, SpanTraceStatus};
+use std::{error::Error, fmt, mem};
+
+/// The span context of a failed attempt.
+#[derive(Debug, Clone)]
+pub struct AttemptTrace {
+    /// The number of the failed attempt.
+    pub attempt: usize,
+    /// The span context the attempt has failed in.
+    pub span_trace: SpanTrace,
+}
+
+/// The error a retry loop has given up on, along with the span context of every failed attempt.
+///
+/// Created by a [`SpanTraced`](struct.SpanTraced.html) handler.
+#[derive(Debug, Clone)]
+pub struct Traced<E> {
+    /// The forwarded error.
+    pub error: E,
+    /// The failed attempts since the last success, the first one first.
+    pub attempts: Vec<AttemptTrace>,
+}
+
+impl<E> Traced<E> {
+    /// Returns the span context of the first failed attempt.
+    pub fn first(&self) -> Option<&SpanTrace> {
+        self.attempts.first().map(|attempt| &attempt.span_trace)
+    }
+
+    /// Returns the inner error.
+    pub fn into_inner(self) -> E {
+        self.error
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Traced<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.error)?;
+        match self.attempts.first() {
+            Some(first) if first.span_trace.status() == SpanTraceStatus::CAPTURED => write!(
+                f,
+                "\n\nthe first of {} failed attempts (attempt {}) was made in:\n{}",
+                self.attempts.len(),
+                first.attempt,
+                first.span_trace
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for Traced<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// An error handler that captures a `SpanTrace` at every failure before passing the error to an
+/// inner one, and wraps the forwarded errors into [`Traced`](struct.Traced.html) ones.
+///
+/// ```
+/// use futures_retry::{tracing_error::SpanTraced, ErrorHandler, RetryPolicy};
+///
+/// let mut handler = SpanTraced::new(|e| match e {
+///     "busy" => RetryPolicy::Repeat,
+///     e => RetryPolicy::ForwardError(e),
+/// });
+/// assert!(matches!(handler.handle(1, "busy"), RetryPolicy::Repeat));
+/// match handler.handle(2, "gone") {
+///     RetryPolicy::ForwardError(e) => assert_eq!(("gone", 2), (e.error, e.attempts.len())),
+///     policy => panic!("Unexpected policy {:?}", policy),
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct SpanTraced<H> {
+    inner: H,
+    attempts: Vec<AttemptTrace>,
+}
+
+impl<H> SpanTraced<H> {
+    /// Wraps an error handler.
+    pub fn new(inner: H) -> Self {
+        Self {
+            inner,
+            attempts: Vec::new(),
+        }
+    }
+}
+
+impl<E, H: ErrorHandler<E>> ErrorHandler<E> for SpanTraced<H> {
+    type OutError = Traced<H::OutError>;
+
+    fn handle(&mut self, attempt: usize, e: E) -> RetryPolicy<Self::OutError> {
+        self.attempts.push(AttemptTrace {
+            attempt,
+            span_trace: SpanTrace::capture(),
+        });
+        match self.inner.handle(attempt, e) {
+            RetryPolicy::ForwardError(error) => RetryPolicy::ForwardError(Traced {
+                error,
+                attempts: mem::take(&mut self.attempts),
+            }),
+            RetryPolicy::Repeat => RetryPolicy::Repeat,
+            RetryPolicy::RepeatWithoutCounting => RetryPolicy::RepeatWithoutCounting,
+            RetryPolicy::WaitRetry(delay) => RetryPolicy::WaitRetry(delay),
+            RetryPolicy::RetryAs { attempt, delay } => RetryPolicy::RetryAs { attempt, delay },
+        }
+    }
+
+    fn ok(&mut self, attempt: usize) {
+        self.attempts.clear();
+        self.inner.ok(attempt);
+    }
+
+    fn ok_with<V: ?Sized>(&mut self, attempt: usize, value: &V) {
+        self.attempts.clear();
+        self.inner.ok_with(attempt, value);
+    }
+
+    fn exhausted(&mut self, attempt: usize, error: &Self::OutError) {
+        self.inner.exhausted(attempt, &error.error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FutureRetry;
+    use futures::future::err;
+
+    #[tokio::test]
+    async fn keeps_every_attempt() {
+        let mut errors = 0;
+        let result = FutureRetry::new(
+            || err::<(), _>("nope"),
+            SpanTraced::new(move |e| {
+                errors += 1;
+                match errors {
+                    1 | 2 => RetryPolicy::Repeat,
+                    _ => RetryPolicy::ForwardError(e),
+                }
+            }),
+        )
+        .await;
+        let (e, attempt) = result.unwrap_err();
+        let attempts: Vec<_> = e.attempts.iter().map(|trace| trace.attempt).collect();
+        assert_eq!((vec![1, 2, 3], 3), (attempts, attempt));
+        // No `ErrorLayer` is installed, so nothing is captured and only the error is displayed.
+        assert_eq!("nope", e.to_string());
+    }
+}
@@ -0,0 +1,112 @@
+use crate::{ErrorHandler, RetryPolicy, Sleeper, TokioSleeper};
+use std::future::Future;
+
+/// Retries the futures created from a state, in the `try_unfold` style: a failed attempt hands
+/// the state back along with the error, and the next attempt is created from it.
+///
+/// This way the resources that can't be cloned (a half-initialized connection, a partially
+/// consumed body) are reused across the attempts without resorting to an `Arc<Mutex<_>>`. The
+/// state is dropped once the handler gives up. Resolves into the value (or the error) along with
+/// the number of the attempt, the same way a [`FutureRetry`](struct.FutureRetry.html) does.
+///
+/// ```
+/// use futures_retry::{retry_unfold, RetryPolicy};
+///
+/// struct Connection {
+///     handshakes: usize,
+/// }
+///
+/// # #[tokio::main] async fn main() {
+/// let result = retry_unfold(
+///     Connection { handshakes: 0 },
+///     |mut connection| async move {
+///         connection.handshakes += 1;
+///         match connection.handshakes {
+///             3 => Ok(connection),
+///             _ => Err(("handshake failed", connection)),
+///         }
+///     },
+///     |_| RetryPolicy::Repeat::<&str>,
+/// )
+/// .await;
+/// let (connection, attempt) = result.unwrap();
+/// assert_eq!((3, 3), (connection.handshakes, attempt));
+/// # }
+/// ```
+pub async fn retry_unfold<S, F, Fut, T, E, R>(
+    init: S,
+    mut factory: F,
+    mut error_action: R,
+) -> Result<(T, usize), (R::OutError, usize)>
+where
+    F: FnMut(S) -> Fut,
+    Fut: Future<Output = Result<T, (E, S)>>,
+    R: ErrorHandler<E>,
+{
+    let mut state = init;
+    let mut attempt = 1;
+    loop {
+        match factory(state).await {
+            Ok(x) => {
+                error_action.ok_with(attempt, &x);
+                return Ok((x, attempt));
+            }
+            Err((e, returned)) => {
+                state = returned;
+                let policy = error_action.handle(attempt, e);
+                let next = policy.next_attempt(attempt);
+                match policy {
+                    RetryPolicy::ForwardError(e) => {
+                        error_action.exhausted(attempt, &e);
+                        return Err((e, attempt));
+                    }
+                    RetryPolicy::Repeat | RetryPolicy::RepeatWithoutCounting => {}
+                    RetryPolicy::WaitRetry(delay) | RetryPolicy::RetryAs { delay, .. } => {
+                        TokioSleeper.sleep(delay).await
+                    }
+                }
+                attempt = next;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::ready;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn threads_the_state() {
+        // The body is consumed a chunk per attempt, so it can't be recreated.
+        let body = vec![1u8, 2, 3].into_iter();
+        let result = retry_unfold(
+            body,
+            |mut body| {
+                ready(match body.next() {
+                    Some(3) => Ok(body.len()),
+                    Some(chunk) => Err((chunk, body)),
+                    None => unreachable!(),
+                })
+            },
+            |_| RetryPolicy::WaitRetry::<()>(Duration::from_millis(1)),
+        )
+        .await;
+        assert_eq!(Ok((0, 3)), result);
+    }
+
+    #[tokio::test]
+    async fn gives_up() {
+        let result = retry_unfold(
+            0u8,
+            |state| ready(Err::<(), _>((state, state + 1))),
+            |e| match e {
+                0 => RetryPolicy::Repeat,
+                e => RetryPolicy::ForwardError(e),
+            },
+        )
+        .await;
+        assert_eq!(Err((1, 2)), result);
+    }
+}
@@ -0,0 +1,183 @@
+use std::time::Duration;
+
+/// What to do when a future resolves successfully. Used in
+/// [`Waiter::with_value_handler`](struct.Waiter.html#method.with_value_handler).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ValuePolicy<T> {
+    /// The value is fine, pass it further to the user.
+    Accept(T),
+    /// Discard the value and create and poll a new future immediately.
+    Repeat,
+    /// Discard the value, wait for a given duration and make another attempt then.
+    WaitRetry(Duration),
+}
+
+/// A success classifier: a trait that decides whether a successfully obtained value is final, or
+/// another attempt should be made anyway, e.g. on an HTTP 202 or a `status: "PENDING"` payload.
+///
+/// Like [`ErrorHandler`](trait.ErrorHandler.html), this trait is implemented for any `FnMut`
+/// closure with a compatible signature.
+///
+/// ```
+/// use futures_retry::{RetryPolicy, ValuePolicy, Waiter};
+/// use std::time::Duration;
+///
+/// # #[tokio::main] async fn main() {
+/// let mut responses = vec![(202, ""), (202, ""), (200, "done")].into_iter();
+/// let waiter = Waiter::with_value_handler(
+///     || futures::future::ok::<_, ()>(responses.next().unwrap()),
+///     |(status, body)| match status {
+///         202 => ValuePolicy::WaitRetry(Duration::from_millis(10)),
+///         _ => ValuePolicy::Accept(body),
+///     },
+///     RetryPolicy::ForwardError,
+/// );
+/// assert_eq!(Ok(("done", 3)), waiter.await);
+/// # }
+/// ```
+pub trait ValueHandler<T> {
+    /// A value that is produced once it is accepted.
+    type OutValue;
+
+    /// Handles a value.
+    ///
+    /// Refer to the [`ValuePolicy`](enum.ValuePolicy.html) type to understand what this method
+    /// might return.
+    fn handle(&mut self, attempt: usize, value: T) -> ValuePolicy<Self::OutValue>;
+}
+
+impl<T, F, OutValue> ValueHandler<T> for F
+where
+    F: Unpin + FnMut(T) -> ValuePolicy<OutValue>,
+{
+    type OutValue = OutValue;
+
+    fn handle(&mut self, _attempt: usize, value: T) -> ValuePolicy<OutValue> {
+        (self)(value)
+    }
+}
+
+/// A value handler that accepts values satisfying a predicate and retries after a fixed delay
+/// otherwise.
+///
+/// Created by [`Waiter::new`](struct.Waiter.html#method.new).
+#[derive(Debug, Clone)]
+pub struct Until<P> {
+    predicate: P,
+    delay: Duration,
+}
+
+impl<P> Until<P> {
+    /// Creates a handler that waits for the `delay` whenever the `predicate` doesn't hold.
+    pub fn new(predicate: P, delay: Duration) -> Self {
+        Self { predicate, delay }
+    }
+}
+
+impl<T, P> ValueHandler<T> for Until<P>
+where
+    P: FnMut(&T) -> bool,
+{
+    type OutValue = T;
+
+    fn handle(&mut self, _attempt: usize, value: T) -> ValuePolicy<T> {
+        if (self.predicate)(&value) {
+            ValuePolicy::Accept(value)
+        } else {
+            ValuePolicy::WaitRetry(self.delay)
+        }
+    }
+}
+
+/// A value handler that accepts a value only after it has stayed the same for a number of
+/// consecutive polls, waiting for a fixed delay between the polls.
+///
+/// Useful to wait for an eventually consistent system to converge.
+///
+/// ```
+/// use futures_retry::{RetryPolicy, Stable, Waiter};
+/// use std::time::Duration;
+///
+/// # #[tokio::main] async fn main() {
+/// let mut replicas = vec![1, 2, 3, 3, 2, 3, 3, 3].into_iter();
+/// let waiter = Waiter::with_value_handler(
+///     || futures::future::ok::<_, ()>(replicas.next().unwrap()),
+///     Stable::new(3, Duration::from_millis(1)),
+///     RetryPolicy::ForwardError,
+/// );
+/// assert_eq!(Ok((3, 8)), waiter.await);
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Stable<T, C> {
+    polls: usize,
+    delay: Duration,
+    comparator: C,
+    last: Option<T>,
+    count: usize,
+}
+
+impl<T: PartialEq> Stable<T, fn(&T, &T) -> bool> {
+    /// Creates a handler that accepts a value once it has been equal to the previous ones for
+    /// `polls` consecutive polls.
+    pub fn new(polls: usize, delay: Duration) -> Self {
+        Self::by(polls, delay, PartialEq::eq)
+    }
+}
+
+impl<T, C> Stable<T, C>
+where
+    C: FnMut(&T, &T) -> bool,
+{
+    /// Creates a handler that accepts a value once the `comparator` has deemed it identical to the
+    /// previous ones for `polls` consecutive polls.
+    pub fn by(polls: usize, delay: Duration, comparator: C) -> Self {
+        Self {
+            polls,
+            delay,
+            comparator,
+            last: None,
+            count: 0,
+        }
+    }
+}
+
+impl<T, C> ValueHandler<T> for Stable<T, C>
+where
+    C: FnMut(&T, &T) -> bool,
+{
+    type OutValue = T;
+
+    fn handle(&mut self, _attempt: usize, value: T) -> ValuePolicy<T> {
+        self.count = match &self.last {
+            Some(last) if (self.comparator)(last, &value) => self.count + 1,
+            _ => 1,
+        };
+        if self.count >= self.polls {
+            self.last = None;
+            self.count = 0;
+            ValuePolicy::Accept(value)
+        } else {
+            self.last = Some(value);
+            ValuePolicy::WaitRetry(self.delay)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stable_by_comparator() {
+        let mut stable = Stable::by(2, Duration::from_secs(1), |a: &f64, b: &f64| {
+            (a - b).abs() < 0.1
+        });
+        let delay = ValuePolicy::WaitRetry(Duration::from_secs(1));
+        assert_eq!(delay, stable.handle(1, 1.0));
+        assert_eq!(delay, stable.handle(2, 2.0));
+        assert_eq!(ValuePolicy::Accept(2.05), stable.handle(3, 2.05));
+        // Starts over after a value has been accepted.
+        assert_eq!(delay, stable.handle(1, 2.05));
+    }
+}
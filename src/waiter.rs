@@ -0,0 +1,264 @@
+use crate::{
+    ErrorHandler, FutureFactory, RetryPolicy, Sleeper, TokioSleeper, Until, ValueHandler,
+    ValuePolicy,
+};
+use futures::{ready, TryFuture};
+use pin_project_lite::pin_project;
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+pin_project! {
+    /// A future that polls a resource until it reaches the desired state, in the spirit of the AWS
+    /// SDK waiters.
+    ///
+    /// The factory creates a future that fetches the current state of the resource. When the
+    /// future succeeds, the predicate is checked against the value: if the predicate holds, the
+    /// waiter resolves into the value, otherwise it waits for the `delay` and fetches the state
+    /// again. Errors are passed to the `error_action` as usual.
+    ///
+    /// The waiter counts all the attempts, the unsatisfied ones included. It has no limit on its
+    /// own, so wrap it in `tokio::time::timeout` if it shouldn't wait forever.
+    ///
+    /// For finer control over the successful values, a waiter can also be built with a
+    /// [`ValueHandler`](trait.ValueHandler.html), see
+    /// [`with_value_handler`](#method.with_value_handler).
+    ///
+    /// ```
+    /// use futures_retry::{RetryPolicy, Waiter};
+    /// use std::{
+    ///     sync::atomic::{AtomicUsize, Ordering},
+    ///     time::Duration,
+    /// };
+    ///
+    /// # #[tokio::main] async fn main() {
+    /// let progress = AtomicUsize::new(0);
+    /// let waiter = Waiter::new(
+    ///     || async { Ok::<_, ()>(progress.fetch_add(25, Ordering::SeqCst) + 25) },
+    ///     |progress: &usize| *progress == 100,
+    ///     Duration::from_millis(10),
+    ///     RetryPolicy::ForwardError,
+    /// );
+    /// assert_eq!(Ok((100, 4)), waiter.await);
+    /// # }
+    /// ```
+    pub struct Waiter<F, V, R, S = TokioSleeper>
+    where
+        F: FutureFactory,
+        S: Sleeper,
+    {
+        factory: F,
+        value_action: V,
+        error_action: R,
+        sleeper: S,
+        attempt: usize,
+        #[pin]
+        state: WaitState<F::FutureItem, S::Sleep>,
+    }
+}
+
+pin_project! {
+    #[project = WaitStateProj]
+    enum WaitState<F, D> {
+        NotStarted,
+        WaitingForFuture { #[pin] future: F },
+        TimerActive { #[pin] delay: D },
+    }
+}
+
+impl<F: FutureFactory, P, R> Waiter<F, Until<P>, R> {
+    /// Creates a waiter that fetches the state with futures created by the `factory` until the
+    /// `predicate` holds, waiting for the `delay` between the unsatisfied attempts.
+    pub fn new(factory: F, predicate: P, delay: Duration, error_action: R) -> Self {
+        Self::with_value_handler(factory, Until::new(predicate, delay), error_action)
+    }
+}
+
+impl<F: FutureFactory, V, R> Waiter<F, V, R> {
+    /// Creates a waiter that passes every successfully obtained value to the `value_action`,
+    /// which decides whether to accept the value or to make another attempt.
+    pub fn with_value_handler(factory: F, value_action: V, error_action: R) -> Self {
+        Self::with_sleeper(factory, value_action, error_action, TokioSleeper)
+    }
+}
+
+impl<F: FutureFactory, V, R, S: Sleeper> Waiter<F, V, R, S> {
+    /// Like [`with_value_handler`](#method.with_value_handler), but the delays between the
+    /// attempts are timed by a custom [`Sleeper`](trait.Sleeper.html).
+    pub fn with_sleeper(factory: F, value_action: V, error_action: R, sleeper: S) -> Self {
+        Self {
+            factory,
+            value_action,
+            error_action,
+            sleeper,
+            attempt: 1,
+            state: WaitState::NotStarted,
+        }
+    }
+}
+
+impl<F: FutureFactory, V, R, S: Sleeper> fmt::Debug for Waiter<F, V, R, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Waiter")
+            .field("attempt", &self.attempt)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F, V, R, S> Future for Waiter<F, V, R, S>
+where
+    F: FutureFactory,
+    S: Sleeper,
+    V: ValueHandler<<F::FutureItem as TryFuture>::Ok>,
+    R: ErrorHandler<<F::FutureItem as TryFuture>::Error>,
+{
+    type Output = Result<(V::OutValue, usize), (R::OutError, usize)>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        loop {
+            let this = self.as_mut().project();
+            let attempt = *this.attempt;
+            let new_state = match this.state.project() {
+                WaitStateProj::NotStarted => WaitState::WaitingForFuture {
+                    future: this.factory.new(),
+                },
+                WaitStateProj::TimerActive { delay } => {
+                    ready!(delay.poll(cx));
+                    WaitState::WaitingForFuture {
+                        future: this.factory.new(),
+                    }
+                }
+                WaitStateProj::WaitingForFuture { future } => {
+                    let result = ready!(future.try_poll(cx));
+                    *this.attempt += 1;
+                    match result {
+                        Ok(value) => match this.value_action.handle(attempt, value) {
+                            ValuePolicy::Accept(value) => {
+                                this.error_action.ok_with(attempt, &value);
+                                return Poll::Ready(Ok((value, attempt)));
+                            }
+                            ValuePolicy::Repeat => WaitState::WaitingForFuture {
+                                future: this.factory.new(),
+                            },
+                            ValuePolicy::WaitRetry(duration) => WaitState::TimerActive {
+                                delay: this.sleeper.sleep(duration),
+                            },
+                        },
+                        Err(e) => {
+                            let policy = this.error_action.handle(attempt, e);
+                            *this.attempt = policy.next_attempt(attempt);
+                            match policy {
+                                RetryPolicy::ForwardError(e) => {
+                                    this.error_action.exhausted(attempt, &e);
+                                    return Poll::Ready(Err((e, attempt)));
+                                }
+                                RetryPolicy::Repeat | RetryPolicy::RepeatWithoutCounting => {
+                                    WaitState::WaitingForFuture {
+                                        future: this.factory.new(),
+                                    }
+                                }
+                                RetryPolicy::WaitRetry(duration)
+                                | RetryPolicy::RetryAs {
+                                    delay: duration, ..
+                                } => WaitState::TimerActive {
+                                    delay: this.sleeper.sleep(duration),
+                                },
+                            }
+                        }
+                    }
+                }
+            };
+            self.as_mut().project().state.set(new_state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handler::tests::Successes;
+    use futures::future::{err, ok, Ready};
+
+    fn states(
+        states: Vec<Result<&'static str, u8>>,
+    ) -> impl FnMut() -> Ready<Result<&'static str, u8>> {
+        let mut states = states.into_iter();
+        move || futures::future::ready(states.next().expect("No more states"))
+    }
+
+    #[tokio::test]
+    async fn waits_for_state() {
+        let waiter = Waiter::new(
+            states(vec![Ok("pending"), Err(1), Ok("pending"), Ok("ready")]),
+            |state: &&str| *state == "ready",
+            Duration::from_millis(5),
+            |_| RetryPolicy::Repeat::<u8>,
+        );
+        assert_eq!(Ok(("ready", 4)), waiter.await);
+    }
+
+    #[tokio::test]
+    async fn forwards_errors() {
+        let waiter = Waiter::new(
+            || err::<&str, _>(1u8),
+            |_: &&str| true,
+            Duration::from_millis(5),
+            RetryPolicy::ForwardError,
+        );
+        assert_eq!(Err((1, 1)), waiter.await);
+        let waiter = Waiter::new(
+            || ok::<_, u8>("ready"),
+            |_: &&str| true,
+            Duration::from_millis(5),
+            RetryPolicy::ForwardError,
+        );
+        assert_eq!(Ok(("ready", 1)), waiter.await);
+    }
+
+    #[tokio::test]
+    async fn value_handler() {
+        let waiter = Waiter::with_value_handler(
+            states(vec![Ok(""), Ok("pending"), Ok("42")]),
+            |body: &'static str| match body {
+                "" => ValuePolicy::Repeat,
+                "pending" => ValuePolicy::WaitRetry(Duration::from_millis(5)),
+                body => ValuePolicy::Accept(body.parse::<u32>().unwrap()),
+            },
+            RetryPolicy::ForwardError,
+        );
+        assert_eq!(Ok((42, 3)), waiter.await);
+    }
+
+    #[cfg(feature = "test_util")]
+    #[tokio::test]
+    async fn waits_on_the_sleeper() {
+        let sleeper = crate::test_util::MockSleeper::new();
+        let waiter = Waiter::with_sleeper(
+            states(vec![Ok("pending"), Ok("ready")]),
+            Until::new(|state: &&str| *state == "ready", Duration::from_secs(3600)),
+            RetryPolicy::ForwardError,
+            sleeper.clone(),
+        );
+        futures::pin_mut!(waiter);
+        assert!(futures::poll!(waiter.as_mut()).is_pending());
+        sleeper.advance(Duration::from_secs(3600));
+        assert_eq!(Ok(("ready", 2)), waiter.await);
+        assert_eq!(vec![Duration::from_secs(3600)], sleeper.requested());
+    }
+
+    #[tokio::test]
+    async fn passes_success_values() {
+        let successes = Successes::new(|_| RetryPolicy::Repeat::<u8>);
+        let waiter = Waiter::with_value_handler(
+            states(vec![Err(1), Ok("42")]),
+            |body: &'static str| ValuePolicy::Accept(body.parse::<u32>().unwrap()),
+            successes.clone(),
+        );
+        assert_eq!(Ok((42, 2)), waiter.await);
+        assert_eq!(vec![(2, "u32")], successes.seen());
+    }
+}